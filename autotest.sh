@@ -1,2 +1,4 @@
 # cargo run
-gt-auto --config testcase/gw-auto-sse.toml --casePath testcase/sse_test_case.csv
+cargo run --bin testcase_runner -- testcase/sse_test_case.csv 127.0.0.1:9010
+# or with the external tool:
+# gt-auto --config testcase/gw-auto-sse.toml --casePath testcase/sse_test_case.csv
@@ -0,0 +1,42 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use exchange_matcher::engine::match_engine::MatchEngine;
+use exchange_matcher::types::{EngineCommand, OrderSide, RbCmd, TimeInForce};
+use tokio::sync::mpsc::{channel, unbounded_channel};
+
+const SECURITIES: [&str; 4] = ["600000", "600036", "600519", "601318"];
+
+fn order(oid: i64, security_id: &str, side: OrderSide) -> EngineCommand {
+    EngineCommand::NewOrder(RbCmd {
+        session_id: 1,
+        side,
+        match_event_list: vec![],
+        price: 10_000,
+        volume: 100,
+        mid: oid,
+        uid: 0,
+        oid,
+        security_id: security_id.to_string(),
+        time_in_force: TimeInForce::Day,
+    })
+}
+
+// a sell and the buy trading with it, round the securities, with the
+// events taken off the queue as a channel would
+fn handle(c: &mut Criterion) {
+    let (_cmd_tx, cmd_rx) = channel(1);
+    let (event_tx, mut event_rx) = unbounded_channel();
+    let mut engine = MatchEngine::new(cmd_rx, event_tx);
+    let mut oid = 0;
+    c.bench_function("match_engine/cross", |b| {
+        b.iter(|| {
+            let security_id = SECURITIES[(oid / 2) as usize % SECURITIES.len()];
+            engine.handle(order(oid + 1, security_id, OrderSide::Sell));
+            engine.handle(order(oid + 2, security_id, OrderSide::Buy));
+            oid += 2;
+            while event_rx.try_recv().is_ok() {}
+        })
+    });
+}
+
+criterion_group!(benches, handle);
+criterion_main!(benches);
@@ -0,0 +1,104 @@
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
+use exchange_matcher::order_book::OrderBook;
+use exchange_matcher::types::{OrderSide, RbCmd, TimeInForce};
+
+const ASK: i64 = 10_000;
+const LOT: i64 = 100;
+
+fn cmd(oid: i64, side: OrderSide, price: i64, volume: i64) -> RbCmd {
+    RbCmd {
+        session_id: 1,
+        side,
+        match_event_list: vec![],
+        price,
+        volume,
+        mid: oid,
+        uid: 0,
+        oid,
+        security_id: "600000".to_string(),
+        time_in_force: TimeInForce::Day,
+    }
+}
+
+// `levels` ask levels from ASK up, `per_level` lots each; oids run from 1
+fn book(levels: i64, per_level: i64) -> OrderBook {
+    let mut book = OrderBook::new("600000".to_string());
+    let mut oid = 0;
+    for level in 0..levels {
+        for _ in 0..per_level {
+            oid += 1;
+            book.new_order(&mut cmd(oid, OrderSide::Sell, ASK + level, LOT));
+        }
+    }
+    book
+}
+
+fn new_order(c: &mut Criterion) {
+    let mut group = c.benchmark_group("new_order");
+    // rests behind the book without trading
+    group.bench_function("rest", |b| {
+        b.iter_batched(
+            || (book(10, 10), cmd(1_000, OrderSide::Buy, ASK - 1, LOT)),
+            |(mut book, mut cmd)| {
+                book.new_order(&mut cmd);
+                book
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    // takes the first order of the best level
+    group.bench_function("match", |b| {
+        b.iter_batched(
+            || (book(10, 10), cmd(1_000, OrderSide::Buy, ASK, LOT)),
+            |(mut book, mut cmd)| {
+                book.new_order(&mut cmd);
+                book
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn cancel_order(c: &mut Criterion) {
+    // the middle order of the middle level
+    c.bench_function("cancel_order", |b| {
+        b.iter_batched(
+            || (book(10, 10), cmd(55, OrderSide::Sell, ASK + 5, LOT)),
+            |(mut book, mut cmd)| {
+                book.cancel_order(&mut cmd, 0);
+                book
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn sweep(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sweep");
+    for levels in [1, 10, 100] {
+        // a buy taking every order of every level
+        group.bench_with_input(
+            BenchmarkId::from_parameter(levels),
+            &levels,
+            |b, &levels| {
+                b.iter_batched(
+                    || {
+                        let volume = levels * 10 * LOT;
+                        let buy = cmd(1_000_000, OrderSide::Buy, ASK + levels, volume);
+                        (book(levels, 10), buy)
+                    },
+                    |(mut book, mut cmd)| {
+                        book.new_order(&mut cmd);
+                        book
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, new_order, cancel_order, sweep);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+//! cargo +nightly fuzz run frame_decoder
+//!
+//! The first byte picks the protocol, the second how the rest is cut into
+//! reads. Decoding must not panic, must make progress on every error and
+//! must keep its buffer within the frame limit.
+
+#![no_main]
+
+use exchange_matcher::protocol::fix::FixDecoder;
+use exchange_matcher::protocol::proto::{FrameDecoder, ProtocolDecoder, SseDecoder};
+use exchange_matcher::protocol::szse::SzseDecoder;
+use libfuzzer_sys::fuzz_target;
+
+const MAX_FRAME_LEN: usize = 4096;
+
+fn run<D: ProtocolDecoder>(decoder: D, chunk_len: usize, data: &[u8]) {
+    let mut decoder = FrameDecoder::new(decoder).with_max_frame_len(MAX_FRAME_LEN);
+    for chunk in data.chunks(chunk_len) {
+        decoder.feed(chunk);
+        loop {
+            let before = decoder.buffered();
+            match decoder.next_frame() {
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(_) => assert!(decoder.buffered() < before),
+            }
+        }
+        assert!(decoder.buffered() <= MAX_FRAME_LEN);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let [protocol, chunk_len, data @ ..] = data else {
+        return;
+    };
+    let chunk_len = *chunk_len as usize + 1;
+    match protocol % 3 {
+        0 => run(SseDecoder, chunk_len, data),
+        1 => run(SzseDecoder, chunk_len, data),
+        _ => run(FixDecoder, chunk_len, data),
+    }
+});
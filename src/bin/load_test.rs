@@ -0,0 +1,169 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, anyhow, bail};
+use binary_codec::BinaryCodec;
+use bytes::BytesMut;
+use exchange_matcher::engine::match_engine::{DEFAULT_QUEUE_CAPACITY, MatchEngine};
+use exchange_matcher::interface::channel::{AcceptorChannel, TcpAcceptorChannel};
+use exchange_matcher::protocol::proto::{FrameDecoder, SseDecoder};
+use exchange_matcher::simulator::auto_simulator::AutoSimulator;
+use sse_binary::new_order_single::NewOrderSingle;
+use sse_binary::sse_binary::{SseBinary, SseBinaryBodyEnum};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, unbounded_channel};
+use tracing::Level;
+
+const NEW_ORDER_SINGLE_MSG_TYPE: u32 = 58;
+const DEFAULT_SESSIONS: usize = 100;
+const DEFAULT_ORDERS: usize = 1_000;
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+const SECURITIES: [&str; 4] = ["600000", "600036", "600519", "601318"];
+
+// load_test [sessions] [orders per session] [matcher address]
+//
+// Each session sends an order, waits for its ack and sends the next. The ack
+// is the first answer to the order: a confirm when it rests or is rejected,
+// an execution report when it trades. Without an address the matcher runs
+// in-process as an auto engine.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt().with_max_level(Level::WARN).init();
+    let mut args = std::env::args().skip(1);
+    let sessions = match args.next() {
+        Some(n) => n.parse().context("bad session count")?,
+        None => DEFAULT_SESSIONS,
+    };
+    let orders = match args.next() {
+        Some(n) => n.parse().context("bad order count")?,
+        None => DEFAULT_ORDERS,
+    };
+    let addr = match args.next() {
+        Some(addr) => addr.parse().context("bad matcher address")?,
+        None => start_matcher().await?,
+    };
+
+    println!("{sessions} sessions x {orders} orders against {addr}");
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..sessions)
+        .map(|session| tokio::spawn(run_session(addr, session, orders)))
+        .collect();
+    let mut latencies = Vec::with_capacity(sessions * orders);
+    for task in tasks {
+        latencies.extend(task.await??);
+    }
+    let elapsed = start.elapsed();
+
+    latencies.sort_unstable();
+    println!(
+        "{} orders in {:.2?}, {:.0} orders/s",
+        latencies.len(),
+        elapsed,
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!("order to ack latency:");
+    for p in [50.0, 90.0, 99.0, 99.9] {
+        println!("  p{:<5} {:>10.1?}", p, percentile(&latencies, p));
+    }
+    println!(
+        "  max    {:>10.1?}",
+        latencies.last().copied().unwrap_or_default()
+    );
+    Ok(())
+}
+
+// an auto engine behind an SSE channel on a free port
+async fn start_matcher() -> anyhow::Result<SocketAddr> {
+    let (cmd_tx, cmd_rx) = channel(DEFAULT_QUEUE_CAPACITY);
+    let (event_tx, event_rx) = unbounded_channel();
+    let mut engine =
+        MatchEngine::new(cmd_rx, event_tx).with_auto_simulator(AutoSimulator::new(vec![]));
+    tokio::spawn(async move { engine.start().await });
+
+    let channel = TcpAcceptorChannel::new("127.0.0.1:0".parse().unwrap(), cmd_tx);
+    Arc::clone(&channel).start(event_rx).await?;
+    channel.local_addr().context("matcher not listening")
+}
+
+async fn run_session(
+    addr: SocketAddr,
+    session: usize,
+    orders: usize,
+) -> anyhow::Result<Vec<Duration>> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    let mut decoder = FrameDecoder::new(SseDecoder);
+    let mut buf = BytesMut::new();
+    let mut read_buf = [0u8; 4096];
+    let mut latencies = Vec::with_capacity(orders);
+    for n in 0..orders {
+        let cl_ord_id = (session * orders + n).to_string();
+        buf.clear();
+        new_order(&cl_ord_id, SECURITIES[n % SECURITIES.len()], n as u64 + 1).encode(&mut buf);
+        let sent = Instant::now();
+        stream.write_all(&buf).await?;
+
+        // answers to earlier orders are skipped
+        tokio::time::timeout(ACK_TIMEOUT, async {
+            loop {
+                while let Some(msg) = decoder.next_frame()? {
+                    let answered = match &msg.body {
+                        SseBinaryBodyEnum::Confirm(confirm) => confirm.cl_ord_id == cl_ord_id,
+                        SseBinaryBodyEnum::Report(report) => report.cl_ord_id == cl_ord_id,
+                        _ => false,
+                    };
+                    if answered {
+                        return Ok(());
+                    }
+                }
+                let n = stream.read(&mut read_buf).await?;
+                if n == 0 {
+                    bail!("matcher closed the connection");
+                }
+                decoder.feed(&read_buf[..n]);
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("no ack for order {cl_ord_id} within {ACK_TIMEOUT:?}"))??;
+        latencies.push(sent.elapsed());
+    }
+    Ok(latencies)
+}
+
+fn new_order(cl_ord_id: &str, security_id: &str, seq: u64) -> SseBinary {
+    SseBinary {
+        msg_type: NEW_ORDER_SINGLE_MSG_TYPE,
+        msg_seq_num: seq,
+        msg_body_len: 0,
+        body: SseBinaryBodyEnum::NewOrderSingle(NewOrderSingle {
+            biz_id: 10,
+            biz_pbu: "pbu001".to_string(),
+            cl_ord_id: cl_ord_id.to_string(),
+            security_id: security_id.to_string(),
+            account: "a00001".to_string(),
+            owner_type: 1,
+            side: "1".to_string(),
+            price: 10_000,
+            order_qty: 100,
+            ord_type: "2".to_string(),
+            time_in_force: "0".to_string(),
+            transact_time: 0,
+            credit_tag: "1".to_string(),
+            clearing_firm: "1001".to_string(),
+            branch_id: "b001".to_string(),
+            user_info: String::new(),
+        }),
+        checksum: 0,
+    }
+}
+
+// nearest rank of sorted `latencies`
+fn percentile(latencies: &[Duration], p: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
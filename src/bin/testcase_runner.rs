@@ -0,0 +1,30 @@
+use std::net::SocketAddr;
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use exchange_matcher::testcase::{TestCaseRunner, load_steps};
+
+const DEFAULT_CASE_FILE: &str = "testcase/sse_test_case.csv";
+const DEFAULT_ADDR: &str = "127.0.0.1:9010";
+
+// testcase_runner [case file] [matcher address]
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let mut args = std::env::args().skip(1);
+    let case_file = args.next().unwrap_or_else(|| DEFAULT_CASE_FILE.to_string());
+    let addr: SocketAddr = args
+        .next()
+        .unwrap_or_else(|| DEFAULT_ADDR.to_string())
+        .parse()
+        .context("bad matcher address")?;
+
+    let steps = load_steps(&case_file)?;
+    let dir = Path::new(&case_file).parent().unwrap_or(Path::new("."));
+    let report = TestCaseRunner::new(addr, dir).run(&steps).await;
+    print!("{report}");
+    if !report.passed() {
+        bail!("{} failed", case_file);
+    }
+    Ok(())
+}
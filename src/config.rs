@@ -0,0 +1,494 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+
+use crate::engine::breaker::CircuitBreakerConfig;
+use crate::engine::halt::{HaltConfig, VolatilityConfig};
+use crate::engine::pipeline::PipelineConfig;
+use crate::interface::channel::ThrottleConfig;
+use crate::simulator::auto_simulator::AutoRule;
+use crate::simulator::order_flow::OrderFlowConfig;
+use crate::simulator::replay::ReplayConfig;
+
+/// Contents of `config/match_app.toml`: one entry per matcher app.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatchAppConfig {
+    pub apps: Vec<AppConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppConfig {
+    pub name: String,
+    pub engine: EngineConfig,
+    #[serde(default)]
+    pub channels: Vec<ChannelConfig>,
+    /// background orders from other market participants
+    pub flow: Option<OrderFlowConfig>,
+    /// historical data to rebuild the books from
+    pub replay: Option<ReplayConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EngineConfig {
+    #[serde(rename = "type")]
+    pub engine_type: EngineType,
+    /// market the app simulates, decides the wire protocol
+    pub symbol: Market,
+    /// how an auto engine answers orders, first match wins
+    #[serde(default)]
+    pub rules: Vec<AutoRule>,
+    /// commands waiting for the engine, 65536 if omitted
+    #[serde(default)]
+    pub queue_capacity: Option<usize>,
+    /// runs the engine on a thread of its own feeding a ring buffer
+    pub pipeline: Option<PipelineConfig>,
+    /// engines the securities are split across, one if omitted
+    #[serde(default)]
+    pub shards: Option<usize>,
+    /// what halted books accept and how they re-open
+    #[serde(default)]
+    pub halt: HaltConfig,
+    /// interrupts trading in books whose price jumps, never if omitted
+    pub volatility: Option<VolatilityConfig>,
+    /// halts every book on a move of an index, never if omitted
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineType {
+    /// orders only trade against each other
+    Match,
+    /// the engine provides the counterparty itself
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum Market {
+    #[serde(rename = "SSE")]
+    Sse,
+    #[serde(rename = "SZSE")]
+    Szse,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelConfig {
+    #[serde(rename = "type")]
+    pub channel_type: ChannelType,
+    pub endpoint: Endpoint,
+    /// sessions a tcp channel serves at once, 1024 if omitted
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// events waiting for each session of a tcp channel, 4096 if omitted
+    #[serde(default)]
+    pub queue_capacity: Option<usize>,
+    /// order rate limit of each session of a tcp channel
+    #[serde(default)]
+    pub throttle: Option<ThrottleConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelType {
+    MarketData,
+    /// order entry in the market's own protocol
+    Trading,
+    /// order entry over FIX 4.2 / 4.4 into the same engine
+    Fix,
+    /// HTTP/JSON monitoring and operator commands
+    Admin,
+    /// Prometheus scrape endpoint
+    Metrics,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// A `tcp://host:port` or `udp://host:port` address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Endpoint {
+    pub transport: Transport,
+    pub addr: SocketAddr,
+}
+
+impl FromStr for Endpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, addr) = s
+            .split_once("://")
+            .with_context(|| format!("endpoint {s:?} has no scheme"))?;
+        let transport = match scheme {
+            "tcp" => Transport::Tcp,
+            "udp" => Transport::Udp,
+            other => bail!("endpoint {s:?}: unknown scheme {other:?}"),
+        };
+        let addr = addr
+            .parse()
+            .with_context(|| format!("endpoint {s:?}: bad address"))?;
+        Ok(Self { transport, addr })
+    }
+}
+
+impl TryFrom<String> for Endpoint {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.transport {
+            Transport::Tcp => "tcp",
+            Transport::Udp => "udp",
+        };
+        write!(f, "{}://{}", scheme, self.addr)
+    }
+}
+
+impl Endpoint {
+    // two listeners clash on the same port unless both name distinct hosts
+    fn conflicts_with(&self, other: &Endpoint) -> bool {
+        self.transport == other.transport
+            && self.addr.port() == other.addr.port()
+            && (self.addr.ip() == other.addr.ip()
+                || self.addr.ip().is_unspecified()
+                || other.addr.ip().is_unspecified())
+    }
+}
+
+impl AppConfig {
+    pub fn channel(&self, channel_type: ChannelType) -> Option<&ChannelConfig> {
+        self.channels
+            .iter()
+            .find(|c| c.channel_type == channel_type)
+    }
+}
+
+impl MatchAppConfig {
+    /// Reads and validates a config file.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let config: Self =
+            toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that app names are unique, every app has exactly one trading
+    /// channel over tcp, at most one fix, admin and metrics channel over tcp
+    /// and at most one market data channel over udp, no two channels listen on the same port,
+    /// queue and session limits are positive, rings are powers of two,
+    /// volatility bands are positive and flows have sane sizes.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.apps.is_empty() {
+            bail!("no apps configured");
+        }
+        let mut names = HashSet::new();
+        let mut endpoints: Vec<(&str, Endpoint)> = vec![];
+        for app in &self.apps {
+            if !names.insert(app.name.as_str()) {
+                bail!("duplicate app name {}", app.name);
+            }
+            for (channel_type, transport) in [
+                (ChannelType::Trading, Transport::Tcp),
+                (ChannelType::Fix, Transport::Tcp),
+                (ChannelType::Admin, Transport::Tcp),
+                (ChannelType::Metrics, Transport::Tcp),
+                (ChannelType::MarketData, Transport::Udp),
+            ] {
+                let channels: Vec<_> = app
+                    .channels
+                    .iter()
+                    .filter(|c| c.channel_type == channel_type)
+                    .collect();
+                if channels.len() > 1 {
+                    bail!("{}: more than one {:?} channel", app.name, channel_type);
+                }
+                if let Some(channel) = channels.first()
+                    && channel.endpoint.transport != transport
+                {
+                    bail!(
+                        "{}: {:?} channel must be {:?}, got {}",
+                        app.name,
+                        channel_type,
+                        transport,
+                        channel.endpoint
+                    );
+                }
+            }
+            if app.channel(ChannelType::Trading).is_none() {
+                bail!("{}: no trading channel", app.name);
+            }
+            if app.engine.queue_capacity == Some(0) {
+                bail!("{}: engine queue_capacity must be positive", app.name);
+            }
+            if let Some(pipeline) = &app.engine.pipeline
+                && !pipeline.ring_size.is_power_of_two()
+            {
+                bail!("{}: pipeline ring_size must be a power of two", app.name);
+            }
+            if let Some(volatility) = &app.engine.volatility
+                && (volatility.band_percent <= 0.0 || !volatility.band_percent.is_finite())
+            {
+                bail!("{}: volatility band_percent must be positive", app.name);
+            }
+            if let Some(breaker) = &app.engine.circuit_breaker {
+                if breaker.constituents.is_empty() || breaker.levels.is_empty() {
+                    bail!(
+                        "{}: circuit breaker needs constituents and levels",
+                        app.name
+                    );
+                }
+                if breaker.constituents.iter().any(|c| {
+                    c.weight <= 0.0 || !c.weight.is_finite() || c.reference.is_some_and(|r| r <= 0)
+                }) {
+                    bail!(
+                        "{}: circuit breaker weights and references must be positive",
+                        app.name
+                    );
+                }
+                let moves: Vec<_> = breaker.levels.iter().map(|l| l.move_percent).collect();
+                if moves[0] <= 0.0
+                    || !moves.is_sorted_by(|a, b| a < b)
+                    || !moves[moves.len() - 1].is_finite()
+                {
+                    bail!(
+                        "{}: circuit breaker levels must move by increasing positive percents",
+                        app.name
+                    );
+                }
+            }
+            match app.engine.shards {
+                Some(0) => bail!("{}: engine shards must be positive", app.name),
+                Some(_) if app.engine.pipeline.is_some() => {
+                    bail!("{}: a sharded engine can't run as a pipeline", app.name)
+                }
+                _ => {}
+            }
+            if let Some(flow) = &app.flow
+                && (flow.security_ids.is_empty() || flow.tick_size <= 0 || flow.lot_size <= 0)
+            {
+                bail!(
+                    "{}: flow needs security_ids and positive tick_size and lot_size",
+                    app.name
+                );
+            }
+            for channel in &app.channels {
+                let limits = [
+                    channel.max_connections,
+                    channel.queue_capacity,
+                    channel.throttle.map(|t| t.rate as usize),
+                ];
+                if limits.iter().any(Option::is_some)
+                    && (limits.contains(&Some(0)) || channel.endpoint.transport != Transport::Tcp)
+                {
+                    bail!(
+                        "{}: max_connections, queue_capacity and throttle rate must be positive and set on tcp channels only",
+                        app.name
+                    );
+                }
+                if let Some((owner, used)) = endpoints
+                    .iter()
+                    .find(|(_, e)| e.conflicts_with(&channel.endpoint))
+                {
+                    bail!(
+                        "{}: endpoint {} clashes with {} of {}",
+                        app.name,
+                        channel.endpoint,
+                        used,
+                        owner
+                    );
+                }
+                endpoints.push((&app.name, channel.endpoint));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::halt::{DEFAULT_INTERRUPTION_MS, HaltedCancels, HaltedOrders};
+    use crate::interface::channel::ThrottleAction;
+
+    use super::*;
+
+    #[test]
+    fn test_load_sample_config() {
+        let config = MatchAppConfig::load("config/match_app.toml").unwrap();
+        assert_eq!(config.apps.len(), 2);
+        let sse = &config.apps[0];
+        assert_eq!(sse.name, "SSE-MATCHER");
+        assert_eq!(sse.engine.symbol, Market::Sse);
+        let trading = sse.channel(ChannelType::Trading).unwrap();
+        assert_eq!(trading.endpoint.transport, Transport::Tcp);
+        assert_eq!(trading.endpoint.addr.port(), 9010);
+    }
+
+    #[test]
+    fn test_reject_shared_endpoint() {
+        let config: MatchAppConfig = toml::from_str(
+            r#"
+            [[apps]]
+            name = "A"
+            engine = { type = "match", symbol = "SSE" }
+            channels = [{ type = "trading", endpoint = "tcp://0.0.0.0:9001" }]
+
+            [[apps]]
+            name = "B"
+            engine = { type = "match", symbol = "SZSE" }
+            channels = [{ type = "trading", endpoint = "tcp://127.0.0.1:9001" }]
+            "#,
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("clashes"), "{err}");
+    }
+
+    #[test]
+    fn test_channel_limits() {
+        let parse = |channels: &str| {
+            toml::from_str::<MatchAppConfig>(&format!(
+                r#"
+                [[apps]]
+                name = "A"
+                engine = {{ type = "match", symbol = "SSE", queue_capacity = 128 }}
+                channels = [{channels}]
+                "#
+            ))
+            .unwrap()
+        };
+        let config = parse(
+            r#"{ type = "trading", endpoint = "tcp://0.0.0.0:9001", throttle = { rate = 50, action = "delay" } }"#,
+        );
+        config.validate().unwrap();
+        let app = &config.apps[0];
+        assert_eq!(app.engine.queue_capacity, Some(128));
+        let throttle = app.channel(ChannelType::Trading).unwrap().throttle.unwrap();
+        assert_eq!(throttle.rate, 50);
+        assert_eq!(throttle.action, ThrottleAction::Delay);
+
+        let config = parse(
+            r#"{ type = "trading", endpoint = "tcp://0.0.0.0:9001" },
+            { type = "market_data", endpoint = "udp://0.0.0.0:9000", throttle = { rate = 50 } }"#,
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_engine_shards() {
+        let parse = |engine: &str| {
+            toml::from_str::<MatchAppConfig>(&format!(
+                r#"
+                [[apps]]
+                name = "A"
+                engine = {{ type = "match", symbol = "SSE", {engine} }}
+                channels = [{{ type = "trading", endpoint = "tcp://0.0.0.0:9001" }}]
+                "#
+            ))
+            .unwrap()
+        };
+        let config = parse("shards = 4");
+        config.validate().unwrap();
+        assert_eq!(config.apps[0].engine.shards, Some(4));
+        assert!(parse("shards = 0").validate().is_err());
+        assert!(parse("shards = 4, pipeline = {}").validate().is_err());
+    }
+
+    #[test]
+    fn test_halts_and_volatility() {
+        let parse = |engine: &str| {
+            toml::from_str::<MatchAppConfig>(&format!(
+                r#"
+                [[apps]]
+                name = "A"
+                engine = {{ type = "match", symbol = "SSE", {engine} }}
+                channels = [{{ type = "trading", endpoint = "tcp://0.0.0.0:9001" }}]
+                "#
+            ))
+            .unwrap()
+        };
+        let config = parse(
+            r#"halt = { orders = "queue", auction_ms = 500 }, volatility = { band_percent = 2.5 }"#,
+        );
+        config.validate().unwrap();
+        let engine = &config.apps[0].engine;
+        assert_eq!(engine.halt.orders, HaltedOrders::Queue);
+        assert_eq!(engine.halt.cancels, HaltedCancels::Accept);
+        assert_eq!(engine.halt.auction_ms, 500);
+        let volatility = engine.volatility.unwrap();
+        assert_eq!(volatility.band_percent, 2.5);
+        assert_eq!(volatility.auction_ms, DEFAULT_INTERRUPTION_MS);
+        assert!(
+            parse("volatility = { band_percent = 0.0 }")
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let parse = |breaker: &str| {
+            toml::from_str::<MatchAppConfig>(&format!(
+                r#"
+                [[apps]]
+                name = "A"
+                channels = [{{ type = "trading", endpoint = "tcp://0.0.0.0:9001" }}]
+                [apps.engine]
+                type = "match"
+                symbol = "SSE"
+                [apps.engine.circuit_breaker]
+                {breaker}
+                "#
+            ))
+            .unwrap()
+        };
+        let config = parse(
+            r#"
+            constituents = [{ security_id = "600000" }, { security_id = "600519", weight = 2.0, reference = 150000 }]
+            levels = [{ move_percent = 5.0, halt_ms = 900000 }, { move_percent = 7.0 }]
+            "#,
+        );
+        config.validate().unwrap();
+        let breaker = config.apps[0].engine.circuit_breaker.as_ref().unwrap();
+        assert_eq!(breaker.constituents[0].weight, 1.0);
+        assert_eq!(breaker.constituents[1].reference, Some(150000));
+        assert_eq!(breaker.levels[0].halt_ms, Some(900000));
+        assert_eq!(breaker.levels[1].halt_ms, None);
+
+        let constituents = r#"constituents = [{ security_id = "600000" }]"#;
+        for levels in [
+            "levels = []",
+            "levels = [{ move_percent = 0.0 }]",
+            "levels = [{ move_percent = 7.0 }, { move_percent = 5.0 }]",
+        ] {
+            assert!(
+                parse(&format!("{constituents}\n{levels}"))
+                    .validate()
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_reject_bad_endpoint() {
+        assert!("http://0.0.0.0:80".parse::<Endpoint>().is_err());
+        assert!("tcp://localhost".parse::<Endpoint>().is_err());
+        let ep: Endpoint = "udp://0.0.0.0:9000".parse().unwrap();
+        assert_eq!(ep.to_string(), "udp://0.0.0.0:9000");
+    }
+}
@@ -0,0 +1,275 @@
+//! Market-wide circuit breaker: an index over the last prices of configured
+//! constituents, and every book halted once it has moved too far in a day.
+//! The engines of a sharded app share one, so the index sees every
+//! constituent whichever shard trades it.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::NaiveDate;
+use serde::Deserialize;
+use tracing::warn;
+
+/// `[apps.engine.circuit_breaker]` in the app config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CircuitBreakerConfig {
+    pub constituents: Vec<Constituent>,
+    /// by increasing move, each trips at most once a day
+    pub levels: Vec<BreakerLevel>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Constituent {
+    pub security_id: String,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    /// price moves are measured from, the first trade of the day if
+    /// omitted; the close takes over on the following days
+    pub reference: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct BreakerLevel {
+    /// index move either way, in percent of its reference
+    pub move_percent: f64,
+    /// how long every book stays halted, the rest of the day if omitted
+    pub halt_ms: Option<u64>,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+/// A market-wide halt in force.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerHalt {
+    /// one up for every halt, so engines apply each once
+    pub id: u64,
+    /// ms since the Unix epoch
+    pub until: i64,
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+    // halts so far, read without the lock
+    halts: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    // by security id
+    references: HashMap<String, i64>,
+    last_prices: HashMap<String, i64>,
+    // levels tripped today
+    tripped: usize,
+    halt: Option<BreakerHalt>,
+    // latest trading day closed
+    closed: Option<NaiveDate>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        let references = config
+            .constituents
+            .iter()
+            .filter_map(|c| Some((c.security_id.clone(), c.reference?)))
+            .collect();
+        Self {
+            config,
+            state: Mutex::new(BreakerState {
+                references,
+                ..BreakerState::default()
+            }),
+            halts: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_constituent(&self, security_id: &str) -> bool {
+        self.constituents().any(|c| c == security_id)
+    }
+
+    pub fn constituents(&self) -> impl Iterator<Item = &str> {
+        self.config
+            .constituents
+            .iter()
+            .map(|c| c.security_id.as_str())
+    }
+
+    /// Halts so far, one up for every halt.
+    pub fn halts(&self) -> u64 {
+        self.halts.load(Ordering::Acquire)
+    }
+
+    /// Takes the last prices of constituents that changed, 0 for one that
+    /// hasn't traded, and trips the next level the index has reached.
+    /// `close` is when a halt for the rest of the day ends. Returns the halt
+    /// in force at `now`, if any.
+    pub fn observe(
+        &self,
+        last_prices: impl IntoIterator<Item = (String, i64)>,
+        now: i64,
+        close: i64,
+    ) -> Option<BreakerHalt> {
+        let mut state = self.state.lock().unwrap();
+        for (security_id, price) in last_prices {
+            if price <= 0 {
+                continue;
+            }
+            state.references.entry(security_id.clone()).or_insert(price);
+            state.last_prices.insert(security_id, price);
+        }
+        if state.halt.is_some_and(|h| h.until <= now) {
+            state.halt = None;
+        }
+        if state.halt.is_none()
+            && let Some(index_move) = self.index_move(&state)
+        {
+            let reached = self
+                .config
+                .levels
+                .iter()
+                .take_while(|l| index_move.abs() >= l.move_percent)
+                .count();
+            if reached > state.tripped {
+                let level = self.config.levels[reached - 1];
+                state.tripped = reached;
+                let id = self.halts.fetch_add(1, Ordering::AcqRel) + 1;
+                let until = level.halt_ms.map_or(close, |ms| now + ms as i64).min(close);
+                state.halt = Some(BreakerHalt { id, until });
+                warn!(
+                    "Circuit breaker level {} tripped, index moved {:.2}%, every book halted until {}",
+                    reached, index_move, until
+                );
+            }
+        }
+        state.halt
+    }
+
+    /// The index move in percent, None before any constituent traded.
+    pub fn current_move(&self) -> Option<f64> {
+        self.index_move(&self.state.lock().unwrap())
+    }
+
+    /// Ends trading `day`: closing prices become the references and every
+    /// level may trip again. Later calls for the same day do nothing.
+    pub fn close_day(&self, day: NaiveDate) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed.is_some_and(|closed| closed >= day) {
+            return;
+        }
+        state.closed = Some(day);
+        // one that didn't trade keeps its reference
+        let closes = state.last_prices.clone();
+        state.references.extend(closes);
+        state.tripped = 0;
+        state.halt = None;
+    }
+
+    // weighted mean of the constituents' moves, one without a price yet
+    // counting as unmoved, rounded so a move of exactly a level reaches it
+    fn index_move(&self, state: &BreakerState) -> Option<f64> {
+        let constituents = &self.config.constituents;
+        let weights: f64 = constituents.iter().map(|c| c.weight).sum();
+        let moves: Vec<_> = constituents
+            .iter()
+            .filter_map(|c| {
+                let reference = *state.references.get(&c.security_id)?;
+                let last = *state.last_prices.get(&c.security_id)?;
+                Some(c.weight * (last as f64 / reference as f64 - 1.0))
+            })
+            .collect();
+        if moves.is_empty() || weights <= 0.0 {
+            return None;
+        }
+        let sum: f64 = moves.iter().sum();
+        Some((sum / weights * 1e8).round() / 1e6)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_trip_once_a_day() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            constituents: vec![
+                Constituent {
+                    security_id: "A".to_string(),
+                    weight: 1.0,
+                    reference: Some(100),
+                },
+                Constituent {
+                    security_id: "B".to_string(),
+                    weight: 3.0,
+                    reference: None,
+                },
+            ],
+            levels: vec![
+                BreakerLevel {
+                    move_percent: 5.0,
+                    halt_ms: Some(1000),
+                },
+                BreakerLevel {
+                    move_percent: 7.0,
+                    halt_ms: None,
+                },
+            ],
+        });
+        let prices = |a: i64, b: i64| vec![("A".to_string(), a), ("B".to_string(), b)];
+        let close = 100_000;
+
+        // B has no reference before it trades and counts as unmoved
+        assert_eq!(breaker.observe(prices(96, 0), 0, close), None);
+        assert_eq!(breaker.current_move(), Some(-1.0));
+        assert_eq!(breaker.observe(prices(96, 200), 0, close), None);
+        assert_eq!(breaker.current_move(), Some(-1.0));
+
+        // (-8% + 3 * -5%) / 4
+        let halt = breaker.observe(prices(92, 190), 10, close);
+        assert_eq!(halt, Some(BreakerHalt { id: 1, until: 1010 }));
+        assert_eq!(breaker.observe(prices(92, 190), 1009, close), halt);
+        assert_eq!(breaker.observe(prices(92, 190), 1010, close), None);
+
+        // the second level halts until the close
+        let halt = breaker.observe(prices(90, 186), 2000, close);
+        assert_eq!(
+            halt,
+            Some(BreakerHalt {
+                id: 2,
+                until: close
+            })
+        );
+
+        // the next day measures from the close
+        breaker.close_day(NaiveDate::from_ymd_opt(2016, 1, 4).unwrap());
+        assert_eq!(breaker.current_move(), Some(0.0));
+        assert_eq!(breaker.observe(prices(85, 186), close + 1, 2 * close), None);
+    }
+
+    #[test]
+    fn test_untraded_constituent_keeps_its_reference() {
+        let constituent = |security_id: &str| Constituent {
+            security_id: security_id.to_string(),
+            weight: 1.0,
+            reference: Some(100),
+        };
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            constituents: vec![constituent("A"), constituent("B")],
+            levels: vec![BreakerLevel {
+                move_percent: 5.0,
+                halt_ms: None,
+            }],
+        });
+
+        assert_eq!(breaker.observe(vec![("B".to_string(), 98)], 0, 1000), None);
+        breaker.close_day(NaiveDate::from_ymd_opt(2016, 1, 4).unwrap());
+
+        // A still measures from 100, B from its close
+        let halt = breaker.observe(vec![("A".to_string(), 90)], 2000, 3000);
+        assert_eq!(halt, Some(BreakerHalt { id: 1, until: 3000 }));
+    }
+}
@@ -0,0 +1,53 @@
+//! How books behave when trading in them stops: operator halts, volatility
+//! interruptions, and the re-opening auction that follows either.
+
+use serde::Deserialize;
+
+/// Re-opening auction after a volatility interruption unless configured
+/// otherwise.
+pub const DEFAULT_INTERRUPTION_MS: u64 = 2_000;
+
+/// `[apps.engine.halt]` in the app config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default)]
+pub struct HaltConfig {
+    /// what happens to orders entered into a halted book
+    pub orders: HaltedOrders,
+    /// whether resting orders may be cancelled while halted
+    pub cancels: HaltedCancels,
+    /// length of the auction a resumed book re-opens with, 0 resumes
+    /// continuous trading at once
+    pub auction_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HaltedOrders {
+    #[default]
+    Reject,
+    /// acknowledged and left in the book unmatched, they trade in the
+    /// re-opening auction
+    Queue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HaltedCancels {
+    #[default]
+    Accept,
+    Reject,
+}
+
+/// `[apps.engine.volatility]` in the app config: an order that would trade
+/// further than `band_percent` from the last price interrupts continuous
+/// trading for an auction of `auction_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct VolatilityConfig {
+    pub band_percent: f64,
+    #[serde(default = "default_interruption_ms")]
+    pub auction_ms: u64,
+}
+
+fn default_interruption_ms() -> u64 {
+    DEFAULT_INTERRUPTION_MS
+}
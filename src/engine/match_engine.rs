@@ -1,47 +1,1339 @@
 use std::collections::HashMap;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::{Receiver, UnboundedSender};
+use tracing::{info, warn};
+
+use crate::engine::breaker::CircuitBreaker;
+use crate::engine::halt::{HaltConfig, HaltedCancels, HaltedOrders, VolatilityConfig};
+use crate::engine::metrics::EngineMetrics;
+use crate::engine::query::{Query, QueryRequest, QueryResult};
+use crate::match_policy::{MatchPolicy, PriceTimePolicy};
 use crate::order_book::OrderBook;
-use crate::types::{EngineCommand, EngineEvent, RbCmd};
+use crate::simulator::auto_simulator::{AUTO_SESSION_ID, AutoDecision, AutoSimulator};
+use crate::simulator::replay::{self, REPLAY_SESSION_ID};
+use crate::types::{
+    CmdResultCode, EngineCommand, EngineEvent, L1MarketData, MassCancel, MatchEvent, RbCmd,
+    TimeInForce, TradingPhase,
+};
+use crate::utils::clock::{Clock, SystemClock};
+
+/// How often the engine looks at the clock when no command arrives, also
+/// the resolution of delayed auto fills and of auction ends.
+pub const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
 
-pub struct MatchEngine {
-    order_book_map: HashMap<String, OrderBook>,
-    cmd_rx: UnboundedReceiver<EngineCommand>,
+/// Commands that may wait for the engine unless configured otherwise.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 65_536;
+
+pub struct MatchEngine<P: MatchPolicy = PriceTimePolicy> {
+    order_book_map: HashMap<String, OrderBook<P>>,
+    // policy every new order book is created with
+    policy: P,
+    cmd_rx: Receiver<EngineCommand>,
     event_tx: UnboundedSender<EngineEvent>,
+    // book snapshots for the market data channel, if there is one
+    md_tx: Option<UnboundedSender<L1MarketData>>,
+    clock: Arc<dyn Clock>,
+    // trading day in progress, it ends at market_close local time
+    trading_day: NaiveDate,
+    market_close: NaiveTime,
+    utc_offset: FixedOffset,
+    // earliest good-till-date expiry among resting orders
+    next_expiry: Option<i64>,
+    // counterparty of an auto engine
+    auto: Option<AutoSimulator>,
+    // results kept for a pipeline to pick up instead of being sent
+    captured: Option<Captured>,
+    // questions answered between commands
+    query_rx: Option<Receiver<QueryRequest>>,
+    // phase of books created from now on
+    phase: TradingPhase,
+    metrics: Option<Arc<EngineMetrics>>,
+    halts: HaltConfig,
+    volatility: Option<VolatilityConfig>,
+    // when the auction of each book in one ends
+    auctions: HashMap<String, i64>,
+    breaker: Option<Arc<CircuitBreaker>>,
+    // last prices of constituents told to the breaker
+    breaker_prices: HashMap<String, i64>,
+    // halts of the breaker this engine has seen
+    breaker_halts: u64,
+    // the market-wide halt in force, if any
+    breaker_halt: Option<BreakerHalted>,
+}
+
+#[derive(Default)]
+struct Captured {
+    events: Vec<MatchEvent>,
+    snapshots: Vec<L1MarketData>,
+}
+
+struct BreakerHalted {
+    id: u64,
+    until: i64,
+    // books halted or closed apart from the breaker, they stay so after it
+    kept: Vec<String>,
+    // phase of new books once it is over
+    phase: TradingPhase,
+}
+
+// orders the engine makes up or replays have nobody to send events to
+fn is_client(session_id: u64) -> bool {
+    session_id != AUTO_SESSION_ID && session_id != REPLAY_SESSION_ID
 }
 
 impl MatchEngine {
-    pub fn new(
-        cmd_rx: UnboundedReceiver<EngineCommand>,
+    pub fn new(cmd_rx: Receiver<EngineCommand>, event_tx: UnboundedSender<EngineEvent>) -> Self {
+        Self::with_policy(cmd_rx, event_tx, PriceTimePolicy)
+    }
+}
+
+impl<P: MatchPolicy> MatchEngine<P> {
+    pub fn with_policy(
+        cmd_rx: Receiver<EngineCommand>,
         event_tx: UnboundedSender<EngineEvent>,
+        policy: P,
     ) -> Self {
-        Self {
+        // SSE/SZSE close at 15:00 China time
+        let utc_offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let market_close = NaiveTime::from_hms_opt(15, 0, 0).unwrap();
+        let mut engine = Self {
             order_book_map: HashMap::new(),
+            policy,
             cmd_rx,
             event_tx,
+            md_tx: None,
+            clock: Arc::new(SystemClock),
+            trading_day: NaiveDate::default(),
+            market_close,
+            utc_offset,
+            next_expiry: None,
+            auto: None,
+            captured: None,
+            query_rx: None,
+            phase: TradingPhase::default(),
+            metrics: None,
+            halts: HaltConfig::default(),
+            volatility: None,
+            auctions: HashMap::new(),
+            breaker: None,
+            breaker_prices: HashMap::new(),
+            breaker_halts: 0,
+            breaker_halt: None,
+        };
+        engine.trading_day = engine.local_date(engine.clock.now_millis());
+        engine
+    }
+
+    /// Drives expiry from `clock` instead of the wall clock. The current
+    /// trading day is taken from the clock's time.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self.trading_day = self.local_date(self.clock.now_millis());
+        self
+    }
+
+    /// Sets the local time of the daily close and the market's UTC offset.
+    pub fn with_market_close(mut self, market_close: NaiveTime, utc_offset: FixedOffset) -> Self {
+        self.market_close = market_close;
+        self.utc_offset = utc_offset;
+        self.trading_day = self.local_date(self.clock.now_millis());
+        self
+    }
+
+    /// Publishes a snapshot of every book a command touched to `md_tx`.
+    pub fn with_market_data(mut self, md_tx: UnboundedSender<L1MarketData>) -> Self {
+        self.md_tx = Some(md_tx);
+        self
+    }
+
+    /// Makes this an auto engine: `simulator` answers every client order
+    /// instead of other clients.
+    pub fn with_auto_simulator(mut self, simulator: AutoSimulator) -> Self {
+        self.auto = Some(simulator);
+        self
+    }
+
+    /// Answers the queries sent on `query_rx` between commands.
+    pub fn with_queries(mut self, query_rx: Receiver<QueryRequest>) -> Self {
+        self.query_rx = Some(query_rx);
+        self
+    }
+
+    /// Counts what every book does, rejects and command latency into
+    /// `metrics`, which other engines may share.
+    pub fn with_metrics(mut self, metrics: Arc<EngineMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Sets what halted books do with orders and cancels, and how long they
+    /// auction before trading continuously again.
+    pub fn with_halts(mut self, halts: HaltConfig) -> Self {
+        self.halts = halts;
+        self
+    }
+
+    /// Interrupts continuous trading in a book whose price would move too
+    /// far at once, see `VolatilityConfig`.
+    pub fn with_volatility(mut self, volatility: VolatilityConfig) -> Self {
+        self.volatility = Some(volatility);
+        self
+    }
+
+    /// Halts every book while `breaker` is tripped. The shards of an engine
+    /// share one, see `CircuitBreaker`.
+    pub fn with_circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Keeps events and book snapshots for `take_output` instead of sending
+    /// them, for a pipeline that passes them on itself.
+    pub(crate) fn capture_output(&mut self) {
+        self.captured = Some(Captured::default());
+    }
+
+    /// Moves what was captured since the last call to the end of `events`
+    /// and `snapshots`. False if there was nothing.
+    pub(crate) fn take_output(
+        &mut self,
+        events: &mut Vec<MatchEvent>,
+        snapshots: &mut Vec<L1MarketData>,
+    ) -> bool {
+        let Some(captured) = self.captured.as_mut() else {
+            return false;
+        };
+        let any = !captured.events.is_empty() || !captured.snapshots.is_empty();
+        events.append(&mut captured.events);
+        snapshots.append(&mut captured.snapshots);
+        any
+    }
+
+    /// Time of the engine clock, ms since the Unix epoch.
+    pub(crate) fn now_millis(&self) -> i64 {
+        self.clock.now_millis()
+    }
+
+    /// The next command if one is waiting, for callers off the runtime.
+    pub(crate) fn try_recv(&mut self) -> Result<EngineCommand, TryRecvError> {
+        self.cmd_rx.try_recv()
+    }
+
+    /// The next query if one is waiting, for callers off the runtime.
+    pub(crate) fn try_recv_query(&mut self) -> Option<QueryRequest> {
+        self.query_rx.as_mut()?.try_recv().ok()
+    }
+
+    /// The next command, None once every sender is gone.
+    pub(crate) async fn recv(&mut self) -> Option<EngineCommand> {
+        self.cmd_rx.recv().await
+    }
+
+    fn emit(&mut self, event: MatchEvent) {
+        match self.captured.as_mut() {
+            Some(captured) => captured.events.push(event),
+            None => {
+                let _ = self.event_tx.send(EngineEvent::MatchEvent(event));
+            }
+        }
+    }
+
+    fn publish_snapshot(&mut self, security_id: &str) {
+        if self.md_tx.is_none() && self.captured.is_none() {
+            return;
+        }
+        let Some(book) = self.order_book_map.get(security_id) else {
+            return;
+        };
+        let snapshot = book.l1_snapshot(L1MarketData::L1_SIZE, self.clock.now_millis());
+        match (&mut self.captured, &self.md_tx) {
+            (Some(captured), _) => captured.snapshots.push(snapshot),
+            (None, Some(md_tx)) => {
+                let _ = md_tx.send(snapshot);
+            }
+            (None, None) => {}
+        }
+    }
+
+    fn local_date(&self, millis: i64) -> NaiveDate {
+        DateTime::from_timestamp_millis(millis)
+            .unwrap_or_default()
+            .with_timezone(&self.utc_offset)
+            .date_naive()
+    }
+
+    fn close_millis(&self, day: NaiveDate) -> i64 {
+        day.and_time(self.market_close)
+            .and_local_timezone(self.utc_offset)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    fn get_order_book(&mut self, security_id: &str) -> &mut OrderBook<P> {
+        if !self.order_book_map.contains_key(security_id) {
+            let mut book = OrderBook::with_policy(security_id.to_string(), self.policy.clone());
+            book.set_phase(self.phase);
+            book.set_volatility_band(self.volatility.map(|v| v.band_percent));
+            if let Some(metrics) = &self.metrics {
+                book.set_metrics(metrics.security(security_id));
+            }
+            self.order_book_map.insert(security_id.to_string(), book);
         }
+        self.order_book_map.get_mut(security_id).unwrap()
     }
-    fn get_order_book(&mut self, security_id: String) -> &mut OrderBook {
+
+    fn phase_of(&self, security_id: &str) -> TradingPhase {
         self.order_book_map
-            .entry(security_id.to_string())
-            .or_insert_with(|| OrderBook::new(security_id))
+            .get(security_id)
+            .map_or(self.phase, |book| book.phase())
     }
 
-    fn match_order(&mut self, cmd: &mut RbCmd) {
-        let order_book = self.get_order_book(cmd.security_id.clone());
-        order_book.new_order(cmd);
+    fn send_events(&mut self, cmd: &RbCmd) {
         for event in cmd.match_event_list.iter() {
-            let _ = self.event_tx.send(EngineEvent::MatchEvent(event.clone()));
+            if !is_client(event.session_id) {
+                continue;
+            }
+            self.emit(event.clone());
+        }
+    }
+
+    fn match_order(&mut self, cmd: &mut RbCmd) {
+        // queued orders expire too
+        if let TimeInForce::Gtd(expire_time) = cmd.time_in_force {
+            self.next_expiry = Some(self.next_expiry.map_or(expire_time, |t| t.min(expire_time)));
+        }
+        match self.phase_of(&cmd.security_id) {
+            TradingPhase::Continuous => {}
+            TradingPhase::Auction => return self.queue_order(cmd),
+            TradingPhase::Halted if self.halts.orders == HaltedOrders::Queue => {
+                return self.queue_order(cmd);
+            }
+            TradingPhase::Halted | TradingPhase::Closed => {
+                self.reject(cmd, CmdResultCode::NotTrading);
+                self.send_events(cmd);
+                return;
+            }
+        }
+        let mut counter = None;
+        if let Some(auto) = self.auto.as_mut()
+            && is_client(cmd.session_id)
+        {
+            let mid = self
+                .order_book_map
+                .get(&cmd.security_id)
+                .and_then(|b| b.mid_price());
+            match auto.on_new_order(cmd, mid, self.clock.now_millis()) {
+                AutoDecision::Reject => {
+                    self.reject(cmd, CmdResultCode::AutoRejected);
+                    self.send_events(cmd);
+                    return;
+                }
+                AutoDecision::Provide(c) => counter = Some(c),
+                AutoDecision::Delayed | AutoDecision::Pass => {}
+            }
+        }
+        let order_book = self.get_order_book(&cmd.security_id);
+        let result = match counter.as_mut() {
+            Some(counter) => order_book.new_order_with_counter(cmd, counter),
+            None => order_book.new_order(cmd),
+        };
+        replay::settle_jumped(order_book, cmd);
+        let interrupted = order_book.phase() == TradingPhase::Auction;
+        if result != CmdResultCode::Success {
+            self.reject(cmd, result);
+        }
+        self.send_events(cmd);
+        if interrupted && let Some(volatility) = self.volatility {
+            info!(
+                "{} interrupted, price beyond {}% of the last one",
+                cmd.security_id, volatility.band_percent
+            );
+            self.start_auction(&cmd.security_id, volatility.auction_ms);
+        }
+        self.publish_snapshot(&cmd.security_id);
+    }
+
+    // in a book that doesn't match, the order waits for the auction
+    fn queue_order(&mut self, cmd: &mut RbCmd) {
+        let result = self.get_order_book(&cmd.security_id).rest_order(cmd);
+        if result != CmdResultCode::Success {
+            self.reject(cmd, result);
+        }
+        self.send_events(cmd);
+        self.publish_snapshot(&cmd.security_id);
+    }
+
+    fn cancel_order(&mut self, cmd: &mut RbCmd) {
+        let halts = self.halts;
+        let now = self.clock.now_millis();
+        let result = match self.order_book_map.get_mut(&cmd.security_id) {
+            Some(order_book)
+                if order_book.phase() == TradingPhase::Closed
+                    || order_book.phase() == TradingPhase::Halted
+                        && halts.cancels == HaltedCancels::Reject =>
+            {
+                CmdResultCode::NotTrading
+            }
+            Some(order_book) => order_book.cancel_order(cmd, now),
+            None => CmdResultCode::InvalidOrderId,
+        };
+        if result != CmdResultCode::Success {
+            self.reject(cmd, result);
+        }
+        self.send_events(cmd);
+        self.publish_snapshot(&cmd.security_id);
+    }
+
+    /// Stops matching in the book of `security_id`, every book if None.
+    pub fn halt(&mut self, security_id: Option<&str>) {
+        self.set_phase(security_id, TradingPhase::Halted);
+    }
+
+    /// Re-opens halted books through an auction of the configured length.
+    pub fn resume(&mut self, security_id: Option<&str>) {
+        self.set_phase(security_id, TradingPhase::Continuous);
+    }
+
+    // Books going back to continuous trading re-open with an auction, so
+    // do those told to auction. Without a security the phase also holds for
+    // books created later, unless it is an auction.
+    // A market-wide halt keeps every book halted until it is over.
+    fn set_phase(&mut self, security_id: Option<&str>, phase: TradingPhase) {
+        if self.breaker_halt.is_some() {
+            if matches!(phase, TradingPhase::Continuous | TradingPhase::Auction) {
+                warn!(
+                    "{} stays halted by the circuit breaker",
+                    security_id.unwrap_or("every book")
+                );
+                return;
+            }
+            let security_ids: Vec<_> = match security_id {
+                Some(security_id) => vec![security_id.to_string()],
+                None => self.order_book_map.keys().cloned().collect(),
+            };
+            info!("{} now {}", security_id.unwrap_or("every book"), phase);
+            for security_id in &security_ids {
+                self.get_order_book(security_id).set_phase(phase);
+            }
+            let halted = self.breaker_halt.as_mut().unwrap();
+            if security_id.is_none() {
+                halted.phase = phase;
+                self.phase = phase;
+            }
+            halted.kept.extend(security_ids);
+            return;
+        }
+        let security_ids = match security_id {
+            Some(security_id) => vec![security_id.to_string()],
+            None => {
+                if phase != TradingPhase::Auction {
+                    self.phase = phase;
+                }
+                self.order_book_map.keys().cloned().collect()
+            }
+        };
+        info!("{} now {}", security_id.unwrap_or("every book"), phase);
+        for security_id in security_ids {
+            let from = self.get_order_book(&security_id).phase();
+            match phase {
+                TradingPhase::Continuous if from == TradingPhase::Continuous => {}
+                TradingPhase::Continuous | TradingPhase::Auction => {
+                    self.start_auction(&security_id, self.halts.auction_ms);
+                }
+                TradingPhase::Halted | TradingPhase::Closed => {
+                    self.auctions.remove(&security_id);
+                    self.get_order_book(&security_id).set_phase(phase);
+                }
+            }
+        }
+    }
+
+    // collects orders for `duration_ms`, none re-opens at once
+    fn start_auction(&mut self, security_id: &str, duration_ms: u64) {
+        if duration_ms == 0 {
+            self.reopen(security_id);
+            return;
+        }
+        let end = self.clock.now_millis() + duration_ms as i64;
+        self.get_order_book(security_id)
+            .set_phase(TradingPhase::Auction);
+        self.auctions.insert(security_id.to_string(), end);
+        info!("{} auction until {}", security_id, end);
+    }
+
+    // uncrosses the book and trades it continuously again
+    fn reopen(&mut self, security_id: &str) {
+        self.auctions.remove(security_id);
+        let now = self.clock.now_millis();
+        let book = self.get_order_book(security_id);
+        let fills = book.uncross(now);
+        book.set_phase(TradingPhase::Continuous);
+        info!(
+            "{} re-opened at {} with {} fills",
+            security_id,
+            book.last_price(),
+            fills.len() / 2
+        );
+        for event in fills {
+            if is_client(event.session_id) {
+                self.emit(event);
+            }
+        }
+        self.publish_snapshot(security_id);
+    }
+
+    /// Ends the auctions that are due.
+    pub fn process_auctions(&mut self) {
+        let now = self.clock.now_millis();
+        let mut due: Vec<_> = self
+            .auctions
+            .iter()
+            .filter(|&(_, &end)| end <= now)
+            .map(|(security_id, _)| security_id.clone())
+            .collect();
+        due.sort_unstable();
+        for security_id in due {
+            self.reopen(&security_id);
+        }
+    }
+
+    /// Reports the constituents' last prices that changed to the circuit
+    /// breaker, halts every book once it trips and re-opens the books it
+    /// halted once it is over. Does nothing unless a price changed, another
+    /// engine tripped the breaker or the halt in force is due to end.
+    pub fn process_breaker(&mut self) {
+        let Some(breaker) = self.breaker.clone() else {
+            return;
+        };
+        let now = self.clock.now_millis();
+        let changed: Vec<_> = breaker
+            .constituents()
+            .filter_map(|security_id| {
+                let price = self.order_book_map.get(security_id)?.last_price();
+                let known = self.breaker_prices.get(security_id);
+                (price > 0 && known != Some(&price)).then(|| (security_id.to_string(), price))
+            })
+            .collect();
+        let halt_over = self.breaker_halt.as_ref().is_some_and(|h| h.until <= now);
+        if changed.is_empty() && !halt_over && breaker.halts() == self.breaker_halts {
+            return;
+        }
+        self.breaker_prices.extend(changed.iter().cloned());
+        let close = self.close_millis(self.trading_day);
+        let halt = breaker.observe(changed, now, close);
+        self.breaker_halts = breaker.halts();
+        match (halt, &self.breaker_halt) {
+            (Some(halt), Some(halted)) if halt.id == halted.id => {}
+            (Some(halt), halted) => {
+                let (kept, phase) = match halted {
+                    Some(halted) => (halted.kept.clone(), halted.phase),
+                    None => {
+                        let kept = self
+                            .order_book_map
+                            .iter()
+                            .filter(|(_, book)| {
+                                matches!(book.phase(), TradingPhase::Halted | TradingPhase::Closed)
+                            })
+                            .map(|(security_id, _)| security_id.clone())
+                            .collect();
+                        (kept, self.phase)
+                    }
+                };
+                info!(
+                    "Every book halted by the circuit breaker until {}",
+                    halt.until
+                );
+                self.auctions.clear();
+                for book in self.order_book_map.values_mut() {
+                    if book.phase() != TradingPhase::Closed {
+                        book.set_phase(TradingPhase::Halted);
+                    }
+                }
+                if self.phase != TradingPhase::Closed {
+                    self.phase = TradingPhase::Halted;
+                }
+                self.breaker_halt = Some(BreakerHalted {
+                    id: halt.id,
+                    until: halt.until,
+                    kept,
+                    phase,
+                });
+            }
+            (None, Some(_)) => {
+                let halted = self.breaker_halt.take().unwrap();
+                info!("Circuit breaker halt over");
+                self.phase = halted.phase;
+                if halted.phase != TradingPhase::Continuous {
+                    return;
+                }
+                let mut security_ids: Vec<_> = self
+                    .order_book_map
+                    .keys()
+                    .filter(|security_id| !halted.kept.contains(security_id))
+                    .cloned()
+                    .collect();
+                security_ids.sort_unstable();
+                for security_id in security_ids {
+                    self.start_auction(&security_id, self.halts.auction_ms);
+                }
+            }
+            (None, None) => {}
         }
     }
 
+    fn mass_cancel(&mut self, filter: &MassCancel) {
+        let now = self.clock.now_millis();
+        let mut changed = vec![];
+        let mut cancelled = vec![];
+        for (security_id, book) in self.order_book_map.iter_mut() {
+            if filter
+                .security_id
+                .as_ref()
+                .is_some_and(|s| s != security_id)
+            {
+                continue;
+            }
+            let events = book.cancel_orders(now, |o| filter.matches(o));
+            if !events.is_empty() {
+                changed.push(security_id.clone());
+            }
+            cancelled.extend(events);
+        }
+        info!("Mass cancel {:?}: {} orders", filter, cancelled.len());
+        for event in cancelled {
+            if is_client(event.session_id) {
+                self.emit(event);
+            }
+        }
+        for security_id in changed {
+            self.publish_snapshot(&security_id);
+        }
+    }
+
+    fn reject(&self, cmd: &mut RbCmd, code: CmdResultCode) {
+        if let Some(metrics) = &self.metrics {
+            metrics.reject(code);
+        }
+        let rejected = MatchEvent::rejected(cmd, self.clock.now_millis());
+        cmd.match_event_list.push(rejected);
+    }
+
+    /// Sends the auto simulator's delayed counter orders that are due into
+    /// their books. Each trades against whatever rests at its price, the
+    /// rest is cancelled.
+    pub fn process_auto_fills(&mut self) {
+        let now = self.clock.now_millis();
+        let Some(due) = self.auto.as_mut().map(|a| a.due(now)) else {
+            return;
+        };
+        for (oid, mut counter) in due {
+            // a halted book misses its fill
+            if self.phase_of(&counter.security_id) != TradingPhase::Continuous {
+                continue;
+            }
+            self.get_order_book(&counter.security_id)
+                .fill_with_counter(oid, &mut counter);
+            self.send_events(&counter);
+            self.publish_snapshot(&counter.security_id);
+        }
+    }
+
+    /// Checks the engine clock and expires orders that are due: day orders
+    /// once the market closes, good-till-date orders once their time passes.
+    /// Each removed order is reported with an `Expired` match event.
+    pub fn process_expiry(&mut self) {
+        let now = self.clock.now_millis();
+        let mut end_of_day = false;
+        while now >= self.close_millis(self.trading_day) {
+            end_of_day = true;
+            info!("End of trading day {}", self.trading_day);
+            if let Some(breaker) = &self.breaker {
+                breaker.close_day(self.trading_day);
+            }
+            self.trading_day = self.trading_day.succ_opt().unwrap();
+        }
+        let gtd_due = self.next_expiry.is_some_and(|t| t <= now);
+        if !end_of_day && !gtd_due {
+            return;
+        }
+
+        let mut next_expiry = None;
+        let mut changed = vec![];
+        let mut expired = vec![];
+        for (security_id, book) in self.order_book_map.iter_mut() {
+            let events = book.expire_orders(now, end_of_day);
+            if !events.is_empty() {
+                changed.push(security_id.clone());
+            }
+            expired.extend(events);
+            if let Some(t) = book.next_expiry() {
+                next_expiry = Some(next_expiry.map_or(t, |n: i64| n.min(t)));
+            }
+        }
+        self.next_expiry = next_expiry;
+        for event in expired {
+            if is_client(event.session_id) {
+                self.emit(event);
+            }
+        }
+        for security_id in changed {
+            self.publish_snapshot(&security_id);
+        }
+    }
+
+    /// Answers `query` from the books as they are.
+    pub fn query(&self, query: &Query) -> QueryResult {
+        match query {
+            Query::Order { oid } => QueryResult::Order(
+                self.order_book_map
+                    .values()
+                    .find_map(|book| book.order_info(*oid)),
+            ),
+            Query::Books => {
+                let mut books: Vec<_> = self
+                    .order_book_map
+                    .values()
+                    .map(|book| book.top_of_book())
+                    .collect();
+                books.sort_by(|a, b| a.security_id.cmp(&b.security_id));
+                QueryResult::Books(books)
+            }
+            Query::Depth { security_id } => {
+                QueryResult::Depth(self.order_book_map.get(security_id).map(|b| b.depth()))
+            }
+            Query::OpenOrders { session_id, uid } => {
+                let mut orders: Vec<_> = self
+                    .order_book_map
+                    .values()
+                    .flat_map(|book| {
+                        book.open_orders(|o| {
+                            session_id.is_none_or(|s| o.session_id == s)
+                                && uid.is_none_or(|u| o.uid == u)
+                        })
+                    })
+                    .collect();
+                orders.sort_by_key(|o| o.oid);
+                QueryResult::OpenOrders(orders)
+            }
+        }
+    }
+
+    /// Carries out one command, after whatever fell due before it.
+    pub fn handle(&mut self, cmd: EngineCommand) {
+        self.tick();
+        let started = Instant::now();
+        // only trades and auctions move the index
+        let moves_index = self.breaker.as_ref().is_some_and(|breaker| match &cmd {
+            EngineCommand::NewOrder(rb_cmd) => breaker.is_constituent(&rb_cmd.security_id),
+            EngineCommand::SetPhase { .. } => true,
+            EngineCommand::CancelOrder(_) | EngineCommand::MassCancel(_) => false,
+        });
+        match cmd {
+            EngineCommand::NewOrder(mut rb_cmd) => {
+                self.match_order(&mut rb_cmd);
+            }
+            EngineCommand::CancelOrder(mut rb_cmd) => {
+                self.cancel_order(&mut rb_cmd);
+            }
+            EngineCommand::SetPhase { security_id, phase } => {
+                self.set_phase(security_id.as_deref(), phase);
+            }
+            EngineCommand::MassCancel(filter) => {
+                self.mass_cancel(&filter);
+            }
+        }
+        // halts before the next command if this one tripped the breaker
+        if moves_index {
+            self.process_breaker();
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_latency(started.elapsed());
+        }
+    }
+
+    /// Expires orders, ends auctions, sends auto fills that are due and then
+    /// applies or lifts a market-wide halt on the prices they left.
+    pub fn tick(&mut self) {
+        self.process_expiry();
+        self.process_auctions();
+        self.process_auto_fills();
+        self.process_breaker();
+    }
+
     pub async fn start(&mut self) {
-        while let Some(cmd) = self.cmd_rx.recv().await {
-            match cmd {
-                EngineCommand::NewOrder(mut rb_cmd) => {
-                    self.match_order(&mut rb_cmd);
+        let mut ticker = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                // queries see every command queued before them
+                biased;
+                cmd = self.cmd_rx.recv() => {
+                    let Some(cmd) = cmd else {
+                        break;
+                    };
+                    self.handle(cmd);
                 }
+                Some(request) = next_query(&mut self.query_rx) => {
+                    let result = self.query(&request.query);
+                    request.answer(result);
+                }
+                _ = ticker.tick() => self.tick(),
             }
         }
     }
 }
+
+// waits forever without a query queue
+pub(crate) async fn next_query(
+    query_rx: &mut Option<Receiver<QueryRequest>>,
+) -> Option<QueryRequest> {
+    match query_rx {
+        Some(query_rx) => query_rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::{UnboundedReceiver, channel, unbounded_channel};
+
+    use crate::engine::breaker::{BreakerLevel, CircuitBreakerConfig, Constituent};
+    use crate::engine::halt::HaltedCancels;
+    use crate::engine::query::query_channel;
+    use crate::simulator::auto_simulator::{AutoAction, AutoRule, FillPrice};
+    use crate::types::{OrderSide, OrderStatus};
+    use crate::utils::clock::ManualClock;
+
+    use super::*;
+
+    const HOUR: i64 = 3600 * 1000;
+    // 2025-01-06 10:00 China time
+    const MONDAY_10AM: i64 = 1_736_128_800_000;
+
+    fn cmd(oid: i64, side: OrderSide, price: i64, time_in_force: TimeInForce) -> RbCmd {
+        RbCmd {
+            session_id: 1,
+            side,
+            match_event_list: vec![],
+            price,
+            volume: 100,
+            mid: oid,
+            uid: 1,
+            oid,
+            security_id: "600519".to_string(),
+            time_in_force,
+        }
+    }
+
+    fn expired(rx: &mut UnboundedReceiver<EngineEvent>) -> Vec<MatchEvent> {
+        let mut events = vec![];
+        while let Ok(EngineEvent::MatchEvent(me)) = rx.try_recv() {
+            if me.status == OrderStatus::Expired {
+                events.push(me);
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_day_orders_expire_at_close() {
+        let (_cmd_tx, cmd_rx) = channel(16);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let clock = ManualClock::new(MONDAY_10AM);
+        let mut engine = MatchEngine::new(cmd_rx, event_tx).with_clock(clock.clone());
+
+        engine.match_order(&mut cmd(1, OrderSide::Buy, 100, TimeInForce::Day));
+        engine.match_order(&mut cmd(2, OrderSide::Buy, 99, TimeInForce::Gtc));
+        engine.match_order(&mut cmd(
+            3,
+            OrderSide::Sell,
+            110,
+            TimeInForce::Gtd(MONDAY_10AM + 26 * HOUR),
+        ));
+
+        clock.advance(4 * HOUR);
+        engine.process_expiry();
+        assert!(expired(&mut event_rx).is_empty());
+
+        // 15:00 monday: the day order goes
+        clock.advance(HOUR);
+        engine.process_expiry();
+        let events = expired(&mut event_rx);
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].oid, events[0].volume), (1, 100));
+
+        // tuesday 12:00: the GTD order goes, the GTC order stays
+        clock.set(MONDAY_10AM + 26 * HOUR);
+        engine.process_expiry();
+        let events = expired(&mut event_rx);
+        assert_eq!(events.iter().map(|e| e.oid).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(engine.next_expiry, None);
+
+        clock.advance(10 * 24 * HOUR);
+        engine.process_expiry();
+        assert!(expired(&mut event_rx).is_empty());
+    }
+
+    #[test]
+    fn test_gtd_order_queued_in_an_auction_expires() {
+        let (_cmd_tx, cmd_rx) = channel(16);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let clock = ManualClock::new(MONDAY_10AM);
+        let mut engine = MatchEngine::new(cmd_rx, event_tx).with_clock(clock.clone());
+
+        engine.handle(EngineCommand::SetPhase {
+            security_id: Some("600519".to_string()),
+            phase: TradingPhase::Auction,
+        });
+        engine.handle(EngineCommand::NewOrder(cmd(
+            1,
+            OrderSide::Buy,
+            100,
+            TimeInForce::Gtd(MONDAY_10AM + HOUR),
+        )));
+        assert_eq!(engine.next_expiry, Some(MONDAY_10AM + HOUR));
+
+        clock.advance(HOUR);
+        engine.process_expiry();
+        let events = expired(&mut event_rx);
+        assert_eq!(events.iter().map(|e| e.oid).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_auto_engine_fills_client_orders() {
+        let (_cmd_tx, cmd_rx) = channel(16);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let clock = ManualClock::new(MONDAY_10AM);
+        let rules = vec![
+            AutoRule {
+                security_id: None,
+                side: Some(OrderSide::Sell),
+                action: AutoAction::Reject,
+            },
+            AutoRule {
+                security_id: None,
+                side: None,
+                action: AutoAction::Fill {
+                    percent: 40,
+                    price: FillPrice::Limit,
+                    delay_ms: 1000,
+                },
+            },
+        ];
+        let mut engine = MatchEngine::new(cmd_rx, event_tx)
+            .with_clock(clock.clone())
+            .with_auto_simulator(AutoSimulator::new(rules));
+        let mut events = || {
+            let mut events = vec![];
+            while let Ok(EngineEvent::MatchEvent(me)) = event_rx.try_recv() {
+                events.push((me.oid, me.status, me.volume, me.leaves_volume));
+            }
+            events
+        };
+
+        engine.match_order(&mut cmd(1, OrderSide::Sell, 100, TimeInForce::Day));
+        assert_eq!(events(), vec![(1, OrderStatus::Rejected, 0, 0)]);
+
+        engine.match_order(&mut cmd(2, OrderSide::Buy, 100, TimeInForce::Day));
+        assert_eq!(events(), vec![(2, OrderStatus::OrderEd, 0, 100)]);
+
+        clock.advance(1000);
+        engine.process_auto_fills();
+        assert_eq!(events(), vec![(2, OrderStatus::PartTrade, 40, 60)]);
+        let book = &engine.order_book_map["600519"];
+        assert_eq!((book.best_bid(), book.best_ask()), (Some(100), None));
+    }
+
+    #[test]
+    fn test_auto_counter_trades_only_with_the_client() {
+        let (_cmd_tx, cmd_rx) = channel(16);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let rules = vec![
+            AutoRule {
+                security_id: None,
+                side: Some(OrderSide::Sell),
+                action: AutoAction::Pass,
+            },
+            AutoRule {
+                security_id: None,
+                side: None,
+                action: AutoAction::Fill {
+                    percent: 40,
+                    price: FillPrice::Limit,
+                    delay_ms: 0,
+                },
+            },
+        ];
+        let mut engine = MatchEngine::new(cmd_rx, event_tx)
+            .with_clock(ManualClock::new(MONDAY_10AM))
+            .with_auto_simulator(AutoSimulator::new(rules));
+
+        // another session's better offer is there first
+        let mut offer = cmd(1, OrderSide::Sell, 99, TimeInForce::Day);
+        offer.session_id = 2;
+        engine.match_order(&mut offer);
+        engine.match_order(&mut cmd(2, OrderSide::Buy, 100, TimeInForce::Day));
+
+        let mut events = vec![];
+        while let Ok(EngineEvent::MatchEvent(me)) = event_rx.try_recv() {
+            events.push((me.oid, me.status, me.volume, me.price));
+        }
+        assert_eq!(
+            events,
+            vec![
+                (1, OrderStatus::OrderEd, 0, 99),
+                (2, OrderStatus::PartTrade, 40, 100),
+                (2, OrderStatus::TradeEd, 60, 99),
+                (1, OrderStatus::PartTrade, 60, 99),
+            ]
+        );
+        let book = &engine.order_book_map["600519"];
+        assert_eq!(book.order_info(1).map(|o| o.leaves), Some(40));
+        book.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_halt_and_mass_cancel() {
+        let (_cmd_tx, cmd_rx) = channel(16);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let mut engine = MatchEngine::new(cmd_rx, event_tx);
+        let mut events = || {
+            let mut events = vec![];
+            while let Ok(EngineEvent::MatchEvent(me)) = event_rx.try_recv() {
+                events.push((me.oid, me.status));
+            }
+            events
+        };
+        let halt = |security_id: Option<&str>, phase| EngineCommand::SetPhase {
+            security_id: security_id.map(str::to_string),
+            phase,
+        };
+
+        engine.handle(EngineCommand::NewOrder(cmd(
+            1,
+            OrderSide::Sell,
+            100,
+            TimeInForce::Day,
+        )));
+        engine.handle(halt(Some("600519"), TradingPhase::Halted));
+        engine.handle(EngineCommand::NewOrder(cmd(
+            2,
+            OrderSide::Buy,
+            100,
+            TimeInForce::Day,
+        )));
+        let mut other = cmd(3, OrderSide::Sell, 100, TimeInForce::Day);
+        other.security_id = "600000".to_string();
+        engine.handle(EngineCommand::NewOrder(other));
+        assert_eq!(
+            events(),
+            vec![
+                (1, OrderStatus::OrderEd),
+                (2, OrderStatus::Rejected),
+                (3, OrderStatus::OrderEd)
+            ]
+        );
+
+        // closing every book stops cancels too, books opened later included
+        engine.handle(halt(None, TradingPhase::Closed));
+        engine.handle(EngineCommand::CancelOrder(cmd(
+            1,
+            OrderSide::Sell,
+            0,
+            TimeInForce::Day,
+        )));
+        let mut late = cmd(4, OrderSide::Buy, 100, TimeInForce::Day);
+        late.security_id = "601318".to_string();
+        engine.handle(EngineCommand::NewOrder(late));
+        assert_eq!(
+            events(),
+            vec![(1, OrderStatus::Rejected), (4, OrderStatus::Rejected)]
+        );
+
+        engine.handle(halt(None, TradingPhase::Continuous));
+        engine.handle(EngineCommand::MassCancel(MassCancel {
+            session_id: Some(1),
+            ..MassCancel::default()
+        }));
+        let mut cancelled = events();
+        // books are cleared in no particular order
+        cancelled.sort_by_key(|e| e.0);
+        assert_eq!(
+            cancelled,
+            vec![(1, OrderStatus::CancelEd), (3, OrderStatus::CancelEd)]
+        );
+        let QueryResult::Books(books) = engine.query(&Query::Books) else {
+            panic!("no books");
+        };
+        let ids: Vec<_> = books.iter().map(|b| b.security_id.as_str()).collect();
+        // a rejected order opens no book
+        assert_eq!(ids, vec!["600000", "600519"]);
+        assert!(
+            books
+                .iter()
+                .all(|b| b.phase == TradingPhase::Continuous && b.ask.is_none())
+        );
+    }
+
+    // (oid, status, price) of every event sent so far
+    fn fills(rx: &mut UnboundedReceiver<EngineEvent>) -> Vec<(i64, OrderStatus, i64)> {
+        let mut events = vec![];
+        while let Ok(EngineEvent::MatchEvent(me)) = rx.try_recv() {
+            events.push((me.oid, me.status, me.price));
+        }
+        events
+    }
+
+    #[test]
+    fn test_halt_queues_orders_for_the_reopening_auction() {
+        let (_cmd_tx, cmd_rx) = channel(16);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let clock = ManualClock::new(MONDAY_10AM);
+        let mut engine = MatchEngine::new(cmd_rx, event_tx)
+            .with_clock(clock.clone())
+            .with_halts(HaltConfig {
+                orders: HaltedOrders::Queue,
+                cancels: HaltedCancels::Reject,
+                auction_ms: 1000,
+            });
+        let order =
+            |oid, side, price| EngineCommand::NewOrder(cmd(oid, side, price, TimeInForce::Gtc));
+
+        engine.handle(order(1, OrderSide::Sell, 100));
+        engine.halt(Some("600519"));
+        engine.handle(order(2, OrderSide::Buy, 101));
+        engine.handle(order(3, OrderSide::Buy, 100));
+        engine.handle(order(4, OrderSide::Sell, 99));
+        engine.handle(EngineCommand::CancelOrder(cmd(
+            1,
+            OrderSide::Sell,
+            0,
+            TimeInForce::Gtc,
+        )));
+        assert_eq!(
+            fills(&mut event_rx),
+            vec![
+                (1, OrderStatus::OrderEd, 100),
+                (2, OrderStatus::OrderEd, 101),
+                (3, OrderStatus::OrderEd, 100),
+                (4, OrderStatus::OrderEd, 99),
+                (1, OrderStatus::Rejected, 0),
+            ]
+        );
+
+        // the auction collects orders until it ends
+        engine.resume(Some("600519"));
+        assert_eq!(engine.phase_of("600519"), TradingPhase::Auction);
+        engine.handle(order(5, OrderSide::Buy, 98));
+        clock.advance(999);
+        engine.tick();
+        assert_eq!(fills(&mut event_rx), vec![(5, OrderStatus::OrderEd, 98)]);
+
+        // 200 trade at 100, against 100 at 99 or 101
+        clock.advance(1);
+        engine.tick();
+        assert_eq!(
+            fills(&mut event_rx),
+            vec![
+                (2, OrderStatus::TradeEd, 100),
+                (4, OrderStatus::TradeEd, 100),
+                (3, OrderStatus::TradeEd, 100),
+                (1, OrderStatus::TradeEd, 100),
+            ]
+        );
+        assert_eq!(engine.phase_of("600519"), TradingPhase::Continuous);
+        let QueryResult::OpenOrders(open) = engine.query(&Query::OpenOrders {
+            session_id: None,
+            uid: None,
+        }) else {
+            panic!("no open orders");
+        };
+        assert_eq!(open.iter().map(|o| o.oid).collect::<Vec<_>>(), vec![5]);
+    }
+
+    #[test]
+    fn test_volatility_interruption() {
+        let (_cmd_tx, cmd_rx) = channel(16);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let clock = ManualClock::new(MONDAY_10AM);
+        let mut engine = MatchEngine::new(cmd_rx, event_tx)
+            .with_clock(clock.clone())
+            .with_volatility(VolatilityConfig {
+                band_percent: 5.0,
+                auction_ms: 2000,
+            });
+        let order =
+            |oid, side, price| EngineCommand::NewOrder(cmd(oid, side, price, TimeInForce::Gtc));
+
+        engine.handle(order(1, OrderSide::Sell, 100));
+        engine.handle(order(2, OrderSide::Buy, 100));
+        engine.handle(order(3, OrderSide::Sell, 103));
+        engine.handle(order(4, OrderSide::Sell, 110));
+        fills(&mut event_rx);
+
+        // the buy takes 103 and stops short of 110
+        let mut sweep = cmd(5, OrderSide::Buy, 110, TimeInForce::Gtc);
+        sweep.volume = 200;
+        engine.handle(EngineCommand::NewOrder(sweep));
+        assert_eq!(
+            fills(&mut event_rx),
+            vec![
+                (5, OrderStatus::PartTrade, 103),
+                (3, OrderStatus::TradeEd, 103)
+            ]
+        );
+        assert_eq!(engine.phase_of("600519"), TradingPhase::Auction);
+
+        engine.handle(order(6, OrderSide::Sell, 108));
+        clock.advance(2000);
+        engine.tick();
+        assert_eq!(
+            fills(&mut event_rx),
+            vec![
+                (6, OrderStatus::OrderEd, 108),
+                (5, OrderStatus::TradeEd, 108),
+                (6, OrderStatus::TradeEd, 108),
+            ]
+        );
+        assert_eq!(engine.phase_of("600519"), TradingPhase::Continuous);
+        let QueryResult::Depth(Some(depth)) = engine.query(&Query::Depth {
+            security_id: "600519".to_string(),
+        }) else {
+            panic!("no book");
+        };
+        assert_eq!(depth.last_price, 108);
+        assert_eq!(depth.asks[0].price, 110);
+        assert!(depth.bids.is_empty());
+    }
+
+    #[test]
+    fn test_circuit_breaker_halts_every_shard() {
+        let breaker = Arc::new(CircuitBreaker::new(CircuitBreakerConfig {
+            constituents: vec![Constituent {
+                security_id: "600519".to_string(),
+                weight: 1.0,
+                reference: Some(100),
+            }],
+            levels: vec![
+                BreakerLevel {
+                    move_percent: 5.0,
+                    halt_ms: Some(1000),
+                },
+                BreakerLevel {
+                    move_percent: 7.0,
+                    halt_ms: None,
+                },
+            ],
+        }));
+        let clock = ManualClock::new(MONDAY_10AM);
+        let mut engines: Vec<_> = (0..2)
+            .map(|_| {
+                let (_cmd_tx, cmd_rx) = channel(16);
+                let (event_tx, event_rx) = unbounded_channel();
+                let engine = MatchEngine::new(cmd_rx, event_tx)
+                    .with_clock(clock.clone())
+                    .with_circuit_breaker(breaker.clone());
+                (engine, event_rx)
+            })
+            .collect();
+        let order =
+            |oid, side, price| EngineCommand::NewOrder(cmd(oid, side, price, TimeInForce::Gtc));
+        let other = |oid, security_id: &str| {
+            let mut other = cmd(oid, OrderSide::Buy, 10, TimeInForce::Gtc);
+            other.security_id = security_id.to_string();
+            EngineCommand::NewOrder(other)
+        };
+
+        // the other shard trades a security outside the index, and an
+        // operator halts one of its books
+        let (engine, _) = &mut engines[1];
+        engine.handle(other(1, "600000"));
+        engine.halt(Some("000001"));
+
+        // a 5% drop halts every book for a second
+        let (engine, event_rx) = &mut engines[0];
+        engine.handle(order(2, OrderSide::Sell, 95));
+        engine.handle(order(3, OrderSide::Buy, 95));
+        fills(event_rx);
+        assert_eq!(breaker.current_move(), Some(-5.0));
+        assert_eq!(engine.phase_of("600519"), TradingPhase::Halted);
+        engine.resume(Some("600519"));
+        assert_eq!(engine.phase_of("600519"), TradingPhase::Halted);
+        let (engine, _) = &mut engines[1];
+        engine.tick();
+        assert_eq!(engine.phase_of("600000"), TradingPhase::Halted);
+        engine.handle(other(4, "600036"));
+        assert_eq!(engine.phase_of("600036"), TradingPhase::Halted);
+
+        clock.advance(1000);
+        for (engine, _) in engines.iter_mut() {
+            engine.tick();
+        }
+        let (engine, _) = &mut engines[1];
+        assert_eq!(engine.phase_of("600000"), TradingPhase::Continuous);
+        assert_eq!(engine.phase_of("600036"), TradingPhase::Continuous);
+        assert_eq!(engine.phase_of("000001"), TradingPhase::Halted);
+
+        // 7% halts for the rest of the day
+        let (engine, event_rx) = &mut engines[0];
+        assert_eq!(engine.phase_of("600519"), TradingPhase::Continuous);
+        engine.handle(order(5, OrderSide::Sell, 93));
+        engine.handle(order(6, OrderSide::Buy, 93));
+        assert_eq!(fills(event_rx).len(), 3);
+        assert_eq!(engine.phase_of("600519"), TradingPhase::Halted);
+        clock.advance(HOUR);
+        engine.tick();
+        assert_eq!(engine.phase_of("600519"), TradingPhase::Halted);
+
+        // the next day measures from the close
+        clock.advance(5 * HOUR);
+        engine.tick();
+        assert_eq!(engine.phase_of("600519"), TradingPhase::Continuous);
+        assert_eq!(breaker.current_move(), Some(0.0));
+    }
+
+    #[tokio::test]
+    async fn test_queries_see_the_books() {
+        let (cmd_tx, cmd_rx) = channel(16);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let (client, query_rx) = query_channel(4);
+        let mut engine = MatchEngine::new(cmd_rx, event_tx).with_queries(query_rx);
+        tokio::spawn(async move { engine.start().await });
+
+        for oid in 1..=3 {
+            let mut order = cmd(oid, OrderSide::Sell, 100, TimeInForce::Day);
+            order.uid = oid as u64;
+            cmd_tx.send(EngineCommand::NewOrder(order)).await.unwrap();
+        }
+        for _ in 1..=3 {
+            event_rx.recv().await.unwrap();
+        }
+
+        let QueryResult::Order(Some(info)) = client.ask(Query::Order { oid: 3 }).await.unwrap()
+        else {
+            panic!("order 3 not found");
+        };
+        assert_eq!((info.queue_position, info.volume_ahead), (2, 200));
+        let result = client
+            .ask(Query::OpenOrders {
+                session_id: Some(1),
+                uid: Some(2),
+            })
+            .await
+            .unwrap();
+        let QueryResult::OpenOrders(orders) = result else {
+            panic!("unexpected {result:?}");
+        };
+        assert_eq!(orders.iter().map(|o| o.oid).collect::<Vec<_>>(), vec![2]);
+        let result = client
+            .ask(Query::Depth {
+                security_id: "600000".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(result, QueryResult::Depth(None));
+    }
+}
@@ -0,0 +1,100 @@
+//! What the engines of one app have done since it started, shared between
+//! the engines and whoever reports it.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::types::CmdResultCode;
+use crate::utils::metrics::{Exposition, Histogram};
+
+/// Counters of one security's book.
+#[derive(Debug, Default)]
+pub struct SecurityMetrics {
+    /// orders that reached the book
+    pub orders: AtomicU64,
+    /// resting orders cancelled, one by one or en masse
+    pub cancels: AtomicU64,
+    /// fills of a resting order
+    pub trades: AtomicU64,
+    pub traded_volume: AtomicU64,
+}
+
+impl SecurityMetrics {
+    // in the order they are written out
+    fn counters(&self) -> [&AtomicU64; 4] {
+        [
+            &self.orders,
+            &self.cancels,
+            &self.trades,
+            &self.traded_volume,
+        ]
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EngineMetrics {
+    securities: Mutex<BTreeMap<String, Arc<SecurityMetrics>>>,
+    // by CmdResultCode::REJECTS
+    rejects: [AtomicU64; CmdResultCode::REJECTS.len()],
+    // from taking a command to having carried it out
+    latency: Histogram,
+}
+
+impl EngineMetrics {
+    /// The counters of `security_id`, created on first use.
+    pub fn security(&self, security_id: &str) -> Arc<SecurityMetrics> {
+        self.securities
+            .lock()
+            .unwrap()
+            .entry(security_id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    pub fn reject(&self, code: CmdResultCode) {
+        if let Some(i) = CmdResultCode::REJECTS.iter().position(|&c| c == code) {
+            self.rejects[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn observe_latency(&self, elapsed: Duration) {
+        self.latency.observe(elapsed);
+    }
+
+    /// Adds every family kept here to `out`.
+    pub fn write(&self, out: &mut Exposition) {
+        let securities = self.securities.lock().unwrap();
+        let families = [
+            ("matcher_orders_total", "Orders entered into the book."),
+            ("matcher_cancels_total", "Resting orders cancelled."),
+            ("matcher_trades_total", "Fills of resting orders."),
+            ("matcher_traded_volume_total", "Volume traded."),
+        ];
+        for (i, (name, help)) in families.into_iter().enumerate() {
+            out.family(name, "counter", help);
+            for (security_id, metrics) in securities.iter() {
+                let value = metrics.counters()[i].load(Ordering::Relaxed);
+                out.sample(name, &[("security_id", security_id)], value);
+            }
+        }
+        drop(securities);
+
+        out.family(
+            "matcher_rejects_total",
+            "counter",
+            "Commands rejected, by reason.",
+        );
+        for (code, count) in CmdResultCode::REJECTS.iter().zip(&self.rejects) {
+            let code = code.to_string();
+            let count = count.load(Ordering::Relaxed);
+            out.sample("matcher_rejects_total", &[("code", &code)], count);
+        }
+        out.histogram(
+            "matcher_command_latency_seconds",
+            "Time the engine took to carry out a command.",
+            &self.latency,
+        );
+    }
+}
@@ -1 +1,8 @@
+pub mod breaker;
+pub mod halt;
 pub mod match_engine;
+pub mod metrics;
+pub mod pipeline;
+pub mod query;
+pub mod ring;
+pub mod shard;
@@ -0,0 +1,453 @@
+//! Runs the engine on a thread of its own, off the tokio runtime and
+//! without a mutex. Every step of the engine, a command or a timer tick,
+//! goes into a pre-allocated ring, and each consumer reads the ring by
+//! sequence on a thread of its own: reports to the channels, market data,
+//! the journal. A consumer that panics is logged and left behind, the
+//! others go on.
+
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use anyhow::Context;
+use serde::Deserialize;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::error::TryRecvError;
+use tracing::{error, info};
+
+use crate::engine::match_engine::{EXPIRY_CHECK_INTERVAL, MatchEngine};
+use crate::engine::ring::{WaitStrategy, Writer, ring};
+use crate::match_policy::MatchPolicy;
+use crate::simulator::replay::HEADER;
+use crate::types::{
+    EngineCommand, EngineEvent, L1MarketData, MatchEvent, OrderSide, RbCmd, TimeInForce,
+};
+
+/// Slots in the ring unless configured otherwise.
+pub const DEFAULT_RING_SIZE: usize = 65_536;
+
+// commands handled at most before a waiting query is answered, so a busy
+// queue doesn't hold queries back
+const QUERY_EVERY: u32 = 64;
+
+/// `[apps.engine.pipeline]` in the app config.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    /// slots in the ring, a power of two
+    pub ring_size: usize,
+    /// how idle threads wait for work
+    pub wait: WaitStrategy,
+    /// file every command is appended to, in the replay format
+    pub journal: Option<String>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            ring_size: DEFAULT_RING_SIZE,
+            wait: WaitStrategy::default(),
+            journal: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepKind {
+    /// orders expired, auctions ended or auto fills sent without a command
+    Timer,
+    NewOrder,
+    CancelOrder,
+    /// a phase change or mass cancel of the operator
+    Admin,
+}
+
+/// One step of the engine as the consumers see it.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub kind: StepKind,
+    /// ms since the Unix epoch, by the engine clock
+    pub timestamp: i64,
+    /// the command, that of an earlier step on timer and admin steps
+    pub cmd: RbCmd,
+    /// events for the channels, in the order the engine made them
+    pub events: Vec<MatchEvent>,
+    /// books the step changed
+    pub snapshots: Vec<L1MarketData>,
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self {
+            kind: StepKind::Timer,
+            timestamp: 0,
+            cmd: RbCmd {
+                session_id: 0,
+                side: OrderSide::Buy,
+                match_event_list: vec![],
+                price: 0,
+                volume: 0,
+                mid: 0,
+                uid: 0,
+                oid: 0,
+                security_id: String::new(),
+                time_in_force: TimeInForce::Day,
+            },
+            events: vec![],
+            snapshots: vec![],
+        }
+    }
+}
+
+impl Step {
+    // makes the slot this step's, keeping its allocations
+    fn begin(&mut self, kind: StepKind, timestamp: i64) {
+        self.kind = kind;
+        self.timestamp = timestamp;
+        self.events.clear();
+        self.snapshots.clear();
+    }
+}
+
+// reads the ring on a thread of its own
+trait Consumer: Send {
+    fn step(&mut self, seq: i64, step: &Step);
+
+    // caught up with the engine, before waiting for more
+    fn idle(&mut self) {}
+}
+
+impl<F: FnMut(i64, &Step) + Send> Consumer for F {
+    fn step(&mut self, seq: i64, step: &Step) {
+        self(seq, step)
+    }
+}
+
+// replay rows, flushed once the consumer has caught up rather than per row
+struct Journal {
+    path: String,
+    out: BufWriter<File>,
+}
+
+impl Consumer for Journal {
+    fn step(&mut self, _: i64, step: &Step) {
+        if let Err(e) = journal(&mut self.out, step) {
+            error!("Writing journal {} failed: {}", self.path, e);
+        }
+    }
+
+    fn idle(&mut self) {
+        if let Err(e) = self.out.flush() {
+            error!("Flushing journal {} failed: {}", self.path, e);
+        }
+    }
+}
+
+/// An engine with the consumers of its ring, not yet running.
+pub struct MatchPipeline<P: MatchPolicy> {
+    engine: MatchEngine<P>,
+    config: PipelineConfig,
+    consumers: Vec<(String, Box<dyn Consumer>)>,
+}
+
+impl<P: MatchPolicy + 'static> MatchPipeline<P> {
+    /// `engine` keeps taking commands from its queue, its events and book
+    /// snapshots go only to the ring.
+    pub fn new(mut engine: MatchEngine<P>, config: PipelineConfig) -> Self {
+        engine.capture_output();
+        Self {
+            engine,
+            config,
+            consumers: vec![],
+        }
+    }
+
+    /// Adds a consumer reading every step on a thread called `name`.
+    pub fn with_consumer(
+        self,
+        name: &str,
+        consumer: impl FnMut(i64, &Step) + Send + 'static,
+    ) -> Self {
+        self.with_boxed_consumer(name, Box::new(consumer))
+    }
+
+    /// Sends the events of every step on to the channels.
+    pub fn with_reports(self, event_tx: UnboundedSender<EngineEvent>) -> Self {
+        self.with_consumer("reports", move |_, step| {
+            for event in &step.events {
+                let _ = event_tx.send(EngineEvent::MatchEvent(event.clone()));
+            }
+        })
+    }
+
+    /// Sends the book snapshots of every step to the market data channel.
+    pub fn with_market_data(self, md_tx: UnboundedSender<L1MarketData>) -> Self {
+        self.with_consumer("market-data", move |_, step| {
+            for snapshot in &step.snapshots {
+                let _ = md_tx.send(snapshot.clone());
+            }
+        })
+    }
+
+    /// Appends every command to the replay file at `path`, so that the
+    /// session can be played again.
+    pub fn with_journal(self, path: &str) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening journal {path}"))?;
+        let fresh = file.metadata()?.len() == 0;
+        let mut out = BufWriter::new(file);
+        if fresh {
+            writeln!(out, "{HEADER}")?;
+        }
+        let journal = Journal {
+            path: path.to_string(),
+            out,
+        };
+        Ok(self.with_boxed_consumer("journal", Box::new(journal)))
+    }
+
+    fn with_boxed_consumer(mut self, name: &str, consumer: Box<dyn Consumer>) -> Self {
+        self.consumers.push((name.to_string(), consumer));
+        self
+    }
+
+    /// Starts the engine thread and a thread per consumer. They run until
+    /// every sender of the engine's command queue is gone.
+    pub fn start(self) -> anyhow::Result<PipelineHandle> {
+        let wait = self.config.wait;
+        let (writer, readers) = ring(
+            self.config.ring_size,
+            self.consumers.len(),
+            wait,
+            Step::default,
+        );
+        let mut threads = vec![];
+        for ((name, mut consumer), reader) in self.consumers.into_iter().zip(readers) {
+            let thread = std::thread::Builder::new().name(name).spawn(move || {
+                // both callbacks need it, never at the same time
+                let consumer = RefCell::new(&mut consumer);
+                reader.run_with_idle(
+                    |seq, step| consumer.borrow_mut().step(seq, step),
+                    || consumer.borrow_mut().idle(),
+                )
+            })?;
+            threads.push(thread);
+        }
+        let mut engine = self.engine;
+        let thread = std::thread::Builder::new()
+            .name("match-engine".to_string())
+            .spawn(move || run_engine(&mut engine, writer, wait))?;
+        threads.push(thread);
+        info!(
+            "Match pipeline started, {} slots, {} consumers",
+            self.config.ring_size,
+            threads.len() - 1
+        );
+        Ok(PipelineHandle { threads })
+    }
+}
+
+/// The threads of a running pipeline.
+pub struct PipelineHandle {
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl PipelineHandle {
+    /// Waits until the engine and every consumer are done.
+    pub fn join(self) {
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_engine<P: MatchPolicy>(
+    engine: &mut MatchEngine<P>,
+    mut writer: Writer<Step>,
+    wait: WaitStrategy,
+) {
+    let mut spins = 0;
+    let mut last_tick = Instant::now();
+    // commands handled since a query was last looked for
+    let mut handled = 0;
+    // timer output, held until it has a slot
+    let (mut events, mut snapshots) = (vec![], vec![]);
+    loop {
+        match engine.try_recv() {
+            Ok(cmd) => {
+                spins = 0;
+                let now = engine.now_millis();
+                writer.publish(|step| {
+                    match &cmd {
+                        EngineCommand::NewOrder(rb_cmd) => {
+                            step.begin(StepKind::NewOrder, now);
+                            step.cmd.clone_from(rb_cmd);
+                        }
+                        EngineCommand::CancelOrder(rb_cmd) => {
+                            step.begin(StepKind::CancelOrder, now);
+                            step.cmd.clone_from(rb_cmd);
+                        }
+                        EngineCommand::SetPhase { .. } | EngineCommand::MassCancel(_) => {
+                            step.begin(StepKind::Admin, now);
+                        }
+                    }
+                    engine.handle(cmd);
+                    engine.take_output(&mut step.events, &mut step.snapshots);
+                });
+                handled += 1;
+                if handled >= QUERY_EVERY {
+                    handled = 0;
+                    answer_query(engine);
+                }
+            }
+            Err(TryRecvError::Empty) => {
+                handled = 0;
+                if answer_query(engine) {
+                    spins = 0;
+                } else if last_tick.elapsed() >= EXPIRY_CHECK_INTERVAL {
+                    last_tick = Instant::now();
+                    engine.tick();
+                    if engine.take_output(&mut events, &mut snapshots) {
+                        let now = engine.now_millis();
+                        writer.publish(|step| {
+                            step.begin(StepKind::Timer, now);
+                            step.events.append(&mut events);
+                            step.snapshots.append(&mut snapshots);
+                        });
+                    }
+                } else {
+                    wait.idle(&mut spins);
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        }
+    }
+    info!("Match engine thread finished");
+}
+
+// answers the next query if there is one
+fn answer_query<P: MatchPolicy>(engine: &mut MatchEngine<P>) -> bool {
+    let Some(request) = engine.try_recv_query() else {
+        return false;
+    };
+    let result = engine.query(&request.query);
+    request.answer(result);
+    true
+}
+
+// one replay row per command
+fn journal(out: &mut impl Write, step: &Step) -> std::io::Result<()> {
+    let action = match step.kind {
+        StepKind::NewOrder => "A",
+        StepKind::CancelOrder => "D",
+        // the replay format only has orders and cancels
+        StepKind::Timer | StepKind::Admin => return Ok(()),
+    };
+    let cmd = &step.cmd;
+    let side = match cmd.side {
+        OrderSide::Buy => "B",
+        OrderSide::Sell => "S",
+    };
+    writeln!(
+        out,
+        "{},{},{},{},{},{},{}",
+        step.timestamp, cmd.security_id, action, cmd.oid, side, cmd.price, cmd.volume
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+
+    use crate::types::OrderStatus;
+
+    use super::*;
+
+    fn order(oid: i64, side: OrderSide, price: i64) -> EngineCommand {
+        EngineCommand::NewOrder(RbCmd {
+            session_id: 1,
+            side,
+            match_event_list: vec![],
+            price,
+            volume: 10,
+            mid: oid,
+            uid: 0,
+            oid,
+            security_id: "600000".to_string(),
+            time_in_force: TimeInForce::Day,
+        })
+    }
+
+    #[test]
+    fn test_consumers_see_every_step() {
+        let (cmd_tx, cmd_rx) = channel(16);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let (step_tx, step_rx) = mpsc::channel();
+        let engine = MatchEngine::new(cmd_rx, event_tx.clone());
+        let config = PipelineConfig {
+            ring_size: 2,
+            ..PipelineConfig::default()
+        };
+        let pipeline = MatchPipeline::new(engine, config)
+            .with_reports(event_tx)
+            .with_consumer("steps", move |seq, step| {
+                let _ = step_tx.send((seq, step.kind, step.snapshots.len()));
+            })
+            .start()
+            .unwrap();
+
+        for oid in 1..=3 {
+            cmd_tx
+                .blocking_send(order(oid, OrderSide::Sell, 100))
+                .unwrap();
+        }
+        cmd_tx.blocking_send(order(4, OrderSide::Buy, 100)).unwrap();
+        drop(cmd_tx);
+        pipeline.join();
+
+        let steps: Vec<_> = step_rx.iter().collect();
+        assert_eq!(steps.len(), 4);
+        for (i, (seq, kind, snapshots)) in steps.into_iter().enumerate() {
+            assert_eq!(seq, i as i64);
+            assert_eq!(kind, StepKind::NewOrder);
+            assert_eq!(snapshots, 1);
+        }
+        let mut statuses = vec![];
+        while let Ok(EngineEvent::MatchEvent(me)) = event_rx.try_recv() {
+            statuses.push((me.oid, me.status));
+        }
+        assert_eq!(
+            statuses,
+            vec![
+                (1, OrderStatus::OrderEd),
+                (2, OrderStatus::OrderEd),
+                (3, OrderStatus::OrderEd),
+                (4, OrderStatus::TradeEd),
+                (1, OrderStatus::TradeEd),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_journal_row() {
+        let mut step = Step::default();
+        step.begin(StepKind::CancelOrder, 1_736_128_800_000);
+        step.cmd.oid = 7;
+        step.cmd.side = OrderSide::Sell;
+        step.cmd.price = 174900;
+        step.cmd.security_id = "600519".to_string();
+        let mut out = vec![];
+        journal(&mut out, &step).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1736128800000,600519,D,7,S,174900,0\n"
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Questions for a running engine, answered from its books between commands
+//! without changing them. A `QueryClient` sends them, the engine answers
+//! each on the queue given to `MatchEngine::with_queries`.
+
+use anyhow::{Context, anyhow};
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+use tokio::sync::oneshot;
+
+use crate::types::{BookDepth, OrderInfo, TopOfBook};
+
+/// Queries waiting for the engine unless configured otherwise.
+pub const DEFAULT_QUERY_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    /// the top of every book
+    Books,
+    /// a resting order with its place in the queue
+    Order { oid: i64 },
+    /// every level of a security's book
+    Depth { security_id: String },
+    /// resting orders of a session and/or user, all of them if neither is
+    /// given
+    OpenOrders {
+        session_id: Option<u64>,
+        uid: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryResult {
+    /// by security id
+    Books(Vec<TopOfBook>),
+    /// None once the order traded, was cancelled or never existed
+    Order(Option<OrderInfo>),
+    /// None for a security without a book
+    Depth(Option<BookDepth>),
+    /// by order id
+    OpenOrders(Vec<OrderInfo>),
+}
+
+impl QueryResult {
+    /// The answer of an engine whose securities are split between the
+    /// engines answering `self` and `other`.
+    pub fn merge(self, other: QueryResult) -> QueryResult {
+        match (self, other) {
+            (QueryResult::Books(mut a), QueryResult::Books(b)) => {
+                a.extend(b);
+                a.sort_by(|x, y| x.security_id.cmp(&y.security_id));
+                QueryResult::Books(a)
+            }
+            (QueryResult::Order(a), QueryResult::Order(b)) => QueryResult::Order(a.or(b)),
+            (QueryResult::Depth(a), QueryResult::Depth(b)) => QueryResult::Depth(a.or(b)),
+            (QueryResult::OpenOrders(mut a), QueryResult::OpenOrders(b)) => {
+                a.extend(b);
+                a.sort_by_key(|o| o.oid);
+                QueryResult::OpenOrders(a)
+            }
+            (a, _) => a,
+        }
+    }
+}
+
+/// A query on its way to the engine, with where the answer goes.
+#[derive(Debug)]
+pub struct QueryRequest {
+    pub query: Query,
+    reply: oneshot::Sender<QueryResult>,
+}
+
+impl QueryRequest {
+    pub fn answer(self, result: QueryResult) {
+        // the client may have given up waiting
+        let _ = self.reply.send(result);
+    }
+}
+
+/// Asks an engine queries; clones share the engine's queue.
+#[derive(Debug, Clone)]
+pub struct QueryClient {
+    tx: Sender<QueryRequest>,
+}
+
+/// A client and the queue to hand to the engine.
+pub fn query_channel(capacity: usize) -> (QueryClient, Receiver<QueryRequest>) {
+    let (tx, rx) = channel(capacity);
+    (QueryClient { tx }, rx)
+}
+
+impl QueryClient {
+    pub async fn ask(&self, query: Query) -> anyhow::Result<QueryResult> {
+        let (reply, answer) = oneshot::channel();
+        self.tx
+            .send(QueryRequest { query, reply })
+            .await
+            .map_err(|_| anyhow!("engine stopped"))?;
+        answer.await.context("engine dropped the query")
+    }
+}
@@ -0,0 +1,288 @@
+//! A pre-allocated ring of slots with a single writer and any number of
+//! readers, each following the writer by sequence at its own pace. Slots
+//! are reused in place, so their allocations are too. The writer never
+//! overtakes the slowest reader, and nothing takes a lock. A reader that
+//! is dropped, by a panic too, is no longer waited for.
+
+use std::cell::UnsafeCell;
+use std::hint::spin_loop;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::error;
+
+// spins before yielding, yields before sleeping
+const SPINS: u32 = 100;
+const YIELDS: u32 = 200;
+const SLEEP: Duration = Duration::from_micros(50);
+
+/// What a thread does while there is nothing to read or no slot to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitStrategy {
+    /// lowest latency, a core per thread spins all the time
+    BusySpin,
+    /// spins a little, then yields to other threads
+    #[default]
+    Yield,
+    /// spins, yields, then sleeps briefly; gentlest on a shared machine
+    Sleep,
+}
+
+impl WaitStrategy {
+    /// Waits a little, longer the more often it was called since `spins`
+    /// was last reset.
+    pub fn idle(&self, spins: &mut u32) {
+        *spins = spins.saturating_add(1);
+        match self {
+            WaitStrategy::BusySpin => spin_loop(),
+            _ if *spins < SPINS => spin_loop(),
+            WaitStrategy::Sleep if *spins >= YIELDS => std::thread::sleep(SLEEP),
+            _ => std::thread::yield_now(),
+        }
+    }
+}
+
+// a sequence alone on its cache line, so threads don't invalidate each
+// other's
+#[repr(align(64))]
+struct Sequence(AtomicI64);
+
+impl Sequence {
+    fn new() -> Self {
+        Sequence(AtomicI64::new(-1))
+    }
+}
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<T>]>,
+    mask: i64,
+    // last sequence published
+    cursor: Sequence,
+    // last sequence each reader is done with
+    readers: Box<[Sequence]>,
+    closed: AtomicBool,
+    wait: WaitStrategy,
+}
+
+// Slots are only written by the writer while no reader may look at them,
+// and only read after being published, see `Writer::publish`.
+unsafe impl<T: Send + Sync> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn slot(&self, seq: i64) -> *mut T {
+        self.slots[(seq & self.mask) as usize].get()
+    }
+
+    fn slowest_reader(&self) -> i64 {
+        self.readers
+            .iter()
+            .map(|r| r.0.load(Ordering::Acquire))
+            .min()
+            .unwrap_or(i64::MAX)
+    }
+}
+
+/// A ring of `size` slots made by `init`, with its writer and `readers`
+/// readers. `size` is rounded up to a power of two.
+pub fn ring<T: Send + Sync>(
+    size: usize,
+    readers: usize,
+    wait: WaitStrategy,
+    mut init: impl FnMut() -> T,
+) -> (Writer<T>, Vec<Reader<T>>) {
+    let size = size.max(1).next_power_of_two();
+    let ring = Arc::new(Ring {
+        slots: (0..size).map(|_| UnsafeCell::new(init())).collect(),
+        mask: size as i64 - 1,
+        cursor: Sequence::new(),
+        readers: (0..readers).map(|_| Sequence::new()).collect(),
+        closed: AtomicBool::new(false),
+        wait,
+    });
+    let writer = Writer {
+        ring: ring.clone(),
+        next: 0,
+    };
+    let readers = (0..readers)
+        .map(|index| Reader {
+            ring: ring.clone(),
+            index,
+            next: 0,
+        })
+        .collect();
+    (writer, readers)
+}
+
+/// The one thread filling the ring. Dropping it lets readers finish.
+pub struct Writer<T> {
+    ring: Arc<Ring<T>>,
+    next: i64,
+}
+
+impl<T> Writer<T> {
+    /// Fills the next slot with `fill` and publishes it, after waiting while
+    /// the slowest reader is a whole ring behind. Returns what `fill` does.
+    pub fn publish<R>(&mut self, fill: impl FnOnce(&mut T) -> R) -> R {
+        let ring = &self.ring;
+        let seq = self.next;
+        let wrap = seq - ring.slots.len() as i64;
+        let mut spins = 0;
+        while ring.slowest_reader() < wrap {
+            ring.wait.idle(&mut spins);
+        }
+        // SAFETY: every reader is done with `wrap`, the slot's previous
+        // sequence, and none looks at `seq` before the cursor reaches it.
+        let result = fill(unsafe { &mut *ring.slot(seq) });
+        ring.cursor.0.store(seq, Ordering::Release);
+        self.next += 1;
+        result
+    }
+
+    /// Sequence the next slot will get.
+    pub fn next_sequence(&self) -> i64 {
+        self.next
+    }
+}
+
+impl<T> Drop for Writer<T> {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+}
+
+/// Reads every slot in sequence order, on one thread.
+pub struct Reader<T> {
+    ring: Arc<Ring<T>>,
+    index: usize,
+    next: i64,
+}
+
+impl<T> Reader<T> {
+    /// Hands the slots published since the last poll to `read` with their
+    /// sequence, and returns how many there were.
+    pub fn poll(&mut self, mut read: impl FnMut(i64, &T)) -> usize {
+        let ring = &self.ring;
+        let available = ring.cursor.0.load(Ordering::Acquire);
+        let start = self.next;
+        while self.next <= available {
+            // SAFETY: published, and the writer leaves it alone until this
+            // reader's sequence passes it.
+            read(self.next, unsafe { &*ring.slot(self.next) });
+            self.next += 1;
+        }
+        if self.next > start {
+            ring.readers[self.index]
+                .0
+                .store(self.next - 1, Ordering::Release);
+        }
+        (self.next - start) as usize
+    }
+
+    /// Reads every slot until the writer is gone and nothing is left.
+    pub fn run(self, read: impl FnMut(i64, &T)) {
+        self.run_with_idle(read, || {});
+    }
+
+    /// Like `run`, calling `idle` whenever it has caught up with the
+    /// writer, before it waits for more.
+    pub fn run_with_idle(mut self, mut read: impl FnMut(i64, &T), mut idle: impl FnMut()) {
+        let mut spins = 0;
+        loop {
+            // looked at before polling, or the last slots could be missed
+            let closed = self.ring.closed.load(Ordering::Acquire);
+            if self.poll(&mut read) > 0 {
+                spins = 0;
+                continue;
+            }
+            if spins == 0 {
+                idle();
+            }
+            if closed {
+                return;
+            }
+            self.ring.wait.idle(&mut spins);
+        }
+    }
+}
+
+impl<T> Drop for Reader<T> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            error!(
+                "Ring reader {} panicked at sequence {}, no longer waited for",
+                self.index, self.next
+            );
+        }
+        self.ring.readers[self.index]
+            .0
+            .store(i64::MAX, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_readers_see_every_slot_in_order() {
+        let (mut writer, readers) = ring(8, 2, WaitStrategy::Yield, || 0u64);
+        let handles: Vec<_> = readers
+            .into_iter()
+            .map(|reader| {
+                thread::spawn(move || {
+                    let mut seen = vec![];
+                    reader.run(|seq, value| {
+                        assert_eq!(*value, seq as u64 * 3);
+                        seen.push(*value);
+                    });
+                    seen.len()
+                })
+            })
+            .collect();
+        // many times around the ring, so the writer has to wait
+        for i in 0..10_000u64 {
+            writer.publish(|slot| *slot = i * 3);
+        }
+        drop(writer);
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 10_000);
+        }
+    }
+
+    #[test]
+    fn test_writer_skips_a_panicked_reader() {
+        let (mut writer, mut readers) = ring(4, 2, WaitStrategy::Yield, || 0);
+        let failing = readers.pop().unwrap();
+        writer.publish(|slot| *slot = -1);
+        readers[0].poll(|_, _| {});
+        let handle = thread::spawn(move || failing.run(|_, _| panic!("consumer bug")));
+        assert!(handle.join().is_err());
+        // many times around the ring with nobody reading the failed slots
+        for i in 0..100 {
+            writer.publish(|slot| *slot = i);
+            assert_eq!(readers[0].poll(|_, _| {}), 1);
+        }
+    }
+
+    #[test]
+    fn test_writer_waits_for_slowest_reader() {
+        let (mut writer, mut readers) = ring(4, 1, WaitStrategy::BusySpin, || 0);
+        for i in 0..4 {
+            writer.publish(|slot| *slot = i);
+        }
+        assert_eq!(readers[0].poll(|_, _| {}), 4);
+        assert_eq!(writer.next_sequence(), 4);
+        // the ring is free again
+        for i in 4..8 {
+            writer.publish(|slot| *slot = i);
+        }
+        let mut values = vec![];
+        readers[0].poll(|_, value| values.push(*value));
+        assert_eq!(values, vec![4, 5, 6, 7]);
+    }
+}
@@ -0,0 +1,516 @@
+//! Splits the securities of one market across several engines, each with
+//! its own command queue and books, so that matching runs on more than one
+//! core. A router in front sends every command to the shard owning its
+//! security and merges what the shards report, so that a session hears
+//! about its commands and orders in the order it sent them.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+use tokio::sync::mpsc::{
+    Receiver, Sender, UnboundedReceiver, UnboundedSender, channel, unbounded_channel,
+};
+use tracing::{error, info};
+
+use crate::engine::match_engine::{EXPIRY_CHECK_INTERVAL, MatchEngine, next_query};
+use crate::engine::query::{
+    DEFAULT_QUERY_CAPACITY, Query, QueryClient, QueryRequest, QueryResult, query_channel,
+};
+use crate::interface::admin::ADMIN_SESSION_ID;
+use crate::match_policy::MatchPolicy;
+use crate::types::{EngineCommand, EngineEvent, L1MarketData, MatchEvent};
+
+/// The shard of `count` that owns `security_id`.
+pub fn shard_of(security_id: &str, count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    security_id.hash(&mut hasher);
+    (hasher.finish() % count as u64) as usize
+}
+
+// what a shard did, in the order it did it
+#[derive(Debug)]
+enum ShardOutput {
+    /// events of the next command routed to `shard`
+    Step {
+        shard: usize,
+        events: Vec<MatchEvent>,
+    },
+    /// orders expired or auto fills sent without a command
+    Timer {
+        shard: usize,
+        events: Vec<MatchEvent>,
+    },
+}
+
+/// Engines for disjoint sets of securities behind one command queue.
+pub struct ShardedEngine<P: MatchPolicy> {
+    cmd_rx: Receiver<EngineCommand>,
+    event_tx: UnboundedSender<EngineEvent>,
+    md_tx: Option<UnboundedSender<L1MarketData>>,
+    query_rx: Option<Receiver<QueryRequest>>,
+    shards: Vec<(Sender<EngineCommand>, MatchEngine<P>)>,
+}
+
+impl<P: MatchPolicy + 'static> ShardedEngine<P> {
+    /// `count` shards made by `new_shard` from their own command queue of
+    /// `capacity` commands.
+    pub fn new(
+        cmd_rx: Receiver<EngineCommand>,
+        event_tx: UnboundedSender<EngineEvent>,
+        count: usize,
+        capacity: usize,
+        mut new_shard: impl FnMut(Receiver<EngineCommand>) -> MatchEngine<P>,
+    ) -> Self {
+        let shards = (0..count.max(1))
+            .map(|_| {
+                let (tx, rx) = channel(capacity);
+                let mut engine = new_shard(rx);
+                engine.capture_output();
+                (tx, engine)
+            })
+            .collect();
+        Self {
+            cmd_rx,
+            event_tx,
+            md_tx: None,
+            query_rx: None,
+            shards,
+        }
+    }
+
+    /// Publishes a snapshot of every book a command touched to `md_tx`.
+    pub fn with_market_data(mut self, md_tx: UnboundedSender<L1MarketData>) -> Self {
+        self.md_tx = Some(md_tx);
+        self
+    }
+
+    /// Answers the queries sent on `query_rx`, asking the shards that may
+    /// know.
+    pub fn with_queries(mut self, query_rx: Receiver<QueryRequest>) -> Self {
+        self.query_rx = Some(query_rx);
+        self
+    }
+
+    /// Starts a task per shard and routes commands to them until every
+    /// sender of the command queue is gone and the shards are done.
+    pub async fn start(self) {
+        let count = self.shards.len();
+        let (out_tx, out_rx) = unbounded_channel();
+        let mut senders = Vec::with_capacity(count);
+        let mut clients = Vec::with_capacity(count);
+        for (index, (tx, engine)) in self.shards.into_iter().enumerate() {
+            let (client, query_rx) = query_channel(DEFAULT_QUERY_CAPACITY);
+            let md_tx = self.md_tx.clone();
+            tokio::spawn(run_shard(index, engine, query_rx, out_tx.clone(), md_tx));
+            senders.push(tx);
+            clients.push(client);
+        }
+        drop(out_tx);
+        info!("Match engine started with {} shards", count);
+        let queries = (self.query_rx, clients);
+        route(self.cmd_rx, senders, queries, out_rx, self.event_tx).await;
+    }
+}
+
+async fn run_shard<P: MatchPolicy>(
+    shard: usize,
+    mut engine: MatchEngine<P>,
+    query_rx: Receiver<QueryRequest>,
+    out_tx: UnboundedSender<ShardOutput>,
+    md_tx: Option<UnboundedSender<L1MarketData>>,
+) {
+    let mut ticker = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+    let mut snapshots = vec![];
+    let mut query_rx = Some(query_rx);
+    loop {
+        let mut events = vec![];
+        let output = tokio::select! {
+            biased;
+            cmd = engine.recv() => {
+                let Some(cmd) = cmd else {
+                    break;
+                };
+                engine.handle(cmd);
+                engine.take_output(&mut events, &mut snapshots);
+                ShardOutput::Step { shard, events }
+            }
+            Some(request) = next_query(&mut query_rx) => {
+                let result = engine.query(&request.query);
+                request.answer(result);
+                continue;
+            }
+            _ = ticker.tick() => {
+                engine.tick();
+                if !engine.take_output(&mut events, &mut snapshots) {
+                    continue;
+                }
+                ShardOutput::Timer { shard, events }
+            }
+        };
+        for snapshot in snapshots.drain(..) {
+            if let Some(md_tx) = &md_tx {
+                let _ = md_tx.send(snapshot);
+            }
+        }
+        let _ = out_tx.send(output);
+    }
+}
+
+async fn route(
+    mut cmd_rx: Receiver<EngineCommand>,
+    mut senders: Vec<Sender<EngineCommand>>,
+    (mut query_rx, shards): (Option<Receiver<QueryRequest>>, Vec<QueryClient>),
+    mut out_rx: UnboundedReceiver<ShardOutput>,
+    event_tx: UnboundedSender<EngineEvent>,
+) {
+    let mut merge = Merge::new(senders.len());
+    let mut emit = |event: MatchEvent| {
+        let _ = event_tx.send(EngineEvent::MatchEvent(event));
+    };
+    loop {
+        tokio::select! {
+            // reports first, then commands ahead of the queries sent after
+            // them
+            biased;
+            output = out_rx.recv() => match output {
+                Some(ShardOutput::Step { shard, events }) => merge.reported(shard, events, &mut emit),
+                Some(ShardOutput::Timer { shard, events }) => merge.timer(shard, events, &mut emit),
+                None => break,
+            },
+            cmd = cmd_rx.recv(), if !senders.is_empty() => {
+                let Some(cmd) = cmd else {
+                    // the shards finish what they have, then stop
+                    senders.clear();
+                    continue;
+                };
+                let session_id = cmd.rb_cmd().map_or(ADMIN_SESSION_ID, |c| c.session_id);
+                // commands for every book go to every shard
+                let shards = match cmd.security_id() {
+                    Some(security_id) => {
+                        let shard = shard_of(security_id, senders.len());
+                        shard..shard + 1
+                    }
+                    None => 0..senders.len(),
+                };
+                let mut gone = false;
+                for shard in shards {
+                    merge.routed(shard, session_id);
+                    if senders[shard].send(cmd.clone()).await.is_err() {
+                        error!("Match engine shard {} is gone", shard);
+                        gone = true;
+                    }
+                }
+                if gone {
+                    break;
+                }
+            }
+            Some(request) = next_query(&mut query_rx) => {
+                tokio::spawn(ask_shards(shards.clone(), request));
+            }
+        }
+    }
+}
+
+// Depth only from the shard owning the security, anything else from all
+async fn ask_shards(shards: Vec<QueryClient>, request: QueryRequest) {
+    let asked = match &request.query {
+        Query::Depth { security_id } => {
+            let shard = shard_of(security_id, shards.len());
+            &shards[shard..=shard]
+        }
+        _ => &shards[..],
+    };
+    let mut result: Option<QueryResult> = None;
+    for shard in asked {
+        match shard.ask(request.query.clone()).await {
+            Ok(answer) => {
+                result = Some(match result {
+                    Some(result) => result.merge(answer),
+                    None => answer,
+                });
+            }
+            // dropping the request tells the client
+            Err(e) => {
+                error!("Query {:?} failed: {}", request.query, e);
+                return;
+            }
+        }
+    }
+    if let Some(result) = result {
+        request.answer(result);
+    }
+}
+
+// Puts the events of each session back in the order the session sent its
+// commands. A command's own events go out once every earlier command of its
+// session has been reported. Those of the resting orders it traded with go
+// to their owners after every command the owners sent before it, so that an
+// owner never hears of a fill ahead of its order being accepted.
+struct Merge {
+    // number the next command routed gets, over all sessions
+    routed: u64,
+    // per shard, the commands it has yet to report
+    pending: Vec<VecDeque<Pending>>,
+    sessions: HashMap<u64, SessionOrder>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    session_id: u64,
+    // sequence within the session
+    seq: u64,
+    // number over all sessions
+    number: u64,
+}
+
+#[derive(Default)]
+struct SessionOrder {
+    // sequence the next command of the session gets
+    sent: u64,
+    // sequence of the next command whose events may go out
+    released: u64,
+    // number of each command from `released` on
+    numbers: VecDeque<u64>,
+    // events of commands reported ahead of an earlier one
+    held: BTreeMap<u64, Vec<MatchEvent>>,
+    // events of the session's orders made by other commands, to go out
+    // after the command of this sequence
+    after: BTreeMap<u64, Vec<MatchEvent>>,
+}
+
+impl Merge {
+    fn new(shards: usize) -> Self {
+        Self {
+            routed: 0,
+            pending: vec![VecDeque::new(); shards],
+            sessions: HashMap::new(),
+        }
+    }
+
+    fn routed(&mut self, shard: usize, session_id: u64) {
+        let order = self.sessions.entry(session_id).or_default();
+        self.pending[shard].push_back(Pending {
+            session_id,
+            seq: order.sent,
+            number: self.routed,
+        });
+        order.numbers.push_back(self.routed);
+        order.sent += 1;
+        self.routed += 1;
+    }
+
+    fn reported(
+        &mut self,
+        shard: usize,
+        events: Vec<MatchEvent>,
+        mut emit: impl FnMut(MatchEvent),
+    ) {
+        let Some(pending) = self.pending[shard].pop_front() else {
+            error!("Match engine shard {} reported an unknown command", shard);
+            return;
+        };
+        let mut own = vec![];
+        for event in events {
+            if event.session_id == pending.session_id {
+                own.push(event);
+            } else {
+                self.deliver(event, pending.number, &mut emit);
+            }
+        }
+        if let Some(order) = self.sessions.get_mut(&pending.session_id) {
+            order.held.insert(pending.seq, own);
+        }
+        self.release(pending.session_id, &mut emit);
+    }
+
+    // events of a shard made without a command, after every command it has
+    // reported
+    fn timer(&mut self, shard: usize, events: Vec<MatchEvent>, mut emit: impl FnMut(MatchEvent)) {
+        let before = self.pending[shard]
+            .front()
+            .map_or(self.routed, |pending| pending.number);
+        for event in events {
+            self.deliver(event, before, &mut emit);
+        }
+    }
+
+    // sends `event` once its session's commands numbered below `before` have
+    // gone out
+    fn deliver(&mut self, event: MatchEvent, before: u64, emit: &mut impl FnMut(MatchEvent)) {
+        let Some(order) = self.sessions.get_mut(&event.session_id) else {
+            return emit(event);
+        };
+        let earlier = order.numbers.partition_point(|&number| number < before);
+        if earlier == 0 {
+            return emit(event);
+        }
+        let seq = order.released + earlier as u64 - 1;
+        order.after.entry(seq).or_default().push(event);
+    }
+
+    fn release(&mut self, session_id: u64, emit: &mut impl FnMut(MatchEvent)) {
+        let Some(order) = self.sessions.get_mut(&session_id) else {
+            return;
+        };
+        while let Some(events) = order.held.remove(&order.released) {
+            events.into_iter().for_each(&mut *emit);
+            if let Some(events) = order.after.remove(&order.released) {
+                events.into_iter().for_each(&mut *emit);
+            }
+            order.numbers.pop_front();
+            order.released += 1;
+        }
+        if order.released == order.sent {
+            self.sessions.remove(&session_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{OrderSide, OrderStatus, RbCmd, TimeInForce};
+
+    use super::*;
+
+    fn order(oid: i64, security_id: &str, side: OrderSide) -> RbCmd {
+        RbCmd {
+            session_id: 1,
+            side,
+            match_event_list: vec![],
+            price: 100,
+            volume: 10,
+            mid: oid,
+            uid: 0,
+            oid,
+            security_id: security_id.to_string(),
+            time_in_force: TimeInForce::Day,
+        }
+    }
+
+    fn event(session_id: u64, oid: i64, status: OrderStatus) -> MatchEvent {
+        let mut cmd = order(oid, "600000", OrderSide::Buy);
+        cmd.session_id = session_id;
+        let mut event = MatchEvent::rejected(&cmd, 0);
+        event.status = status;
+        event
+    }
+
+    #[test]
+    fn test_merge_keeps_session_order() {
+        let mut merge = Merge::new(2);
+        merge.routed(0, 1);
+        merge.routed(1, 1);
+        merge.routed(1, 2);
+        let mut out = vec![];
+        // shard 1 is ahead of shard 0
+        let rejected = OrderStatus::Rejected;
+        merge.reported(1, vec![event(1, 2, rejected)], |e| out.push(e.oid));
+        merge.reported(1, vec![event(2, 3, rejected)], |e| out.push(e.oid));
+        assert_eq!(out, vec![3]);
+        merge.reported(0, vec![event(1, 1, rejected)], |e| out.push(e.oid));
+        assert_eq!(out, vec![3, 1, 2]);
+        assert!(merge.sessions.is_empty());
+    }
+
+    #[test]
+    fn test_merge_keeps_fills_after_the_owners_order() {
+        let mut merge = Merge::new(2);
+        // session 2 sends an order to each shard, session 1 then trades
+        // with the second on shard 1
+        merge.routed(0, 2);
+        merge.routed(1, 2);
+        merge.routed(1, 1);
+        let mut out = vec![];
+        let mut emit = |e: MatchEvent| out.push((e.session_id, e.oid, e.status));
+        merge.reported(1, vec![event(2, 2, OrderStatus::OrderEd)], &mut emit);
+        let trade = vec![
+            event(1, 3, OrderStatus::TradeEd),
+            event(2, 2, OrderStatus::TradeEd),
+        ];
+        merge.reported(1, trade, &mut emit);
+        merge.reported(0, vec![event(2, 1, OrderStatus::OrderEd)], &mut emit);
+        assert_eq!(
+            out,
+            vec![
+                (1, 3, OrderStatus::TradeEd),
+                (2, 1, OrderStatus::OrderEd),
+                (2, 2, OrderStatus::OrderEd),
+                (2, 2, OrderStatus::TradeEd),
+            ]
+        );
+        assert!(merge.sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shards_match_their_own_securities() {
+        let (cmd_tx, cmd_rx) = channel(16);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let shard_tx = event_tx.clone();
+        let (client, query_rx) = query_channel(4);
+        let engine = ShardedEngine::new(cmd_rx, event_tx, 4, 16, move |rx| {
+            MatchEngine::new(rx, shard_tx.clone())
+        })
+        .with_queries(query_rx);
+        let router = tokio::spawn(engine.start());
+
+        let securities = ["600000", "600519", "601318", "000001", "000002"];
+        let mut oid = 0;
+        for security_id in securities {
+            for side in [OrderSide::Sell, OrderSide::Buy] {
+                oid += 1;
+                let cmd = order(oid, security_id, side);
+                cmd_tx.send(EngineCommand::NewOrder(cmd)).await.unwrap();
+            }
+        }
+        // a sell left resting on another shard
+        let rest = order(11, "600519", OrderSide::Sell);
+        cmd_tx.send(EngineCommand::NewOrder(rest)).await.unwrap();
+        let mut events = vec![];
+        while events.len() < 3 * securities.len() + 1 {
+            let Some(EngineEvent::MatchEvent(me)) = event_rx.recv().await else {
+                panic!("engine stopped");
+            };
+            events.push((me.oid, me.status));
+        }
+
+        // every sell rests, then trades with the buy sent after it
+        let mut expected: Vec<_> = (0..securities.len() as i64)
+            .flat_map(|i| {
+                [
+                    (2 * i + 1, OrderStatus::OrderEd),
+                    (2 * i + 2, OrderStatus::TradeEd),
+                    (2 * i + 1, OrderStatus::TradeEd),
+                ]
+            })
+            .collect();
+        expected.push((11, OrderStatus::OrderEd));
+        assert_eq!(events, expected);
+
+        // books of different shards answer as one
+        let result = client
+            .ask(Query::OpenOrders {
+                session_id: Some(1),
+                uid: None,
+            })
+            .await
+            .unwrap();
+        let QueryResult::OpenOrders(orders) = result else {
+            panic!("unexpected {result:?}");
+        };
+        assert_eq!(orders.iter().map(|o| o.oid).collect::<Vec<_>>(), vec![11]);
+        let result = client
+            .ask(Query::Depth {
+                security_id: "600519".to_string(),
+            })
+            .await
+            .unwrap();
+        let QueryResult::Depth(Some(depth)) = result else {
+            panic!("unexpected {result:?}");
+        };
+        assert_eq!((depth.last_price, depth.asks.len()), (100, 1));
+
+        drop(cmd_tx);
+        router.await.unwrap();
+    }
+}
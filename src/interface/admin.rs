@@ -0,0 +1,254 @@
+//! HTTP/JSON interface to one app, for operators and test scripts driving
+//! it from a browser or curl. One request per connection.
+//!
+//! ```text
+//! GET  /books                      top of every book
+//! GET  /books/{security_id}        every level of one book
+//! GET  /orders?session_id=&uid=    resting orders, both filters optional
+//! GET  /orders/{oid}               one resting order and its queue position
+//! GET  /sessions                   connected sessions of each channel
+//! GET  /queues                     commands and events waiting
+//! POST /books/{security_id}/halt   stops matching
+//! POST /books/{security_id}/resume back to continuous trading, through an
+//!                                  auction if one is configured
+//! POST /phase                      {"phase": "closed", "security_id": "600519"}
+//! POST /mass_cancel                {"security_id", "session_id", "uid"}
+//! POST /orders                     {"security_id", "side", "price", "volume"}
+//! ```
+//!
+//! Commands are queued for the engine behind the orders already waiting,
+//! so a reply only says they were queued.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use tokio::sync::mpsc::Sender;
+use tracing::info;
+
+use crate::engine::query::{Query, QueryClient, QueryResult};
+use crate::interface::channel::{ChannelIds, ChannelMonitor};
+use crate::interface::http::{self, Request, Response};
+use crate::types::{EngineCommand, MassCancel, OrderSide, RbCmd, TimeInForce, TradingPhase};
+
+/// Session of the orders entered through the admin interface. No channel
+/// has it, so their events go nowhere.
+pub const ADMIN_SESSION_ID: u64 = u64::MAX;
+
+/// Serves the admin interface of one engine and its channels.
+pub struct AdminServer {
+    addr: SocketAddr,
+    cmd_tx: Sender<EngineCommand>,
+    queries: QueryClient,
+    ids: Arc<ChannelIds>,
+    channels: Vec<(String, Arc<dyn ChannelMonitor>)>,
+}
+
+/// `POST /phase`, every book if no security is given.
+#[derive(Debug, Deserialize)]
+struct PhaseChange {
+    security_id: Option<String>,
+    phase: TradingPhase,
+}
+
+/// `POST /orders`
+#[derive(Debug, Deserialize)]
+struct OrderEntry {
+    security_id: String,
+    side: OrderSide,
+    price: i64,
+    volume: i64,
+    #[serde(default)]
+    uid: u64,
+    #[serde(default)]
+    time_in_force: TimeInForce,
+}
+
+impl AdminServer {
+    /// Commands go to `cmd_tx`, queries to `queries`, and entered orders
+    /// take their ids from `ids` like those of the channels.
+    pub fn new(
+        addr: SocketAddr,
+        cmd_tx: Sender<EngineCommand>,
+        queries: QueryClient,
+        ids: Arc<ChannelIds>,
+    ) -> Self {
+        Self {
+            addr,
+            cmd_tx,
+            queries,
+            ids,
+            channels: vec![],
+        }
+    }
+
+    /// Lists the sessions and queues of `channel` under `name`.
+    pub fn with_channel(mut self, name: &str, channel: Arc<dyn ChannelMonitor>) -> Self {
+        self.channels.push((name.to_string(), channel));
+        self
+    }
+
+    /// Starts listening and serving, returns where it listens.
+    pub async fn start(self) -> std::io::Result<SocketAddr> {
+        let server = Arc::new(self);
+        http::serve(server.addr, "Admin", move |request| {
+            let server = server.clone();
+            async move {
+                info!("Admin {} {}", request.method, request.path);
+                server.handle(&request).await.unwrap_or_else(|e| e)
+            }
+        })
+        .await
+    }
+
+    async fn handle(&self, request: &Request) -> Result<Response, Response> {
+        let response = match (request.method.as_str(), request.segments().as_slice()) {
+            ("GET", ["books"]) => {
+                let QueryResult::Books(books) = self.ask(Query::Books).await? else {
+                    unreachable!("books query answered with something else");
+                };
+                ok(json!(books))
+            }
+            ("GET", ["books", security_id]) => {
+                let security_id = security_id.to_string();
+                match self.ask(Query::Depth { security_id }).await? {
+                    QueryResult::Depth(Some(depth)) => ok(json!(depth)),
+                    _ => not_found(),
+                }
+            }
+            ("GET", ["orders"]) => {
+                let query = Query::OpenOrders {
+                    session_id: param(request, "session_id")?,
+                    uid: param(request, "uid")?,
+                };
+                let QueryResult::OpenOrders(orders) = self.ask(query).await? else {
+                    unreachable!("open orders query answered with something else");
+                };
+                ok(json!(orders))
+            }
+            ("GET", ["orders", oid]) => {
+                let oid = oid
+                    .parse()
+                    .map_err(|_| error(400, "order id is not a number"))?;
+                match self.ask(Query::Order { oid }).await? {
+                    QueryResult::Order(Some(order)) => ok(json!(order)),
+                    _ => not_found(),
+                }
+            }
+            ("GET", ["sessions"]) => {
+                let channels: Vec<_> = self
+                    .channels
+                    .iter()
+                    .map(|(name, channel)| {
+                        json!({
+                            "channel": name,
+                            "addr": channel.local_addr(),
+                            "sessions": channel.session_infos(),
+                        })
+                    })
+                    .collect();
+                ok(json!(channels))
+            }
+            ("GET", ["queues"]) => {
+                let channels: Vec<_> = self
+                    .channels
+                    .iter()
+                    .map(|(name, channel)| {
+                        let depths = channel.queue_depths();
+                        json!({
+                            "channel": name,
+                            "events": depths.events,
+                            "sessions": depths.sessions,
+                        })
+                    })
+                    .collect();
+                let commands = self.cmd_tx.max_capacity() - self.cmd_tx.capacity();
+                ok(json!({ "commands": commands, "channels": channels }))
+            }
+            ("POST", ["books", security_id, action @ ("halt" | "resume")]) => {
+                let phase = match *action {
+                    "halt" => TradingPhase::Halted,
+                    _ => TradingPhase::Continuous,
+                };
+                let security_id = Some(security_id.to_string());
+                self.send(EngineCommand::SetPhase { security_id, phase })
+                    .await?
+            }
+            ("POST", ["phase"]) => {
+                let change: PhaseChange = body(request)?;
+                self.send(EngineCommand::SetPhase {
+                    security_id: change.security_id,
+                    phase: change.phase,
+                })
+                .await?
+            }
+            ("POST", ["mass_cancel"]) => {
+                let filter: MassCancel = body(request)?;
+                self.send(EngineCommand::MassCancel(filter)).await?
+            }
+            ("POST", ["orders"]) => {
+                let entry: OrderEntry = body(request)?;
+                if entry.price <= 0 || entry.volume <= 0 {
+                    return Err(error(400, "price and volume must be positive"));
+                }
+                let oid = self.ids.next_oid();
+                let cmd = RbCmd {
+                    session_id: ADMIN_SESSION_ID,
+                    side: entry.side,
+                    match_event_list: vec![],
+                    price: entry.price,
+                    volume: entry.volume,
+                    mid: oid,
+                    uid: entry.uid,
+                    oid,
+                    security_id: entry.security_id,
+                    time_in_force: entry.time_in_force,
+                };
+                self.send(EngineCommand::NewOrder(cmd)).await?;
+                ok(json!({ "queued": true, "oid": oid }))
+            }
+            _ => not_found(),
+        };
+        Ok(response)
+    }
+
+    async fn ask(&self, query: Query) -> Result<QueryResult, Response> {
+        self.queries.ask(query).await.map_err(|e| error(503, e))
+    }
+
+    async fn send(&self, cmd: EngineCommand) -> Result<Response, Response> {
+        info!("Admin command {:?}", cmd);
+        self.cmd_tx
+            .send(cmd)
+            .await
+            .map_err(|_| error(503, "engine stopped"))?;
+        Ok(ok(json!({ "queued": true })))
+    }
+}
+
+fn ok(body: serde_json::Value) -> Response {
+    Response::json(200, &body)
+}
+
+fn error(status: u16, message: impl ToString) -> Response {
+    Response::json(status, &json!({ "error": message.to_string() }))
+}
+
+fn not_found() -> Response {
+    error(404, "not found")
+}
+
+fn param<T: std::str::FromStr>(request: &Request, name: &str) -> Result<Option<T>, Response> {
+    request
+        .params
+        .get(name)
+        .map(|value| value.parse())
+        .transpose()
+        .map_err(|_| error(400, format!("bad {name}")))
+}
+
+fn body<T: DeserializeOwned>(request: &Request) -> Result<T, Response> {
+    serde_json::from_slice(&request.body).map_err(|e| error(400, e))
+}
@@ -1,78 +1,341 @@
-use binary_codec::*;
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use chrono::Utc;
 use dashmap::DashMap;
-use sse_binary::report::Report;
-use sse_binary::sse_binary::SseBinary;
-use sse_binary::sse_binary::SseBinaryBodyEnum;
-use std::io::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::marker::PhantomData;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
-use tokio::io::BufReader;
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
 use tokio::net::tcp::OwnedWriteHalf;
-use tokio::sync::Mutex;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, watch};
+use tokio::time::Instant;
+use tracing::debug;
 use tracing::error;
 use tracing::info;
+use tracing::trace;
 use tracing::warn;
 
-use crate::protocol::proto::FrameDecoder;
-use crate::protocol::proto::SseDecoder;
+use crate::protocol::adapter::{Inbound, ProtocolAdapter, SessionContext};
+use crate::protocol::proto::{FrameDecoder, FrameEncoder};
+use crate::protocol::sse::SseAdapter;
 use crate::types::EngineCommand;
 use crate::types::EngineEvent;
-use crate::types::Order;
-use crate::types::OrderStatus;
-use crate::types::RbCmd;
 
 pub trait AcceptorChannel {
     fn start(
         self: Arc<Self>,
         event_rx: UnboundedReceiver<EngineEvent>,
     ) -> impl std::future::Future<Output = Result<(), Error>> + Send;
-    fn stop(&mut self) -> impl std::future::Future<Output = Result<(), Error>> + Send;
+    fn stop(&self) -> impl std::future::Future<Output = Result<(), Error>> + Send;
+}
+
+/// Sessions a channel serves at once unless configured otherwise.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+/// Events that may wait for a session unless configured otherwise.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 4096;
+// how long `stop` waits for sessions to close
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+// pause after a failed accept, which mostly means out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Hands every engine event to each of `channels`, for engines with more than
+/// one channel. Channels skip events of sessions they do not know. Nothing
+/// here waits, the channels drain their queues as `TcpAcceptorChannel`'s
+/// `start` explains.
+pub fn fan_out_events(
+    mut event_rx: UnboundedReceiver<EngineEvent>,
+    channels: Vec<UnboundedSender<EngineEvent>>,
+) {
+    tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            for channel in &channels {
+                let _ = channel.send(event.clone());
+            }
+        }
+    });
 }
 
 struct Session {
     id: u64,
-    writer: Arc<Mutex<OwnedWriteHalf>>,
-    tx: UnboundedSender<EngineEvent>,
+    tx: Sender<EngineEvent>,
+    peer: SocketAddr,
+    // ms since the Unix epoch
+    connected_at: i64,
+    received: u64,
+    sent: u64,
+    seq_nums: Option<(u64, u64)>,
 }
 
-pub struct TcpAcceptorChannel {
-    port: u16,
-    cmd_tx: UnboundedSender<EngineCommand>,
+/// Accepts TCP sessions speaking the protocol of `A`, SSE binary by default.
+pub struct TcpAcceptorChannel<A = SseAdapter> {
+    addr: SocketAddr,
+    // where the listener ended up, known once started
+    local_addr: OnceLock<SocketAddr>,
+    cmd_tx: Sender<EngineCommand>,
     session_map: Arc<DashMap<u64, Session>>,
-    next_id: AtomicU64,
+    ids: Arc<ChannelIds>,
+    limits: SessionLimits,
+    // one permit per session and one for the acceptor, all of them once
+    // everything has stopped
+    permits: Arc<Semaphore>,
+    shutdown: watch::Sender<bool>,
+    metrics: ChannelMetrics,
+    protocol: PhantomData<fn() -> A>,
+}
+
+/// What a channel allows its clients.
+#[derive(Debug, Clone)]
+pub struct SessionLimits {
+    /// connections beyond this are closed as soon as they are accepted
+    pub max_connections: usize,
+    /// events waiting for a session, a session that falls further behind
+    /// is disconnected
+    pub queue_capacity: usize,
+    pub throttle: Option<ThrottleConfig>,
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            throttle: None,
+        }
+    }
+}
+
+/// Flow control of the orders and cancels of each session.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct ThrottleConfig {
+    /// commands per second, up to this many may come at once
+    pub rate: u32,
+    #[serde(default)]
+    pub action: ThrottleAction,
+}
+
+/// What happens to a command over the rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThrottleAction {
+    /// answered with a reject, it never reaches the engine
+    #[default]
+    Reject,
+    /// held back until the rate allows it, the session reads nothing
+    /// meanwhile
+    Delay,
+}
+
+/// Messages waiting in the queues a channel feeds.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct QueueDepths {
+    /// commands of all channels not yet taken by the engine
+    pub commands: usize,
+    /// engine events not yet routed to their session
+    pub events: usize,
+    /// events not yet handled, by session id
+    pub sessions: Vec<(u64, usize)>,
+}
+
+/// A connected session as the admin interface shows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionInfo {
+    pub session_id: u64,
+    pub peer: SocketAddr,
+    /// ms since the Unix epoch
+    pub connected_at: i64,
+    /// messages decoded from the client
+    pub received: u64,
+    /// messages sent to the client
+    pub sent: u64,
+    /// last sequence numbers received and sent, for protocols numbering
+    /// their messages
+    pub seq_nums: Option<(u64, u64)>,
+    /// events waiting for the session
+    pub queued: usize,
+}
+
+/// Traffic of all sessions of a channel since it started.
+#[derive(Debug, Default)]
+pub struct ChannelMetrics {
+    pub bytes_received: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    /// frames the protocol could not make sense of
+    pub decode_errors: AtomicU64,
+    /// engine events waiting to be routed to their session, a gauge set
+    /// as each is taken
+    pub events_queued: AtomicU64,
+}
+
+impl ChannelMetrics {
+    /// Bytes received, bytes sent and decode errors.
+    pub fn counters(&self) -> [&AtomicU64; 3] {
+        [&self.bytes_received, &self.bytes_sent, &self.decode_errors]
+    }
+}
+
+/// What a running channel tells about itself, whatever its protocol.
+pub trait ChannelMonitor: Send + Sync {
+    fn local_addr(&self) -> Option<SocketAddr>;
+
+    /// Connected sessions by id.
+    fn session_infos(&self) -> Vec<SessionInfo>;
+
+    fn queue_depths(&self) -> QueueDepths;
+
+    fn metrics(&self) -> &ChannelMetrics;
+}
+
+/// Session and engine order ids, shared by the channels of one engine so
+/// that events find their session and order ids never clash.
+#[derive(Debug)]
+pub struct ChannelIds {
+    next_session: AtomicU64,
+    next_order: AtomicI64,
+}
+
+impl ChannelIds {
+    /// A fresh engine order id, for orders entered outside a session.
+    pub fn next_oid(&self) -> i64 {
+        self.next_order
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+impl Default for ChannelIds {
+    fn default() -> Self {
+        Self {
+            next_session: AtomicU64::new(1),
+            next_order: AtomicI64::new(1),
+        }
+    }
 }
 
 impl TcpAcceptorChannel {
-    pub fn new(port: u16, cmd_tx: UnboundedSender<EngineCommand>) -> Arc<Self> {
+    pub fn new(addr: SocketAddr, cmd_tx: Sender<EngineCommand>) -> Arc<Self> {
+        Self::with_protocol(addr, cmd_tx)
+    }
+}
+
+impl<A: ProtocolAdapter> TcpAcceptorChannel<A> {
+    pub fn with_protocol(addr: SocketAddr, cmd_tx: Sender<EngineCommand>) -> Arc<Self> {
+        Self::with_ids(addr, cmd_tx, Arc::default())
+    }
+
+    /// A channel sharing `ids` with the other channels of its engine.
+    pub fn with_ids(
+        addr: SocketAddr,
+        cmd_tx: Sender<EngineCommand>,
+        ids: Arc<ChannelIds>,
+    ) -> Arc<Self> {
+        Self::with_limits(addr, cmd_tx, ids, SessionLimits::default())
+    }
+
+    pub fn with_limits(
+        addr: SocketAddr,
+        cmd_tx: Sender<EngineCommand>,
+        ids: Arc<ChannelIds>,
+        limits: SessionLimits,
+    ) -> Arc<Self> {
         Arc::new(Self {
-            port: port,
+            addr,
+            local_addr: OnceLock::new(),
             cmd_tx,
             session_map: Arc::new(DashMap::new()),
-            next_id: AtomicU64::new(1),
+            ids,
+            permits: Arc::new(Semaphore::new(limits.max_connections + 1)),
+            limits,
+            shutdown: watch::channel(false).0,
+            metrics: ChannelMetrics::default(),
+            protocol: PhantomData,
         })
     }
 
+    /// Address the channel listens on once started, useful with port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr.get().copied()
+    }
+
+    /// Sessions currently connected.
+    pub fn sessions(&self) -> usize {
+        self.session_map.len()
+    }
+
+    pub fn queue_depths(&self) -> QueueDepths {
+        let mut sessions: Vec<_> = self
+            .session_map
+            .iter()
+            .map(|session| (session.id, depth(&session.tx)))
+            .collect();
+        sessions.sort_unstable();
+        QueueDepths {
+            commands: depth(&self.cmd_tx),
+            events: self.metrics.events_queued.load(Ordering::Relaxed) as usize,
+            sessions,
+        }
+    }
+
+    pub fn session_infos(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<_> = self
+            .session_map
+            .iter()
+            .map(|session| SessionInfo {
+                session_id: session.id,
+                peer: session.peer,
+                connected_at: session.connected_at,
+                received: session.received,
+                sent: session.sent,
+                seq_nums: session.seq_nums,
+                queued: depth(&session.tx),
+            })
+            .collect();
+        sessions.sort_unstable_by_key(|s| s.session_id);
+        sessions
+    }
+
     pub fn next_id(&self) -> u64 {
-        self.next_id
+        self.ids
+            .next_session
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
     }
 }
 
-impl AcceptorChannel for TcpAcceptorChannel {
+impl<A: ProtocolAdapter> ChannelMonitor for TcpAcceptorChannel<A> {
+    fn local_addr(&self) -> Option<SocketAddr> {
+        TcpAcceptorChannel::local_addr(self)
+    }
+
+    fn session_infos(&self) -> Vec<SessionInfo> {
+        TcpAcceptorChannel::session_infos(self)
+    }
+
+    fn queue_depths(&self) -> QueueDepths {
+        TcpAcceptorChannel::queue_depths(self)
+    }
+
+    fn metrics(&self) -> &ChannelMetrics {
+        &self.metrics
+    }
+}
+
+impl<A: ProtocolAdapter> AcceptorChannel for TcpAcceptorChannel<A> {
     async fn start(
         self: Arc<Self>,
         mut event_rx: UnboundedReceiver<EngineEvent>,
     ) -> Result<(), Error> {
-        let listener = TcpListener::bind(format!("0.0.0.0:{}", self.port)).await?;
-        info!("Listening on {}", listener.local_addr()?);
+        let listener = TcpListener::bind(self.addr).await?;
+        let local_addr = listener.local_addr()?;
+        info!("Listening on {}", local_addr);
+        let _ = self.local_addr.set(local_addr);
 
         let c = self.clone();
         tokio::spawn(async move {
@@ -82,22 +345,41 @@ impl AcceptorChannel for TcpAcceptorChannel {
             }
         });
 
+        // The engine never waits for a client, so neither does this: events
+        // are only offered to the bounded queues of the sessions and the
+        // unbounded queue drains as fast as the engine fills it. The
+        // `events_queued` gauge shows it if it ever doesn't.
         tokio::spawn(async move {
-            //self.event_rx
             while let Some(event) = event_rx.recv().await {
+                self.metrics
+                    .events_queued
+                    .store(event_rx.len() as u64, Ordering::Relaxed);
                 match event {
                     EngineEvent::MatchEvent(me) => {
                         info!("Match Event: {:?}", me);
-                        if let Some(session_ref) = self.session_map.get(&me.session_id) {
-                            if let Err(e) = session_ref.tx.send(EngineEvent::MatchEvent(me.clone()))
-                            {
-                                error!(
-                                    "Failed to send MatchEvent to session {}: {}",
-                                    me.session_id, e
-                                );
+                        let sent = match self.session_map.get(&me.session_id) {
+                            Some(session_ref) => {
+                                session_ref.tx.try_send(EngineEvent::MatchEvent(me.clone()))
                             }
-                        } else {
-                            info!("Session {} not found, maybe disconnected", me.session_id);
+                            None => {
+                                // or it belongs to another channel of the engine
+                                debug!("Session {} not found, maybe disconnected", me.session_id);
+                                continue;
+                            }
+                        };
+                        if let Err(TrySendError::Full(_)) = sent {
+                            // the engine doesn't wait for anyone, the session
+                            // closes once it has handled what it has
+                            warn!(
+                                "Session {} is {} events behind, disconnecting",
+                                me.session_id, self.limits.queue_capacity
+                            );
+                            self.session_map.remove(&me.session_id);
+                        } else if let Err(e) = sent {
+                            error!(
+                                "Failed to send MatchEvent to session {}: {}",
+                                me.session_id, e
+                            );
                         }
                     }
                 }
@@ -107,136 +389,363 @@ impl AcceptorChannel for TcpAcceptorChannel {
         Ok(())
     }
 
-    async fn stop(&mut self) -> Result<(), Error> {
-        Ok(())
+    /// Stops accepting, has every session write out the events already
+    /// queued for it and close, and waits until they have.
+    async fn stop(&self) -> Result<(), Error> {
+        self.shutdown.send_replace(true);
+        let all = self.limits.max_connections as u32 + 1;
+        match tokio::time::timeout(DRAIN_TIMEOUT, self.permits.acquire_many(all)).await {
+            Ok(_) => {
+                info!("All sessions of {} closed", self.addr);
+                Ok(())
+            }
+            Err(_) => Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("{} session(s) still open", self.sessions()),
+            )),
+        }
     }
 }
 
-impl TcpAcceptorChannel {
+impl<A: ProtocolAdapter> TcpAcceptorChannel<A> {
     async fn run_acceptor(self: Arc<Self>, listener: TcpListener) -> Result<(), Error> {
+        let _running = self.permits.clone().acquire_owned().await;
+        let mut shutdown = self.shutdown.subscribe();
         loop {
-            let (stream, addr) = listener.accept().await?;
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown.wait_for(|&stop| stop) => return Ok(()),
+            };
+            let (stream, addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // out of file descriptors and the like, may pass
+                    error!("Accept failed: {}", e);
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+                warn!(
+                    "Refused connection from {:?}, {} sessions open",
+                    addr, self.limits.max_connections
+                );
+                continue;
+            };
             info!("Accepted connection from {:?}", addr);
-            let channel = self.clone();
-            if let Err(e) = channel.handle_connection(stream, addr).await {
-                error!("Connection handler error: {}", e);
-            }
+            self.clone().handle_connection(stream, addr, permit);
         }
     }
 
-    async fn handle_connection(
+    // Serves one connection on a task of its own, whatever happens to it
+    // leaves the other sessions alone.
+    fn handle_connection(
         self: Arc<Self>,
         stream: TcpStream,
         addr: SocketAddr,
-    ) -> Result<(), Error> {
-        let (reader, writer) = stream.into_split();
-        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        permit: OwnedSemaphorePermit,
+    ) {
+        let (tx, rx) = tokio::sync::mpsc::channel(self.limits.queue_capacity);
         let session_id = self.next_id();
         let session = Session {
             id: session_id,
-            writer: Arc::new(Mutex::new(writer)),
-            tx: tx,
+            tx,
+            peer: addr,
+            connected_at: Utc::now().timestamp_millis(),
+            received: 0,
+            sent: 0,
+            seq_nums: None,
         };
         self.session_map.insert(session_id, session);
-        let mut decoder = FrameDecoder::new(SseDecoder);
-        let mut buffer = [0u8; 1024];
-
-        let cmd_tx = self.cmd_tx.clone();
-        let session_map = self.session_map.clone();
         tokio::spawn(async move {
-            let mut reader = BufReader::new(reader);
-            loop {
-                let n = reader.read(&mut buffer).await.unwrap();
-                if n == 0 {
-                    info!("Client disconnected");
+            if let Err(e) = self.run_session(session_id, stream, rx).await {
+                warn!("Session {} from {} failed: {}", session_id, addr, e);
+            }
+            self.session_map.remove(&session_id);
+            drop(permit);
+            info!("Session {} from {} closed", session_id, addr);
+        });
+    }
+
+    async fn run_session(
+        &self,
+        session_id: u64,
+        stream: TcpStream,
+        mut rx: Receiver<EngineEvent>,
+    ) -> Result<(), Error> {
+        let (mut reader, half) = stream.into_split();
+        let mut conn = Connection::<A> {
+            session_id,
+            half,
+            frames: FrameEncoder::new(A::encoder()),
+            adapter: A::default(),
+            throttle: self.limits.throttle.map(Throttle::new),
+            received: 0,
+            sent: 0,
+        };
+        let mut decoder = FrameDecoder::new(A::decoder());
+        let mut shutdown = self.shutdown.subscribe();
+        let mut timer = tokio::time::interval(Duration::from_secs(1));
+        let mut buffer = [0u8; 4096];
+
+        'session: loop {
+            let wake = tokio::select! {
+                read = reader.read(&mut buffer) => Wake::Read(read?),
+                event = rx.recv() => match event {
+                    Some(event) => Wake::Event(event),
+                    None => break,
+                },
+                _ = timer.tick() => Wake::Timer,
+                _ = shutdown.wait_for(|&stop| stop) => Wake::Stop,
+            };
+            let ctx = SessionContext::new(session_id, &self.ids.next_order);
+            match wake {
+                Wake::Read(0) => {
+                    info!("Client {} disconnected", session_id);
                     break;
                 }
-
-                decoder.feed(&buffer[..n]);
-                // process rev messages
-                while let Some(msg) = decoder.next_frame() {
-                    match msg.body {
-                        SseBinaryBodyEnum::Logon(logon) => {
-                            info!("Logon received: {:?}", logon);
-                        }
-                        SseBinaryBodyEnum::Heartbeat(_) => {
-                            info!("Heartbeat received");
-                        }
-                        SseBinaryBodyEnum::NewOrderSingle(order) => {
-                            let order_request = Order::from(&order);
-                            let cmd = RbCmd {
-                                session_id,
-                                side: order_request.side,
-                                match_event_list: vec![],
-                                price: order_request.price,
-                                volume: order_request.volume,
-                                mid: 0,
-                                oid: order_request.oid,
-                                uid: order_request.uid,
-                                security_id: order_request.security_id,
-                            };
-                            info!("Order will process: {:?}", cmd);
-                            let _ = cmd_tx.send(EngineCommand::NewOrder(cmd));
+                Wake::Read(n) => {
+                    self.metrics
+                        .bytes_received
+                        .fetch_add(n as u64, Ordering::Relaxed);
+                    decoder.feed(&buffer[..n]);
+                    // process rev messages
+                    loop {
+                        let actions = match decoder.next_frame() {
+                            Ok(Some(msg)) => {
+                                conn.received += 1;
+                                conn.adapter.on_message(&ctx, msg)
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                warn!("Bad frame from client {}: {}", session_id, e);
+                                self.metrics.decode_errors.fetch_add(1, Ordering::Relaxed);
+                                conn.adapter.on_frame_error(&ctx, &e)
+                            }
+                        };
+                        if !self.perform(&mut conn, &ctx, actions).await {
+                            break 'session;
                         }
-                        _ => {
-                            info!("Unknown message type received");
+                    }
+                }
+                Wake::Event(EngineEvent::MatchEvent(me)) => {
+                    info!("Sending Match Event to client {}: {:?}", session_id, me);
+                    let actions = conn.adapter.on_event(&ctx, &me);
+                    if !self.perform(&mut conn, &ctx, actions).await {
+                        break;
+                    }
+                }
+                Wake::Timer => {
+                    let queued = rx.max_capacity() - rx.capacity();
+                    if queued > rx.max_capacity() / 2 {
+                        warn!("Session {} has {} events queued", session_id, queued);
+                    }
+                    let actions = conn.adapter.on_timer(&ctx);
+                    if !self.perform(&mut conn, &ctx, actions).await {
+                        break;
+                    }
+                }
+                Wake::Stop => {
+                    // reports of what the engine already did still go out
+                    while let Ok(EngineEvent::MatchEvent(me)) = rx.try_recv() {
+                        let actions = conn.adapter.on_event(&ctx, &me);
+                        if !self.perform(&mut conn, &ctx, actions).await {
+                            break;
                         }
                     }
+                    break;
                 }
             }
-        });
+            if let Some(mut session) = self.session_map.get_mut(&session_id) {
+                session.received = conn.received;
+                session.sent = conn.sent;
+                session.seq_nums = conn.adapter.seq_nums();
+            }
+        }
+        conn.half.shutdown().await
+    }
 
-        tokio::spawn(async move {
-            // process events, convert to report and send to client
-            while let Some(event) = rx.recv().await {
-                match event {
-                    EngineEvent::MatchEvent(me) => {
-                        info!("Sending Match Event to client {}: {:?}", addr, me);
-                        match me.status {
-                            OrderStatus::OrderEd
-                            | OrderStatus::CancelEd
-                            | OrderStatus::PartCancel => {
-                                //Confirm
-                            }
-                            //
-                            OrderStatus::PartTrade | OrderStatus::TradeEd => {
-                                //report
-                                let report = SseBinaryBodyEnum::Report(Report::from(&me));
-                                let sse_binary = SseBinary {
-                                    msg_type: 103,
-                                    msg_seq_num: 1,
-                                    msg_body_len: 0,
-                                    body: report,
-                                    checksum: 0,
-                                };
-                                let mut buf = BytesMut::new();
-                                sse_binary.encode(&mut buf);
-                                if let Some(session_ref) = session_map.get(&me.session_id) {
-                                    let mut w = session_ref.writer.lock().await;
-                                    info!("Writing to client {}: {:?}", me.session_id, &buf[..]);
-                                    if let Err(e) = w.write_all(&buf).await {
-                                        error!(
-                                            "Failed to write to client {}: {}",
-                                            me.session_id, e
-                                        );
-                                    }
-                                    w.flush().await;
-                                } else {
-                                    info!(
-                                        "Session {} not found, maybe disconnected",
-                                        me.session_id
-                                    );
-                                }
-                            }
-                            _ => {
-                                warn!("Unknown order status: {:?}", me.status);
-                            }
+    // Carries out what the adapter asked for, false once the session is to
+    // be closed.
+    async fn perform(
+        &self,
+        conn: &mut Connection<A>,
+        ctx: &SessionContext<'_>,
+        actions: Vec<Inbound<A::Message>>,
+    ) -> bool {
+        let mut actions = VecDeque::from(actions);
+        let mut open = true;
+        while let Some(action) = actions.pop_front() {
+            match action {
+                Inbound::Command(cmd) => {
+                    if let Some(throttle) = &mut conn.throttle
+                        && let Some(rb_cmd) = cmd.rb_cmd()
+                        && !throttle.admit().await
+                    {
+                        info!("Throttled client {}: {:?}", conn.session_id, cmd);
+                        for reply in conn.adapter.on_throttled(ctx, rb_cmd).into_iter().rev() {
+                            actions.push_front(reply);
                         }
+                        continue;
                     }
+                    // an engine that is behind gets the throttle's answer,
+                    // a reject or the wait, waiting without a throttle
+                    let cmd = match self.cmd_tx.try_send(cmd) {
+                        Ok(()) => continue,
+                        Err(TrySendError::Full(cmd)) => cmd,
+                        Err(TrySendError::Closed(_)) => {
+                            error!("Engine gone, dropping client {}", conn.session_id);
+                            open = false;
+                            break;
+                        }
+                    };
+                    if let Some(throttle) = &conn.throttle
+                        && throttle.config.action == ThrottleAction::Reject
+                        && let Some(rb_cmd) = cmd.rb_cmd()
+                    {
+                        info!(
+                            "Engine behind, rejected client {}: {:?}",
+                            conn.session_id, cmd
+                        );
+                        for reply in conn.adapter.on_throttled(ctx, rb_cmd).into_iter().rev() {
+                            actions.push_front(reply);
+                        }
+                        continue;
+                    }
+                    if self.cmd_tx.send(cmd).await.is_err() {
+                        error!("Engine gone, dropping client {}", conn.session_id);
+                        open = false;
+                        break;
+                    }
+                }
+                Inbound::Reply(msg) => {
+                    conn.sent += 1;
+                    conn.frames.push(&msg);
+                }
+                Inbound::Disconnect => {
+                    open = false;
+                    break;
                 }
             }
-        });
+        }
+        match conn.flush().await {
+            Ok(written) => {
+                self.metrics
+                    .bytes_sent
+                    .fetch_add(written as u64, Ordering::Relaxed);
+                open
+            }
+            Err(e) => {
+                error!("Failed to write to client {}: {}", conn.session_id, e);
+                false
+            }
+        }
+    }
+}
 
-        Ok(())
+fn depth<T>(tx: &Sender<T>) -> usize {
+    tx.max_capacity() - tx.capacity()
+}
+
+// What woke a session up.
+enum Wake {
+    Read(usize),
+    Event(EngineEvent),
+    Timer,
+    Stop,
+}
+
+// One client connection: the write half with the frames waiting to go out,
+// the protocol state and the flow control.
+struct Connection<A: ProtocolAdapter> {
+    session_id: u64,
+    half: OwnedWriteHalf,
+    frames: FrameEncoder<A::Encoder>,
+    adapter: A,
+    throttle: Option<Throttle>,
+    // messages in and out so far
+    received: u64,
+    sent: u64,
+}
+
+impl<A: ProtocolAdapter> Connection<A> {
+    // bytes written
+    async fn flush(&mut self) -> Result<usize, Error> {
+        if self.frames.is_empty() {
+            return Ok(0);
+        }
+        let buf = self.frames.take();
+        trace!("Writing to client {}: {:?}", self.session_id, &buf[..]);
+        self.half.write_all(&buf).await?;
+        self.half.flush().await?;
+        Ok(buf.len())
+    }
+}
+
+// Token bucket holding up to a second's worth of commands.
+struct Throttle {
+    config: ThrottleConfig,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    fn new(config: ThrottleConfig) -> Self {
+        Self {
+            config,
+            tokens: config.rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    // Takes a token, or tells how long until there is one.
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let rate = self.config.rate as f64;
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    // Whether the next command may go, after waiting for it if the throttle
+    // delays.
+    async fn admit(&mut self) -> bool {
+        loop {
+            match self.take(Instant::now()) {
+                Ok(()) => return true,
+                Err(_) if self.config.action == ThrottleAction::Reject => return false,
+                Err(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_refills_at_rate() {
+        let mut throttle = Throttle::new(ThrottleConfig {
+            rate: 4,
+            action: ThrottleAction::Reject,
+        });
+        let start = throttle.last;
+        for _ in 0..4 {
+            assert_eq!(throttle.take(start), Ok(()));
+        }
+        assert_eq!(throttle.take(start), Err(Duration::from_millis(250)));
+        assert_eq!(throttle.take(start + Duration::from_millis(250)), Ok(()));
+        // idle time never saves up more than a second's worth
+        let later = start + Duration::from_secs(10);
+        for _ in 0..4 {
+            assert_eq!(throttle.take(later), Ok(()));
+        }
+        assert!(throttle.take(later).is_err());
     }
 }
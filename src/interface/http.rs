@@ -0,0 +1,185 @@
+//! Just enough HTTP/1.1 for the admin and metrics ports: one request per
+//! connection, answered and closed.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, warn};
+
+// head and body of a request together
+const MAX_REQUEST: usize = 64 * 1024;
+// for the whole request to arrive
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+// pause after a failed accept
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    /// of the query string
+    pub params: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The path split at its slashes.
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.trim_matches('/').split('/').collect()
+    }
+}
+
+pub(crate) struct Response {
+    pub status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn json(status: u16, body: &Value) -> Self {
+        Self::new(status, "application/json", body.to_string())
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            503 => "Service Unavailable",
+            _ => "Error",
+        }
+    }
+}
+
+/// Listens on `addr` and answers every request with `handle`, returns where
+/// it listens. `name` goes into the logs.
+pub(crate) async fn serve<F, Fut>(
+    addr: SocketAddr,
+    name: &'static str,
+    handle: F,
+) -> std::io::Result<SocketAddr>
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!("{} interface on http://{}", name, local_addr);
+    let handle = Arc::new(handle);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("{} accept failed: {}", name, e);
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let handle = handle.clone();
+            tokio::spawn(async move {
+                let response =
+                    match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+                        Ok(Ok(request)) => handle(request).await,
+                        Ok(Err(e)) => Response::new(400, "text/plain", e.to_string()),
+                        Err(_) => Response::new(400, "text/plain", "request timed out".into()),
+                    };
+                if let Err(e) = write_response(&mut stream, &response).await {
+                    warn!("{} request from {} failed: {}", name, peer, e);
+                }
+            });
+        }
+    });
+    Ok(local_addr)
+}
+
+async fn write_response(stream: &mut TcpStream, response: &Response) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// The request line, the headers up to the blank line, and as much body as
+// Content-Length says.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Request> {
+    let bad = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+    let mut buf = Vec::with_capacity(1024);
+    let head_end = loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if buf.len() > MAX_REQUEST {
+            return Err(bad("request too large"));
+        }
+        let mut chunk = [0u8; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(bad("connection closed mid request"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = std::str::from_utf8(&buf[..head_end]).map_err(|_| bad("head is not UTF-8"))?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(bad("bad request line"));
+    };
+    let mut content_length = 0;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length")
+        {
+            content_length = value
+                .trim()
+                .parse()
+                .map_err(|_| bad("bad Content-Length"))?;
+        }
+    }
+    if (head_end + 4)
+        .checked_add(content_length)
+        .is_none_or(|len| len > MAX_REQUEST)
+    {
+        return Err(bad("request too large"));
+    }
+    let mut body = buf[head_end + 4..].to_vec();
+    let have = body.len();
+    if have < content_length {
+        body.resize(content_length, 0);
+        stream.read_exact(&mut body[have..]).await?;
+    }
+    body.truncate(content_length);
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        params,
+        body,
+    })
+}
@@ -0,0 +1,147 @@
+//! Prometheus scrape endpoint of one app: `GET /metrics` in the text
+//! format, counters of the books, rejects, command latency, the command
+//! queue, and sessions and traffic of each channel.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use tokio::sync::mpsc::Sender;
+
+use crate::engine::metrics::EngineMetrics;
+use crate::interface::channel::ChannelMonitor;
+use crate::interface::http::{self, Response};
+use crate::types::EngineCommand;
+use crate::utils::metrics::Exposition;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Serves the metrics of one engine and its channels.
+pub struct MetricsServer {
+    addr: SocketAddr,
+    cmd_tx: Sender<EngineCommand>,
+    engine: Option<Arc<EngineMetrics>>,
+    channels: Vec<(String, Arc<dyn ChannelMonitor>)>,
+}
+
+impl MetricsServer {
+    /// The depth of the command queue is read off `cmd_tx`.
+    pub fn new(addr: SocketAddr, cmd_tx: Sender<EngineCommand>) -> Self {
+        Self {
+            addr,
+            cmd_tx,
+            engine: None,
+            channels: vec![],
+        }
+    }
+
+    /// Reports what the engines counted into `metrics`.
+    pub fn with_engine(mut self, metrics: Arc<EngineMetrics>) -> Self {
+        self.engine = Some(metrics);
+        self
+    }
+
+    /// Reports the sessions and traffic of `channel` under `name`.
+    pub fn with_channel(mut self, name: &str, channel: Arc<dyn ChannelMonitor>) -> Self {
+        self.channels.push((name.to_string(), channel));
+        self
+    }
+
+    /// Starts listening and serving, returns where it listens.
+    pub async fn start(self) -> std::io::Result<SocketAddr> {
+        let server = Arc::new(self);
+        http::serve(server.addr, "Metrics", move |request| {
+            let server = server.clone();
+            async move {
+                match (request.method.as_str(), request.path.as_str()) {
+                    ("GET", "/metrics") => Response::new(200, CONTENT_TYPE, server.render()),
+                    _ => Response::new(404, "text/plain", "not found".into()),
+                }
+            }
+        })
+        .await
+    }
+
+    /// The whole exposition as of now.
+    pub fn render(&self) -> String {
+        let mut out = Exposition::default();
+        if let Some(engine) = &self.engine {
+            engine.write(&mut out);
+        }
+        out.family(
+            "matcher_command_queue_depth",
+            "gauge",
+            "Commands waiting for the engine.",
+        )
+        .sample(
+            "matcher_command_queue_depth",
+            &[],
+            self.cmd_tx.max_capacity() - self.cmd_tx.capacity(),
+        );
+
+        let queues: Vec<_> = self
+            .channels
+            .iter()
+            .map(|(name, channel)| (name.as_str(), channel.queue_depths().sessions))
+            .collect();
+        out.family(
+            "matcher_channel_queued_events",
+            "gauge",
+            "Engine events not yet routed to their session.",
+        );
+        for (name, channel) in &self.channels {
+            out.sample(
+                "matcher_channel_queued_events",
+                &[("channel", name)],
+                channel.queue_depths().events,
+            );
+        }
+        out.family("matcher_sessions", "gauge", "Connected sessions.");
+        for (name, sessions) in &queues {
+            out.sample("matcher_sessions", &[("channel", name)], sessions.len());
+        }
+        out.family(
+            "matcher_session_queued_events",
+            "gauge",
+            "Events waiting for the sessions of a channel.",
+        );
+        for (name, sessions) in &queues {
+            let queued: usize = sessions.iter().map(|(_, depth)| depth).sum();
+            out.sample(
+                "matcher_session_queued_events",
+                &[("channel", name)],
+                queued,
+            );
+        }
+        out.family(
+            "matcher_session_queued_events_max",
+            "gauge",
+            "Events waiting for the furthest behind session of a channel.",
+        );
+        for (name, sessions) in &queues {
+            let max = sessions.iter().map(|(_, depth)| *depth).max().unwrap_or(0);
+            out.sample(
+                "matcher_session_queued_events_max",
+                &[("channel", name)],
+                max,
+            );
+        }
+
+        let families = [
+            ("matcher_bytes_received_total", "Bytes read from clients."),
+            ("matcher_bytes_sent_total", "Bytes written to clients."),
+            (
+                "matcher_decode_errors_total",
+                "Frames that could not be decoded.",
+            ),
+        ];
+        for (i, (family, help)) in families.into_iter().enumerate() {
+            out.family(family, "counter", help);
+            for (name, channel) in &self.channels {
+                let value = channel.metrics().counters()[i].load(Ordering::Relaxed);
+                out.sample(family, &[("channel", name)], value);
+            }
+        }
+        out.finish()
+    }
+}
@@ -1 +1,4 @@
+pub mod admin;
 pub mod channel;
+mod http;
+pub mod metrics;
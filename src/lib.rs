@@ -1,6 +1,13 @@
+pub mod config;
 pub mod engine;
 pub mod interface;
+pub mod market;
+pub mod match_policy;
 pub mod order_book;
 pub mod order_bucket;
+pub mod order_slab;
 pub mod protocol;
+pub mod simulator;
+pub mod testcase;
 pub mod types;
+pub mod utils;
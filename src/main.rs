@@ -1,31 +1,315 @@
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 
+use anyhow::bail;
 use exchange_matcher::{
-    engine::match_engine::MatchEngine,
-    interface::channel::{AcceptorChannel, TcpAcceptorChannel},
+    config::{AppConfig, ChannelConfig, ChannelType, EngineType, Market, MatchAppConfig},
+    engine::{
+        breaker::CircuitBreaker,
+        match_engine::{DEFAULT_QUEUE_CAPACITY, MatchEngine},
+        metrics::EngineMetrics,
+        pipeline::MatchPipeline,
+        query::{DEFAULT_QUERY_CAPACITY, QueryRequest, query_channel},
+        shard::ShardedEngine,
+    },
+    interface::{
+        admin::AdminServer,
+        channel::{
+            AcceptorChannel, ChannelIds, ChannelMonitor, SessionLimits, TcpAcceptorChannel,
+            fan_out_events,
+        },
+        metrics::MetricsServer,
+    },
+    market::publisher::UdpMarketPublisher,
+    match_policy::MatchPolicy,
+    protocol::{fix::FixAdapter, sse::SseAdapter, szse::SzseAdapter},
+    simulator::{
+        auto_simulator::AutoSimulator,
+        order_flow::OrderFlowGenerator,
+        replay::{HistoricalReplay, QueuePositionPolicy},
+    },
+    types::{EngineCommand, EngineEvent, L1MarketData},
 };
-use tracing::info;
-use tracing_subscriber;
+use tokio::sync::mpsc::{Receiver, UnboundedReceiver, UnboundedSender};
+use tracing::{error, info, warn};
+
+const DEFAULT_CONFIG: &str = "config/match_app.toml";
+
+// closes a channel's sessions once awaited
+type Stop = Pin<Box<dyn Future<Output = std::io::Result<()>> + Send>>;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
-    let (cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_CONFIG.to_string());
+    let config = MatchAppConfig::load(&path)?;
+    info!("Loaded {} app(s) from {}", config.apps.len(), path);
+
+    let mut started = 0;
+    let mut stops = vec![];
+    for app in &config.apps {
+        match start_app(app).await {
+            Ok(app_stops) => {
+                started += 1;
+                stops.extend(app_stops);
+            }
+            Err(e) => error!("{}: not started: {:#}", app.name, e),
+        }
+    }
+    if started == 0 {
+        bail!("no app could be started");
+    }
+
+    tokio::signal::ctrl_c().await?;
+    info!("Shutting down...");
+    drain(stops).await;
+    Ok(())
+}
+
+// one engine plus its trading and market data channels
+async fn start_app(app: &AppConfig) -> anyhow::Result<Vec<Stop>> {
+    let capacity = app.engine.queue_capacity.unwrap_or(DEFAULT_QUEUE_CAPACITY);
+    let (cmd_tx, cmd_rx) = tokio::sync::mpsc::channel(capacity);
+    // unbounded as the engine never waits for a client: events are only
+    // offered to the bounded queues of the sessions, see the channels'
+    // events_queued gauge
     let (event_tx, event_rx) = tokio::sync::mpsc::unbounded_channel();
+    let md_tx = match app.channel(ChannelType::MarketData) {
+        Some(market_data) => {
+            let (md_tx, md_rx) = tokio::sync::mpsc::unbounded_channel();
+            UdpMarketPublisher::bind(market_data.endpoint.addr)
+                .await?
+                .start(md_rx);
+            Some(md_tx)
+        }
+        None => None,
+    };
 
-    let engine = Arc::new(tokio::sync::Mutex::new(MatchEngine::new(cmd_rx, event_tx)));
-    {
-        let engine_clone = engine.clone();
-        tokio::spawn(async move {
-            engine_clone.lock().await.start().await;
-        });
+    // only the admin interface asks the engine anything
+    let (queries, query_rx) = match app.channel(ChannelType::Admin) {
+        Some(_) => {
+            let (client, query_rx) = query_channel(DEFAULT_QUERY_CAPACITY);
+            (Some(client), Some(query_rx))
+        }
+        None => (None, None),
+    };
+    let metrics = app
+        .channel(ChannelType::Metrics)
+        .map(|_| Arc::new(EngineMetrics::default()));
+    let engine = EngineOutputs {
+        event_tx,
+        md_tx,
+        query_rx,
+        metrics: metrics.clone(),
+    };
+    match &app.replay {
+        Some(replay) => {
+            let history = HistoricalReplay::load(&replay.path)?;
+            let policy = QueuePositionPolicy::new(replay.queue_position);
+            spawn_engine(app, cmd_rx, engine, |cmd_rx, event_tx| {
+                MatchEngine::with_policy(cmd_rx, event_tx, policy)
+            })?;
+            history.start(cmd_tx.clone(), replay.speed);
+        }
+        None => spawn_engine(app, cmd_rx, engine, MatchEngine::new)?,
     }
-    info!("Match engine started.");
 
-    let mut channel = TcpAcceptorChannel::new(9010, cmd_tx);
-    let _ = channel.start(event_rx).await;
+    if let Some(flow) = &app.flow {
+        OrderFlowGenerator::new(flow.clone()).start(cmd_tx.clone());
+    }
 
-    tokio::signal::ctrl_c().await.unwrap();
-    info!("Shutting down...");
+    let trading = app
+        .channel(ChannelType::Trading)
+        .expect("validated config has a trading channel");
+    let ids = Arc::new(ChannelIds::default());
+    let (trading_tx, trading_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut channels = vec![trading_tx];
+    let mut monitors: Vec<(&str, Arc<dyn ChannelMonitor>)> = vec![];
+    let mut stops = vec![];
+    // once a channel accepts orders, a later failure must not leave it
+    // running undrained
+    let started = async {
+        if let Some(fix) = app.channel(ChannelType::Fix) {
+            let (fix_tx, fix_rx) = tokio::sync::mpsc::unbounded_channel();
+            let channel = TcpAcceptorChannel::<FixAdapter>::with_limits(
+                fix.endpoint.addr,
+                cmd_tx.clone(),
+                ids.clone(),
+                limits(fix),
+            );
+            monitors.push(("fix", channel.clone()));
+            stops.push(start_channel(channel, fix_rx).await?);
+            channels.push(fix_tx);
+        }
+        let addr = trading.endpoint.addr;
+        let limits = limits(trading);
+        let stop = match app.engine.symbol {
+            Market::Sse => {
+                let channel = TcpAcceptorChannel::<SseAdapter>::with_limits(
+                    addr,
+                    cmd_tx.clone(),
+                    ids.clone(),
+                    limits,
+                );
+                monitors.push(("trading", channel.clone()));
+                start_channel(channel, trading_rx).await?
+            }
+            Market::Szse => {
+                let channel = TcpAcceptorChannel::<SzseAdapter>::with_limits(
+                    addr,
+                    cmd_tx.clone(),
+                    ids.clone(),
+                    limits,
+                );
+                monitors.push(("trading", channel.clone()));
+                start_channel(channel, trading_rx).await?
+            }
+        };
+        stops.push(stop);
+        fan_out_events(event_rx, channels);
+
+        if let (Some(channel), Some(metrics)) = (app.channel(ChannelType::Metrics), metrics) {
+            let mut server =
+                MetricsServer::new(channel.endpoint.addr, cmd_tx.clone()).with_engine(metrics);
+            for (name, channel) in &monitors {
+                server = server.with_channel(name, channel.clone());
+            }
+            server.start().await?;
+        }
+        if let (Some(admin), Some(queries)) = (app.channel(ChannelType::Admin), queries) {
+            let mut server = AdminServer::new(admin.endpoint.addr, cmd_tx, queries, ids);
+            for (name, channel) in monitors {
+                server = server.with_channel(name, channel);
+            }
+            server.start().await?;
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(e) = started {
+        drain(stops).await;
+        return Err(e);
+    }
+    Ok(stops)
+}
+
+async fn start_channel<C: AcceptorChannel + Send + Sync + 'static>(
+    channel: Arc<C>,
+    event_rx: UnboundedReceiver<EngineEvent>,
+) -> std::io::Result<Stop> {
+    channel.clone().start(event_rx).await?;
+    Ok(Box::pin(async move { channel.stop().await }))
+}
+
+async fn drain(stops: Vec<Stop>) {
+    for stop in stops {
+        if let Err(e) = stop.await {
+            warn!("Channel not drained: {}", e);
+        }
+    }
+}
+
+fn limits(channel: &ChannelConfig) -> SessionLimits {
+    let mut limits = SessionLimits::default();
+    if let Some(max) = channel.max_connections {
+        limits.max_connections = max;
+    }
+    if let Some(capacity) = channel.queue_capacity {
+        limits.queue_capacity = capacity;
+    }
+    limits.throttle = channel.throttle;
+    limits
+}
+
+// where an engine's events, book snapshots and metrics go, and where its
+// queries come from
+struct EngineOutputs {
+    event_tx: UnboundedSender<EngineEvent>,
+    md_tx: Option<UnboundedSender<L1MarketData>>,
+    query_rx: Option<Receiver<QueryRequest>>,
+    metrics: Option<Arc<EngineMetrics>>,
+}
+
+// `new_engine` makes the engine, or each shard of it
+fn spawn_engine<P: MatchPolicy + 'static>(
+    app: &AppConfig,
+    cmd_rx: Receiver<EngineCommand>,
+    outputs: EngineOutputs,
+    new_engine: impl Fn(Receiver<EngineCommand>, UnboundedSender<EngineEvent>) -> MatchEngine<P>,
+) -> anyhow::Result<()> {
+    if app.engine.engine_type != EngineType::Auto && !app.engine.rules.is_empty() {
+        warn!("{}: rules only apply to auto engines, ignored", app.name);
+    }
+    let EngineOutputs {
+        event_tx,
+        md_tx,
+        query_rx,
+        metrics,
+    } = outputs;
+    // shared by the shards, so it sees every constituent
+    let breaker = app
+        .engine
+        .circuit_breaker
+        .clone()
+        .map(|config| Arc::new(CircuitBreaker::new(config)));
+    let new_engine = |cmd_rx: Receiver<EngineCommand>| {
+        let mut engine = new_engine(cmd_rx, event_tx.clone()).with_halts(app.engine.halt);
+        if let Some(volatility) = app.engine.volatility {
+            engine = engine.with_volatility(volatility);
+        }
+        if let Some(metrics) = &metrics {
+            engine = engine.with_metrics(metrics.clone());
+        }
+        if let Some(breaker) = &breaker {
+            engine = engine.with_circuit_breaker(breaker.clone());
+        }
+        match app.engine.engine_type {
+            EngineType::Auto => {
+                engine.with_auto_simulator(AutoSimulator::new(app.engine.rules.clone()))
+            }
+            EngineType::Match => engine,
+        }
+    };
+
+    if let Some(shards) = app.engine.shards {
+        let capacity = app.engine.queue_capacity.unwrap_or(DEFAULT_QUEUE_CAPACITY);
+        let mut engine = ShardedEngine::new(cmd_rx, event_tx.clone(), shards, capacity, new_engine);
+        if let Some(md_tx) = md_tx {
+            engine = engine.with_market_data(md_tx);
+        }
+        if let Some(query_rx) = query_rx {
+            engine = engine.with_queries(query_rx);
+        }
+        tokio::spawn(engine.start());
+        info!("{}: match engine started.", app.name);
+        return Ok(());
+    }
+
+    let mut engine = new_engine(cmd_rx);
+    if let Some(query_rx) = query_rx {
+        engine = engine.with_queries(query_rx);
+    }
+    if let Some(config) = &app.engine.pipeline {
+        let mut pipeline = MatchPipeline::new(engine, config.clone()).with_reports(event_tx);
+        if let Some(md_tx) = md_tx {
+            pipeline = pipeline.with_market_data(md_tx);
+        }
+        if let Some(path) = &config.journal {
+            pipeline = pipeline.with_journal(path)?;
+        }
+        // the threads end with the command queue
+        pipeline.start()?;
+        info!("{}: match pipeline started.", app.name);
+        return Ok(());
+    }
+
+    if let Some(md_tx) = md_tx {
+        engine = engine.with_market_data(md_tx);
+    }
+    tokio::spawn(async move { engine.start().await });
+    info!("{}: match engine started.", app.name);
     Ok(())
 }
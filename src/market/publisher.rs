@@ -1,4 +1,111 @@
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::{BufMut, BytesMut};
+use dashmap::DashMap;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{info, warn};
+
+use crate::types::L1MarketData;
+
 pub trait MarketPublisher {
-    fn publish_trade(&self, trade: &TradeReport);
-    fn publish_snapshot(&self, snapshot: &OrderBookSnapshot);
+    fn publish_snapshot(&self, snapshot: &L1MarketData);
+}
+
+/// Message type of an encoded L1 snapshot.
+pub const L1_SNAPSHOT_MSG_TYPE: u32 = 1;
+
+/// Encodes a snapshot, all integers big-endian:
+///
+/// | field        | type                        |
+/// |--------------|-----------------------------|
+/// | msg_type     | u32, always 1               |
+/// | security_id  | 8 bytes ascii, 0 padded     |
+/// | timestamp    | i64, ms since epoch         |
+/// | new_price    | i64                         |
+/// | buy_size     | u8                          |
+/// | buy levels   | buy_size x (i64 px, i64 qty) |
+/// | sell_size    | u8                          |
+/// | sell levels  | sell_size x (i64 px, i64 qty)|
+pub fn encode_snapshot(snapshot: &L1MarketData, buf: &mut BytesMut) {
+    buf.put_u32(L1_SNAPSHOT_MSG_TYPE);
+    let mut security_id = [0u8; 8];
+    let len = snapshot.security_id.len().min(8);
+    security_id[..len].copy_from_slice(&snapshot.security_id.as_bytes()[..len]);
+    buf.put_slice(&security_id);
+    buf.put_i64(snapshot.timestamp);
+    buf.put_i64(snapshot.new_price);
+    buf.put_u8(snapshot.buy_size as u8);
+    for i in 0..snapshot.buy_size {
+        buf.put_i64(snapshot.buy_prices[i]);
+        buf.put_i64(snapshot.buy_volumes[i]);
+    }
+    buf.put_u8(snapshot.sell_size as u8);
+    for i in 0..snapshot.sell_size {
+        buf.put_i64(snapshot.sell_prices[i]);
+        buf.put_i64(snapshot.sell_volumes[i]);
+    }
+}
+
+/// Sends L1 snapshots over UDP. A client subscribes by sending any datagram
+/// to the publisher's endpoint and gets every snapshot from then on.
+pub struct UdpMarketPublisher {
+    socket: UdpSocket,
+    subscribers: DashMap<SocketAddr, ()>,
+}
+
+impl UdpMarketPublisher {
+    pub async fn bind(addr: SocketAddr) -> Result<Arc<Self>, Error> {
+        let socket = UdpSocket::bind(addr).await?;
+        info!("Market data on udp://{}", socket.local_addr()?);
+        Ok(Arc::new(Self {
+            socket,
+            subscribers: DashMap::new(),
+        }))
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.socket.local_addr()
+    }
+
+    /// Accepts subscriptions and forwards every snapshot from `md_rx`.
+    pub fn start(self: Arc<Self>, mut md_rx: UnboundedReceiver<L1MarketData>) {
+        let publisher = self.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            loop {
+                match publisher.socket.recv_from(&mut buf).await {
+                    Ok((_, addr)) => {
+                        if publisher.subscribers.insert(addr, ()).is_none() {
+                            info!("Market data subscriber {}", addr);
+                        }
+                    }
+                    Err(e) => warn!("Market data receive error: {}", e),
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            while let Some(snapshot) = md_rx.recv().await {
+                self.publish_snapshot(&snapshot);
+            }
+        });
+    }
+}
+
+impl MarketPublisher for UdpMarketPublisher {
+    fn publish_snapshot(&self, snapshot: &L1MarketData) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let mut buf = BytesMut::new();
+        encode_snapshot(snapshot, &mut buf);
+        for subscriber in self.subscribers.iter() {
+            if let Err(e) = self.socket.try_send_to(&buf, *subscriber.key()) {
+                warn!("Market data send to {} failed: {}", subscriber.key(), e);
+            }
+        }
+    }
 }
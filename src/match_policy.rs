@@ -0,0 +1,328 @@
+use std::fmt::Debug;
+
+/// A resting order as seen by a matching policy: its id, the session that
+/// entered it and its untraded volume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestingOrder {
+    pub oid: i64,
+    pub session_id: u64,
+    pub remaining: i64,
+}
+
+/// Volume given to one resting order by a matching policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allocation {
+    pub oid: i64,
+    pub volume: i64,
+}
+
+/// Decides how an incoming order's volume is shared among the resting orders
+/// of a single price level. Price priority between levels is handled by the
+/// `OrderBook`; a policy only sees one level at a time.
+pub trait MatchPolicy: Debug + Clone + Send {
+    /// Allocates up to `volume` among `level`, given in time priority.
+    ///
+    /// `level_volume` is the total remaining volume of the level and
+    /// `top_order` the order that set the level as the new best price, if it
+    /// is still resting. Allocations are appended to `fills` in execution
+    /// order and must never exceed an order's remaining volume.
+    fn allocate<I>(
+        &self,
+        level: I,
+        level_volume: i64,
+        top_order: Option<RestingOrder>,
+        volume: i64,
+        fills: &mut Vec<Allocation>,
+    ) where
+        I: Iterator<Item = RestingOrder> + Clone;
+}
+
+/// Classic price-time priority: first in, first filled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PriceTimePolicy;
+
+impl MatchPolicy for PriceTimePolicy {
+    fn allocate<I>(
+        &self,
+        level: I,
+        _level_volume: i64,
+        _top_order: Option<RestingOrder>,
+        mut volume: i64,
+        fills: &mut Vec<Allocation>,
+    ) where
+        I: Iterator<Item = RestingOrder> + Clone,
+    {
+        for order in level {
+            if volume <= 0 {
+                break;
+            }
+            let traded = volume.min(order.remaining);
+            if traded <= 0 {
+                continue;
+            }
+            fills.push(Allocation {
+                oid: order.oid,
+                volume: traded,
+            });
+            volume -= traded;
+        }
+    }
+}
+
+/// Pro-rata allocation: every resting order receives a share proportional to
+/// its remaining volume.
+///
+/// Shares are rounded down to a multiple of `round_lot`; shares below
+/// `min_allocation` are dropped. Whatever is left after the proportional pass
+/// is handed out in time priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProRataPolicy {
+    pub min_allocation: i64,
+    pub round_lot: i64,
+}
+
+impl Default for ProRataPolicy {
+    fn default() -> Self {
+        Self {
+            min_allocation: 1,
+            round_lot: 1,
+        }
+    }
+}
+
+impl ProRataPolicy {
+    pub fn new(min_allocation: i64, round_lot: i64) -> Self {
+        Self {
+            min_allocation: min_allocation.max(1),
+            round_lot: round_lot.max(1),
+        }
+    }
+
+    fn share(&self, remaining: i64, level_volume: i64, volume: i64) -> i64 {
+        let raw = (remaining as i128 * volume as i128 / level_volume as i128) as i64;
+        let rounded = raw - raw % self.round_lot;
+        if rounded < self.min_allocation {
+            0
+        } else {
+            rounded.min(remaining)
+        }
+    }
+}
+
+impl MatchPolicy for ProRataPolicy {
+    fn allocate<I>(
+        &self,
+        level: I,
+        level_volume: i64,
+        top_order: Option<RestingOrder>,
+        volume: i64,
+        fills: &mut Vec<Allocation>,
+    ) where
+        I: Iterator<Item = RestingOrder> + Clone,
+    {
+        if volume <= 0 || level_volume <= 0 {
+            return;
+        }
+        if volume >= level_volume {
+            // the whole level trades, nothing to share
+            return PriceTimePolicy.allocate(level, level_volume, top_order, volume, fills);
+        }
+
+        let start = fills.len();
+        let mut allocated = 0;
+        for order in level.clone() {
+            let share = self.share(order.remaining, level_volume, volume);
+            if share > 0 {
+                fills.push(Allocation {
+                    oid: order.oid,
+                    volume: share,
+                });
+                allocated += share;
+            }
+        }
+
+        // leftover from rounding goes out in time priority
+        let mut leftover = volume - allocated;
+        if leftover > 0 {
+            // pro-rata fills are in queue order, so walk them alongside the level
+            let end = fills.len();
+            let mut cursor = start;
+            for order in level {
+                if leftover == 0 {
+                    break;
+                }
+                let idx = if cursor < end && fills[cursor].oid == order.oid {
+                    cursor += 1;
+                    Some(cursor - 1)
+                } else {
+                    None
+                };
+                let already = idx.map(|i| fills[i].volume).unwrap_or(0);
+                let extra = leftover.min(order.remaining - already);
+                if extra <= 0 {
+                    continue;
+                }
+                match idx {
+                    Some(i) => fills[i].volume += extra,
+                    None => fills.push(Allocation {
+                        oid: order.oid,
+                        volume: extra,
+                    }),
+                }
+                leftover -= extra;
+            }
+        }
+    }
+}
+
+/// Gives the top order — the one that set the level as the new best price —
+/// priority up to `max_volume` (0 means unlimited), then hands the rest of the
+/// incoming volume to the `inner` policy.
+///
+/// With a `ProRataPolicy` inner this is the usual hybrid allocation: top order
+/// first, then pro-rata, then the rounding leftover in time priority.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TopOrderPolicy<P: MatchPolicy> {
+    pub inner: P,
+    pub max_volume: i64,
+}
+
+pub type HybridProRataPolicy = TopOrderPolicy<ProRataPolicy>;
+
+impl<P: MatchPolicy> TopOrderPolicy<P> {
+    pub fn new(inner: P, max_volume: i64) -> Self {
+        Self { inner, max_volume }
+    }
+}
+
+impl<P: MatchPolicy> MatchPolicy for TopOrderPolicy<P> {
+    fn allocate<I>(
+        &self,
+        level: I,
+        level_volume: i64,
+        top_order: Option<RestingOrder>,
+        volume: i64,
+        fills: &mut Vec<Allocation>,
+    ) where
+        I: Iterator<Item = RestingOrder> + Clone,
+    {
+        let Some(top) = top_order else {
+            return self
+                .inner
+                .allocate(level, level_volume, None, volume, fills);
+        };
+
+        let mut top_fill = volume.min(top.remaining);
+        if self.max_volume > 0 {
+            top_fill = top_fill.min(self.max_volume);
+        }
+        let top_idx = fills.len();
+        if top_fill > 0 {
+            fills.push(Allocation {
+                oid: top.oid,
+                volume: top_fill,
+            });
+        }
+
+        let rest = level.map(move |o| {
+            if o.oid == top.oid {
+                RestingOrder {
+                    remaining: o.remaining - top_fill,
+                    ..o
+                }
+            } else {
+                o
+            }
+        });
+        let inner_start = fills.len();
+        self.inner.allocate(
+            rest,
+            level_volume - top_fill,
+            None,
+            volume - top_fill,
+            fills,
+        );
+
+        // fold a second allocation to the top order into its first one
+        if top_fill > 0
+            && let Some(i) = fills[inner_start..].iter().position(|f| f.oid == top.oid)
+        {
+            let extra = fills.remove(inner_start + i);
+            fills[top_idx].volume += extra.volume;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(orders: &[(i64, i64)]) -> Vec<RestingOrder> {
+        orders
+            .iter()
+            .map(|&(oid, remaining)| RestingOrder {
+                oid,
+                session_id: 1,
+                remaining,
+            })
+            .collect()
+    }
+
+    fn run<P: MatchPolicy>(
+        policy: &P,
+        orders: &[(i64, i64)],
+        top: Option<i64>,
+        volume: i64,
+    ) -> Vec<(i64, i64)> {
+        let level = level(orders);
+        let total = level.iter().map(|o| o.remaining).sum();
+        let top = top.and_then(|oid| level.iter().copied().find(|o| o.oid == oid));
+        let mut fills = vec![];
+        policy.allocate(level.iter().copied(), total, top, volume, &mut fills);
+        fills.iter().map(|f| (f.oid, f.volume)).collect()
+    }
+
+    #[test]
+    fn test_price_time_fills_in_queue_order() {
+        let fills = run(&PriceTimePolicy, &[(1, 10), (2, 20), (3, 30)], None, 25);
+        assert_eq!(fills, vec![(1, 10), (2, 15)]);
+    }
+
+    #[test]
+    fn test_pro_rata_shares_by_size() {
+        let fills = run(&ProRataPolicy::default(), &[(1, 100), (2, 300)], None, 200);
+        assert_eq!(fills, vec![(1, 50), (2, 150)]);
+    }
+
+    #[test]
+    fn test_pro_rata_min_allocation_and_rounding() {
+        // raw shares 9 / 45 / 45: order 1 rounds to 5 and falls under the minimum
+        let policy = ProRataPolicy::new(10, 5);
+        let fills = run(&policy, &[(1, 22), (2, 100), (3, 100)], None, 100);
+        // 90 allocated pro-rata, 10 left over goes FIFO starting with order 1
+        assert_eq!(fills, vec![(2, 45), (3, 45), (1, 10)]);
+        assert_eq!(fills.iter().map(|f| f.1).sum::<i64>(), 100);
+    }
+
+    #[test]
+    fn test_pro_rata_whole_level() {
+        let fills = run(&ProRataPolicy::default(), &[(1, 10), (2, 20)], None, 50);
+        assert_eq!(fills, vec![(1, 10), (2, 20)]);
+    }
+
+    #[test]
+    fn test_top_order_priority() {
+        let policy = TopOrderPolicy::new(PriceTimePolicy, 0);
+        let fills = run(&policy, &[(1, 10), (2, 20), (3, 30)], Some(2), 25);
+        assert_eq!(fills, vec![(2, 20), (1, 5)]);
+    }
+
+    #[test]
+    fn test_top_order_capped_then_pro_rata() {
+        let policy = HybridProRataPolicy::new(ProRataPolicy::default(), 10);
+        let fills = run(&policy, &[(1, 50), (2, 50)], Some(1), 50);
+        // top gets 10, then 40 split over the remaining 40 + 50 with the
+        // rounding leftover going back to order 1 in time priority
+        assert_eq!(fills, vec![(1, 28), (2, 22)]);
+    }
+}
@@ -1,21 +1,39 @@
 use std::cmp::Ordering as CmpOrdering;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use chrono::Utc;
 
-use crate::order_bucket::{OrderBucket, OrderBucketImpl};
-use crate::types::{CmdResultCode, L1MarketData, MatchEvent, Order, OrderSide, OrderStatus, RbCmd};
+use crate::engine::metrics::SecurityMetrics;
+use crate::match_policy::{MatchPolicy, PriceTimePolicy};
+use crate::order_bucket::{OrderBucket, OrderBucketImpl, next_tid};
+use crate::order_slab::OrderSlab;
+use crate::types::{
+    BookDepth, CmdResultCode, DepthLevel, L1MarketData, MatchEvent, Order, OrderInfo, OrderSide,
+    OrderStatus, RbCmd, TimeInForce, TopOfBook, TradingPhase,
+};
 
 #[derive(Debug)]
-pub struct OrderBook {
+pub struct OrderBook<P: MatchPolicy = PriceTimePolicy> {
     security_id: String,
+    // how volume is shared within a price level
+    policy: P,
     // sell orders
     sell_buckets: BTreeMap<i64, OrderBucketImpl>,
     // buy orders
     buy_buckets: BTreeMap<RevPrice, OrderBucketImpl>,
-    // order cache
-    order_map: HashMap<i64, Order>,
+    // every resting order, linked into the level it rests at
+    orders: OrderSlab,
+    // price of the latest trade, 0 before the first one
+    last_price: i64,
+    // whether orders may come in, left to the engine to enforce
+    phase: TradingPhase,
+    // counted into if the engine keeps metrics
+    metrics: Option<Arc<SecurityMetrics>>,
+    // percent around the last price continuous trading stays within
+    volatility_band: Option<f64>,
 }
 
 // reverse price
@@ -34,60 +52,219 @@ impl Ord for RevPrice {
     }
 }
 
+// Matches `cmd` against the best level of `buckets` until it is filled or the
+// best price no longer crosses. Emptied levels are dropped as they go, so no
+// reference into the map outlives a removal.
+fn sweep<K: Ord, P: MatchPolicy>(
+    buckets: &mut BTreeMap<K, OrderBucketImpl>,
+    orders: &mut OrderSlab,
+    policy: &P,
+    cmd: &mut RbCmd,
+    mut t_volume: i64,
+    crosses: impl Fn(i64) -> bool,
+) -> i64 {
+    while t_volume < cmd.volume {
+        let Some(mut best) = buckets.first_entry() else {
+            break;
+        };
+        if !crosses(best.get().price()) {
+            break;
+        }
+        let bucket = best.get_mut();
+        let traded = bucket.match_orders(orders, policy, cmd.volume - t_volume, cmd);
+        t_volume += traded;
+        if bucket.total_volume() == 0 {
+            best.remove();
+        } else if traded == 0 {
+            // the policy gave nothing away at this level, don't spin on it
+            break;
+        }
+    }
+    t_volume
+}
+
 impl OrderBook {
     pub fn new(security_id: String) -> Self {
+        Self::with_policy(security_id, PriceTimePolicy)
+    }
+}
+
+impl<P: MatchPolicy> OrderBook<P> {
+    pub fn with_policy(security_id: String, policy: P) -> Self {
         Self {
             security_id,
+            policy,
             sell_buckets: BTreeMap::new(),
             buy_buckets: BTreeMap::new(),
-            order_map: HashMap::new(),
+            orders: OrderSlab::default(),
+            last_price: 0,
+            phase: TradingPhase::default(),
+            metrics: None,
+            volatility_band: None,
         }
     }
 
+    /// Counts orders, cancels and trades into `metrics`.
+    pub fn set_metrics(&mut self, metrics: Arc<SecurityMetrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Stops an order from trading further than `percent` from the last
+    /// price. One that would cross it interrupts continuous trading: the book
+    /// switches to `TradingPhase::Auction` with the rest of the order in it.
+    pub fn set_volatility_band(&mut self, percent: Option<f64>) {
+        self.volatility_band = percent;
+    }
+
     pub fn new_order(&mut self, cmd: &mut RbCmd) -> CmdResultCode {
-        if self.order_map.contains_key(&cmd.oid) {
+        if self.orders.contains(cmd.oid) {
             return CmdResultCode::DuplicateOrderId;
         }
+        self.enter(cmd, 0)
+    }
 
-        let mut t_volume = 0;
-        if cmd.side == OrderSide::Sell {
-            let mut sub_buckets: Vec<*mut OrderBucketImpl> = self
-                .buy_buckets
-                .range(..=RevPrice(cmd.price))
-                .map(|(_, b)| b as *const _ as *mut _)
-                .collect();
-            for b_ptr in sub_buckets {
-                let bucket = unsafe { &mut *b_ptr };
-                t_volume += bucket.match_orders(cmd.volume - t_volume, cmd, |order| {
-                    self.order_map.remove(&order.oid);
-                });
-                if bucket.total_volume() == 0 {
-                    let price = bucket.price();
-                    self.buy_buckets.remove(&RevPrice(price));
-                }
-                if t_volume == cmd.volume {
-                    break;
-                }
-            }
+    /// Enters `cmd` like `new_order`, but first trades it straight with
+    /// `counter`, a counterparty the engine makes up, at the counter's
+    /// price. The counter never meets resting orders, whatever of it `cmd`
+    /// doesn't take is dropped.
+    pub fn new_order_with_counter(
+        &mut self,
+        cmd: &mut RbCmd,
+        counter: &mut RbCmd,
+    ) -> CmdResultCode {
+        if self.orders.contains(cmd.oid) {
+            return CmdResultCode::DuplicateOrderId;
+        }
+        // not beyond the volatility band either
+        let limit = self.band_limit(cmd);
+        let within = match cmd.side {
+            OrderSide::Buy => counter.price <= limit,
+            OrderSide::Sell => counter.price >= limit,
+        };
+        let traded = if within {
+            counter.volume.min(cmd.volume)
         } else {
-            let mut sub_buckets: Vec<*mut OrderBucketImpl> = self
-                .sell_buckets
-                .range(..=cmd.price)
-                .map(|(_, b)| b as *const _ as *mut _)
-                .collect();
-            for b_ptr in sub_buckets {
-                let bucket = unsafe { &mut *b_ptr };
-                t_volume += bucket.match_orders(cmd.volume - t_volume, cmd, |order| {
-                    self.order_map.remove(&order.oid);
-                });
-                if bucket.total_volume() == 0 {
-                    let price = bucket.price();
-                    self.sell_buckets.remove(&price);
-                }
-                if t_volume == cmd.volume {
-                    break;
-                }
-            }
+            0
+        };
+        if traded > 0 {
+            let now = Utc::now().timestamp_millis();
+            let tid = next_tid();
+            let leaves = cmd.volume - traded;
+            cmd.match_event_list.push(MatchEvent {
+                session_id: cmd.session_id,
+                timestamp: now,
+                mid: cmd.mid,
+                oid: cmd.oid,
+                status: if leaves == 0 {
+                    OrderStatus::TradeEd
+                } else {
+                    OrderStatus::PartTrade
+                },
+                tid,
+                volume: traded,
+                price: counter.price,
+                cum_volume: traded,
+                leaves_volume: leaves,
+            });
+            let fill = self.counter_fill(counter, traded, tid, now);
+            counter.match_event_list.push(fill);
+        }
+        self.enter(cmd, traded)
+    }
+
+    /// Trades `counter`, a counterparty the engine makes up, with the
+    /// resting order `oid` alone, at the counter's price. Both sides' events
+    /// go to `counter`, whatever of it the order doesn't take is dropped.
+    pub fn fill_with_counter(&mut self, oid: i64, counter: &mut RbCmd) {
+        let Some(remaining) = self.orders.get(oid).map(|o| o.remaining()) else {
+            return;
+        };
+        let traded = remaining.min(counter.volume);
+        let now = Utc::now().timestamp_millis();
+        let tid = next_tid();
+        let Some(resting) = self.fill_resting(oid, traded, counter.price, tid, now) else {
+            return;
+        };
+        let fill = self.counter_fill(counter, traded, tid, now);
+        counter.match_event_list.push(fill);
+        counter.match_event_list.push(resting);
+    }
+
+    // the counter's side of a trade with it, which it leaves filled
+    fn counter_fill(&mut self, counter: &RbCmd, traded: i64, tid: i64, now: i64) -> MatchEvent {
+        self.last_price = counter.price;
+        if let Some(metrics) = &self.metrics {
+            metrics.trades.fetch_add(1, Ordering::Relaxed);
+            metrics
+                .traded_volume
+                .fetch_add(traded as u64, Ordering::Relaxed);
+        }
+        MatchEvent {
+            session_id: counter.session_id,
+            timestamp: now,
+            mid: counter.mid,
+            oid: counter.oid,
+            status: OrderStatus::TradeEd,
+            tid,
+            volume: traded,
+            price: counter.price,
+            cum_volume: traded,
+            leaves_volume: 0,
+        }
+    }
+
+    // matches `cmd`, of which `traded` already has, and rests what is left
+    fn enter(&mut self, cmd: &mut RbCmd, traded: i64) -> CmdResultCode {
+        let first_event = cmd.match_event_list.len();
+
+        // sweep the opposite side from its best level while prices cross,
+        // never beyond the volatility band
+        let limit = self.band_limit(cmd);
+        let t_volume = if cmd.side == OrderSide::Sell {
+            sweep(
+                &mut self.buy_buckets,
+                &mut self.orders,
+                &self.policy,
+                cmd,
+                traded,
+                |p| p >= limit,
+            )
+        } else {
+            sweep(
+                &mut self.sell_buckets,
+                &mut self.orders,
+                &self.policy,
+                cmd,
+                traded,
+                |p| p <= limit,
+            )
+        };
+
+        if t_volume > traded
+            && let Some(last_fill) = cmd.match_event_list.last()
+        {
+            self.last_price = last_fill.price;
+        }
+        if let Some(metrics) = &self.metrics {
+            // one event for each resting order filled
+            let trades = cmd.match_event_list[first_event..]
+                .iter()
+                .filter(|e| e.oid != cmd.oid)
+                .count();
+            metrics.orders.fetch_add(1, Ordering::Relaxed);
+            metrics.trades.fetch_add(trades as u64, Ordering::Relaxed);
+            metrics
+                .traded_volume
+                .fetch_add((t_volume - traded) as u64, Ordering::Relaxed);
+        }
+        if limit != cmd.price
+            && t_volume < cmd.volume
+            && cmd.time_in_force != TimeInForce::Ioc
+            && self.crosses(cmd.side, cmd.price)
+        {
+            // the rest would trade beyond the band, that of an IOC order is
+            // cancelled instead
+            self.phase = TradingPhase::Auction;
         }
 
         if t_volume == cmd.volume {
@@ -95,40 +272,253 @@ impl OrderBook {
             return CmdResultCode::Success;
         }
 
+        if cmd.time_in_force == TimeInForce::Ioc {
+            self.gen_ioc_cancel_event(cmd, t_volume);
+            return CmdResultCode::Success;
+        }
+
+        if t_volume == 0 {
+            //委托确认
+            self.gen_match_event(cmd, OrderStatus::OrderEd);
+        }
+        self.rest(cmd, t_volume);
+        CmdResultCode::Success
+    }
+
+    /// Puts `cmd` in the book without matching it, for auctions and halts,
+    /// the book may cross until `uncross`. An IOC order is cancelled
+    /// instead.
+    pub fn rest_order(&mut self, cmd: &mut RbCmd) -> CmdResultCode {
+        if self.orders.contains(cmd.oid) {
+            return CmdResultCode::DuplicateOrderId;
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.orders.fetch_add(1, Ordering::Relaxed);
+        }
+        if cmd.time_in_force == TimeInForce::Ioc {
+            self.gen_ioc_cancel_event(cmd, 0);
+            return CmdResultCode::Success;
+        }
+        self.gen_match_event(cmd, OrderStatus::OrderEd);
+        self.rest(cmd, 0);
+        CmdResultCode::Success
+    }
+
+    // the rest of `cmd` joins the back of its level
+    fn rest(&mut self, cmd: &RbCmd, t_volume: i64) {
         let order = Order {
             session_id: cmd.session_id,
             mid: cmd.mid,
             uid: cmd.uid,
-            security_id: self.security_id.clone(),
             side: cmd.side,
             price: cmd.price,
             volume: cmd.volume,
             tvolume: t_volume,
             oid: cmd.oid,
+            time_in_force: cmd.time_in_force,
             timestamp: Utc::now().timestamp_millis(),
         };
-
-        if t_volume == 0 {
-            //委托确认
-            self.gen_match_event(cmd, OrderStatus::OrderEd);
-        }
-        //增加到订单簿
         if cmd.side == OrderSide::Sell {
+            // a new level better than the current best makes this the top order
+            let improves = self
+                .sell_buckets
+                .keys()
+                .next()
+                .is_none_or(|&p| cmd.price < p);
             let bucket = self
                 .sell_buckets
                 .entry(cmd.price)
                 .or_insert_with(|| OrderBucketImpl::new(cmd.price));
-            bucket.put(order.clone());
+            bucket.put(&mut self.orders, order);
+            if improves {
+                bucket.set_top_order(cmd.oid);
+            }
         } else {
+            let improves = self
+                .buy_buckets
+                .values()
+                .next()
+                .is_none_or(|b| cmd.price > b.price());
             let bucket = self
                 .buy_buckets
                 .entry(RevPrice(cmd.price))
                 .or_insert_with(|| OrderBucketImpl::new(cmd.price));
-            bucket.put(order.clone());
+            bucket.put(&mut self.orders, order);
+            if improves {
+                bucket.set_top_order(cmd.oid);
+            }
         }
+    }
 
-        self.order_map.insert(order.oid, order);
-        CmdResultCode::Success
+    // the furthest price `cmd` may trade at, its own limit unless the
+    // volatility band ends before it
+    fn band_limit(&self, cmd: &RbCmd) -> i64 {
+        let Some(percent) = self.volatility_band else {
+            return cmd.price;
+        };
+        if self.last_price == 0 {
+            return cmd.price;
+        }
+        let band = ((self.last_price as f64 * percent / 100.0).round() as i64).max(1);
+        match cmd.side {
+            OrderSide::Buy => cmd.price.min(self.last_price + band),
+            OrderSide::Sell => cmd.price.max(self.last_price - band),
+        }
+    }
+
+    // whether an order of `side` at `price` would trade with the other side
+    fn crosses(&self, side: OrderSide, price: i64) -> bool {
+        match side {
+            OrderSide::Buy => self
+                .sell_buckets
+                .keys()
+                .next()
+                .is_some_and(|&ask| ask <= price),
+            OrderSide::Sell => self
+                .buy_buckets
+                .values()
+                .next()
+                .is_some_and(|bid| bid.price() >= price),
+        }
+    }
+
+    /// The price a crossed book trades at once its auction ends, and the
+    /// volume that trades there: the price executing the most volume, then
+    /// leaving the smallest surplus, then nearest the last price.
+    pub fn auction_price(&self) -> Option<(i64, i64)> {
+        let best_bid = self.buy_buckets.values().next()?.price();
+        let best_ask = *self.sell_buckets.keys().next()?;
+        if best_bid < best_ask {
+            return None;
+        }
+        let prices: BTreeSet<i64> = self
+            .buy_buckets
+            .values()
+            .map(|b| b.price())
+            .chain(self.sell_buckets.keys().copied())
+            .filter(|p| (best_ask..=best_bid).contains(p))
+            .collect();
+        prices
+            .into_iter()
+            .map(|price| {
+                let demand: i64 = self
+                    .buy_buckets
+                    .values()
+                    .take_while(|b| b.price() >= price)
+                    .map(|b| b.total_volume())
+                    .sum();
+                let supply: i64 = self
+                    .sell_buckets
+                    .range(..=price)
+                    .map(|(_, b)| b.total_volume())
+                    .sum();
+                let distance = if self.last_price > 0 {
+                    (price - self.last_price).abs()
+                } else {
+                    0
+                };
+                (price, demand.min(supply), (demand - supply).abs(), distance)
+            })
+            .min_by_key(|&(price, volume, surplus, distance)| {
+                (std::cmp::Reverse(volume), surplus, distance, price)
+            })
+            .map(|(price, volume, _, _)| (price, volume))
+    }
+
+    /// Ends an auction: the crossed part of the book trades at
+    /// `auction_price`, orders filling in price then time priority whatever
+    /// the policy. Returns both sides of every fill.
+    pub fn uncross(&mut self, now: i64) -> Vec<MatchEvent> {
+        let Some((price, volume)) = self.auction_price() else {
+            return vec![];
+        };
+        let buys: Vec<i64> = self
+            .buy_buckets
+            .values()
+            .take_while(|b| b.price() >= price)
+            .flat_map(|b| b.orders(&self.orders).map(|o| o.oid))
+            .collect();
+        let sells: Vec<i64> = self
+            .sell_buckets
+            .range(..=price)
+            .flat_map(|(_, b)| b.orders(&self.orders).map(|o| o.oid))
+            .collect();
+
+        let mut events = vec![];
+        let (mut buys, mut sells) = (buys.into_iter(), sells.into_iter());
+        let (mut buy, mut sell) = (buys.next(), sells.next());
+        let mut left = volume;
+        while left > 0
+            && let (Some(buy_oid), Some(sell_oid)) = (buy, sell)
+        {
+            let remaining = |oid| self.orders.get(oid).map_or(0, |o| o.remaining());
+            let traded = left.min(remaining(buy_oid)).min(remaining(sell_oid));
+            let tid = next_tid();
+            let buy_fill = self.fill_resting(buy_oid, traded, price, tid, now);
+            let sell_fill = self.fill_resting(sell_oid, traded, price, tid, now);
+            left -= traded;
+            if buy_fill.as_ref().is_none_or(|e| e.leaves_volume == 0) {
+                buy = buys.next();
+            }
+            if sell_fill.as_ref().is_none_or(|e| e.leaves_volume == 0) {
+                sell = sells.next();
+            }
+            events.extend(buy_fill);
+            events.extend(sell_fill);
+        }
+        self.last_price = price;
+        if let Some(metrics) = &self.metrics {
+            let trades = events.len() as u64 / 2;
+            metrics.trades.fetch_add(trades, Ordering::Relaxed);
+            metrics
+                .traded_volume
+                .fetch_add((volume - left) as u64, Ordering::Relaxed);
+        }
+        events
+    }
+
+    // trades `volume` of a resting order at `price`, dropping the level if
+    // it empties
+    fn fill_resting(
+        &mut self,
+        oid: i64,
+        volume: i64,
+        price: i64,
+        tid: i64,
+        now: i64,
+    ) -> Option<MatchEvent> {
+        let (side, level) = self.orders.get(oid).map(|o| (o.side, o.price))?;
+        let order = if side == OrderSide::Sell {
+            let bucket = self.sell_buckets.get_mut(&level)?;
+            let order = bucket.fill(&mut self.orders, oid, volume)?;
+            if bucket.total_volume() == 0 {
+                self.sell_buckets.remove(&level);
+            }
+            order
+        } else {
+            let bucket = self.buy_buckets.get_mut(&RevPrice(level))?;
+            let order = bucket.fill(&mut self.orders, oid, volume)?;
+            if bucket.total_volume() == 0 {
+                self.buy_buckets.remove(&RevPrice(level));
+            }
+            order
+        };
+        Some(MatchEvent {
+            session_id: order.session_id,
+            timestamp: now,
+            mid: order.mid,
+            oid: order.oid,
+            status: if order.remaining() == 0 {
+                OrderStatus::TradeEd
+            } else {
+                OrderStatus::PartTrade
+            },
+            tid,
+            volume,
+            price,
+            cum_volume: order.tvolume,
+            leaves_volume: order.remaining(),
+        })
     }
 
     fn gen_match_event(&self, cmd: &mut RbCmd, status: OrderStatus) {
@@ -143,54 +533,382 @@ impl OrderBook {
         ev.oid = cmd.oid;
         ev.status = status;
         ev.volume = 0;
+        ev.price = cmd.price;
+        ev.leaves_volume = cmd.volume;
         cmd.match_event_list.push(ev);
     }
 
-    pub fn cancel_order(&mut self, cmd: &mut RbCmd) -> CmdResultCode {
-        let order = match self.order_map.get(&cmd.oid).cloned() {
-            Some(o) => o,
-            None => return CmdResultCode::InvalidOrderId,
+    // the untraded rest of an IOC order
+    fn gen_ioc_cancel_event(&self, cmd: &mut RbCmd, t_volume: i64) {
+        let mut ev = MatchEvent::default();
+        ev.session_id = cmd.session_id;
+        ev.timestamp = Utc::now().timestamp_millis();
+        ev.mid = cmd.mid;
+        ev.oid = cmd.oid;
+        ev.status = if t_volume == 0 {
+            OrderStatus::CancelEd
+        } else {
+            OrderStatus::PartCancel
         };
+        ev.volume = cmd.volume - t_volume;
+        ev.price = cmd.price;
+        ev.cum_volume = t_volume;
+        cmd.match_event_list.push(ev);
+    }
 
-        let target_bucket = if order.side == OrderSide::Sell {
-            self.sell_buckets.get_mut(&order.price)
-        } else {
-            self.buy_buckets.get_mut(&RevPrice(order.price))
+    /// Takes the resting order `cmd.oid` off the book at `now`. Only the
+    /// session that entered it may cancel it.
+    pub fn cancel_order(&mut self, cmd: &mut RbCmd, now: i64) -> CmdResultCode {
+        if self
+            .orders
+            .get(cmd.oid)
+            .is_none_or(|o| o.session_id != cmd.session_id)
+        {
+            return CmdResultCode::InvalidOrderId;
+        }
+        let Some(order) = self.remove_resting(cmd.oid) else {
+            return CmdResultCode::InvalidOrderId;
         };
+        cmd.match_event_list.push(cancel_event(&order, now));
+        self.count_cancels(1);
+        CmdResultCode::Success
+    }
 
-        if let Some(bucket) = target_bucket {
-            bucket.remove(order.oid);
-            if bucket.total_volume() == 0 {
-                if order.side == OrderSide::Sell {
-                    self.sell_buckets.remove(&order.price);
-                } else {
-                    self.buy_buckets.remove(&RevPrice(order.price));
-                }
+    /// Takes up to `volume` off the orders resting at `price` on `side` that
+    /// `pick` accepts, in time priority, as if they had traded. No events
+    /// are made. Returns the volume taken.
+    pub fn take_volume(
+        &mut self,
+        side: OrderSide,
+        price: i64,
+        volume: i64,
+        pick: impl Fn(&Order) -> bool,
+    ) -> i64 {
+        let Some(bucket) = self.bucket(side, price) else {
+            return 0;
+        };
+        let picked: Vec<(i64, i64)> = bucket
+            .orders(&self.orders)
+            .filter(|o| pick(o))
+            .map(|o| (o.oid, o.remaining()))
+            .collect();
+        let mut taken = 0;
+        for (oid, remaining) in picked {
+            if taken == volume {
+                break;
             }
+            let take = remaining.min(volume - taken);
+            self.fill_resting(oid, take, price, 0, 0);
+            taken += take;
         }
+        taken
+    }
 
-        // cancel event
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as i64;
-        let mut ev = MatchEvent::default();
-        ev.timestamp = now;
-        ev.mid = order.mid;
-        ev.oid = order.oid;
-        ev.status = if order.tvolume == 0 {
-            OrderStatus::CancelEd
+    /// Cancels every resting order `keep` accepts, oldest first. Returns one
+    /// cancel event per removed order.
+    pub fn cancel_orders(&mut self, now: i64, keep: impl Fn(&Order) -> bool) -> Vec<MatchEvent> {
+        let mut cancelled: Vec<(i64, i64)> = self
+            .orders
+            .iter()
+            .filter(|o| keep(o))
+            .map(|o| (o.timestamp, o.oid))
+            .collect();
+        cancelled.sort_unstable();
+        let events: Vec<_> = cancelled
+            .into_iter()
+            .filter_map(|(_, oid)| self.remove_resting(oid))
+            .map(|order| cancel_event(&order, now))
+            .collect();
+        self.count_cancels(events.len());
+        events
+    }
+
+    fn count_cancels(&self, n: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.cancels.fetch_add(n as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Removes every resting order whose time in force has run out at `now`.
+    /// `end_of_day` also expires day orders. Returns one `Expired` event per
+    /// removed order, carrying its untraded volume.
+    pub fn expire_orders(&mut self, now: i64, end_of_day: bool) -> Vec<MatchEvent> {
+        let mut expired: Vec<(i64, i64)> = self
+            .orders
+            .iter()
+            .filter(|o| o.time_in_force.is_expired(now, end_of_day))
+            .map(|o| (o.timestamp, o.oid))
+            .collect();
+        // deterministic event order regardless of slab layout
+        expired.sort_unstable();
+
+        let mut events = Vec::with_capacity(expired.len());
+        for (_, oid) in expired {
+            let Some(order) = self.remove_resting(oid) else {
+                continue;
+            };
+            let mut ev = MatchEvent::default();
+            ev.session_id = order.session_id;
+            ev.timestamp = now;
+            ev.mid = order.mid;
+            ev.oid = order.oid;
+            ev.status = OrderStatus::Expired;
+            ev.volume = order.remaining();
+            ev.price = order.price;
+            ev.cum_volume = order.tvolume;
+            events.push(ev);
+        }
+        events
+    }
+
+    /// Earliest good-till-date expiry among resting orders.
+    pub fn next_expiry(&self) -> Option<i64> {
+        self.orders
+            .iter()
+            .filter_map(|o| match o.time_in_force {
+                TimeInForce::Gtd(expire_time) => Some(expire_time),
+                _ => None,
+            })
+            .min()
+    }
+
+    // take a resting order out of its bucket and the slab
+    fn remove_resting(&mut self, oid: i64) -> Option<Order> {
+        let (side, price) = self.orders.get(oid).map(|o| (o.side, o.price))?;
+        let bucket = if side == OrderSide::Sell {
+            self.sell_buckets.get_mut(&price)
         } else {
-            OrderStatus::PartCancel
-        };
-        ev.volume = order.tvolume - order.volume;
-        cmd.match_event_list.push(ev);
+            self.buy_buckets.get_mut(&RevPrice(price))
+        }?;
+        let removed = bucket.remove(&mut self.orders, oid);
+        if bucket.total_volume() == 0 {
+            if side == OrderSide::Sell {
+                self.sell_buckets.remove(&price);
+            } else {
+                self.buy_buckets.remove(&RevPrice(price));
+            }
+        }
+        removed
+    }
 
-        CmdResultCode::Success
+    /// Verifies the book's internal bookkeeping: every order in the slab is
+    /// linked into the bucket for its side and price, bucket totals match
+    /// their orders' volume less what they traded, no bucket is empty and a
+    /// continuously trading book is not crossed. Meant to be run by tests
+    /// after every command.
+    #[cfg(any(test, debug_assertions))]
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let sides = [
+            (
+                OrderSide::Sell,
+                self.sell_buckets
+                    .iter()
+                    .map(|(k, b)| (*k, b))
+                    .collect::<Vec<_>>(),
+            ),
+            (
+                OrderSide::Buy,
+                self.buy_buckets
+                    .iter()
+                    .map(|(k, b)| (k.0, b))
+                    .collect::<Vec<_>>(),
+            ),
+        ];
+
+        let mut resting = 0;
+        for (side, buckets) in &sides {
+            for (key, bucket) in buckets {
+                let price = bucket.price();
+                if *key != price {
+                    return Err(format!("{side:?} bucket {price} stored under {key}"));
+                }
+                let (mut volume, mut traded) = (0, 0);
+                let mut count = 0;
+                for order in bucket.orders(&self.orders) {
+                    if order.side != *side || order.price != price {
+                        return Err(format!(
+                            "order {} ({:?} @ {}) in {side:?} bucket {price}",
+                            order.oid, order.side, order.price
+                        ));
+                    }
+                    if order.remaining() <= 0 || order.tvolume < 0 {
+                        return Err(format!(
+                            "order {} rests with volume {} traded {}",
+                            order.oid, order.volume, order.tvolume
+                        ));
+                    }
+                    if !self
+                        .orders
+                        .get(order.oid)
+                        .is_some_and(|indexed| std::ptr::eq(indexed, order))
+                    {
+                        return Err(format!("order {} not indexed by its id", order.oid));
+                    }
+                    volume += order.volume;
+                    traded += order.tvolume;
+                    count += 1;
+                }
+                if count == 0 {
+                    return Err(format!("empty {side:?} bucket at {price}"));
+                }
+                if count != bucket.len() {
+                    return Err(format!(
+                        "{side:?} bucket {price} holds {} orders but links {count}",
+                        bucket.len()
+                    ));
+                }
+                // a fill counted twice, or not at all, shows up here
+                if volume - traded != bucket.total_volume() {
+                    return Err(format!(
+                        "{side:?} bucket {price} total {} but orders hold {volume} with {traded} traded",
+                        bucket.total_volume()
+                    ));
+                }
+                if let Some(top) = bucket.top_order()
+                    && !bucket.orders(&self.orders).any(|o| o.oid == top)
+                {
+                    return Err(format!(
+                        "{side:?} bucket {price} top order {top} not resting"
+                    ));
+                }
+                resting += count;
+            }
+        }
+        if resting != self.orders.len() {
+            return Err(format!(
+                "{} orders in the slab but {resting} resting",
+                self.orders.len()
+            ));
+        }
+
+        if self.phase == TradingPhase::Continuous
+            && let (Some(bid), Some(ask)) = (
+                self.buy_buckets.values().next(),
+                self.sell_buckets.values().next(),
+            )
+            && bid.price() >= ask.price()
+        {
+            return Err(format!(
+                "book crossed: bid {} >= ask {}",
+                bid.price(),
+                ask.price()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn security_id(&self) -> &str {
+        &self.security_id
+    }
+
+    pub fn last_price(&self) -> i64 {
+        self.last_price
+    }
+
+    pub fn phase(&self) -> TradingPhase {
+        self.phase
+    }
+
+    pub fn set_phase(&mut self, phase: TradingPhase) {
+        self.phase = phase;
+    }
+
+    pub fn best_bid(&self) -> Option<i64> {
+        self.buy_buckets.keys().next().map(|p| p.0)
+    }
+
+    pub fn best_ask(&self) -> Option<i64> {
+        self.sell_buckets.keys().next().copied()
+    }
+
+    fn bucket(&self, side: OrderSide, price: i64) -> Option<&OrderBucketImpl> {
+        match side {
+            OrderSide::Sell => self.sell_buckets.get(&price),
+            OrderSide::Buy => self.buy_buckets.get(&RevPrice(price)),
+        }
+    }
+
+    /// Resting order `oid` with its place in the queue at its price, None
+    /// unless it rests in this book.
+    pub fn order_info(&self, oid: i64) -> Option<OrderInfo> {
+        let order = self.orders.get(oid)?;
+        let bucket = self.bucket(order.side, order.price)?;
+        let (mut position, mut volume_ahead) = (0, 0);
+        for ahead in bucket.orders(&self.orders).take_while(|o| o.oid != oid) {
+            position += 1;
+            volume_ahead += ahead.remaining();
+        }
+        Some(OrderInfo::new(
+            order,
+            &self.security_id,
+            position,
+            volume_ahead,
+        ))
+    }
+
+    /// Resting orders `keep` accepts, asks then bids, best price first.
+    pub fn open_orders(&self, mut keep: impl FnMut(&Order) -> bool) -> Vec<OrderInfo> {
+        let mut orders = vec![];
+        for bucket in self.sell_buckets.values().chain(self.buy_buckets.values()) {
+            let (mut position, mut volume_ahead) = (0, 0);
+            for order in bucket.orders(&self.orders) {
+                if keep(order) {
+                    orders.push(OrderInfo::new(
+                        order,
+                        &self.security_id,
+                        position,
+                        volume_ahead,
+                    ));
+                }
+                position += 1;
+                volume_ahead += order.remaining();
+            }
+        }
+        orders
+    }
+
+    /// Every level of both sides.
+    pub fn depth(&self) -> BookDepth {
+        BookDepth {
+            security_id: self.security_id.clone(),
+            last_price: self.last_price,
+            bids: self.buy_buckets.values().map(level).collect(),
+            asks: self.sell_buckets.values().map(level).collect(),
+        }
+    }
+
+    /// The best level of each side.
+    pub fn top_of_book(&self) -> TopOfBook {
+        TopOfBook {
+            security_id: self.security_id.clone(),
+            phase: self.phase,
+            last_price: self.last_price,
+            bid: self.buy_buckets.values().next().map(level),
+            ask: self.sell_buckets.values().next().map(level),
+        }
+    }
+
+    /// Middle of the best bid and ask, rounded down. None unless both sides
+    /// have orders.
+    pub fn mid_price(&self) -> Option<i64> {
+        Some((self.best_bid()? + self.best_ask()?).div_euclid(2))
+    }
+
+    /// Top `depth` levels of both sides plus the last trade price.
+    pub fn l1_snapshot(&self, depth: usize, timestamp: i64) -> L1MarketData {
+        let buy_size = self.limit_buy_bucket_size(depth);
+        let sell_size = self.limit_sell_bucket_size(depth);
+        let mut data = L1MarketData::new(buy_size, sell_size);
+        self.fill_code(&mut data);
+        self.fill_buys(buy_size, &mut data);
+        self.fill_sells(sell_size, &mut data);
+        data.new_price = self.last_price;
+        data.timestamp = timestamp;
+        data
     }
 
     pub fn fill_code(&self, data: &mut L1MarketData) {
-        data.security_id = self.security_id.clone();
+        data.security_id.clone_from(&self.security_id);
     }
 
     pub fn fill_sells(&self, size: usize, data: &mut L1MarketData) {
@@ -235,3 +953,266 @@ impl OrderBook {
         max_size.min(self.sell_buckets.len())
     }
 }
+
+fn level(bucket: &OrderBucketImpl) -> DepthLevel {
+    DepthLevel {
+        price: bucket.price(),
+        volume: bucket.total_volume(),
+        orders: bucket.len(),
+    }
+}
+
+// what is left of `order` leaving the book on request
+fn cancel_event(order: &Order, now: i64) -> MatchEvent {
+    let mut ev = MatchEvent::default();
+    ev.session_id = order.session_id;
+    ev.timestamp = now;
+    ev.mid = order.mid;
+    ev.oid = order.oid;
+    ev.status = if order.tvolume == 0 {
+        OrderStatus::CancelEd
+    } else {
+        OrderStatus::PartCancel
+    };
+    ev.volume = order.remaining();
+    ev.price = order.price;
+    ev.cum_volume = order.tvolume;
+    ev
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::match_policy::{HybridProRataPolicy, ProRataPolicy};
+
+    use super::*;
+
+    fn cmd(oid: i64, side: OrderSide, price: i64, volume: i64) -> RbCmd {
+        RbCmd {
+            session_id: 1,
+            side,
+            match_event_list: vec![],
+            price,
+            volume,
+            mid: oid,
+            uid: 1,
+            oid,
+            security_id: "600519".to_string(),
+            time_in_force: TimeInForce::Gtc,
+        }
+    }
+
+    // (resting oid, traded volume) for each fill of an aggressive order
+    fn fills(cmd: &RbCmd) -> Vec<(i64, i64)> {
+        cmd.match_event_list
+            .chunks(2)
+            .map(|pair| (pair[1].oid, pair[0].volume))
+            .collect()
+    }
+
+    #[test]
+    fn test_hybrid_top_order_priority() {
+        let mut book = OrderBook::with_policy(
+            "600519".to_string(),
+            HybridProRataPolicy::new(ProRataPolicy::default(), 4),
+        );
+        // 1 rests at 101, 2 improves to 100 and becomes top order, 3 joins it
+        book.new_order(&mut cmd(1, OrderSide::Sell, 101, 10));
+        book.new_order(&mut cmd(2, OrderSide::Sell, 100, 10));
+        book.new_order(&mut cmd(3, OrderSide::Sell, 100, 10));
+
+        let mut buy = cmd(4, OrderSide::Buy, 100, 15);
+        book.new_order(&mut buy);
+        // top order gets 4, 11 is shared 6:10 -> 4 + 6, leftover 1 goes FIFO
+        assert_eq!(fills(&buy), vec![(2, 9), (3, 6)]);
+
+        let mut data = L1MarketData::new(L1MarketData::L1_SIZE, L1MarketData::L1_SIZE);
+        book.fill_sells(L1MarketData::L1_SIZE, &mut data);
+        assert_eq!(data.sell_size, 2);
+        assert_eq!((data.sell_prices[0], data.sell_volumes[0]), (100, 5));
+        assert_eq!((data.sell_prices[1], data.sell_volumes[1]), (101, 10));
+    }
+
+    #[test]
+    fn test_partial_fill_and_cancel_events() {
+        let mut book = OrderBook::new("600519".to_string());
+        let mut sell = cmd(1, OrderSide::Sell, 100, 100);
+        sell.session_id = 7;
+        book.new_order(&mut sell);
+
+        let mut buy = cmd(2, OrderSide::Buy, 100, 30);
+        buy.session_id = 8;
+        book.new_order(&mut buy);
+        let events = &buy.match_event_list;
+        assert_eq!(events.len(), 2);
+        let (taker, maker) = (&events[0], &events[1]);
+        assert_eq!((taker.oid, taker.session_id), (2, 8));
+        assert_eq!((maker.oid, maker.session_id), (1, 7));
+        assert_eq!(taker.status, OrderStatus::TradeEd);
+        assert_eq!(maker.status, OrderStatus::PartTrade);
+        assert_eq!(taker.tid, maker.tid);
+        assert_eq!(
+            (taker.volume, taker.cum_volume, taker.leaves_volume),
+            (30, 30, 0)
+        );
+        assert_eq!(
+            (maker.volume, maker.cum_volume, maker.leaves_volume),
+            (30, 30, 70)
+        );
+
+        // only the owner may cancel
+        let mut cancel = cmd(1, OrderSide::Sell, 0, 0);
+        cancel.session_id = 8;
+        assert_eq!(
+            book.cancel_order(&mut cancel, 0),
+            CmdResultCode::InvalidOrderId
+        );
+        cancel.session_id = 7;
+        assert_eq!(book.cancel_order(&mut cancel, 5), CmdResultCode::Success);
+        let ev = &cancel.match_event_list[0];
+        assert_eq!(
+            (ev.oid, ev.session_id, ev.status, ev.timestamp),
+            (1, 7, OrderStatus::PartCancel, 5)
+        );
+        assert_eq!((ev.volume, ev.cum_volume, ev.leaves_volume), (70, 30, 0));
+        assert_eq!(
+            book.cancel_order(&mut cancel, 0),
+            CmdResultCode::InvalidOrderId
+        );
+    }
+
+    #[test]
+    fn test_ioc_rest_is_cancelled() {
+        let mut book = OrderBook::new("600519".to_string());
+        book.new_order(&mut cmd(1, OrderSide::Sell, 100, 30));
+
+        let mut ioc = cmd(2, OrderSide::Buy, 101, 50);
+        ioc.time_in_force = TimeInForce::Ioc;
+        book.new_order(&mut ioc);
+        let ev = ioc.match_event_list.last().unwrap();
+        assert_eq!((ev.oid, ev.status), (2, OrderStatus::PartCancel));
+        assert_eq!((ev.volume, ev.cum_volume, ev.leaves_volume), (20, 30, 0));
+        assert_eq!((book.best_bid(), book.best_ask()), (None, None));
+    }
+
+    #[test]
+    fn test_deep_level_cancels() {
+        let mut book = OrderBook::new("600519".to_string());
+        let n = 100_000;
+        for oid in 1..=n {
+            book.new_order(&mut cmd(oid, OrderSide::Sell, 100 + oid % 2, 10));
+        }
+        // every other order of each level, from the back
+        for oid in (1..=n).rev().filter(|oid| oid % 4 < 2) {
+            let mut cancel = cmd(oid, OrderSide::Sell, 0, 0);
+            assert_eq!(book.cancel_order(&mut cancel, 0), CmdResultCode::Success);
+        }
+        book.check_invariants().unwrap();
+
+        let mut buy = cmd(n + 1, OrderSide::Buy, 101, 20);
+        book.new_order(&mut buy);
+        // the two oldest left at 100
+        assert_eq!(fills(&buy), vec![(2, 10), (6, 10)]);
+        book.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_queue_position_and_depth() {
+        let mut book = OrderBook::new("600519".to_string());
+        for (oid, volume) in [(1, 10), (2, 20), (3, 30)] {
+            book.new_order(&mut cmd(oid, OrderSide::Sell, 100, volume));
+        }
+        book.new_order(&mut cmd(4, OrderSide::Buy, 99, 5));
+        book.new_order(&mut cmd(5, OrderSide::Buy, 100, 4));
+
+        let info = book.order_info(3).unwrap();
+        assert_eq!((info.queue_position, info.volume_ahead), (2, 26));
+        assert_eq!((info.traded, info.leaves), (0, 30));
+        let info = book.order_info(1).unwrap();
+        assert_eq!((info.queue_position, info.traded, info.leaves), (0, 4, 6));
+        assert_eq!(book.order_info(5), None);
+
+        let bids: Vec<_> = book.open_orders(|o| o.side == OrderSide::Buy);
+        assert_eq!(bids.iter().map(|o| o.oid).collect::<Vec<_>>(), vec![4]);
+
+        let depth = book.depth();
+        assert_eq!(
+            depth.asks,
+            vec![DepthLevel {
+                price: 100,
+                volume: 56,
+                orders: 3
+            }]
+        );
+        assert_eq!(depth.bids.len(), 1);
+        assert_eq!(depth.last_price, 100);
+    }
+
+    #[test]
+    fn test_volatility_interruption_and_uncross() {
+        let mut book = OrderBook::new("600519".to_string());
+        book.set_volatility_band(Some(5.0));
+        book.new_order(&mut cmd(1, OrderSide::Sell, 100, 10));
+        book.new_order(&mut cmd(2, OrderSide::Buy, 100, 10));
+        book.new_order(&mut cmd(3, OrderSide::Sell, 104, 10));
+        book.new_order(&mut cmd(4, OrderSide::Sell, 110, 30));
+
+        // 104 is within 5% of 100, 110 is not
+        let mut buy = cmd(5, OrderSide::Buy, 110, 30);
+        book.new_order(&mut buy);
+        assert_eq!(fills(&buy), vec![(3, 10)]);
+        assert_eq!(book.phase(), TradingPhase::Auction);
+        assert_eq!(book.last_price(), 104);
+        book.check_invariants().unwrap();
+
+        let mut sell = cmd(6, OrderSide::Sell, 106, 5);
+        book.rest_order(&mut sell);
+        assert_eq!(sell.match_event_list[0].status, OrderStatus::OrderEd);
+        // 20 trade at 110, only 5 at 106
+        assert_eq!(book.auction_price(), Some((110, 20)));
+
+        let events: Vec<_> = book
+            .uncross(0)
+            .iter()
+            .map(|e| (e.oid, e.status, e.volume, e.price))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (5, OrderStatus::PartTrade, 5, 110),
+                (6, OrderStatus::TradeEd, 5, 110),
+                (5, OrderStatus::TradeEd, 15, 110),
+                (4, OrderStatus::PartTrade, 15, 110),
+            ]
+        );
+        book.set_phase(TradingPhase::Continuous);
+        book.check_invariants().unwrap();
+        assert_eq!(book.last_price(), 110);
+        assert_eq!(book.auction_price(), None);
+        let info = book.order_info(4).unwrap();
+        assert_eq!((info.queue_position, info.leaves), (0, 15));
+    }
+
+    #[test]
+    fn test_ioc_order_at_the_band_keeps_trading() {
+        let mut book = OrderBook::new("600519".to_string());
+        book.set_volatility_band(Some(5.0));
+        book.new_order(&mut cmd(1, OrderSide::Sell, 100, 10));
+        book.new_order(&mut cmd(2, OrderSide::Buy, 100, 10));
+        book.new_order(&mut cmd(3, OrderSide::Sell, 104, 10));
+        book.new_order(&mut cmd(4, OrderSide::Sell, 110, 30));
+
+        let mut buy = cmd(5, OrderSide::Buy, 110, 30);
+        buy.time_in_force = TimeInForce::Ioc;
+        book.new_order(&mut buy);
+        // the rest is cancelled, not left to trade beyond the band
+        let cancel = buy.match_event_list.pop().unwrap();
+        assert_eq!(fills(&buy), vec![(3, 10)]);
+        assert_eq!(
+            (cancel.status, cancel.volume),
+            (OrderStatus::PartCancel, 20)
+        );
+        assert_eq!(book.phase(), TradingPhase::Continuous);
+        assert_eq!(book.best_ask(), Some(110));
+        book.check_invariants().unwrap();
+    }
+}
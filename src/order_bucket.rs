@@ -1,32 +1,46 @@
-use indexmap::IndexMap;
 use std::sync::atomic::{AtomicI64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::match_policy::{Allocation, MatchPolicy, RestingOrder};
+use crate::order_slab::{OrderList, OrderSlab};
 use crate::types::{MatchEvent, Order, OrderStatus, RbCmd};
 static TID_GEN: AtomicI64 = AtomicI64::new(1);
+
+/// A fresh trade id, shared by both sides of a fill.
+pub fn next_tid() -> i64 {
+    TID_GEN.fetch_add(1, Ordering::Relaxed)
+}
+
 pub trait OrderBucket {
-    fn put(&mut self, order: Order);
-    fn remove(&mut self, oid: i64) -> Option<Order>;
-    fn match_orders<F>(
+    fn put(&mut self, slab: &mut OrderSlab, order: Order);
+    fn remove(&mut self, slab: &mut OrderSlab, oid: i64) -> Option<Order>;
+    fn match_orders<P>(
         &mut self,
+        slab: &mut OrderSlab,
+        policy: &P,
         volume_left: i64,
         trigger_cmd: &mut RbCmd,
-        remove_order_callback: F,
     ) -> i64
     where
-        F: FnMut(&Order);
+        P: MatchPolicy;
+    fn set_top_order(&mut self, oid: i64);
+    fn top_order(&self) -> Option<i64>;
     fn price(&self) -> i64;
     fn total_volume(&self) -> i64;
 }
 
+/// A price level. Its orders live in the book's slab, the level only holds
+/// the ends of their list.
 #[derive(Debug, Default)]
 pub struct OrderBucketImpl {
     // 价格：每个价格一个 Bucket
     price: i64,
     // 总未成交量
     total_volume: i64,
-    // insertion-ordered map: key=oid, value=Order
-    entries: IndexMap<i64, Order>,
+    // orders in time priority
+    orders: OrderList,
+    // order that set this price as the new best, while it rests
+    top_order: Option<i64>,
 }
 
 impl OrderBucketImpl {
@@ -34,31 +48,54 @@ impl OrderBucketImpl {
         Self {
             price,
             total_volume: 0,
-            entries: IndexMap::new(),
+            orders: OrderList::default(),
+            top_order: None,
         }
     }
 
-    fn gen_match_event(
-        order: &Order,
-        cmd: &mut RbCmd,
-        full_match: bool,
-        cmd_full_match: bool,
-        traded: i64,
-    ) {
+    /// Resting orders in time priority.
+    pub fn orders<'a>(&self, slab: &'a OrderSlab) -> impl Iterator<Item = &'a Order> {
+        self.orders.iter(slab)
+    }
+
+    /// Number of resting orders.
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    /// Takes `volume` off resting order `oid`, which leaves the level once
+    /// filled. Returns the order as it is after.
+    pub fn fill(&mut self, slab: &mut OrderSlab, oid: i64, volume: i64) -> Option<Order> {
+        let order = slab.get_mut(oid)?;
+        order.tvolume += volume;
+        self.total_volume -= volume;
+        let order = order.clone();
+        if order.remaining() == 0 {
+            self.remove(slab, oid);
+        }
+        Some(order)
+    }
+
+    // one event per side of a fill, `order` already carries this fill
+    fn gen_match_event(order: &Order, cmd: &mut RbCmd, traded: i64, cmd_leaves: i64) {
         let now_ms = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis() as i64;
 
-        let tid = TID_GEN.fetch_add(1, Ordering::Relaxed);
+        let tid = next_tid();
 
-        // current order match event (bidEvent)
-        let bid_event = MatchEvent {
-            session_id: order.session_id,
+        // incoming order match event
+        let cmd_event = MatchEvent {
+            session_id: cmd.session_id,
             timestamp: now_ms,
             mid: cmd.mid,
             oid: cmd.oid,
-            status: if cmd_full_match {
+            status: if cmd_leaves == 0 {
                 OrderStatus::TradeEd
             } else {
                 OrderStatus::PartTrade
@@ -66,116 +103,121 @@ impl OrderBucketImpl {
             tid,
             volume: traded,
             price: order.price,
+            cum_volume: cmd.volume - cmd_leaves,
+            leaves_volume: cmd_leaves,
         };
-        cmd.match_event_list.push(bid_event);
+        cmd.match_event_list.push(cmd_event);
 
-        // order match event (ofrEvent)
-        let ofr_event = MatchEvent {
-            session_id: cmd.session_id,
+        // resting order match event
+        let resting_event = MatchEvent {
+            session_id: order.session_id,
             timestamp: now_ms,
             mid: order.mid,
             oid: order.oid,
-            status: if full_match {
+            status: if order.remaining() == 0 {
                 OrderStatus::TradeEd
             } else {
                 OrderStatus::PartTrade
             },
             tid,
-            volume: order.volume,
+            volume: traded,
             price: order.price,
+            cum_volume: order.tvolume,
+            leaves_volume: order.remaining(),
         };
-        cmd.match_event_list.push(ofr_event);
+        cmd.match_event_list.push(resting_event);
     }
 }
 
 impl OrderBucket for OrderBucketImpl {
-    fn put(&mut self, order: Order) {
+    fn put(&mut self, slab: &mut OrderSlab, order: Order) {
         self.total_volume += order.volume - order.tvolume;
-        self.entries.insert(order.oid, order);
+        self.orders.push_back(slab, order);
     }
 
-    fn remove(&mut self, oid: i64) -> Option<Order> {
-        if let Some(order) = self.entries.shift_remove(&oid) {
-            self.total_volume -= order.volume - order.tvolume;
-            Some(order)
-        } else {
-            None
+    fn remove(&mut self, slab: &mut OrderSlab, oid: i64) -> Option<Order> {
+        let order = self.orders.remove(slab, oid)?;
+        self.total_volume -= order.volume - order.tvolume;
+        if self.top_order == Some(oid) {
+            self.top_order = None;
         }
+        Some(order)
     }
 
-    fn match_orders<F>(
+    fn match_orders<P>(
         &mut self,
+        slab: &mut OrderSlab,
+        policy: &P,
         mut volume_left: i64,
         trigger_cmd: &mut RbCmd,
-        mut remove_order_callback: F,
     ) -> i64
     where
-        F: FnMut(&Order),
+        P: MatchPolicy,
     {
-        let mut volume_match = 0_i64;
-
-        // interate orders use index
-        let mut idx = 0_usize;
-        while idx < self.entries.len() && volume_left > 0 {
-            // get oid
-            let oid = {
-                let (oid_ref, _) = self.entries.get_index(idx).expect("index valid");
-                *oid_ref
-            };
+        let top_order = self
+            .top_order
+            .and_then(|oid| slab.get(oid))
+            .map(|o| RestingOrder {
+                oid: o.oid,
+                session_id: o.session_id,
+                remaining: o.remaining(),
+            });
+        let mut fills: Vec<Allocation> = Vec::new();
+        policy.allocate(
+            self.orders.iter(slab).map(|o| RestingOrder {
+                oid: o.oid,
+                session_id: o.session_id,
+                remaining: o.remaining(),
+            }),
+            self.total_volume,
+            top_order,
+            volume_left,
+            &mut fills,
+        );
 
+        let mut volume_match = 0_i64;
+        for fill in fills {
             // current order
-            {
-                let order = self.entries.get_index_mut(idx).expect("index valid").1;
-                let can_trade = order.remaining().max(0);
-                if can_trade <= 0 {
-                    // no trade
-                    idx += 1;
-                    continue;
-                }
-                let traded = volume_left.min(can_trade);
-
-                volume_match += traded;
-                order.tvolume += traded;
-                volume_left -= traded;
-                self.total_volume -= traded;
-
-                let full_match = order.volume == order.tvolume;
-                let cmd_full_match = volume_left == 0;
-                // gen match event
-                OrderBucketImpl::gen_match_event(
-                    order,
-                    trigger_cmd,
-                    full_match,
-                    cmd_full_match,
-                    traded,
-                );
+            let Some(order) = slab.get_mut(fill.oid) else {
+                continue;
+            };
+            let traded = fill.volume.min(order.remaining()).min(volume_left);
+            if traded <= 0 {
+                // no trade
+                continue;
             }
 
-            // remove order if full matched
-            let full_matched_now = {
-                let order = self
-                    .entries
-                    .get(&oid)
-                    .expect("exists after above borrow ends");
-                order.volume == order.tvolume
-            };
+            volume_match += traded;
+            order.tvolume += traded;
+            volume_left -= traded;
+            self.total_volume -= traded;
 
-            if full_matched_now {
-                // callback
-                if let Some(order_done) = self.entries.get(&oid).cloned() {
-                    remove_order_callback(&order_done);
+            let full_match = order.volume == order.tvolume;
+            // gen match event
+            OrderBucketImpl::gen_match_event(order, trigger_cmd, traded, volume_left);
+
+            // remove order if full matched
+            if full_match {
+                self.orders.remove(slab, fill.oid);
+                if self.top_order == Some(fill.oid) {
+                    self.top_order = None;
                 }
-                // delete order
-                let _ = self.entries.shift_remove(&oid);
-                // continue
-            } else {
-                idx += 1;
             }
         }
 
         volume_match
     }
 
+    /// Makes `oid`, resting here, the top order.
+    fn set_top_order(&mut self, oid: i64) {
+        self.top_order = Some(oid);
+    }
+
+    #[inline]
+    fn top_order(&self) -> Option<i64> {
+        self.top_order
+    }
+
     #[inline]
     fn price(&self) -> i64 {
         self.price
@@ -191,38 +233,46 @@ impl OrderBucket for OrderBucketImpl {
 mod tests {
     use chrono::Utc;
 
-    use crate::types::OrderSide;
+    use crate::match_policy::{PriceTimePolicy, ProRataPolicy};
+    use crate::types::{OrderSide, TimeInForce};
 
     use super::*;
 
     #[test]
     fn test_bucket_basic() {
+        let mut slab = OrderSlab::default();
         let mut bucket = OrderBucketImpl::new(45);
 
-        bucket.put(Order {
-            session_id: 1,
-            oid: 11,
-            mid: 1,
-            price: 45,
-            volume: 20,
-            tvolume: 0,
-            uid: 1,
-            security_id: "000001".to_string(),
-            side: OrderSide::Buy,
-            timestamp: Utc::now().timestamp(),
-        });
-        bucket.put(Order {
-            session_id: 1,
-            oid: 20,
-            mid: 2,
-            price: 45,
-            volume: 10,
-            tvolume: 0,
-            uid: 1,
-            security_id: "000001".to_string(),
-            side: OrderSide::Buy,
-            timestamp: Utc::now().timestamp(),
-        });
+        bucket.put(
+            &mut slab,
+            Order {
+                session_id: 1,
+                oid: 11,
+                mid: 1,
+                price: 45,
+                volume: 20,
+                tvolume: 0,
+                uid: 1,
+                side: OrderSide::Buy,
+                time_in_force: TimeInForce::Gtc,
+                timestamp: Utc::now().timestamp(),
+            },
+        );
+        bucket.put(
+            &mut slab,
+            Order {
+                session_id: 1,
+                oid: 20,
+                mid: 2,
+                price: 45,
+                volume: 10,
+                tvolume: 0,
+                uid: 1,
+                side: OrderSide::Buy,
+                time_in_force: TimeInForce::Gtc,
+                timestamp: Utc::now().timestamp(),
+            },
+        );
 
         let mut cmd = RbCmd {
             session_id: 1,
@@ -234,14 +284,64 @@ mod tests {
             price: 45,
             volume: 25,
             uid: 1,
+            time_in_force: TimeInForce::Gtc,
         };
 
-        let removed: &mut Vec<i64> = &mut vec![];
-        let total = bucket.match_orders(25, &mut cmd, |o| removed.push(o.oid));
+        let total = bucket.match_orders(&mut slab, &PriceTimePolicy, 25, &mut cmd);
 
         assert_eq!(total, 25);
-        assert_eq!(removed, &vec![11]);
+        // the filled order left the slab too
+        assert!(!slab.contains(11));
+        assert_eq!(bucket.len(), 1);
         assert_eq!(bucket.total_volume(), 5);
         assert!(cmd.match_event_list.len() >= 2);
     }
+
+    #[test]
+    fn test_bucket_pro_rata() {
+        let mut slab = OrderSlab::default();
+        let mut bucket = OrderBucketImpl::new(45);
+        for (oid, volume) in [(1, 100), (2, 300)] {
+            bucket.put(
+                &mut slab,
+                Order {
+                    session_id: 1,
+                    oid,
+                    mid: oid,
+                    price: 45,
+                    volume,
+                    tvolume: 0,
+                    uid: 1,
+                    side: OrderSide::Buy,
+                    time_in_force: TimeInForce::Gtc,
+                    timestamp: Utc::now().timestamp(),
+                },
+            );
+        }
+
+        let mut cmd = RbCmd {
+            session_id: 2,
+            security_id: "000001".to_string(),
+            mid: 999,
+            oid: 1000,
+            match_event_list: vec![],
+            side: OrderSide::Sell,
+            price: 45,
+            volume: 200,
+            uid: 2,
+            time_in_force: TimeInForce::Gtc,
+        };
+
+        let total = bucket.match_orders(&mut slab, &ProRataPolicy::default(), 200, &mut cmd);
+
+        assert_eq!(total, 200);
+        assert_eq!(bucket.total_volume(), 200);
+        let traded: Vec<i64> = cmd
+            .match_event_list
+            .iter()
+            .filter(|e| e.oid == 1000)
+            .map(|e| e.volume)
+            .collect();
+        assert_eq!(traded, vec![50, 150]);
+    }
 }
@@ -0,0 +1,264 @@
+//! Resting orders of a book, each stored once in a slab slot that is reused
+//! once the order leaves. The orders of a price level are chained through
+//! their slots in time priority, so an order is unlinked from anywhere in
+//! its level in constant time.
+
+use std::collections::HashMap;
+
+use crate::types::Order;
+
+// end of a list, or of the free slots
+const NIL: u32 = u32::MAX;
+
+#[derive(Debug)]
+struct Node {
+    order: Order,
+    prev: u32,
+    next: u32,
+}
+
+#[derive(Debug)]
+enum Slot {
+    Occupied(Node),
+    // next free slot
+    Vacant(u32),
+}
+
+/// Orders by slot, plus the slot of each order id.
+#[derive(Debug)]
+pub struct OrderSlab {
+    slots: Vec<Slot>,
+    free: u32,
+    index: HashMap<i64, u32>,
+}
+
+impl Default for OrderSlab {
+    fn default() -> Self {
+        Self {
+            slots: vec![],
+            free: NIL,
+            index: HashMap::new(),
+        }
+    }
+}
+
+impl OrderSlab {
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn contains(&self, oid: i64) -> bool {
+        self.index.contains_key(&oid)
+    }
+
+    /// The resting order `oid`.
+    pub fn get(&self, oid: i64) -> Option<&Order> {
+        self.index.get(&oid).map(|&slot| &self.node(slot).order)
+    }
+
+    // for filling in place; price and side must not change
+    pub(crate) fn get_mut(&mut self, oid: i64) -> Option<&mut Order> {
+        let slot = *self.index.get(&oid)?;
+        Some(&mut self.node_mut(slot).order)
+    }
+
+    /// Every resting order, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Order> {
+        self.slots.iter().filter_map(|slot| match slot {
+            Slot::Occupied(node) => Some(&node.order),
+            Slot::Vacant(_) => None,
+        })
+    }
+
+    fn node(&self, slot: u32) -> &Node {
+        match &self.slots[slot as usize] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant(_) => panic!("order slot {slot} is vacant"),
+        }
+    }
+
+    fn node_mut(&mut self, slot: u32) -> &mut Node {
+        match &mut self.slots[slot as usize] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant(_) => panic!("order slot {slot} is vacant"),
+        }
+    }
+
+    fn insert(&mut self, order: Order) -> u32 {
+        let oid = order.oid;
+        let node = Slot::Occupied(Node {
+            order,
+            prev: NIL,
+            next: NIL,
+        });
+        let slot = if self.free == NIL {
+            self.slots.push(node);
+            (self.slots.len() - 1) as u32
+        } else {
+            let slot = self.free;
+            let Slot::Vacant(next_free) = std::mem::replace(&mut self.slots[slot as usize], node)
+            else {
+                unreachable!("free list points at an order");
+            };
+            self.free = next_free;
+            slot
+        };
+        self.index.insert(oid, slot);
+        slot
+    }
+
+    fn remove(&mut self, slot: u32) -> Order {
+        let Slot::Occupied(node) =
+            std::mem::replace(&mut self.slots[slot as usize], Slot::Vacant(self.free))
+        else {
+            panic!("order slot {slot} is vacant");
+        };
+        self.free = slot;
+        self.index.remove(&node.order.oid);
+        node.order
+    }
+}
+
+/// The orders of one price level in time priority, chained through their
+/// slab slots.
+#[derive(Debug)]
+pub struct OrderList {
+    head: u32,
+    tail: u32,
+    len: usize,
+}
+
+impl Default for OrderList {
+    fn default() -> Self {
+        Self {
+            head: NIL,
+            tail: NIL,
+            len: 0,
+        }
+    }
+}
+
+impl OrderList {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Stores `order` in `slab` at the back of the list.
+    pub fn push_back(&mut self, slab: &mut OrderSlab, order: Order) {
+        let slot = slab.insert(order);
+        slab.node_mut(slot).prev = self.tail;
+        if self.tail == NIL {
+            self.head = slot;
+        } else {
+            slab.node_mut(self.tail).next = slot;
+        }
+        self.tail = slot;
+        self.len += 1;
+    }
+
+    /// Takes `oid` out of the list and `slab`. The caller makes sure the
+    /// order is on this list.
+    pub fn remove(&mut self, slab: &mut OrderSlab, oid: i64) -> Option<Order> {
+        let slot = *slab.index.get(&oid)?;
+        let (prev, next) = {
+            let node = slab.node(slot);
+            (node.prev, node.next)
+        };
+        if prev == NIL {
+            self.head = next;
+        } else {
+            slab.node_mut(prev).next = next;
+        }
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            slab.node_mut(next).prev = prev;
+        }
+        self.len -= 1;
+        Some(slab.remove(slot))
+    }
+
+    /// The orders front to back.
+    pub fn iter<'a>(&self, slab: &'a OrderSlab) -> Iter<'a> {
+        Iter {
+            slab,
+            next: self.head,
+        }
+    }
+}
+
+/// Orders of an `OrderList` front to back.
+#[derive(Clone)]
+pub struct Iter<'a> {
+    slab: &'a OrderSlab,
+    next: u32,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<&'a Order> {
+        if self.next == NIL {
+            return None;
+        }
+        let node = self.slab.node(self.next);
+        self.next = node.next;
+        Some(&node.order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{OrderSide, TimeInForce};
+
+    use super::*;
+
+    fn order(oid: i64) -> Order {
+        Order {
+            session_id: 1,
+            oid,
+            mid: oid,
+            uid: 1,
+            side: OrderSide::Buy,
+            price: 100,
+            volume: 10,
+            tvolume: 0,
+            time_in_force: TimeInForce::Day,
+            timestamp: 0,
+        }
+    }
+
+    fn oids(list: &OrderList, slab: &OrderSlab) -> Vec<i64> {
+        list.iter(slab).map(|o| o.oid).collect()
+    }
+
+    #[test]
+    fn test_unlink_anywhere_and_reuse_slots() {
+        let mut slab = OrderSlab::default();
+        let mut list = OrderList::default();
+        for oid in 1..=4 {
+            list.push_back(&mut slab, order(oid));
+        }
+        // middle, head, tail
+        assert_eq!(list.remove(&mut slab, 2).map(|o| o.oid), Some(2));
+        assert_eq!(list.remove(&mut slab, 1).map(|o| o.oid), Some(1));
+        assert_eq!(list.remove(&mut slab, 4).map(|o| o.oid), Some(4));
+        assert_eq!(list.remove(&mut slab, 4), None);
+        assert_eq!(oids(&list, &slab), vec![3]);
+
+        list.push_back(&mut slab, order(5));
+        list.push_back(&mut slab, order(6));
+        assert_eq!(oids(&list, &slab), vec![3, 5, 6]);
+        assert_eq!((list.len(), slab.len()), (3, 3));
+        // the freed slots were taken again
+        assert_eq!(slab.slots.len(), 4);
+    }
+}
@@ -0,0 +1,171 @@
+//! The seam between channels and wire protocols. A protocol brings a
+//! `ProtocolDecoder` and a `ProtocolEncoder` for its frames plus a
+//! `ProtocolAdapter` turning messages into engine commands and engine events
+//! back into messages. `TcpAcceptorChannel::<A>::with_protocol` serves any
+//! such adapter without protocol code of its own.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use chrono::Utc;
+
+use crate::protocol::proto::{FrameError, ProtocolDecoder, ProtocolEncoder};
+use crate::types::{EngineCommand, MatchEvent, OrderStatus, RbCmd};
+
+/// What a session does in answer to a message, an engine event or a timer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inbound<M> {
+    /// hand over to the engine
+    Command(EngineCommand),
+    /// answer the client directly
+    Reply(M),
+    /// close the connection after what came before
+    Disconnect,
+}
+
+/// The channel side of a session, passed to the adapter with each call.
+pub struct SessionContext<'a> {
+    pub session_id: u64,
+    /// milliseconds since the Unix epoch
+    pub now: i64,
+    order_ids: &'a AtomicI64,
+}
+
+impl<'a> SessionContext<'a> {
+    pub fn new(session_id: u64, order_ids: &'a AtomicI64) -> Self {
+        Self {
+            session_id,
+            now: Utc::now().timestamp_millis(),
+            order_ids,
+        }
+    }
+
+    /// The same context at another time.
+    pub fn at(self, now: i64) -> Self {
+        Self { now, ..self }
+    }
+
+    /// A fresh engine order id. Client order ids are only unique within a
+    /// session, so every order gets one of these.
+    pub fn next_oid(&self) -> i64 {
+        self.order_ids.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Converts between one wire protocol and the engine, for a single
+/// connection: inbound messages to `RbCmd`s, `MatchEvent`s to outbound
+/// messages. A channel creates one adapter per connection and calls
+/// `on_timer` about once a second.
+pub trait ProtocolAdapter: Default + Send + 'static {
+    type Message: Debug + Send + Sync + 'static;
+    type Decoder: ProtocolDecoder<Message = Self::Message> + Send + 'static;
+    type Encoder: ProtocolEncoder<Message = Self::Message> + Send + 'static;
+
+    fn decoder() -> Self::Decoder;
+
+    fn encoder() -> Self::Encoder;
+
+    fn on_message(
+        &mut self,
+        ctx: &SessionContext,
+        msg: Self::Message,
+    ) -> Vec<Inbound<Self::Message>>;
+
+    fn on_event(&mut self, ctx: &SessionContext, event: &MatchEvent)
+    -> Vec<Inbound<Self::Message>>;
+
+    fn on_timer(&mut self, _ctx: &SessionContext) -> Vec<Inbound<Self::Message>> {
+        vec![]
+    }
+
+    /// What to do about bytes that did not make a message. By default the
+    /// session goes on unless the stream can't be read any further.
+    fn on_frame_error(
+        &mut self,
+        _ctx: &SessionContext,
+        error: &FrameError,
+    ) -> Vec<Inbound<Self::Message>> {
+        if error.is_fatal() {
+            vec![Inbound::Disconnect]
+        } else {
+            vec![]
+        }
+    }
+
+    /// Last sequence numbers received and sent, None for protocols that
+    /// don't number their messages.
+    fn seq_nums(&self) -> Option<(u64, u64)> {
+        None
+    }
+
+    /// A command the session sent faster than its throttle allows, dropped
+    /// before the engine. By default answered like an engine reject.
+    fn on_throttled(&mut self, ctx: &SessionContext, cmd: &RbCmd) -> Vec<Inbound<Self::Message>> {
+        self.on_event(ctx, &MatchEvent::rejected(cmd, ctx.now))
+    }
+}
+
+/// A session's live orders by engine order id and by client order id.
+#[derive(Debug)]
+pub struct ClientOrders<T> {
+    orders: HashMap<i64, (String, T)>,
+    oids: HashMap<String, i64>,
+    // engine order id -> client id of the cancel request waiting on it
+    cancels: HashMap<i64, String>,
+}
+
+impl<T> Default for ClientOrders<T> {
+    fn default() -> Self {
+        Self {
+            orders: HashMap::new(),
+            oids: HashMap::new(),
+            cancels: HashMap::new(),
+        }
+    }
+}
+
+impl<T> ClientOrders<T> {
+    pub fn insert(&mut self, oid: i64, cl_ord_id: String, order: T) {
+        self.oids.insert(cl_ord_id.clone(), oid);
+        self.orders.insert(oid, (cl_ord_id, order));
+    }
+
+    pub fn oid(&self, cl_ord_id: &str) -> Option<i64> {
+        self.oids.get(cl_ord_id).copied()
+    }
+
+    /// Client order id and order of the engine order `oid`.
+    pub fn get(&self, oid: i64) -> Option<(&str, &T)> {
+        self.orders.get(&oid).map(|(id, o)| (id.as_str(), o))
+    }
+
+    pub fn get_mut(&mut self, oid: i64) -> Option<&mut T> {
+        self.orders.get_mut(&oid).map(|(_, o)| o)
+    }
+
+    /// Remembers that cancel request `cl_ord_id` targets `oid`.
+    pub fn cancel_requested(&mut self, oid: i64, cl_ord_id: String) {
+        self.cancels.insert(oid, cl_ord_id);
+    }
+
+    /// The cancel request waiting on `oid`, if any. It is answered by the
+    /// next cancel or reject event of the order.
+    pub fn pending_cancel(&self, oid: i64) -> Option<&str> {
+        self.cancels.get(&oid).map(String::as_str)
+    }
+
+    /// Forgets the order once `event` leaves nothing of it in the book. A
+    /// reject with a cancel pending refuses the cancel, not the order.
+    pub fn on_event(&mut self, event: &MatchEvent) {
+        match event.status {
+            OrderStatus::OrderEd | OrderStatus::PartTrade => return,
+            OrderStatus::Rejected if self.cancels.remove(&event.oid).is_some() => return,
+            _ => {}
+        }
+        self.cancels.remove(&event.oid);
+        if let Some((cl_ord_id, _)) = self.orders.remove(&event.oid) {
+            self.oids.remove(&cl_ord_id);
+        }
+    }
+}
@@ -0,0 +1,1260 @@
+//! FIX 4.2 / 4.4 tag-value order entry.
+//!
+//! The session layer covers logon, heartbeats, test requests, resend
+//! requests and sequence resets. Sequence numbers start over with every
+//! connection. Orders come in as NewOrderSingle, OrderCancelRequest and
+//! OrderCancelReplaceRequest and are answered with ExecutionReports and
+//! OrderCancelRejects. A replace cancels the order and enters the new one
+//! once the cancel is confirmed, so it loses its place in the queue.
+//!
+//! Engine prices are FIX prices times `PRICE_SCALE`, quantities are used as
+//! they are.
+
+use std::collections::{BTreeMap, HashMap};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{TimeZone, Utc};
+use tracing::{info, warn};
+
+use crate::protocol::adapter::{ClientOrders, Inbound, ProtocolAdapter, SessionContext};
+use crate::protocol::proto::{FrameError, ProtocolDecoder, ProtocolEncoder};
+use crate::types::{EngineCommand, MatchEvent, OrderSide, OrderStatus, RbCmd, TimeInForce};
+
+pub const FIX_42: &str = "FIX.4.2";
+pub const FIX_44: &str = "FIX.4.4";
+pub const SOH: u8 = 0x01;
+
+/// Engine price units per unit of a FIX price.
+pub const PRICE_SCALE: i64 = 10_000;
+const PRICE_DECIMALS: usize = 4;
+
+pub mod tag {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECK_SUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const EXEC_TRANS_TYPE: u32 = 20;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_TAG_ID: u32 = 371;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+}
+
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+
+    /// Session level messages, never resent.
+    pub fn is_admin(msg_type: &str) -> bool {
+        matches!(msg_type, "0" | "1" | "2" | "3" | "4" | "5" | "A")
+    }
+}
+
+// SessionRejectReason codes
+const REQUIRED_TAG_MISSING: u32 = 1;
+const VALUE_INCORRECT: u32 = 5;
+const INVALID_MSG_TYPE: u32 = 11;
+
+// OrdRejReason and CxlRejReason codes
+const TOO_LATE: u32 = 0;
+const UNKNOWN_ORDER: u32 = 1;
+const ALREADY_PENDING: u32 = 3;
+const DUPLICATE_ORDER: u32 = 6;
+const OTHER: u32 = 99;
+const THROTTLED_TEXT: &str = "throttle limit exceeded";
+
+// how long a heartbeat may be late before the peer is asked for one, in
+// percent of the interval
+const HEARTBEAT_GRACE: i64 = 20;
+
+/// One FIX message: BeginString, MsgType and the other fields in wire order.
+/// BodyLength and CheckSum are worked out when encoding.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FixMessage {
+    pub begin_string: String,
+    pub msg_type: String,
+    pub fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        Self {
+            begin_string: FIX_44.to_string(),
+            msg_type: msg_type.to_string(),
+            fields: vec![],
+        }
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets `tag`, in place if present, at the end otherwise.
+    pub fn set(&mut self, tag: u32, value: impl ToString) -> &mut Self {
+        let value = value.to_string();
+        match self.fields.iter_mut().find(|(t, _)| *t == tag) {
+            Some(field) => field.1 = value,
+            None => self.fields.push((tag, value)),
+        }
+        self
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.set(tag, value);
+        self
+    }
+
+    fn flag(&self, tag: u32) -> bool {
+        self.get(tag) == Some("Y")
+    }
+
+    pub fn seq_num(&self) -> Option<u64> {
+        self.get(tag::MSG_SEQ_NUM)?.parse().ok()
+    }
+
+    /// Appends the message as it goes on the wire.
+    pub fn encode(&self, buf: &mut BytesMut) {
+        let mut body = BytesMut::new();
+        put_field(&mut body, tag::MSG_TYPE, &self.msg_type);
+        for (tag, value) in &self.fields {
+            put_field(&mut body, *tag, value);
+        }
+        let start = buf.len();
+        put_field(buf, tag::BEGIN_STRING, &self.begin_string);
+        put_field(buf, tag::BODY_LENGTH, &body.len().to_string());
+        buf.put_slice(&body);
+        let checksum = checksum(&buf[start..]);
+        put_field(buf, tag::CHECK_SUM, &format!("{checksum:03}"));
+    }
+
+    /// Parses one complete message. Gives None for malformed messages and
+    /// wrong checksums.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let trailer = bytes.len().checked_sub(7)?;
+        let (body, check) = bytes.split_at(trailer);
+        let check = check.strip_prefix(b"10=")?.strip_suffix(&[SOH])?;
+        if std::str::from_utf8(check).ok()?.parse::<u32>().ok()? != checksum(body) {
+            return None;
+        }
+        let text = std::str::from_utf8(body).ok()?;
+        let mut fields = text
+            .strip_suffix(SOH as char)?
+            .split(SOH as char)
+            .map(|field| {
+                let (tag, value) = field.split_once('=')?;
+                Some((tag.parse::<u32>().ok()?, value.to_string()))
+            });
+        let (tag::BEGIN_STRING, begin_string) = fields.next()?? else {
+            return None;
+        };
+        let (tag::BODY_LENGTH, _) = fields.next()?? else {
+            return None;
+        };
+        let (tag::MSG_TYPE, msg_type) = fields.next()?? else {
+            return None;
+        };
+        Some(Self {
+            begin_string,
+            msg_type,
+            fields: fields.collect::<Option<_>>()?,
+        })
+    }
+}
+
+fn put_field(buf: &mut BytesMut, tag: u32, value: &str) {
+    buf.put_slice(tag.to_string().as_bytes());
+    buf.put_u8(b'=');
+    buf.put_slice(value.as_bytes());
+    buf.put_u8(SOH);
+}
+
+/// Sum of `bytes` modulo 256.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |sum, &b| (sum + b as u32) % 256)
+}
+
+/// Engine price of a FIX price, None if negative or finer than the scale.
+pub fn parse_price(s: &str) -> Option<i64> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    let frac = frac.trim_end_matches('0');
+    if int.is_empty() && frac.is_empty()
+        || frac.len() > PRICE_DECIMALS
+        || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let int: i64 = if int.is_empty() { 0 } else { int.parse().ok()? };
+    let frac: i64 = format!("{frac:0<PRICE_DECIMALS$}").parse().ok()?;
+    int.checked_mul(PRICE_SCALE)?.checked_add(frac)
+}
+
+/// FIX price of an engine price.
+pub fn format_price(price: i64) -> String {
+    let frac = format!("{:0PRICE_DECIMALS$}", price % PRICE_SCALE);
+    match frac.trim_end_matches('0') {
+        "" => (price / PRICE_SCALE).to_string(),
+        frac => format!("{}.{}", price / PRICE_SCALE, frac),
+    }
+}
+
+fn format_time(millis: i64) -> String {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .unwrap_or_default()
+        .format("%Y%m%d-%H:%M:%S%.3f")
+        .to_string()
+}
+
+pub struct FixDecoder;
+impl ProtocolDecoder for FixDecoder {
+    type Message = FixMessage;
+
+    fn frame_len(&self, buf: &[u8]) -> Result<Option<usize>, FrameError> {
+        // 8=FIX.4.x|9=len| then the body and 10=nnn|
+        if !b"8=".starts_with(&buf[..buf.len().min(2)]) {
+            return Err(FrameError::BadHeader);
+        }
+        let Some(begin_end) = buf.iter().position(|&b| b == SOH) else {
+            return Ok(None);
+        };
+        let len_field = &buf[begin_end + 1..];
+        if !b"9=".starts_with(&len_field[..len_field.len().min(2)]) {
+            return Err(FrameError::BadHeader);
+        }
+        let Some(len_end) = len_field.iter().position(|&b| b == SOH) else {
+            return Ok(None);
+        };
+        let body_len: usize = std::str::from_utf8(&len_field[2..len_end])
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .ok_or(FrameError::BadHeader)?;
+        Ok(Some((begin_end + len_end + 2 + 7).saturating_add(body_len)))
+    }
+
+    fn verify(&self, frame: &[u8]) -> Result<(), FrameError> {
+        let trailer = frame.len().saturating_sub(7);
+        let actual = frame[trailer..]
+            .strip_prefix(b"10=")
+            .and_then(|check| check.strip_suffix(&[SOH]))
+            .and_then(|check| std::str::from_utf8(check).ok()?.parse().ok())
+            .ok_or(FrameError::Undecodable { len: frame.len() })?;
+        let expected = checksum(&frame[..trailer]);
+        if actual != expected {
+            return Err(FrameError::Checksum { expected, actual });
+        }
+        Ok(())
+    }
+
+    // the next BeginString, or what might be the start of one
+    fn resync(&self, buf: &[u8]) -> Option<usize> {
+        Some(
+            buf.windows(5)
+                .position(|w| w == b"8=FIX")
+                .unwrap_or(buf.len().saturating_sub(4)),
+        )
+    }
+
+    fn decode(&mut self, buf: &mut Bytes) -> Option<Self::Message> {
+        FixMessage::decode(buf)
+    }
+}
+
+/// Header fields are the adapter's business, see `FixAdapter`.
+pub struct FixEncoder;
+impl ProtocolEncoder for FixEncoder {
+    type Message = FixMessage;
+
+    fn encode(&mut self, msg: &FixMessage, buf: &mut BytesMut) {
+        msg.encode(buf);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum SessionState {
+    #[default]
+    AwaitingLogon,
+    Active,
+}
+
+/// A client order as the session knows it. Quantities add up over
+/// replaces, the engine only ever sees what is left.
+#[derive(Debug, Clone)]
+struct FixOrder {
+    account: Option<String>,
+    symbol: String,
+    side: OrderSide,
+    ord_type: String,
+    time_in_force: TimeInForce,
+    qty: i64,
+    // limit price, engine units
+    price: Option<i64>,
+    cum_qty: i64,
+    // sum of price times quantity over all fills
+    amount: i128,
+    // acknowledged to the client, by a new or a replaced report
+    acked: bool,
+}
+
+impl FixOrder {
+    fn avg_px(&self) -> i64 {
+        if self.cum_qty == 0 {
+            return 0;
+        }
+        (self.amount / self.cum_qty as i128) as i64
+    }
+
+    fn fix_side(&self) -> &'static str {
+        match self.side {
+            OrderSide::Buy => "1",
+            OrderSide::Sell => "2",
+        }
+    }
+
+    fn command(&self, ctx: &SessionContext, oid: i64) -> RbCmd {
+        let price = self.price.unwrap_or(match self.side {
+            OrderSide::Buy => i64::MAX,
+            OrderSide::Sell => 0,
+        });
+        RbCmd {
+            session_id: ctx.session_id,
+            side: self.side,
+            match_event_list: vec![],
+            price,
+            volume: self.qty - self.cum_qty,
+            mid: oid,
+            uid: 0,
+            oid,
+            security_id: self.symbol.clone(),
+            time_in_force: self.time_in_force,
+        }
+    }
+}
+
+// a field the client got wrong, answered with a session or order reject
+struct Invalid {
+    tag: u32,
+    reason: u32,
+    text: String,
+}
+
+impl Invalid {
+    fn missing(tag: u32) -> Self {
+        Self {
+            tag,
+            reason: REQUIRED_TAG_MISSING,
+            text: format!("tag {tag} missing"),
+        }
+    }
+
+    fn value(tag: u32, value: &str) -> Self {
+        Self {
+            tag,
+            reason: VALUE_INCORRECT,
+            text: format!("bad value {value:?} for tag {tag}"),
+        }
+    }
+}
+
+fn required(msg: &FixMessage, tag: u32) -> Result<&str, Invalid> {
+    msg.get(tag).ok_or_else(|| Invalid::missing(tag))
+}
+
+fn parse_order(msg: &FixMessage) -> Result<FixOrder, Invalid> {
+    let side = match required(msg, tag::SIDE)? {
+        "1" => OrderSide::Buy,
+        "2" => OrderSide::Sell,
+        other => return Err(Invalid::value(tag::SIDE, other)),
+    };
+    let qty = required(msg, tag::ORDER_QTY)?;
+    let qty = qty
+        .parse::<i64>()
+        .ok()
+        .filter(|&q| q > 0)
+        .ok_or_else(|| Invalid::value(tag::ORDER_QTY, qty))?;
+    let ord_type = required(msg, tag::ORD_TYPE)?.to_string();
+    let price = match ord_type.as_str() {
+        "1" => None,
+        "2" => {
+            let price = required(msg, tag::PRICE)?;
+            Some(parse_price(price).ok_or_else(|| Invalid::value(tag::PRICE, price))?)
+        }
+        other => return Err(Invalid::value(tag::ORD_TYPE, other)),
+    };
+    let time_in_force = match (ord_type.as_str(), msg.get(tag::TIME_IN_FORCE)) {
+        // market orders trade what they can and leave nothing behind
+        ("1", _) | (_, Some("3")) => TimeInForce::Ioc,
+        (_, None | Some("0")) => TimeInForce::Day,
+        (_, Some("1")) => TimeInForce::Gtc,
+        (_, Some(other)) => return Err(Invalid::value(tag::TIME_IN_FORCE, other)),
+    };
+    Ok(FixOrder {
+        account: msg.get(tag::ACCOUNT).map(str::to_string),
+        symbol: required(msg, tag::SYMBOL)?.to_string(),
+        side,
+        ord_type,
+        time_in_force,
+        qty,
+        price,
+        cum_qty: 0,
+        amount: 0,
+        acked: false,
+    })
+}
+
+/// FIX sessions: the session layer plus order entry into the engine.
+#[derive(Debug, Default)]
+pub struct FixAdapter {
+    state: SessionState,
+    begin_string: String,
+    // ours, the client's TargetCompID
+    sender_comp_id: String,
+    target_comp_id: String,
+    heart_bt_int: i64,
+    // next sequence number expected from the client
+    in_seq: u64,
+    // last sequence number sent
+    out_seq: u64,
+    resend_requested: bool,
+    last_received: i64,
+    last_sent: i64,
+    test_request_sent: bool,
+    // application messages sent, for resend requests
+    sent: BTreeMap<u64, FixMessage>,
+    orders: ClientOrders<FixOrder>,
+    // engine order id -> replacing client order id and order
+    replaces: HashMap<i64, (String, FixOrder)>,
+    exec_id: u64,
+}
+
+impl FixAdapter {
+    // Fills in the header and takes the next sequence number.
+    fn send(&mut self, ctx: &SessionContext, msg: FixMessage) -> Inbound<FixMessage> {
+        if self.begin_string.is_empty() {
+            self.begin_string = FIX_44.to_string();
+        }
+        let mut out = FixMessage {
+            begin_string: self.begin_string.clone(),
+            msg_type: msg.msg_type,
+            fields: vec![],
+        };
+        self.out_seq += 1;
+        out.set(tag::SENDER_COMP_ID, &self.sender_comp_id)
+            .set(tag::TARGET_COMP_ID, &self.target_comp_id)
+            .set(tag::MSG_SEQ_NUM, self.out_seq)
+            .set(tag::SENDING_TIME, format_time(ctx.now));
+        out.fields.extend(msg.fields);
+        if !msg_type::is_admin(&out.msg_type) {
+            self.sent.insert(self.out_seq, out.clone());
+        }
+        self.last_sent = ctx.now;
+        Inbound::Reply(out)
+    }
+
+    fn logout(&mut self, ctx: &SessionContext, text: &str) -> Vec<Inbound<FixMessage>> {
+        warn!("Logging out FIX session {}: {}", ctx.session_id, text);
+        let logout = FixMessage::new(msg_type::LOGOUT).with(tag::TEXT, text);
+        vec![self.send(ctx, logout), Inbound::Disconnect]
+    }
+
+    fn session_reject(
+        &mut self,
+        ctx: &SessionContext,
+        msg: &FixMessage,
+        invalid: Invalid,
+    ) -> Inbound<FixMessage> {
+        let reject = FixMessage::new(msg_type::REJECT)
+            .with(tag::REF_SEQ_NUM, msg.seq_num().unwrap_or_default())
+            .with(tag::REF_TAG_ID, invalid.tag)
+            .with(tag::REF_MSG_TYPE, &msg.msg_type)
+            .with(tag::SESSION_REJECT_REASON, invalid.reason)
+            .with(tag::TEXT, invalid.text);
+        self.send(ctx, reject)
+    }
+
+    fn on_logon(&mut self, ctx: &SessionContext, msg: FixMessage) -> Vec<Inbound<FixMessage>> {
+        self.begin_string = msg.begin_string.clone();
+        self.sender_comp_id = msg.get(tag::TARGET_COMP_ID).unwrap_or_default().to_string();
+        self.target_comp_id = msg.get(tag::SENDER_COMP_ID).unwrap_or_default().to_string();
+        self.out_seq = 0;
+        if ![FIX_42, FIX_44].contains(&msg.begin_string.as_str()) {
+            self.begin_string = FIX_44.to_string();
+            return self.logout(ctx, "unsupported BeginString");
+        }
+        let Some(heart_bt_int) = msg
+            .get(tag::HEART_BT_INT)
+            .and_then(|h| h.parse().ok())
+            .filter(|&h| h > 0)
+        else {
+            return self.logout(ctx, "HeartBtInt missing or invalid");
+        };
+        info!(
+            "FIX logon of {} on session {}",
+            self.target_comp_id, ctx.session_id
+        );
+        self.heart_bt_int = heart_bt_int;
+        self.in_seq = msg.seq_num().unwrap_or(1) + 1;
+        self.state = SessionState::Active;
+        let mut logon = FixMessage::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, heart_bt_int);
+        if msg.flag(tag::RESET_SEQ_NUM_FLAG) {
+            logon.set(tag::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        vec![self.send(ctx, logon)]
+    }
+
+    // Sends again what the client missed. Application messages go out as
+    // possible duplicates, admin messages are skipped with a gap fill.
+    fn resend(&mut self, ctx: &SessionContext, msg: &FixMessage) -> Vec<Inbound<FixMessage>> {
+        let begin = msg
+            .get(tag::BEGIN_SEQ_NO)
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(1)
+            .max(1);
+        let end = match msg.get(tag::END_SEQ_NO).and_then(|s| s.parse::<u64>().ok()) {
+            Some(0) | None => self.out_seq,
+            Some(end) => end.min(self.out_seq),
+        };
+        let mut out = vec![];
+        let mut gap_start = None;
+        for seq in begin..=end {
+            let Some(sent) = self.sent.get(&seq) else {
+                gap_start.get_or_insert(seq);
+                continue;
+            };
+            if let Some(start) = gap_start.take() {
+                out.push(self.gap_fill(ctx, start, seq));
+            }
+            let mut dup = sent.clone();
+            let orig = dup.get(tag::SENDING_TIME).unwrap_or_default().to_string();
+            dup.set(tag::SENDING_TIME, format_time(ctx.now))
+                .set(tag::POSS_DUP_FLAG, "Y")
+                .set(tag::ORIG_SENDING_TIME, orig);
+            out.push(Inbound::Reply(dup));
+        }
+        if let Some(start) = gap_start {
+            out.push(self.gap_fill(ctx, start, end + 1));
+        }
+        self.last_sent = ctx.now;
+        out
+    }
+
+    fn gap_fill(&self, ctx: &SessionContext, seq: u64, new_seq_no: u64) -> Inbound<FixMessage> {
+        let mut gap_fill = FixMessage::new(msg_type::SEQUENCE_RESET);
+        gap_fill.begin_string = self.begin_string.clone();
+        gap_fill
+            .set(tag::SENDER_COMP_ID, &self.sender_comp_id)
+            .set(tag::TARGET_COMP_ID, &self.target_comp_id)
+            .set(tag::MSG_SEQ_NUM, seq)
+            .set(tag::SENDING_TIME, format_time(ctx.now))
+            .set(tag::POSS_DUP_FLAG, "Y")
+            .set(tag::GAP_FILL_FLAG, "Y")
+            .set(tag::NEW_SEQ_NO, new_seq_no);
+        Inbound::Reply(gap_fill)
+    }
+
+    fn next_exec_id(&mut self) -> u64 {
+        self.exec_id += 1;
+        self.exec_id
+    }
+
+    fn execution_report(
+        &mut self,
+        cl_ord_id: &str,
+        order: &FixOrder,
+        oid: Option<i64>,
+        exec_type: &str,
+        ord_status: &str,
+        leaves_qty: i64,
+    ) -> FixMessage {
+        let mut report = FixMessage::new(msg_type::EXECUTION_REPORT)
+            .with(
+                tag::ORDER_ID,
+                oid.map_or("NONE".to_string(), |oid| oid.to_string()),
+            )
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::EXEC_ID, self.next_exec_id());
+        if self.begin_string == FIX_42 {
+            report.set(tag::EXEC_TRANS_TYPE, 0);
+        }
+        report
+            .set(tag::EXEC_TYPE, exec_type)
+            .set(tag::ORD_STATUS, ord_status);
+        if let Some(account) = &order.account {
+            report.set(tag::ACCOUNT, account);
+        }
+        report
+            .set(tag::SYMBOL, &order.symbol)
+            .set(tag::SIDE, order.fix_side())
+            .set(tag::ORDER_QTY, order.qty)
+            .set(tag::ORD_TYPE, &order.ord_type);
+        if let Some(price) = order.price {
+            report.set(tag::PRICE, format_price(price));
+        }
+        report
+            .set(tag::LEAVES_QTY, leaves_qty)
+            .set(tag::CUM_QTY, order.cum_qty)
+            .set(tag::AVG_PX, format_price(order.avg_px()));
+        report
+    }
+
+    fn order_reject(
+        &mut self,
+        ctx: &SessionContext,
+        cl_ord_id: &str,
+        order: &FixOrder,
+        oid: Option<i64>,
+        reason: u32,
+        text: &str,
+    ) -> Inbound<FixMessage> {
+        let mut report = self.execution_report(cl_ord_id, order, oid, "8", "8", 0);
+        report.set(tag::ORD_REJ_REASON, reason).set(tag::TEXT, text);
+        self.send(ctx, report)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn cancel_reject(
+        &mut self,
+        ctx: &SessionContext,
+        oid: Option<i64>,
+        cl_ord_id: &str,
+        orig_cl_ord_id: &str,
+        ord_status: &str,
+        response_to: &str,
+        reason: u32,
+    ) -> Inbound<FixMessage> {
+        let reject = FixMessage::new(msg_type::ORDER_CANCEL_REJECT)
+            .with(
+                tag::ORDER_ID,
+                oid.map_or("NONE".to_string(), |oid| oid.to_string()),
+            )
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id)
+            .with(tag::ORD_STATUS, ord_status)
+            .with(tag::CXL_REJ_RESPONSE_TO, response_to)
+            .with(tag::CXL_REJ_REASON, reason);
+        self.send(ctx, reject)
+    }
+
+    fn new_order(&mut self, ctx: &SessionContext, msg: &FixMessage) -> Vec<Inbound<FixMessage>> {
+        let cl_ord_id = match required(msg, tag::CL_ORD_ID) {
+            Ok(cl_ord_id) => cl_ord_id.to_string(),
+            Err(invalid) => return vec![self.session_reject(ctx, msg, invalid)],
+        };
+        let order = match parse_order(msg) {
+            Ok(order) => order,
+            Err(invalid) if invalid.reason == REQUIRED_TAG_MISSING => {
+                return vec![self.session_reject(ctx, msg, invalid)];
+            }
+            Err(invalid) => {
+                let order = FixOrder {
+                    account: msg.get(tag::ACCOUNT).map(str::to_string),
+                    symbol: msg.get(tag::SYMBOL).unwrap_or_default().to_string(),
+                    side: OrderSide::Buy,
+                    ord_type: msg.get(tag::ORD_TYPE).unwrap_or_default().to_string(),
+                    time_in_force: TimeInForce::Day,
+                    qty: 0,
+                    price: None,
+                    cum_qty: 0,
+                    amount: 0,
+                    acked: false,
+                };
+                return vec![self.order_reject(
+                    ctx,
+                    &cl_ord_id,
+                    &order,
+                    None,
+                    OTHER,
+                    &invalid.text,
+                )];
+            }
+        };
+        if self.orders.oid(&cl_ord_id).is_some() {
+            return vec![self.order_reject(
+                ctx,
+                &cl_ord_id,
+                &order,
+                None,
+                DUPLICATE_ORDER,
+                "duplicate ClOrdID",
+            )];
+        }
+        let oid = ctx.next_oid();
+        let cmd = order.command(ctx, oid);
+        self.orders.insert(oid, cl_ord_id, order);
+        vec![Inbound::Command(EngineCommand::NewOrder(cmd))]
+    }
+
+    // Cancels and replaces both start with a cancel of the original order.
+    fn cancel(
+        &mut self,
+        ctx: &SessionContext,
+        msg: &FixMessage,
+        replace: bool,
+    ) -> Vec<Inbound<FixMessage>> {
+        let response_to = if replace { "2" } else { "1" };
+        let (cl_ord_id, orig_cl_ord_id) = match (
+            required(msg, tag::CL_ORD_ID),
+            required(msg, tag::ORIG_CL_ORD_ID),
+        ) {
+            (Ok(cl), Ok(orig)) => (cl.to_string(), orig.to_string()),
+            (Err(invalid), _) | (_, Err(invalid)) => {
+                return vec![self.session_reject(ctx, msg, invalid)];
+            }
+        };
+        let Some((oid, order)) = self
+            .orders
+            .oid(&orig_cl_ord_id)
+            .and_then(|oid| self.orders.get(oid).map(|(_, o)| (oid, o.clone())))
+        else {
+            return vec![self.cancel_reject(
+                ctx,
+                None,
+                &cl_ord_id,
+                &orig_cl_ord_id,
+                "8",
+                response_to,
+                UNKNOWN_ORDER,
+            )];
+        };
+        let ord_status = if order.cum_qty > 0 { "1" } else { "0" };
+        if self.orders.pending_cancel(oid).is_some() || self.orders.oid(&cl_ord_id).is_some() {
+            let reason = if self.orders.pending_cancel(oid).is_some() {
+                ALREADY_PENDING
+            } else {
+                DUPLICATE_ORDER
+            };
+            return vec![self.cancel_reject(
+                ctx,
+                Some(oid),
+                &cl_ord_id,
+                &orig_cl_ord_id,
+                ord_status,
+                response_to,
+                reason,
+            )];
+        }
+        if replace {
+            let replacement = match parse_order(msg) {
+                Ok(new) if new.side == order.side && new.symbol == order.symbol => new,
+                Ok(_) | Err(_) => {
+                    return vec![self.cancel_reject(
+                        ctx,
+                        Some(oid),
+                        &cl_ord_id,
+                        &orig_cl_ord_id,
+                        ord_status,
+                        response_to,
+                        OTHER,
+                    )];
+                }
+            };
+            if replacement.qty <= order.cum_qty {
+                return vec![self.cancel_reject(
+                    ctx,
+                    Some(oid),
+                    &cl_ord_id,
+                    &orig_cl_ord_id,
+                    ord_status,
+                    response_to,
+                    TOO_LATE,
+                )];
+            }
+            self.replaces.insert(oid, (cl_ord_id.clone(), replacement));
+        }
+        let mut cmd = order.command(ctx, oid);
+        cmd.volume = 0;
+        self.orders.cancel_requested(oid, cl_ord_id);
+        vec![Inbound::Command(EngineCommand::CancelOrder(cmd))]
+    }
+
+    fn on_cancelled(
+        &mut self,
+        ctx: &SessionContext,
+        me: &MatchEvent,
+        cl_ord_id: String,
+        order: FixOrder,
+    ) -> Vec<Inbound<FixMessage>> {
+        let Some(cancel_id) = self.orders.pending_cancel(me.oid).map(str::to_string) else {
+            // what an IOC order could not trade
+            let report = self.execution_report(&cl_ord_id, &order, Some(me.oid), "4", "4", 0);
+            return vec![self.send(ctx, report)];
+        };
+        let Some((_, mut replacement)) = self.replaces.remove(&me.oid) else {
+            let mut report = self.execution_report(&cancel_id, &order, Some(me.oid), "4", "4", 0);
+            report.set(tag::ORIG_CL_ORD_ID, cl_ord_id);
+            return vec![self.send(ctx, report)];
+        };
+        replacement.cum_qty = order.cum_qty;
+        replacement.amount = order.amount;
+        replacement.acked = true;
+        let leaves_qty = replacement.qty - replacement.cum_qty;
+        if leaves_qty <= 0 {
+            // filled up to the new quantity while the replace was on its way
+            let mut report =
+                self.execution_report(&cancel_id, &replacement, Some(me.oid), "4", "4", 0);
+            report.set(tag::ORIG_CL_ORD_ID, cl_ord_id);
+            return vec![self.send(ctx, report)];
+        }
+        let oid = ctx.next_oid();
+        let cmd = replacement.command(ctx, oid);
+        let ord_status = if replacement.cum_qty > 0 { "1" } else { "0" };
+        let mut report = self.execution_report(
+            &cancel_id,
+            &replacement,
+            Some(oid),
+            "5",
+            ord_status,
+            leaves_qty,
+        );
+        report.set(tag::ORIG_CL_ORD_ID, cl_ord_id);
+        self.orders.insert(oid, cancel_id, replacement);
+        vec![
+            self.send(ctx, report),
+            Inbound::Command(EngineCommand::NewOrder(cmd)),
+        ]
+    }
+
+    fn on_rejected(
+        &mut self,
+        ctx: &SessionContext,
+        me: &MatchEvent,
+        cl_ord_id: String,
+        order: FixOrder,
+    ) -> Vec<Inbound<FixMessage>> {
+        match self.orders.pending_cancel(me.oid).map(str::to_string) {
+            // the order left the book before the cancel got there
+            Some(cancel_id) => {
+                let response_to = if self.replaces.remove(&me.oid).is_some() {
+                    "2"
+                } else {
+                    "1"
+                };
+                let ord_status = if order.cum_qty >= order.qty { "2" } else { "4" };
+                vec![self.cancel_reject(
+                    ctx,
+                    Some(me.oid),
+                    &cancel_id,
+                    &cl_ord_id,
+                    ord_status,
+                    response_to,
+                    TOO_LATE,
+                )]
+            }
+            None => vec![self.order_reject(
+                ctx,
+                &cl_ord_id,
+                &order,
+                Some(me.oid),
+                OTHER,
+                "rejected by the matcher",
+            )],
+        }
+    }
+}
+
+impl ProtocolAdapter for FixAdapter {
+    type Message = FixMessage;
+    type Decoder = FixDecoder;
+    type Encoder = FixEncoder;
+
+    fn decoder() -> FixDecoder {
+        FixDecoder
+    }
+
+    fn on_message(&mut self, ctx: &SessionContext, msg: FixMessage) -> Vec<Inbound<FixMessage>> {
+        self.last_received = ctx.now;
+        self.test_request_sent = false;
+        if self.state == SessionState::AwaitingLogon {
+            if msg.msg_type != msg_type::LOGON {
+                self.begin_string = msg.begin_string;
+                return self.logout(ctx, "first message must be a logon");
+            }
+            return self.on_logon(ctx, msg);
+        }
+        let Some(seq) = msg.seq_num() else {
+            return self.logout(ctx, "MsgSeqNum missing");
+        };
+        if msg.msg_type == msg_type::SEQUENCE_RESET && !msg.flag(tag::GAP_FILL_FLAG) {
+            if let Some(new_seq_no) = msg.get(tag::NEW_SEQ_NO).and_then(|s| s.parse().ok()) {
+                self.in_seq = new_seq_no;
+            }
+            return vec![];
+        }
+        if seq > self.in_seq {
+            let mut out = vec![];
+            if msg.msg_type == msg_type::RESEND_REQUEST {
+                out.extend(self.resend(ctx, &msg));
+            }
+            if !self.resend_requested {
+                self.resend_requested = true;
+                let resend = FixMessage::new(msg_type::RESEND_REQUEST)
+                    .with(tag::BEGIN_SEQ_NO, self.in_seq)
+                    .with(tag::END_SEQ_NO, 0);
+                out.push(self.send(ctx, resend));
+            }
+            return out;
+        }
+        if seq < self.in_seq {
+            if msg.flag(tag::POSS_DUP_FLAG) {
+                return vec![];
+            }
+            let text = format!("MsgSeqNum too low, expecting {}", self.in_seq);
+            return self.logout(ctx, &text);
+        }
+        self.in_seq += 1;
+        self.resend_requested = false;
+
+        match msg.msg_type.as_str() {
+            msg_type::HEARTBEAT | msg_type::REJECT => vec![],
+            msg_type::TEST_REQUEST => {
+                let mut heartbeat = FixMessage::new(msg_type::HEARTBEAT);
+                if let Some(id) = msg.get(tag::TEST_REQ_ID) {
+                    heartbeat.set(tag::TEST_REQ_ID, id);
+                }
+                vec![self.send(ctx, heartbeat)]
+            }
+            msg_type::RESEND_REQUEST => self.resend(ctx, &msg),
+            msg_type::SEQUENCE_RESET => {
+                if let Some(new_seq_no) = msg.get(tag::NEW_SEQ_NO).and_then(|s| s.parse().ok()) {
+                    self.in_seq = self.in_seq.max(new_seq_no);
+                }
+                vec![]
+            }
+            msg_type::LOGOUT => {
+                info!("FIX logout of {}", self.target_comp_id);
+                let logout = FixMessage::new(msg_type::LOGOUT);
+                vec![self.send(ctx, logout), Inbound::Disconnect]
+            }
+            msg_type::NEW_ORDER_SINGLE => self.new_order(ctx, &msg),
+            msg_type::ORDER_CANCEL_REQUEST => self.cancel(ctx, &msg, false),
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => self.cancel(ctx, &msg, true),
+            _ => {
+                let invalid = Invalid {
+                    tag: tag::MSG_TYPE,
+                    reason: INVALID_MSG_TYPE,
+                    text: format!("unsupported MsgType {}", msg.msg_type),
+                };
+                vec![self.session_reject(ctx, &msg, invalid)]
+            }
+        }
+    }
+
+    fn on_event(&mut self, ctx: &SessionContext, me: &MatchEvent) -> Vec<Inbound<FixMessage>> {
+        let Some((cl_ord_id, order)) = self.orders.get(me.oid) else {
+            return vec![];
+        };
+        let (cl_ord_id, mut order) = (cl_ord_id.to_string(), order.clone());
+        let mut out = vec![];
+        // the engine only confirms orders that rest without trading
+        if !order.acked && me.status != OrderStatus::Rejected {
+            order.acked = true;
+            if let Some(tracked) = self.orders.get_mut(me.oid) {
+                tracked.acked = true;
+            }
+            let leaves_qty = order.qty - order.cum_qty;
+            let report =
+                self.execution_report(&cl_ord_id, &order, Some(me.oid), "0", "0", leaves_qty);
+            out.push(self.send(ctx, report));
+        }
+        out.extend(match me.status {
+            OrderStatus::OrderEd => vec![],
+            OrderStatus::PartTrade | OrderStatus::TradeEd => {
+                order.cum_qty += me.volume;
+                order.amount += me.price as i128 * me.volume as i128;
+                if let Some(tracked) = self.orders.get_mut(me.oid) {
+                    *tracked = order.clone();
+                }
+                let ord_status = if me.leaves_volume == 0 { "2" } else { "1" };
+                let exec_type = match self.begin_string.as_str() {
+                    FIX_42 => ord_status,
+                    _ => "F",
+                };
+                let mut report = self.execution_report(
+                    &cl_ord_id,
+                    &order,
+                    Some(me.oid),
+                    exec_type,
+                    ord_status,
+                    me.leaves_volume,
+                );
+                report
+                    .set(tag::LAST_PX, format_price(me.price))
+                    .set(tag::LAST_QTY, me.volume);
+                vec![self.send(ctx, report)]
+            }
+            OrderStatus::CancelEd | OrderStatus::PartCancel => {
+                self.on_cancelled(ctx, me, cl_ord_id, order)
+            }
+            OrderStatus::Expired => {
+                let report = self.execution_report(&cl_ord_id, &order, Some(me.oid), "C", "C", 0);
+                vec![self.send(ctx, report)]
+            }
+            OrderStatus::Rejected => self.on_rejected(ctx, me, cl_ord_id, order),
+        });
+        self.orders.on_event(me);
+        out
+    }
+
+    fn on_timer(&mut self, ctx: &SessionContext) -> Vec<Inbound<FixMessage>> {
+        if self.state != SessionState::Active {
+            return vec![];
+        }
+        let interval = self.heart_bt_int * 1000;
+        let silence = ctx.now - self.last_received;
+        if silence >= 2 * interval + interval * HEARTBEAT_GRACE / 100 {
+            return self.logout(ctx, "heartbeat timeout");
+        }
+        let mut out = vec![];
+        if silence >= interval + interval * HEARTBEAT_GRACE / 100 && !self.test_request_sent {
+            self.test_request_sent = true;
+            let test_request =
+                FixMessage::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, ctx.now);
+            out.push(self.send(ctx, test_request));
+        }
+        if ctx.now - self.last_sent >= interval {
+            out.push(self.send(ctx, FixMessage::new(msg_type::HEARTBEAT)));
+        }
+        out
+    }
+
+    fn encoder() -> FixEncoder {
+        FixEncoder
+    }
+
+    // Garbled messages are ignored, the sequence gap they leave brings
+    // them back through a resend request.
+    fn on_frame_error(
+        &mut self,
+        ctx: &SessionContext,
+        error: &FrameError,
+    ) -> Vec<Inbound<FixMessage>> {
+        if error.is_fatal() {
+            return self.logout(ctx, &error.to_string());
+        }
+        vec![]
+    }
+
+    // nothing is numbered before the logon
+    fn seq_nums(&self) -> Option<(u64, u64)> {
+        match self.state {
+            SessionState::AwaitingLogon => None,
+            SessionState::Active => Some((self.in_seq.saturating_sub(1), self.out_seq)),
+        }
+    }
+
+    fn on_throttled(&mut self, ctx: &SessionContext, cmd: &RbCmd) -> Vec<Inbound<FixMessage>> {
+        let rejected = MatchEvent::rejected(cmd, ctx.now);
+        let Some((cl_ord_id, order)) = self.orders.get(cmd.oid) else {
+            return vec![];
+        };
+        let (cl_ord_id, order) = (cl_ord_id.to_string(), order.clone());
+        let out = match self.orders.pending_cancel(cmd.oid).map(str::to_string) {
+            Some(cancel_id) => {
+                let response_to = if self.replaces.remove(&cmd.oid).is_some() {
+                    "2"
+                } else {
+                    "1"
+                };
+                let ord_status = if order.cum_qty > 0 { "1" } else { "0" };
+                vec![self.cancel_reject(
+                    ctx,
+                    Some(cmd.oid),
+                    &cancel_id,
+                    &cl_ord_id,
+                    ord_status,
+                    response_to,
+                    OTHER,
+                )]
+            }
+            None => vec![self.order_reject(ctx, &cl_ord_id, &order, None, OTHER, THROTTLED_TEXT)],
+        };
+        self.orders.on_event(&rejected);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicI64;
+
+    use crate::protocol::proto::FrameDecoder;
+
+    use super::*;
+
+    fn replies(actions: Vec<Inbound<FixMessage>>) -> Vec<FixMessage> {
+        actions
+            .into_iter()
+            .filter_map(|a| match a {
+                Inbound::Reply(msg) => Some(msg),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn logged_on(ids: &AtomicI64) -> FixAdapter {
+        let mut adapter = FixAdapter::default();
+        let logon = FixMessage::new(msg_type::LOGON)
+            .with(tag::SENDER_COMP_ID, "OMS")
+            .with(tag::TARGET_COMP_ID, "MATCHER")
+            .with(tag::MSG_SEQ_NUM, 1)
+            .with(tag::HEART_BT_INT, 30);
+        let ctx = SessionContext::new(1, ids).at(0);
+        let out = replies(adapter.on_message(&ctx, logon));
+        assert_eq!(out[0].msg_type, msg_type::LOGON);
+        assert_eq!(out[0].get(tag::SENDER_COMP_ID), Some("MATCHER"));
+        adapter
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let msg = FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tag::CL_ORD_ID, "A1")
+            .with(tag::PRICE, "10.25");
+        let mut buf = BytesMut::new();
+        msg.encode(&mut buf);
+        let text = String::from_utf8(buf.to_vec())
+            .unwrap()
+            .replace('\x01', "|");
+        assert_eq!(text, "8=FIX.4.4|9=20|35=D|11=A1|44=10.25|10=146|");
+
+        let mut decoder = FrameDecoder::new(FixDecoder);
+        decoder.feed(&buf[..10]);
+        assert_eq!(decoder.next_frame(), Ok(None));
+        decoder.feed(&buf[10..]);
+        assert_eq!(decoder.next_frame(), Ok(Some(msg.clone())));
+
+        // garbage in front is skipped up to the next message
+        decoder.feed(b"GET / HTTP/1.1\r\n");
+        decoder.feed(&buf);
+        assert_eq!(decoder.next_frame(), Err(FrameError::Skipped { bytes: 16 }));
+        assert_eq!(decoder.next_frame(), Ok(Some(msg.clone())));
+
+        assert_eq!(parse_price("10.25"), Some(102_500));
+        assert_eq!(parse_price("10.00001"), None);
+        assert_eq!(format_price(102_500), "10.25");
+        assert_eq!(format_price(100_000), "10");
+    }
+
+    #[test]
+    fn test_sequence_gap_and_resend() {
+        let ids = AtomicI64::new(1);
+        let mut adapter = logged_on(&ids);
+        let ctx = SessionContext::new(1, &ids).at(1000);
+
+        // seq 2 went missing
+        let heartbeat = FixMessage::new(msg_type::HEARTBEAT).with(tag::MSG_SEQ_NUM, 3);
+        let out = replies(adapter.on_message(&ctx, heartbeat));
+        assert_eq!(out[0].msg_type, msg_type::RESEND_REQUEST);
+        assert_eq!(out[0].get(tag::BEGIN_SEQ_NO), Some("2"));
+
+        let gap_fill = FixMessage::new(msg_type::SEQUENCE_RESET)
+            .with(tag::MSG_SEQ_NUM, 2)
+            .with(tag::POSS_DUP_FLAG, "Y")
+            .with(tag::GAP_FILL_FLAG, "Y")
+            .with(tag::NEW_SEQ_NO, 4);
+        assert!(adapter.on_message(&ctx, gap_fill).is_empty());
+
+        // the client lost everything after the logon, an order confirmation
+        // among it
+        let order = FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tag::MSG_SEQ_NUM, 4)
+            .with(tag::CL_ORD_ID, "A1")
+            .with(tag::SYMBOL, "600000")
+            .with(tag::SIDE, "1")
+            .with(tag::ORDER_QTY, 100)
+            .with(tag::ORD_TYPE, "2")
+            .with(tag::PRICE, "10.5");
+        let out = adapter.on_message(&ctx, order);
+        let Inbound::Command(EngineCommand::NewOrder(cmd)) = &out[0] else {
+            panic!("expected a new order, got {out:?}");
+        };
+        assert_eq!((cmd.price, cmd.volume), (105_000, 100));
+        let confirm = MatchEvent {
+            session_id: 1,
+            oid: cmd.oid,
+            status: OrderStatus::OrderEd,
+            leaves_volume: 100,
+            ..MatchEvent::default()
+        };
+        let out = replies(adapter.on_event(&ctx, &confirm));
+        assert_eq!(out[0].get(tag::EXEC_TYPE), Some("0"));
+        assert_eq!(out[0].get(tag::MSG_SEQ_NUM), Some("3"));
+
+        let resend = FixMessage::new(msg_type::RESEND_REQUEST)
+            .with(tag::MSG_SEQ_NUM, 5)
+            .with(tag::BEGIN_SEQ_NO, 2)
+            .with(tag::END_SEQ_NO, 0);
+        let out = replies(adapter.on_message(&ctx, resend));
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].msg_type, msg_type::SEQUENCE_RESET);
+        assert_eq!(out[0].get(tag::NEW_SEQ_NO), Some("3"));
+        assert_eq!(out[1].msg_type, msg_type::EXECUTION_REPORT);
+        assert_eq!(out[1].get(tag::POSS_DUP_FLAG), Some("Y"));
+    }
+
+    #[test]
+    fn test_heartbeat_timeout() {
+        let ids = AtomicI64::new(1);
+        let mut adapter = logged_on(&ids);
+        let at = |ms| SessionContext::new(1, &ids).at(ms);
+
+        let out = replies(adapter.on_timer(&at(30_000)));
+        assert_eq!(out[0].msg_type, msg_type::HEARTBEAT);
+        let out = replies(adapter.on_timer(&at(36_000)));
+        assert_eq!(out[0].msg_type, msg_type::TEST_REQUEST);
+        assert!(adapter.on_timer(&at(40_000)).is_empty());
+        let out = adapter.on_timer(&at(66_000));
+        assert_eq!(out.last(), Some(&Inbound::Disconnect));
+    }
+}
@@ -1 +1,5 @@
+pub mod adapter;
+pub mod fix;
 pub mod proto;
+pub mod sse;
+pub mod szse;
@@ -1,28 +1,146 @@
+use anyhow::Context;
 use binary_codec::BinaryCodec;
 use bytes::{Buf, Bytes, BytesMut};
 use chrono::Utc;
 use sse_binary::{new_order_single::NewOrderSingle, report::Report, sse_binary::SseBinary};
+use std::fmt;
 
-use crate::types::{MatchEvent, Order, OrderSide};
+use crate::types::{MatchEvent, Order, OrderSide, TimeInForce};
+
+/// Frames a client sends that can't be told apart from a large read buffer
+/// are cut off at this size.
+pub const DEFAULT_MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// What went wrong reading a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// the header announces more than the decoder takes
+    TooLarge { len: usize, max: usize },
+    /// the header makes no sense and the stream can't be resynced
+    BadHeader,
+    /// garbage in front of the next frame was dropped
+    Skipped { bytes: usize },
+    /// the frame arrived complete but damaged, it was dropped
+    Checksum { expected: u32, actual: u32 },
+    /// a well formed frame of a message the decoder doesn't know
+    Undecodable { len: usize },
+}
+
+impl FrameError {
+    /// After a fatal error the position in the stream is lost and the
+    /// connection is of no further use.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, FrameError::TooLarge { .. } | FrameError::BadHeader)
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::TooLarge { len, max } => {
+                write!(f, "frame of {len} bytes exceeds the limit of {max}")
+            }
+            FrameError::BadHeader => write!(f, "malformed frame header"),
+            FrameError::Skipped { bytes } => write!(f, "skipped {bytes} bytes of garbage"),
+            FrameError::Checksum { expected, actual } => {
+                write!(f, "checksum {actual} does not match {expected}")
+            }
+            FrameError::Undecodable { len } => write!(f, "undecodable frame of {len} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
 
 pub trait ProtocolDecoder: Send + Sync {
     type Message;
 
+    /// Length of the frame at the start of `buf`, header to checksum, once
+    /// enough of the header is there to tell.
+    fn frame_len(&self, buf: &[u8]) -> Result<Option<usize>, FrameError>;
+
+    /// Checks the checksum of a complete frame.
+    fn verify(&self, _frame: &[u8]) -> Result<(), FrameError> {
+        Ok(())
+    }
+
+    /// Where the next frame might start in `buf`, which follows a bad
+    /// header. None if the protocol gives no way to tell.
+    fn resync(&self, _buf: &[u8]) -> Option<usize> {
+        None
+    }
+
     fn decode(&mut self, buf: &mut Bytes) -> Option<Self::Message>;
 }
 
+/// The outbound counterpart of `ProtocolDecoder`.
+pub trait ProtocolEncoder: Send + Sync {
+    type Message;
+
+    /// Appends `msg` to `buf` as one complete frame, header to checksum.
+    fn encode(&mut self, msg: &Self::Message, buf: &mut BytesMut);
+}
+
+/// Checks a binary frame ending in a big-endian u32 checksum, the sum of all
+/// bytes before it modulo 256. That is the Checksum field of both the SSE
+/// TDGW and the SZSE binary interface specifications, the latter gives the
+/// algorithm as `GenerateCheckSum` in its appendix.
+pub fn verify_sum_checksum(frame: &[u8]) -> Result<(), FrameError> {
+    let Some(split) = frame.len().checked_sub(4) else {
+        return Err(FrameError::BadHeader);
+    };
+    let (data, mut trailer) = frame.split_at(split);
+    let expected = data.iter().fold(0u32, |sum, &b| (sum + b as u32) % 256);
+    let actual = trailer.get_u32();
+    if actual != expected {
+        return Err(FrameError::Checksum { expected, actual });
+    }
+    Ok(())
+}
+
 pub struct SseDecoder;
 impl ProtocolDecoder for SseDecoder {
     type Message = SseBinary;
 
+    fn frame_len(&self, buf: &[u8]) -> Result<Option<usize>, FrameError> {
+        // 1. 判断缓冲区是否至少有头部长度
+        if buf.len() < 16 {
+            return Ok(None);
+        }
+
+        // 2. 从缓冲区读取消息头(16字节)，但不移除数据
+        let mut header = &buf[..16];
+        let _msg_type = header.get_u32();
+        let _msg_seq_num = header.get_u64();
+        let msg_body_len = header.get_u32() as usize;
+
+        Ok(Some(16 + msg_body_len + 4)) // 头 + 体 + 校验
+    }
+
+    fn verify(&self, frame: &[u8]) -> Result<(), FrameError> {
+        verify_sum_checksum(frame)
+    }
+
     fn decode(&mut self, buf: &mut Bytes) -> Option<Self::Message> {
         SseBinary::decode(buf)
     }
 }
 
+pub struct SseEncoder;
+impl ProtocolEncoder for SseEncoder {
+    type Message = SseBinary;
+
+    fn encode(&mut self, msg: &SseBinary, buf: &mut BytesMut) {
+        msg.encode(buf);
+    }
+}
+
+/// Cuts a byte stream into frames. Memory stays bounded by the maximum
+/// frame length whatever the client sends.
 pub struct FrameDecoder<D: ProtocolDecoder> {
     buffer: BytesMut,
     decoder: D,
+    max_frame_len: usize,
 }
 
 impl<D: ProtocolDecoder> FrameDecoder<D> {
@@ -30,60 +148,152 @@ impl<D: ProtocolDecoder> FrameDecoder<D> {
         Self {
             buffer: BytesMut::with_capacity(4096),
             decoder,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
         }
     }
 
+    pub fn with_max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+
     pub fn feed(&mut self, data: &[u8]) {
         self.buffer.extend_from_slice(data);
     }
 
-    pub fn next_frame(&mut self) -> Option<D::Message> {
-        // 1. 判断缓冲区是否至少有头部长度
-        if self.buffer.len() < 16 {
-            return None;
-        }
-
-        // 2. 从缓冲区读取消息头(16字节)，但不移除数据
-        let mut header = &self.buffer[..16];
-        let _msg_type = header.get_u32();
-        let _msg_seq_num = header.get_u64();
-        let msg_body_len = header.get_u32() as usize;
+    /// Bytes waiting for the rest of their frame.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
 
-        let total_len = 16 + msg_body_len + 4; // 头 + 体 + 校验
+    /// The next message, None until a whole frame is there. Each error
+    /// stands for bytes taken off the buffer, so calling again makes
+    /// progress; after a fatal one the buffer is empty.
+    pub fn next_frame(&mut self) -> Result<Option<D::Message>, FrameError> {
+        let total_len = match self.decoder.frame_len(&self.buffer) {
+            Ok(Some(0)) | Err(FrameError::BadHeader) => return Err(self.resync()),
+            Ok(Some(len)) => len,
+            Ok(None) if self.buffer.len() > self.max_frame_len => {
+                let len = self.buffer.len();
+                self.buffer.clear();
+                return Err(FrameError::TooLarge {
+                    len,
+                    max: self.max_frame_len,
+                });
+            }
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                self.buffer.clear();
+                return Err(e);
+            }
+        };
+        if total_len > self.max_frame_len {
+            self.buffer.clear();
+            return Err(FrameError::TooLarge {
+                len: total_len,
+                max: self.max_frame_len,
+            });
+        }
 
         // 3. 判断缓冲区是否包含完整消息
         if self.buffer.len() < total_len {
-            return None;
+            return Ok(None);
         }
 
         // 4. 拆出完整消息字节
         let msg_bytes = self.buffer.split_to(total_len).freeze();
+        self.decoder.verify(&msg_bytes)?;
 
         // 5. 解码
         let mut msg_buf = msg_bytes.clone();
-        self.decoder.decode(&mut msg_buf)
+        self.decoder
+            .decode(&mut msg_buf)
+            .map(Some)
+            .ok_or(FrameError::Undecodable { len: total_len })
     }
+
+    // Drops the bad header up to where the decoder thinks the next frame
+    // starts, or everything if it can't tell.
+    fn resync(&mut self) -> FrameError {
+        if self.buffer.is_empty() {
+            return FrameError::BadHeader;
+        }
+        match self.decoder.resync(&self.buffer[1..]) {
+            Some(offset) => {
+                let bytes = offset + 1;
+                self.buffer.advance(bytes);
+                FrameError::Skipped { bytes }
+            }
+            None => {
+                self.buffer.clear();
+                FrameError::BadHeader
+            }
+        }
+    }
+}
+
+/// Collects outbound frames until they are written out.
+pub struct FrameEncoder<E: ProtocolEncoder> {
+    buffer: BytesMut,
+    encoder: E,
 }
 
-impl From<&NewOrderSingle> for Order {
-    fn from(order: &NewOrderSingle) -> Self {
+impl<E: ProtocolEncoder> FrameEncoder<E> {
+    pub fn new(encoder: E) -> Self {
+        Self {
+            buffer: BytesMut::with_capacity(4096),
+            encoder,
+        }
+    }
+
+    pub fn push(&mut self, msg: &E::Message) {
+        self.encoder.encode(msg, &mut self.buffer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// The frames pushed so far, leaving the buffer empty.
+    pub fn take(&mut self) -> Bytes {
+        self.buffer.split().freeze()
+    }
+}
+
+/// TimeInForce of an SSE order: 0 good for the day, 3 immediate or cancel.
+/// None for what the matcher doesn't support.
+pub fn sse_time_in_force(value: &str) -> Option<TimeInForce> {
+    match value {
+        "0" => Some(TimeInForce::Day),
+        "3" => Some(TimeInForce::Ioc),
+        _ => None,
+    }
+}
+
+impl TryFrom<&NewOrderSingle> for Order {
+    type Error = anyhow::Error;
+
+    fn try_from(order: &NewOrderSingle) -> anyhow::Result<Self> {
         let side = match order.side.as_str() {
             "1" => OrderSide::Buy,
             _ => OrderSide::Sell,
         };
+        let time_in_force = sse_time_in_force(&order.time_in_force)
+            .with_context(|| format!("unsupported TimeInForce {:?}", order.time_in_force))?;
 
-        Order {
+        Ok(Order {
             session_id: 0,
-            oid: order.cl_ord_id.parse::<i64>().unwrap(),
-            security_id: order.security_id.clone(),
+            // channels give orders engine ids, ClOrdID need not be numeric
+            oid: order.cl_ord_id.parse::<i64>().unwrap_or_default(),
             side: side,
             price: order.price,
             volume: order.order_qty,
             mid: 0,
             uid: 0,
             tvolume: 0,
+            time_in_force,
             timestamp: Utc::now().timestamp_millis(),
-        }
+        })
     }
 }
 
@@ -105,8 +315,8 @@ impl From<&MatchEvent> for Report {
             last_qty: me.volume,
             gross_trade_amt: me.price * me.volume,
             side: "1".to_string(),
-            order_qty: 1,
-            leaves_qty: 1,
+            order_qty: me.cum_volume + me.leaves_volume,
+            leaves_qty: me.leaves_volume,
             ord_status: "".to_string(),
             credit_tag: "".to_string(),
             clearing_firm: "".to_string(),
@@ -127,7 +337,7 @@ mod tests {
     use super::*;
     #[test]
     fn test_order_request_from_new_order_single() {
-        let order = NewOrderSingle {
+        let mut order = NewOrderSingle {
             cl_ord_id: "123".to_string(),
             security_id: "AAPL".to_string(),
             side: "BUY".to_string(),
@@ -145,7 +355,48 @@ mod tests {
             branch_id: "test".to_string(),
             user_info: "xxx".to_string(),
         };
-        let order = Order::from(&order);
-        assert_eq!(order.oid, 123);
+        let decoded = Order::try_from(&order).unwrap();
+        assert_eq!(decoded.oid, 123);
+        assert_eq!(decoded.time_in_force, TimeInForce::Ioc);
+
+        order.time_in_force = "0".to_string();
+        assert_eq!(
+            Order::try_from(&order).unwrap().time_in_force,
+            TimeInForce::Day
+        );
+        // fill or kill is not supported
+        order.time_in_force = "4".to_string();
+        assert!(Order::try_from(&order).is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_checksum() {
+        // msg type 33, seq 1, no body: 33 + 1
+        let heartbeat = [0, 0, 0, 33, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 34];
+        assert_eq!(verify_sum_checksum(&heartbeat), Ok(()));
+        let mut damaged = heartbeat;
+        damaged[11] = 2;
+        assert_eq!(
+            verify_sum_checksum(&damaged),
+            Err(FrameError::Checksum {
+                expected: 35,
+                actual: 34
+            })
+        );
+    }
+
+    #[test]
+    fn test_oversized_frame_is_refused() {
+        let mut decoder = FrameDecoder::new(SseDecoder).with_max_frame_len(1024);
+        // a header announcing a 4 GB body
+        decoder.feed(&[0, 0, 0, 58, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(
+            decoder.next_frame(),
+            Err(FrameError::TooLarge {
+                len: 16 + u32::MAX as usize + 4,
+                max: 1024
+            })
+        );
+        assert_eq!(decoder.buffered(), 0);
     }
 }
@@ -0,0 +1,314 @@
+use sse_binary::confirm::Confirm;
+use sse_binary::new_order_single::NewOrderSingle;
+use sse_binary::report::Report;
+use sse_binary::sse_binary::{SseBinary, SseBinaryBodyEnum};
+use tracing::{info, warn};
+
+use crate::protocol::adapter::{ClientOrders, Inbound, ProtocolAdapter, SessionContext};
+use crate::protocol::proto::{FrameError, SseDecoder, SseEncoder};
+use crate::types::{EngineCommand, MatchEvent, Order, OrderStatus, RbCmd};
+
+pub const CONFIRM_MSG_TYPE: u32 = 32;
+pub const REPORT_MSG_TYPE: u32 = 103;
+
+// OrdRejReason of an order the matcher refuses
+const UNSUPPORTED_REJECT_REASON: u32 = 1;
+
+/// SSE binary sessions: new orders in; a confirm for orders resting in the
+/// book, rejected, cancelled or expired, and execution reports for fills
+/// out. A damaged frame ends the session, there is no message to reject one
+/// with.
+#[derive(Debug, Default)]
+pub struct SseAdapter {
+    orders: ClientOrders<NewOrderSingle>,
+}
+
+impl ProtocolAdapter for SseAdapter {
+    type Message = SseBinary;
+    type Decoder = SseDecoder;
+    type Encoder = SseEncoder;
+
+    fn decoder() -> SseDecoder {
+        SseDecoder
+    }
+
+    fn on_message(&mut self, ctx: &SessionContext, msg: SseBinary) -> Vec<Inbound<SseBinary>> {
+        match msg.body {
+            SseBinaryBodyEnum::Logon(logon) => {
+                info!("Logon received: {:?}", logon);
+            }
+            SseBinaryBodyEnum::Heartbeat(_) => {
+                info!("Heartbeat received");
+            }
+            SseBinaryBodyEnum::NewOrderSingle(order) => {
+                let order_request = match Order::try_from(&order) {
+                    Ok(order_request) => order_request,
+                    Err(e) => {
+                        warn!("Rejected order {}: {}", order.cl_ord_id, e);
+                        let reject = confirm(ctx, &order, "8", UNSUPPORTED_REJECT_REASON);
+                        return vec![Inbound::Reply(reject)];
+                    }
+                };
+                let oid = ctx.next_oid();
+                let cmd = RbCmd {
+                    session_id: ctx.session_id,
+                    side: order_request.side,
+                    match_event_list: vec![],
+                    price: order_request.price,
+                    volume: order_request.volume,
+                    mid: oid,
+                    oid,
+                    uid: order_request.uid,
+                    security_id: order.security_id.clone(),
+                    time_in_force: order_request.time_in_force,
+                };
+                info!("Order will process: {:?}", cmd);
+                self.orders.insert(oid, order.cl_ord_id.clone(), order);
+                return vec![Inbound::Command(EngineCommand::NewOrder(cmd))];
+            }
+            _ => {
+                info!("Unknown message type received");
+            }
+        }
+        vec![]
+    }
+
+    fn on_event(&mut self, ctx: &SessionContext, me: &MatchEvent) -> Vec<Inbound<SseBinary>> {
+        let mut out = vec![];
+        let Some((cl_ord_id, order)) = self.orders.get(me.oid) else {
+            return out;
+        };
+        match me.status {
+            // the engine only confirms orders that rest without trading
+            OrderStatus::OrderEd => out.push(Inbound::Reply(confirm(ctx, order, "0", 0))),
+            OrderStatus::Rejected => {
+                let reject = confirm(ctx, order, "8", UNSUPPORTED_REJECT_REASON);
+                out.push(Inbound::Reply(reject));
+            }
+            OrderStatus::PartTrade | OrderStatus::TradeEd => {
+                let mut report = Report::from(me);
+                report.cl_ord_id = cl_ord_id.to_string();
+                report.security_id = order.security_id.clone();
+                report.side = order.side.clone();
+                out.push(Inbound::Reply(SseBinary {
+                    msg_type: REPORT_MSG_TYPE,
+                    msg_seq_num: 1,
+                    msg_body_len: 0,
+                    body: SseBinaryBodyEnum::Report(report),
+                    checksum: 0,
+                }));
+            }
+            // an IOC rest cancelled or an order expired, what was taken off
+            // the book is cancelled
+            OrderStatus::CancelEd | OrderStatus::PartCancel | OrderStatus::Expired => {
+                let mut cancel = confirm(ctx, order, "4", 0);
+                if let SseBinaryBodyEnum::Confirm(confirm) = &mut cancel.body {
+                    confirm.cxl_qty = me.volume;
+                }
+                out.push(Inbound::Reply(cancel));
+            }
+        }
+        self.orders.on_event(me);
+        out
+    }
+
+    fn on_frame_error(
+        &mut self,
+        ctx: &SessionContext,
+        error: &FrameError,
+    ) -> Vec<Inbound<SseBinary>> {
+        warn!("Closing session {}: {}", ctx.session_id, error);
+        vec![Inbound::Disconnect]
+    }
+
+    fn encoder() -> SseEncoder {
+        SseEncoder
+    }
+}
+
+// An order confirm of `exec_type`, 0 accepted, 4 cancelled and 8 rejected,
+// which is also the order's status.
+fn confirm(
+    ctx: &SessionContext,
+    order: &NewOrderSingle,
+    exec_type: &str,
+    ord_rej_reason: u32,
+) -> SseBinary {
+    let leaves_qty = match exec_type {
+        "4" | "8" => 0,
+        _ => order.order_qty,
+    };
+    SseBinary {
+        msg_type: CONFIRM_MSG_TYPE,
+        msg_seq_num: 1,
+        msg_body_len: 0,
+        body: SseBinaryBodyEnum::Confirm(Confirm {
+            pbu: order.biz_pbu.clone(),
+            set_id: 1,
+            report_index: 1,
+            biz_id: order.biz_id,
+            exec_type: exec_type.to_string(),
+            biz_pbu: order.biz_pbu.clone(),
+            cl_ord_id: order.cl_ord_id.clone(),
+            security_id: order.security_id.clone(),
+            account: order.account.clone(),
+            owner_type: order.owner_type,
+            side: order.side.clone(),
+            price: order.price,
+            order_qty: order.order_qty,
+            leaves_qty,
+            cxl_qty: 0,
+            ord_type: order.ord_type.clone(),
+            time_in_force: order.time_in_force.clone(),
+            ord_status: exec_type.to_string(),
+            credit_tag: order.credit_tag.clone(),
+            orig_cl_ord_id: "".to_string(),
+            clearing_firm: order.clearing_firm.clone(),
+            branch_id: order.branch_id.clone(),
+            ord_rej_reason,
+            ord_cnfm_id: "".to_string(),
+            orig_ord_cnfm_id: "".to_string(),
+            trade_date: 1,
+            transact_time: ctx.now as u64,
+            user_info: order.user_info.clone(),
+        }),
+        checksum: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicI64;
+
+    use crate::types::TimeInForce;
+
+    use super::*;
+
+    fn order(time_in_force: &str) -> NewOrderSingle {
+        NewOrderSingle {
+            biz_id: 10,
+            biz_pbu: "pbu001".to_string(),
+            cl_ord_id: "100013".to_string(),
+            security_id: "600519".to_string(),
+            account: "a00001".to_string(),
+            owner_type: 1,
+            side: "1".to_string(),
+            price: 100,
+            order_qty: 500,
+            ord_type: "2".to_string(),
+            time_in_force: time_in_force.to_string(),
+            transact_time: 0,
+            credit_tag: "1".to_string(),
+            clearing_firm: "1001".to_string(),
+            branch_id: "b001".to_string(),
+            user_info: "".to_string(),
+        }
+    }
+
+    fn message(order: NewOrderSingle) -> SseBinary {
+        SseBinary {
+            msg_type: 58,
+            msg_seq_num: 1,
+            msg_body_len: 0,
+            body: SseBinaryBodyEnum::NewOrderSingle(order),
+            checksum: 0,
+        }
+    }
+
+    #[test]
+    fn test_bad_frame_ends_the_session() {
+        let ids = AtomicI64::new(1);
+        let ctx = SessionContext::new(1, &ids);
+        let mut adapter = SseAdapter::default();
+        for error in [
+            FrameError::Checksum {
+                expected: 35,
+                actual: 34,
+            },
+            FrameError::Undecodable { len: 20 },
+        ] {
+            let actions = adapter.on_frame_error(&ctx, &error);
+            assert!(matches!(actions[..], [Inbound::Disconnect]), "{actions:?}");
+        }
+    }
+
+    #[test]
+    fn test_resting_order_is_confirmed() {
+        let ids = AtomicI64::new(1);
+        let ctx = SessionContext::new(1, &ids);
+        let mut adapter = SseAdapter::default();
+        let actions = adapter.on_message(&ctx, message(order("0")));
+        let [Inbound::Command(EngineCommand::NewOrder(cmd))] = &actions[..] else {
+            panic!("{actions:?}");
+        };
+        let mut rested = MatchEvent::rejected(cmd, 0);
+        rested.status = OrderStatus::OrderEd;
+        rested.leaves_volume = cmd.volume;
+
+        let actions = adapter.on_event(&ctx, &rested);
+        let [Inbound::Reply(reply)] = &actions[..] else {
+            panic!("{actions:?}");
+        };
+        let SseBinaryBodyEnum::Confirm(confirm) = &reply.body else {
+            panic!("{reply:?}");
+        };
+        assert_eq!(reply.msg_type, CONFIRM_MSG_TYPE);
+        assert_eq!((confirm.exec_type.as_str(), confirm.leaves_qty), ("0", 500));
+        assert_eq!(confirm.cl_ord_id, "100013");
+    }
+
+    #[test]
+    fn test_ioc_rest_is_confirmed_cancelled() {
+        let ids = AtomicI64::new(1);
+        let ctx = SessionContext::new(1, &ids);
+        let mut adapter = SseAdapter::default();
+        let actions = adapter.on_message(&ctx, message(order("3")));
+        let [Inbound::Command(EngineCommand::NewOrder(cmd))] = &actions[..] else {
+            panic!("{actions:?}");
+        };
+        // 200 traded, the other 300 cancelled
+        let mut cancelled = MatchEvent::rejected(cmd, 0);
+        cancelled.status = OrderStatus::PartCancel;
+        cancelled.volume = 300;
+        cancelled.cum_volume = 200;
+
+        let actions = adapter.on_event(&ctx, &cancelled);
+        let [Inbound::Reply(reply)] = &actions[..] else {
+            panic!("{actions:?}");
+        };
+        let SseBinaryBodyEnum::Confirm(confirm) = &reply.body else {
+            panic!("{reply:?}");
+        };
+        assert_eq!(
+            (confirm.exec_type.as_str(), confirm.ord_status.as_str()),
+            ("4", "4")
+        );
+        assert_eq!((confirm.cxl_qty, confirm.leaves_qty), (300, 0));
+        // the order is done with
+        assert!(adapter.on_event(&ctx, &cancelled).is_empty());
+    }
+
+    #[test]
+    fn test_unsupported_time_in_force_is_rejected() {
+        let ids = AtomicI64::new(1);
+        let ctx = SessionContext::new(1, &ids);
+        let mut adapter = SseAdapter::default();
+        let actions = adapter.on_message(&ctx, message(order("3")));
+        let [Inbound::Command(EngineCommand::NewOrder(cmd))] = &actions[..] else {
+            panic!("{actions:?}");
+        };
+        assert_eq!(cmd.time_in_force, TimeInForce::Ioc);
+
+        let actions = adapter.on_message(&ctx, message(order("4")));
+        let [Inbound::Reply(reply)] = &actions[..] else {
+            panic!("{actions:?}");
+        };
+        let SseBinaryBodyEnum::Confirm(confirm) = &reply.body else {
+            panic!("{reply:?}");
+        };
+        assert_eq!(reply.msg_type, CONFIRM_MSG_TYPE);
+        assert_eq!(confirm.exec_type, "8");
+        assert_eq!(confirm.cl_ord_id, "100013");
+        assert_eq!(confirm.ord_rej_reason, UNSUPPORTED_REJECT_REASON);
+    }
+}
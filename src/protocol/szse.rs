@@ -0,0 +1,667 @@
+//! SZSE STEP-binary messages used by the matcher.
+//!
+//! Every message is `MsgType: u32, BodyLength: u32, body, Checksum: u32`,
+//! integers big-endian. The checksum is the sum of all preceding bytes of the
+//! message modulo 256. Character fields are fixed width, padded with spaces.
+//! Prices (N13(4)) and quantities (N15(2)) are carried as the scaled integers
+//! found on the wire and used as such by the engine.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use chrono::Utc;
+use tracing::info;
+
+use crate::protocol::adapter::{ClientOrders, Inbound, ProtocolAdapter, SessionContext};
+use crate::protocol::proto::{FrameError, ProtocolDecoder, ProtocolEncoder, verify_sum_checksum};
+use crate::types::{EngineCommand, MatchEvent, OrderSide, OrderStatus, RbCmd, TimeInForce};
+
+pub const LOGON_MSG_TYPE: u32 = 1;
+pub const LOGOUT_MSG_TYPE: u32 = 2;
+pub const HEARTBEAT_MSG_TYPE: u32 = 3;
+pub const NEW_ORDER_MSG_TYPE: u32 = 100101;
+pub const ORDER_CANCEL_REQUEST_MSG_TYPE: u32 = 190007;
+pub const EXECUTION_REPORT_MSG_TYPE: u32 = 200102;
+pub const TRADE_REPORT_MSG_TYPE: u32 = 200115;
+
+pub const HEADER_LEN: usize = 8;
+pub const CHECKSUM_LEN: usize = 4;
+
+// OrdRejReason codes
+const UNSUPPORTED_REJECT_REASON: u16 = 1;
+const DUPLICATE_REJECT_REASON: u16 = 6;
+const UNKNOWN_ORDER_REJECT_REASON: u16 = 5;
+const THROTTLED_REJECT_REASON: u16 = 20;
+
+fn put_chars(buf: &mut BytesMut, value: &str, width: usize) {
+    let bytes = value.as_bytes();
+    let len = bytes.len().min(width);
+    buf.put_slice(&bytes[..len]);
+    buf.put_bytes(b' ', width - len);
+}
+
+fn get_chars(buf: &mut Bytes, width: usize) -> Option<String> {
+    if buf.remaining() < width {
+        return None;
+    }
+    let raw = buf.split_to(width);
+    let text = std::str::from_utf8(&raw).ok()?;
+    Some(text.trim_end_matches([' ', '\0']).to_string())
+}
+
+fn get_i64(buf: &mut Bytes) -> Option<i64> {
+    (buf.remaining() >= 8).then(|| buf.get_i64())
+}
+
+fn get_i32(buf: &mut Bytes) -> Option<i32> {
+    (buf.remaining() >= 4).then(|| buf.get_i32())
+}
+
+fn get_u16(buf: &mut Bytes) -> Option<u16> {
+    (buf.remaining() >= 2).then(|| buf.get_u16())
+}
+
+// Declares a message body with its wire layout: `chars(n)` fields are
+// `String`s of width n, the rest are plain integers.
+macro_rules! szse_body {
+    (@ty chars($n:expr)) => { String };
+    (@ty $t:ident) => { $t };
+    (@put $buf:ident, $v:expr, chars($n:expr)) => { put_chars($buf, &$v, $n) };
+    (@put $buf:ident, $v:expr, i64) => { $buf.put_i64($v) };
+    (@put $buf:ident, $v:expr, i32) => { $buf.put_i32($v) };
+    (@put $buf:ident, $v:expr, u16) => { $buf.put_u16($v) };
+    (@get $buf:ident, chars($n:expr)) => { get_chars($buf, $n)? };
+    (@get $buf:ident, i64) => { get_i64($buf)? };
+    (@get $buf:ident, i32) => { get_i32($buf)? };
+    (@get $buf:ident, u16) => { get_u16($buf)? };
+    ($(#[$meta:meta])* $name:ident { $($field:ident: $kind:ident $(($n:expr))?),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Default)]
+        pub struct $name {
+            $(pub $field: szse_body!(@ty $kind $(($n))?),)*
+        }
+
+        // `buf` goes unused for empty bodies
+        #[allow(unused_variables)]
+        impl $name {
+            pub fn encode(&self, buf: &mut BytesMut) {
+                $(szse_body!(@put buf, self.$field.clone(), $kind $(($n))?);)*
+            }
+
+            pub fn decode(buf: &mut Bytes) -> Option<Self> {
+                Some(Self {
+                    $($field: szse_body!(@get buf, $kind $(($n))?),)*
+                })
+            }
+        }
+    };
+}
+
+szse_body!(Logon {
+    sender_comp_id: chars(20),
+    target_comp_id: chars(20),
+    heart_bt_int: i32,
+    password: chars(16),
+    default_appl_ver_id: chars(32),
+});
+
+szse_body!(Logout {
+    session_status: i32,
+    text: chars(200),
+});
+
+szse_body!(Heartbeat {});
+
+szse_body!(
+    /// New order for the cash market (ApplID 010).
+    NewOrder {
+        appl_id: chars(3),
+        submitting_pbu_id: chars(6),
+        security_id: chars(8),
+        security_id_source: chars(4),
+        owner_type: u16,
+        clearing_firm: chars(2),
+        transact_time: i64,
+        user_info: chars(8),
+        cl_ord_id: chars(10),
+        account_id: chars(12),
+        branch_id: chars(4),
+        order_restrictions: chars(4),
+        side: chars(1),
+        ord_type: chars(1),
+        order_qty: i64,
+        price: i64,
+        stop_px: i64,
+        min_qty: i64,
+        max_price_levels: u16,
+        time_in_force: chars(1),
+    }
+);
+
+szse_body!(OrderCancelRequest {
+    appl_id: chars(3),
+    submitting_pbu_id: chars(6),
+    security_id: chars(8),
+    security_id_source: chars(4),
+    owner_type: u16,
+    clearing_firm: chars(2),
+    transact_time: i64,
+    user_info: chars(8),
+    cl_ord_id: chars(10),
+    orig_cl_ord_id: chars(10),
+    side: chars(1),
+    order_id: chars(16),
+    order_qty: i64,
+});
+
+szse_body!(
+    /// Order confirmation, cancel and reject report.
+    ExecutionReport {
+        partition_no: i32,
+        report_index: i64,
+        appl_id: chars(3),
+        reporting_pbu_id: chars(6),
+        submitting_pbu_id: chars(6),
+        security_id: chars(8),
+        security_id_source: chars(4),
+        owner_type: u16,
+        clearing_firm: chars(2),
+        transact_time: i64,
+        user_info: chars(8),
+        order_id: chars(16),
+        cl_ord_id: chars(10),
+        orig_cl_ord_id: chars(10),
+        exec_id: chars(16),
+        exec_type: chars(1),
+        ord_status: chars(1),
+        ord_rej_reason: u16,
+        leaves_qty: i64,
+        cum_qty: i64,
+        side: chars(1),
+        ord_type: chars(1),
+        order_qty: i64,
+        price: i64,
+        account_id: chars(12),
+        branch_id: chars(4),
+        order_restrictions: chars(4),
+    }
+);
+
+szse_body!(
+    /// Fill report.
+    TradeReport {
+        partition_no: i32,
+        report_index: i64,
+        appl_id: chars(3),
+        reporting_pbu_id: chars(6),
+        submitting_pbu_id: chars(6),
+        security_id: chars(8),
+        security_id_source: chars(4),
+        owner_type: u16,
+        clearing_firm: chars(2),
+        transact_time: i64,
+        user_info: chars(8),
+        order_id: chars(16),
+        cl_ord_id: chars(10),
+        exec_id: chars(16),
+        exec_type: chars(1),
+        ord_status: chars(1),
+        last_px: i64,
+        last_qty: i64,
+        leaves_qty: i64,
+        cum_qty: i64,
+        side: chars(1),
+        account_id: chars(12),
+        branch_id: chars(4),
+    }
+);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SzseMessage {
+    Logon(Logon),
+    Logout(Logout),
+    Heartbeat(Heartbeat),
+    NewOrder(NewOrder),
+    OrderCancelRequest(OrderCancelRequest),
+    ExecutionReport(ExecutionReport),
+    TradeReport(TradeReport),
+}
+
+impl SzseMessage {
+    pub fn msg_type(&self) -> u32 {
+        match self {
+            SzseMessage::Logon(_) => LOGON_MSG_TYPE,
+            SzseMessage::Logout(_) => LOGOUT_MSG_TYPE,
+            SzseMessage::Heartbeat(_) => HEARTBEAT_MSG_TYPE,
+            SzseMessage::NewOrder(_) => NEW_ORDER_MSG_TYPE,
+            SzseMessage::OrderCancelRequest(_) => ORDER_CANCEL_REQUEST_MSG_TYPE,
+            SzseMessage::ExecutionReport(_) => EXECUTION_REPORT_MSG_TYPE,
+            SzseMessage::TradeReport(_) => TRADE_REPORT_MSG_TYPE,
+        }
+    }
+
+    /// Appends the framed message: header, body and checksum.
+    pub fn encode(&self, buf: &mut BytesMut) {
+        let mut body = BytesMut::new();
+        match self {
+            SzseMessage::Logon(m) => m.encode(&mut body),
+            SzseMessage::Logout(m) => m.encode(&mut body),
+            SzseMessage::Heartbeat(m) => m.encode(&mut body),
+            SzseMessage::NewOrder(m) => m.encode(&mut body),
+            SzseMessage::OrderCancelRequest(m) => m.encode(&mut body),
+            SzseMessage::ExecutionReport(m) => m.encode(&mut body),
+            SzseMessage::TradeReport(m) => m.encode(&mut body),
+        }
+        let start = buf.len();
+        buf.put_u32(self.msg_type());
+        buf.put_u32(body.len() as u32);
+        buf.put_slice(&body);
+        let checksum = checksum(&buf[start..]);
+        buf.put_u32(checksum);
+    }
+
+    /// Decodes one complete framed message. Unknown types and short bodies
+    /// give None.
+    pub fn decode(buf: &mut Bytes) -> Option<Self> {
+        if buf.remaining() < HEADER_LEN {
+            return None;
+        }
+        let msg_type = buf.get_u32();
+        let body_len = buf.get_u32() as usize;
+        if buf.remaining() < body_len + CHECKSUM_LEN {
+            return None;
+        }
+        let mut body = buf.split_to(body_len);
+        buf.advance(CHECKSUM_LEN);
+        let body = &mut body;
+        Some(match msg_type {
+            LOGON_MSG_TYPE => SzseMessage::Logon(Logon::decode(body)?),
+            LOGOUT_MSG_TYPE => SzseMessage::Logout(Logout::decode(body)?),
+            HEARTBEAT_MSG_TYPE => SzseMessage::Heartbeat(Heartbeat::decode(body)?),
+            NEW_ORDER_MSG_TYPE => SzseMessage::NewOrder(NewOrder::decode(body)?),
+            ORDER_CANCEL_REQUEST_MSG_TYPE => {
+                SzseMessage::OrderCancelRequest(OrderCancelRequest::decode(body)?)
+            }
+            EXECUTION_REPORT_MSG_TYPE => {
+                SzseMessage::ExecutionReport(ExecutionReport::decode(body)?)
+            }
+            TRADE_REPORT_MSG_TYPE => SzseMessage::TradeReport(TradeReport::decode(body)?),
+            _ => return None,
+        })
+    }
+}
+
+/// Sum of `bytes` modulo 256.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |sum, &b| (sum + b as u32) % 256)
+}
+
+pub struct SzseDecoder;
+impl ProtocolDecoder for SzseDecoder {
+    type Message = SzseMessage;
+
+    fn frame_len(&self, buf: &[u8]) -> Result<Option<usize>, FrameError> {
+        if buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let body_len = u32::from_be_bytes(buf[4..8].try_into().unwrap()) as usize;
+        Ok(Some(HEADER_LEN + body_len + CHECKSUM_LEN))
+    }
+
+    fn verify(&self, frame: &[u8]) -> Result<(), FrameError> {
+        verify_sum_checksum(frame)
+    }
+
+    fn decode(&mut self, buf: &mut Bytes) -> Option<Self::Message> {
+        SzseMessage::decode(buf)
+    }
+}
+
+pub struct SzseEncoder;
+impl ProtocolEncoder for SzseEncoder {
+    type Message = SzseMessage;
+
+    fn encode(&mut self, msg: &SzseMessage, buf: &mut BytesMut) {
+        msg.encode(buf);
+    }
+}
+
+/// SZSE binary sessions: logon, logout, new orders and cancels in,
+/// execution and fill reports out.
+#[derive(Debug, Default)]
+pub struct SzseAdapter {
+    orders: ClientOrders<NewOrder>,
+    report_index: i64,
+}
+
+impl SzseAdapter {
+    fn next_report_index(&mut self) -> i64 {
+        self.report_index += 1;
+        self.report_index
+    }
+
+    fn execution_report(&mut self, order: &NewOrder, oid: i64) -> ExecutionReport {
+        ExecutionReport {
+            partition_no: 1,
+            report_index: self.next_report_index(),
+            appl_id: order.appl_id.clone(),
+            reporting_pbu_id: order.submitting_pbu_id.clone(),
+            submitting_pbu_id: order.submitting_pbu_id.clone(),
+            security_id: order.security_id.clone(),
+            security_id_source: order.security_id_source.clone(),
+            owner_type: order.owner_type,
+            clearing_firm: order.clearing_firm.clone(),
+            transact_time: Utc::now().timestamp_millis(),
+            user_info: order.user_info.clone(),
+            order_id: oid.to_string(),
+            cl_ord_id: order.cl_ord_id.clone(),
+            side: order.side.clone(),
+            ord_type: order.ord_type.clone(),
+            order_qty: order.order_qty,
+            price: order.price,
+            account_id: order.account_id.clone(),
+            branch_id: order.branch_id.clone(),
+            order_restrictions: order.order_restrictions.clone(),
+            ..ExecutionReport::default()
+        }
+    }
+
+    // reject of a message that never reached the engine
+    fn reject(&mut self, order: &NewOrder, reason: u16) -> SzseMessage {
+        let mut report = self.execution_report(order, 0);
+        report.exec_type = "8".to_string();
+        report.ord_status = "8".to_string();
+        report.ord_rej_reason = reason;
+        SzseMessage::ExecutionReport(report)
+    }
+
+    fn new_order(&mut self, ctx: &SessionContext, order: NewOrder) -> Inbound<SzseMessage> {
+        let side = match order.side.as_str() {
+            "1" => OrderSide::Buy,
+            "2" => OrderSide::Sell,
+            _ => return Inbound::Reply(self.reject(&order, UNSUPPORTED_REJECT_REASON)),
+        };
+        let mut time_in_force = match order.time_in_force.as_str() {
+            "3" => TimeInForce::Ioc,
+            _ => TimeInForce::Day,
+        };
+        let price = match order.ord_type.as_str() {
+            "2" => order.price,
+            // market orders trade what they can and leave nothing behind
+            "1" => {
+                time_in_force = TimeInForce::Ioc;
+                match side {
+                    OrderSide::Buy => i64::MAX,
+                    OrderSide::Sell => 0,
+                }
+            }
+            _ => return Inbound::Reply(self.reject(&order, UNSUPPORTED_REJECT_REASON)),
+        };
+        if self.orders.oid(&order.cl_ord_id).is_some() {
+            return Inbound::Reply(self.reject(&order, DUPLICATE_REJECT_REASON));
+        }
+        let oid = ctx.next_oid();
+        let cmd = RbCmd {
+            session_id: ctx.session_id,
+            side,
+            match_event_list: vec![],
+            price,
+            volume: order.order_qty,
+            mid: oid,
+            uid: 0,
+            oid,
+            security_id: order.security_id.clone(),
+            time_in_force,
+        };
+        self.orders.insert(oid, order.cl_ord_id.clone(), order);
+        Inbound::Command(EngineCommand::NewOrder(cmd))
+    }
+
+    fn cancel(&mut self, ctx: &SessionContext, cancel: OrderCancelRequest) -> Inbound<SzseMessage> {
+        let Some((oid, order)) = self
+            .orders
+            .oid(&cancel.orig_cl_ord_id)
+            .and_then(|oid| self.orders.get(oid).map(|(_, o)| (oid, o.clone())))
+        else {
+            let order = NewOrder {
+                appl_id: cancel.appl_id,
+                submitting_pbu_id: cancel.submitting_pbu_id,
+                security_id: cancel.security_id,
+                security_id_source: cancel.security_id_source,
+                owner_type: cancel.owner_type,
+                clearing_firm: cancel.clearing_firm,
+                user_info: cancel.user_info,
+                cl_ord_id: cancel.cl_ord_id,
+                side: cancel.side,
+                ..NewOrder::default()
+            };
+            let mut reject = self.reject(&order, UNKNOWN_ORDER_REJECT_REASON);
+            if let SzseMessage::ExecutionReport(report) = &mut reject {
+                report.orig_cl_ord_id = cancel.orig_cl_ord_id;
+            }
+            return Inbound::Reply(reject);
+        };
+        self.orders.cancel_requested(oid, cancel.cl_ord_id);
+        Inbound::Command(EngineCommand::CancelOrder(RbCmd {
+            session_id: ctx.session_id,
+            side: if order.side == "1" {
+                OrderSide::Buy
+            } else {
+                OrderSide::Sell
+            },
+            match_event_list: vec![],
+            price: order.price,
+            volume: 0,
+            mid: oid,
+            uid: 0,
+            oid,
+            security_id: order.security_id,
+            time_in_force: TimeInForce::Day,
+        }))
+    }
+}
+
+impl ProtocolAdapter for SzseAdapter {
+    type Message = SzseMessage;
+    type Decoder = SzseDecoder;
+    type Encoder = SzseEncoder;
+
+    fn decoder() -> SzseDecoder {
+        SzseDecoder
+    }
+
+    fn on_message(&mut self, ctx: &SessionContext, msg: SzseMessage) -> Vec<Inbound<SzseMessage>> {
+        match msg {
+            SzseMessage::Logon(logon) => {
+                info!("Logon received: {:?}", logon);
+                vec![Inbound::Reply(SzseMessage::Logon(Logon {
+                    sender_comp_id: logon.target_comp_id,
+                    target_comp_id: logon.sender_comp_id,
+                    heart_bt_int: logon.heart_bt_int,
+                    password: String::new(),
+                    default_appl_ver_id: logon.default_appl_ver_id,
+                }))]
+            }
+            SzseMessage::Logout(logout) => {
+                info!("Logout received: {:?}", logout);
+                vec![
+                    Inbound::Reply(SzseMessage::Logout(Logout {
+                        session_status: 0,
+                        text: String::new(),
+                    })),
+                    Inbound::Disconnect,
+                ]
+            }
+            SzseMessage::Heartbeat(_) => vec![],
+            SzseMessage::NewOrder(order) => vec![self.new_order(ctx, order)],
+            SzseMessage::OrderCancelRequest(cancel) => vec![self.cancel(ctx, cancel)],
+            other => {
+                info!("Unexpected message received: {:?}", other);
+                vec![]
+            }
+        }
+    }
+
+    fn on_event(&mut self, _ctx: &SessionContext, me: &MatchEvent) -> Vec<Inbound<SzseMessage>> {
+        let Some((cl_ord_id, order)) = self.orders.get(me.oid) else {
+            return vec![];
+        };
+        let (cl_ord_id, order) = (cl_ord_id.to_string(), order.clone());
+        let cancel_id = self.orders.pending_cancel(me.oid).map(str::to_string);
+        let msg = match me.status {
+            OrderStatus::PartTrade | OrderStatus::TradeEd => {
+                SzseMessage::TradeReport(TradeReport {
+                    partition_no: 1,
+                    report_index: self.next_report_index(),
+                    appl_id: order.appl_id.clone(),
+                    reporting_pbu_id: order.submitting_pbu_id.clone(),
+                    submitting_pbu_id: order.submitting_pbu_id.clone(),
+                    security_id: order.security_id.clone(),
+                    security_id_source: order.security_id_source.clone(),
+                    owner_type: order.owner_type,
+                    clearing_firm: order.clearing_firm.clone(),
+                    transact_time: me.timestamp,
+                    user_info: order.user_info.clone(),
+                    order_id: me.oid.to_string(),
+                    cl_ord_id,
+                    exec_id: me.tid.to_string(),
+                    exec_type: "F".to_string(),
+                    ord_status: if me.leaves_volume == 0 { "2" } else { "1" }.to_string(),
+                    last_px: me.price,
+                    last_qty: me.volume,
+                    leaves_qty: me.leaves_volume,
+                    cum_qty: me.cum_volume,
+                    side: order.side.clone(),
+                    account_id: order.account_id.clone(),
+                    branch_id: order.branch_id.clone(),
+                })
+            }
+            OrderStatus::OrderEd => {
+                let mut report = self.execution_report(&order, me.oid);
+                report.exec_type = "0".to_string();
+                report.ord_status = "0".to_string();
+                report.leaves_qty = me.leaves_volume;
+                SzseMessage::ExecutionReport(report)
+            }
+            OrderStatus::CancelEd | OrderStatus::PartCancel | OrderStatus::Expired => {
+                let mut report = self.execution_report(&order, me.oid);
+                report.exec_type = "4".to_string();
+                report.ord_status = "4".to_string();
+                report.cum_qty = me.cum_volume;
+                if let Some(cancel_id) = cancel_id {
+                    report.cl_ord_id = cancel_id;
+                    report.orig_cl_ord_id = cl_ord_id;
+                }
+                SzseMessage::ExecutionReport(report)
+            }
+            OrderStatus::Rejected => {
+                let mut report = self.execution_report(&order, me.oid);
+                report.exec_type = "8".to_string();
+                report.ord_status = "8".to_string();
+                match cancel_id {
+                    Some(cancel_id) => {
+                        report.cl_ord_id = cancel_id;
+                        report.orig_cl_ord_id = cl_ord_id;
+                        report.ord_rej_reason = UNKNOWN_ORDER_REJECT_REASON;
+                    }
+                    None => report.ord_rej_reason = UNSUPPORTED_REJECT_REASON,
+                }
+                SzseMessage::ExecutionReport(report)
+            }
+        };
+        self.orders.on_event(me);
+        vec![Inbound::Reply(msg)]
+    }
+
+    fn encoder() -> SzseEncoder {
+        SzseEncoder
+    }
+
+    fn on_frame_error(
+        &mut self,
+        _ctx: &SessionContext,
+        error: &FrameError,
+    ) -> Vec<Inbound<SzseMessage>> {
+        if !error.is_fatal() {
+            return vec![];
+        }
+        vec![
+            Inbound::Reply(SzseMessage::Logout(Logout {
+                session_status: 0,
+                text: error.to_string(),
+            })),
+            Inbound::Disconnect,
+        ]
+    }
+
+    fn on_throttled(&mut self, ctx: &SessionContext, cmd: &RbCmd) -> Vec<Inbound<SzseMessage>> {
+        let mut out = self.on_event(ctx, &MatchEvent::rejected(cmd, ctx.now));
+        for action in &mut out {
+            if let Inbound::Reply(SzseMessage::ExecutionReport(report)) = action {
+                report.ord_rej_reason = THROTTLED_REJECT_REASON;
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::proto::{FrameDecoder, FrameEncoder};
+
+    use super::*;
+
+    #[test]
+    fn test_new_order_round_trip() {
+        let order = SzseMessage::NewOrder(NewOrder {
+            appl_id: "010".to_string(),
+            security_id: "000001".to_string(),
+            security_id_source: "102".to_string(),
+            cl_ord_id: "A0001".to_string(),
+            side: "1".to_string(),
+            ord_type: "2".to_string(),
+            order_qty: 10_000,
+            price: 123_400,
+            ..NewOrder::default()
+        });
+        let mut buf = BytesMut::new();
+        order.encode(&mut buf);
+        assert_eq!(buf.len(), HEADER_LEN + 108 + CHECKSUM_LEN);
+        let sum = checksum(&buf[..buf.len() - CHECKSUM_LEN]);
+        assert_eq!(&buf[buf.len() - CHECKSUM_LEN..], &sum.to_be_bytes());
+
+        // split delivery, with a heartbeat behind it
+        let mut frames = FrameEncoder::new(SzseEncoder);
+        frames.push(&order);
+        frames.push(&SzseMessage::Heartbeat(Heartbeat {}));
+        let buf = frames.take();
+        assert!(frames.is_empty());
+        let mut decoder = FrameDecoder::new(SzseDecoder);
+        decoder.feed(&buf[..20]);
+        assert_eq!(decoder.next_frame(), Ok(None));
+        decoder.feed(&buf[20..]);
+        assert_eq!(decoder.next_frame(), Ok(Some(order)));
+        assert_eq!(
+            decoder.next_frame(),
+            Ok(Some(SzseMessage::Heartbeat(Heartbeat {})))
+        );
+        assert_eq!(decoder.next_frame(), Ok(None));
+    }
+
+    #[test]
+    fn test_damaged_frame_is_dropped() {
+        let mut buf = BytesMut::new();
+        SzseMessage::Heartbeat(Heartbeat {}).encode(&mut buf);
+        let mut damaged = buf.clone();
+        damaged[3] ^= 0x40;
+        let mut decoder = FrameDecoder::new(SzseDecoder);
+        decoder.feed(&damaged);
+        decoder.feed(&buf);
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(FrameError::Checksum { .. })
+        ));
+        assert_eq!(
+            decoder.next_frame(),
+            Ok(Some(SzseMessage::Heartbeat(Heartbeat {})))
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::types::{OrderSide, RbCmd, TimeInForce};
+
+/// Session id of orders the engine makes up itself, the auto simulator's and
+/// the background flow's. The engine does not send events for it.
+pub const AUTO_SESSION_ID: u64 = 0;
+
+/// Price the simulated counterparty trades at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FillPrice {
+    /// the client order's limit price
+    #[default]
+    Limit,
+    /// the middle of the book, falling back to the limit on an empty book
+    Mid,
+}
+
+/// What the simulator does with a client order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AutoAction {
+    /// Trade `percent` of the order. With a delay the order rests first and
+    /// the counterparty arrives `delay_ms` later at the limit price.
+    Fill {
+        #[serde(default = "full_fill")]
+        percent: u32,
+        #[serde(default)]
+        price: FillPrice,
+        #[serde(default)]
+        delay_ms: u64,
+    },
+    /// Refuse the order.
+    Reject,
+    /// Leave the order to the book like in a normal engine.
+    Pass,
+}
+
+fn full_fill() -> u32 {
+    100
+}
+
+impl Default for AutoAction {
+    fn default() -> Self {
+        AutoAction::Fill {
+            percent: 100,
+            price: FillPrice::Limit,
+            delay_ms: 0,
+        }
+    }
+}
+
+/// Applies `action` to orders matching the optional security and side
+/// filters. The first matching rule wins.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AutoRule {
+    pub security_id: Option<String>,
+    pub side: Option<OrderSide>,
+    #[serde(flatten)]
+    pub action: AutoAction,
+}
+
+impl AutoRule {
+    fn matches(&self, cmd: &RbCmd) -> bool {
+        self.security_id
+            .as_ref()
+            .is_none_or(|s| *s == cmd.security_id)
+            && self.side.is_none_or(|s| s == cmd.side)
+    }
+}
+
+/// Decision for one client order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AutoDecision {
+    /// Trade `counter` straight with the client order as it comes in.
+    Provide(RbCmd),
+    /// Fill later via `AutoSimulator::due`.
+    Delayed,
+    Reject,
+    Pass,
+}
+
+/// Counterparty for a single participant: turns client orders into
+/// synthetic opposite orders according to configurable rules, so an OMS can
+/// be tested without a second client. Orders not covered by a rule are
+/// filled in full at their limit price.
+#[derive(Debug)]
+pub struct AutoSimulator {
+    rules: Vec<AutoRule>,
+    // synthetic orders use negative ids so they never clash with clients'
+    next_oid: i64,
+    // delayed counter orders keyed by (due time, oid), with the client
+    // order each is for
+    pending: BTreeMap<(i64, i64), (i64, RbCmd)>,
+}
+
+impl AutoSimulator {
+    pub fn new(rules: Vec<AutoRule>) -> Self {
+        Self {
+            rules,
+            next_oid: -1,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn action(&self, cmd: &RbCmd) -> AutoAction {
+        self.rules
+            .iter()
+            .find(|r| r.matches(cmd))
+            .map(|r| r.action)
+            .unwrap_or_default()
+    }
+
+    fn counter_order(
+        &mut self,
+        security_id: &str,
+        side: OrderSide,
+        price: i64,
+        volume: i64,
+    ) -> RbCmd {
+        let oid = self.next_oid;
+        self.next_oid -= 1;
+        RbCmd {
+            session_id: AUTO_SESSION_ID,
+            side: match side {
+                OrderSide::Buy => OrderSide::Sell,
+                OrderSide::Sell => OrderSide::Buy,
+            },
+            match_event_list: vec![],
+            price,
+            volume,
+            mid: oid,
+            uid: 0,
+            oid,
+            security_id: security_id.to_string(),
+            time_in_force: TimeInForce::Gtc,
+        }
+    }
+
+    /// Decides how to answer the client order `cmd`. `mid` is the middle of
+    /// the book before the order, `now` the engine time.
+    pub fn on_new_order(&mut self, cmd: &RbCmd, mid: Option<i64>, now: i64) -> AutoDecision {
+        if cmd.session_id == AUTO_SESSION_ID {
+            return AutoDecision::Pass;
+        }
+        match self.action(cmd) {
+            AutoAction::Reject => AutoDecision::Reject,
+            AutoAction::Pass => AutoDecision::Pass,
+            AutoAction::Fill {
+                percent,
+                price,
+                delay_ms,
+            } => {
+                let volume = cmd.volume * percent.min(100) as i64 / 100;
+                if volume <= 0 {
+                    return AutoDecision::Pass;
+                }
+                if delay_ms > 0 {
+                    let counter = self.counter_order(&cmd.security_id, cmd.side, cmd.price, volume);
+                    self.pending
+                        .insert((now + delay_ms as i64, -counter.oid), (cmd.oid, counter));
+                    return AutoDecision::Delayed;
+                }
+                // a mid on the wrong side of the limit would not trade
+                let price = match (price, mid, cmd.side) {
+                    (FillPrice::Mid, Some(mid), OrderSide::Buy) => mid.min(cmd.price),
+                    (FillPrice::Mid, Some(mid), OrderSide::Sell) => mid.max(cmd.price),
+                    _ => cmd.price,
+                };
+                AutoDecision::Provide(self.counter_order(&cmd.security_id, cmd.side, price, volume))
+            }
+        }
+    }
+
+    /// Counter orders of delayed fills whose time has come, oldest first.
+    /// They should be matched against the book and any rest cancelled.
+    pub fn due(&mut self, now: i64) -> Vec<(i64, RbCmd)> {
+        let mut due = vec![];
+        while let Some(entry) = self.pending.first_entry()
+            && entry.key().0 <= now
+        {
+            due.push(entry.remove());
+        }
+        due
+    }
+
+    /// Time of the next delayed fill.
+    pub fn next_due(&self) -> Option<i64> {
+        self.pending.keys().next().map(|k| k.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(security_id: &str, side: OrderSide, price: i64, volume: i64) -> RbCmd {
+        RbCmd {
+            session_id: 5,
+            side,
+            match_event_list: vec![],
+            price,
+            volume,
+            mid: 1,
+            uid: 1,
+            oid: 1,
+            security_id: security_id.to_string(),
+            time_in_force: TimeInForce::Day,
+        }
+    }
+
+    #[test]
+    fn test_default_full_fill_at_limit() {
+        let mut sim = AutoSimulator::new(vec![]);
+        let decision = sim.on_new_order(&order("600519", OrderSide::Buy, 100, 300), Some(95), 0);
+        let AutoDecision::Provide(counter) = decision else {
+            panic!("{decision:?}");
+        };
+        assert_eq!(counter.side, OrderSide::Sell);
+        assert_eq!((counter.price, counter.volume), (100, 300));
+        assert_eq!(counter.session_id, AUTO_SESSION_ID);
+        assert!(counter.oid < 0);
+    }
+
+    #[test]
+    fn test_rules_from_config() {
+        #[derive(Deserialize)]
+        struct Rules {
+            rules: Vec<AutoRule>,
+        }
+        let Rules { rules } = toml::from_str(
+            r#"
+            rules = [
+                { security_id = "600000", type = "reject" },
+                { side = "sell", type = "fill", percent = 50, price = "mid" },
+                { type = "fill", delay_ms = 200 },
+            ]
+            "#,
+        )
+        .unwrap();
+        let mut sim = AutoSimulator::new(rules);
+
+        let reject = sim.on_new_order(&order("600000", OrderSide::Buy, 100, 100), None, 0);
+        assert_eq!(reject, AutoDecision::Reject);
+
+        let AutoDecision::Provide(counter) =
+            sim.on_new_order(&order("600519", OrderSide::Sell, 100, 300), Some(102), 0)
+        else {
+            panic!("expected a counter order");
+        };
+        assert_eq!(
+            (counter.side, counter.price, counter.volume),
+            (OrderSide::Buy, 102, 150)
+        );
+
+        let delayed = sim.on_new_order(&order("600519", OrderSide::Buy, 100, 300), None, 1000);
+        assert_eq!(delayed, AutoDecision::Delayed);
+        assert_eq!(sim.next_due(), Some(1200));
+        assert!(sim.due(1199).is_empty());
+        let due = sim.due(1200);
+        assert_eq!(due.len(), 1);
+        let (oid, counter) = &due[0];
+        assert_eq!(*oid, 1);
+        assert_eq!(
+            (counter.side, counter.price, counter.volume),
+            (OrderSide::Sell, 100, 300)
+        );
+        assert_eq!(sim.next_due(), None);
+    }
+}
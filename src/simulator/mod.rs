@@ -0,0 +1,3 @@
+pub mod auto_simulator;
+pub mod order_flow;
+pub mod replay;
@@ -0,0 +1,332 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+use tracing::info;
+
+use crate::simulator::auto_simulator::AUTO_SESSION_ID;
+use crate::types::{EngineCommand, OrderSide, RbCmd, TimeInForce};
+
+// generated orders count up from here, away from client ids and the auto
+// simulator's counter orders which count down from -1
+const FLOW_OID_BASE: i64 = -(1 << 62);
+
+// how many recent orders per security are kept as cancel candidates
+const CANCEL_CANDIDATES: usize = 1024;
+
+/// Background order flow for one or more securities, from
+/// `[apps.flow]` in the app config. Prices are in ticks of `tick_size`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OrderFlowConfig {
+    pub security_ids: Vec<String>,
+    /// same seed, same flow
+    pub seed: u64,
+    /// reference price all securities start at
+    pub initial_price: i64,
+    pub tick_size: i64,
+    /// mean orders per second for each security
+    pub arrival_rate: f64,
+    /// standard deviation of the reference price move over one second, in ticks
+    pub volatility: f64,
+    /// distance of the best quotes from the reference price, in ticks
+    pub half_spread: i64,
+    /// relative weight of placing a limit order 0, 1, 2... ticks behind the
+    /// best quote
+    pub depth_profile: Vec<f64>,
+    pub lot_size: i64,
+    pub max_lots: i64,
+    /// share of market orders, entered as IOC
+    pub market_ratio: f64,
+    /// share of cancels of earlier orders
+    pub cancel_ratio: f64,
+}
+
+impl Default for OrderFlowConfig {
+    fn default() -> Self {
+        Self {
+            security_ids: vec![],
+            seed: 0,
+            initial_price: 10_000,
+            tick_size: 1,
+            arrival_rate: 10.0,
+            volatility: 2.0,
+            half_spread: 1,
+            depth_profile: vec![4.0, 3.0, 2.0, 1.0, 1.0],
+            lot_size: 100,
+            max_lots: 10,
+            market_ratio: 0.1,
+            cancel_ratio: 0.3,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SecurityFlow {
+    security_id: String,
+    // reference price in ticks, kept fractional so small moves add up
+    reference: f64,
+    recent: VecDeque<(i64, OrderSide)>,
+}
+
+/// Seeded random order flow: orders arrive as a Poisson process around a
+/// reference price doing a random walk. Each arrival is a limit order placed
+/// by the depth profile, a market order or a cancel of an earlier order, sent
+/// as an `EngineCommand` under `AUTO_SESSION_ID` like any session's orders.
+#[derive(Debug)]
+pub struct OrderFlowGenerator {
+    config: OrderFlowConfig,
+    rng: StdRng,
+    securities: Vec<SecurityFlow>,
+    next_oid: i64,
+}
+
+impl OrderFlowGenerator {
+    pub fn new(config: OrderFlowConfig) -> Self {
+        let securities = config
+            .security_ids
+            .iter()
+            .map(|security_id| SecurityFlow {
+                security_id: security_id.clone(),
+                reference: (config.initial_price / config.tick_size) as f64,
+                recent: VecDeque::new(),
+            })
+            .collect();
+        Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            securities,
+            next_oid: FLOW_OID_BASE,
+        }
+    }
+
+    /// Reference price of `security_id`, rounded to a tick.
+    pub fn reference_price(&self, security_id: &str) -> Option<i64> {
+        self.securities
+            .iter()
+            .find(|s| s.security_id == security_id)
+            .map(|s| s.reference.round() as i64 * self.config.tick_size)
+    }
+
+    /// Time to the next arrival and the command that arrives then. None
+    /// without securities or with a zero arrival rate.
+    pub fn next_command(&mut self) -> Option<(Duration, EngineCommand)> {
+        let rate = self.config.arrival_rate * self.securities.len() as f64;
+        if rate <= 0.0 {
+            return None;
+        }
+        // the merged arrivals of all securities are Poisson with the summed rate
+        let wait = -(1.0 - self.rng.random::<f64>()).ln() / rate;
+        for flow in self.securities.iter_mut() {
+            flow.reference += self.config.volatility * wait.sqrt() * gaussian(&mut self.rng);
+            flow.reference = flow.reference.max(1.0 + self.config.half_spread as f64);
+        }
+
+        let index = self.rng.random_range(0..self.securities.len());
+        let draw = self.rng.random::<f64>();
+        let cmd = if draw < self.config.cancel_ratio {
+            // nothing to cancel yet, a limit order instead
+            if self.securities[index].recent.is_empty() {
+                EngineCommand::NewOrder(self.limit_order(index))
+            } else {
+                self.cancel(index)
+            }
+        } else if draw < self.config.cancel_ratio + self.config.market_ratio {
+            EngineCommand::NewOrder(self.market_order(index))
+        } else {
+            EngineCommand::NewOrder(self.limit_order(index))
+        };
+        Some((Duration::from_secs_f64(wait), cmd))
+    }
+
+    fn new_order(&mut self, index: usize, side: OrderSide, price: i64) -> RbCmd {
+        let oid = self.next_oid;
+        self.next_oid += 1;
+        let lots = self.rng.random_range(1..=self.config.max_lots.max(1));
+        RbCmd {
+            session_id: AUTO_SESSION_ID,
+            side,
+            match_event_list: vec![],
+            price,
+            volume: lots * self.config.lot_size,
+            mid: oid,
+            uid: 0,
+            oid,
+            security_id: self.securities[index].security_id.clone(),
+            time_in_force: TimeInForce::Gtc,
+        }
+    }
+
+    fn random_side(&mut self) -> OrderSide {
+        if self.rng.random_bool(0.5) {
+            OrderSide::Buy
+        } else {
+            OrderSide::Sell
+        }
+    }
+
+    fn limit_order(&mut self, index: usize) -> RbCmd {
+        let side = self.random_side();
+        let level = self.depth_level() as i64;
+        let reference = self.securities[index].reference.round() as i64;
+        let offset = self.config.half_spread + level;
+        let ticks = match side {
+            OrderSide::Buy => (reference - offset).max(1),
+            OrderSide::Sell => reference + offset,
+        };
+        let cmd = self.new_order(index, side, ticks * self.config.tick_size);
+        let recent = &mut self.securities[index].recent;
+        if recent.len() == CANCEL_CANDIDATES {
+            recent.pop_front();
+        }
+        recent.push_back((cmd.oid, side));
+        cmd
+    }
+
+    fn market_order(&mut self, index: usize) -> RbCmd {
+        let side = self.random_side();
+        let price = match side {
+            OrderSide::Buy => i64::MAX,
+            OrderSide::Sell => 0,
+        };
+        let mut cmd = self.new_order(index, side, price);
+        cmd.time_in_force = TimeInForce::Ioc;
+        cmd
+    }
+
+    // cancels a random recent order, which may have traded away already
+    fn cancel(&mut self, index: usize) -> EngineCommand {
+        let recent = &mut self.securities[index].recent;
+        let pick = self.rng.random_range(0..recent.len());
+        let (oid, side) = recent.swap_remove_back(pick).unwrap();
+        EngineCommand::CancelOrder(RbCmd {
+            session_id: AUTO_SESSION_ID,
+            side,
+            match_event_list: vec![],
+            price: 0,
+            volume: 0,
+            mid: oid,
+            uid: 0,
+            oid,
+            security_id: self.securities[index].security_id.clone(),
+            time_in_force: TimeInForce::Gtc,
+        })
+    }
+
+    fn depth_level(&mut self) -> usize {
+        let total: f64 = self.config.depth_profile.iter().sum();
+        if total <= 0.0 {
+            return 0;
+        }
+        let mut draw = self.rng.random::<f64>() * total;
+        for (level, weight) in self.config.depth_profile.iter().enumerate() {
+            if draw < *weight {
+                return level;
+            }
+            draw -= weight;
+        }
+        self.config.depth_profile.len() - 1
+    }
+
+    /// Sends the flow to `cmd_tx` in real time until the engine goes away.
+    pub fn start(mut self, cmd_tx: Sender<EngineCommand>) {
+        info!(
+            "Order flow for {:?} at {}/s each",
+            self.config.security_ids, self.config.arrival_rate
+        );
+        tokio::spawn(async move {
+            while let Some((wait, cmd)) = self.next_command() {
+                tokio::time::sleep(wait).await;
+                if cmd_tx.send(cmd).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+// standard normal draw, Box-Muller
+fn gaussian(rng: &mut StdRng) -> f64 {
+    let u1 = 1.0 - rng.random::<f64>();
+    let u2 = rng.random::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::order_book::OrderBook;
+
+    use super::*;
+
+    fn config() -> OrderFlowConfig {
+        OrderFlowConfig {
+            security_ids: vec!["600000".to_string(), "600519".to_string()],
+            seed: 7,
+            ..OrderFlowConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_flow() {
+        let mut a = OrderFlowGenerator::new(config());
+        let mut b = OrderFlowGenerator::new(config());
+        for _ in 0..200 {
+            assert_eq!(a.next_command(), b.next_command());
+        }
+    }
+
+    #[test]
+    fn test_cancel_without_orders_places_a_limit_order() {
+        let mut generator = OrderFlowGenerator::new(OrderFlowConfig {
+            security_ids: vec!["600519".to_string()],
+            cancel_ratio: 1.0,
+            market_ratio: 0.0,
+            ..config()
+        });
+        let Some((_, EngineCommand::NewOrder(cmd))) = generator.next_command() else {
+            panic!("expected a new order");
+        };
+        assert_ne!(cmd.time_in_force, TimeInForce::Ioc);
+        assert!(matches!(
+            generator.next_command(),
+            Some((_, EngineCommand::CancelOrder(_)))
+        ));
+    }
+
+    #[test]
+    fn test_flow_builds_two_sided_book() {
+        let mut generator = OrderFlowGenerator::new(config());
+        let mut book = OrderBook::new("600519".to_string());
+        let (mut limits, mut markets, mut cancels) = (0, 0, 0);
+        for _ in 0..2000 {
+            let Some((wait, cmd)) = generator.next_command() else {
+                panic!("flow ended");
+            };
+            assert!(wait < Duration::from_secs(5));
+            match cmd {
+                EngineCommand::NewOrder(mut cmd) if cmd.security_id == "600519" => {
+                    assert!(cmd.oid < 0 && cmd.volume > 0 && cmd.volume % 100 == 0);
+                    if cmd.time_in_force == TimeInForce::Ioc {
+                        markets += 1;
+                    } else {
+                        limits += 1;
+                    }
+                    book.new_order(&mut cmd);
+                }
+                EngineCommand::CancelOrder(mut cmd) if cmd.security_id == "600519" => {
+                    cancels += 1;
+                    book.cancel_order(&mut cmd, 0);
+                }
+                _ => {}
+            }
+        }
+        assert!(limits > markets && markets > 0 && cancels > 0);
+        let (bid, ask) = (book.best_bid().unwrap(), book.best_ask().unwrap());
+        assert!(bid < ask);
+        let reference = generator.reference_price("600519").unwrap();
+        assert!((bid - reference).abs() < 200 && (ask - reference).abs() < 200);
+    }
+}
@@ -0,0 +1,396 @@
+//! Replays historical tick-by-tick data into the engine.
+//!
+//! A replay file is CSV with a header line; empty lines and lines starting
+//! with `#` are skipped. Rows must be in time order:
+//!
+//! ```text
+//! timestamp,security_id,type,oid,side,price,volume
+//! 1736128800000,600519,A,1001,B,174900,500
+//! 1736128800250,600519,T,1,S,174900,200
+//! 1736128801000,600519,D,1001,B,174900,300
+//! ```
+//!
+//! | column      | meaning                                                    |
+//! |-------------|------------------------------------------------------------|
+//! | timestamp   | ms since the Unix epoch                                    |
+//! | security_id | security the row applies to                                |
+//! | type        | `A` order added, `D` order deleted, `T` trade              |
+//! | oid         | exchange order id for `A`/`D`, trade id for `T`            |
+//! | side        | `B` or `S`; for `T` the side of the aggressor              |
+//! | price       | in the engine's price units, 0 on `A` for a market order   |
+//! | volume      | order volume for `A`, traded volume for `T`, unused for `D`|
+//!
+//! `A` rows enter the book as resting orders and trade if they cross. `T`
+//! rows are for feeds that only carry passive orders: each becomes an
+//! immediate-or-cancel order of the aggressor taking `volume` at `price`, so
+//! it consumes that level's queue, client orders included. A feed must not
+//! carry the same trade both as a crossing `A` and as a `T`.
+//!
+//! Replayed orders traded whatever a replayed trade gave client orders in
+//! the recorded market, so `settle_jumped` takes it off them as well and
+//! later rows find the queue as it was.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+use tracing::info;
+
+use crate::match_policy::{Allocation, MatchPolicy, PriceTimePolicy, RestingOrder};
+use crate::order_book::OrderBook;
+use crate::types::{EngineCommand, OrderSide, OrderStatus, RbCmd, TimeInForce};
+
+/// Session id of replayed orders, next to `ADMIN_SESSION_ID`. Like
+/// `AUTO_SESSION_ID` the engine sends no events for it.
+pub const REPLAY_SESSION_ID: u64 = u64::MAX - 1;
+
+// replayed order and trade ids count down from these, clear of client ids,
+// auto simulator counter orders and the background flow
+const ORDER_OID_BASE: i64 = -(1 << 61);
+const TRADE_OID_BASE: i64 = -(1 << 60);
+
+pub(crate) const HEADER: &str = "timestamp,security_id,type,oid,side,price,volume";
+
+/// `[apps.replay]` in the app config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplayConfig {
+    pub path: String,
+    /// 1.0 replays in real time, 2.0 twice as fast, 0 without pauses
+    #[serde(default = "real_time")]
+    pub speed: f64,
+    #[serde(default)]
+    pub queue_position: QueuePosition,
+}
+
+fn real_time() -> f64 {
+    1.0
+}
+
+/// Where a client order queues relative to replayed orders at its price.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueuePosition {
+    /// ahead of all replayed orders, the optimistic bound
+    Front,
+    /// by arrival time like any order
+    #[default]
+    Fifo,
+    /// behind all replayed orders, even later ones, the pessimistic bound
+    Back,
+}
+
+/// Price-time matching with client orders moved ahead of or behind replayed
+/// liquidity according to `position`. Replayed orders are told apart by
+/// `REPLAY_SESSION_ID`, every other order counts as a client's.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueuePositionPolicy {
+    pub position: QueuePosition,
+}
+
+impl QueuePositionPolicy {
+    pub fn new(position: QueuePosition) -> Self {
+        Self { position }
+    }
+}
+
+impl MatchPolicy for QueuePositionPolicy {
+    fn allocate<I>(
+        &self,
+        level: I,
+        level_volume: i64,
+        top_order: Option<RestingOrder>,
+        volume: i64,
+        fills: &mut Vec<Allocation>,
+    ) where
+        I: Iterator<Item = RestingOrder> + Clone,
+    {
+        let clients_first = match self.position {
+            QueuePosition::Fifo => {
+                PriceTimePolicy.allocate(level, level_volume, top_order, volume, fills);
+                return;
+            }
+            QueuePosition::Front => true,
+            QueuePosition::Back => false,
+        };
+        let start = fills.len();
+        for first in [clients_first, !clients_first] {
+            let allocated: i64 = fills[start..].iter().map(|a| a.volume).sum();
+            PriceTimePolicy.allocate(
+                level
+                    .clone()
+                    .filter(|o| (o.session_id != REPLAY_SESSION_ID) == first),
+                level_volume,
+                top_order,
+                volume - allocated,
+                fills,
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayAction {
+    Add,
+    Delete,
+    Trade,
+}
+
+/// One row of a replay file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayRecord {
+    pub timestamp: i64,
+    pub security_id: String,
+    pub action: ReplayAction,
+    pub oid: i64,
+    pub side: OrderSide,
+    pub price: i64,
+    pub volume: i64,
+}
+
+impl ReplayRecord {
+    fn parse(line: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [timestamp, security_id, action, oid, side, price, volume] = fields[..] else {
+            bail!("expected 7 fields, got {}", fields.len());
+        };
+        let action = match action {
+            "A" => ReplayAction::Add,
+            "D" => ReplayAction::Delete,
+            "T" => ReplayAction::Trade,
+            other => bail!("unknown type {other:?}"),
+        };
+        let side = match side {
+            "B" => OrderSide::Buy,
+            "S" => OrderSide::Sell,
+            other => bail!("unknown side {other:?}"),
+        };
+        let number = |name: &str, value: &str| {
+            value
+                .parse::<i64>()
+                .with_context(|| format!("bad {name} {value:?}"))
+        };
+        Ok(Self {
+            timestamp: number("timestamp", timestamp)?,
+            security_id: security_id.to_string(),
+            action,
+            oid: number("oid", oid)?,
+            side,
+            price: number("price", price)?,
+            volume: number("volume", volume)?,
+        })
+    }
+
+    /// The engine command that plays this row.
+    pub fn to_command(&self) -> EngineCommand {
+        let mut cmd = RbCmd {
+            session_id: REPLAY_SESSION_ID,
+            side: self.side,
+            match_event_list: vec![],
+            price: self.price,
+            volume: self.volume,
+            mid: 0,
+            uid: 0,
+            oid: ORDER_OID_BASE - self.oid,
+            security_id: self.security_id.clone(),
+            time_in_force: TimeInForce::Gtc,
+        };
+        match self.action {
+            ReplayAction::Add => {
+                if self.price == 0 {
+                    cmd.price = match self.side {
+                        OrderSide::Buy => i64::MAX,
+                        OrderSide::Sell => 0,
+                    };
+                    cmd.time_in_force = TimeInForce::Ioc;
+                }
+            }
+            ReplayAction::Delete => return EngineCommand::CancelOrder(cmd),
+            ReplayAction::Trade => {
+                cmd.oid = TRADE_OID_BASE - self.oid;
+                cmd.time_in_force = TimeInForce::Ioc;
+            }
+        }
+        cmd.mid = cmd.oid;
+        EngineCommand::NewOrder(cmd)
+    }
+}
+
+/// After the replayed order `cmd` traded, takes what it gave other orders
+/// off the replayed orders at the same levels too, in time priority: in the
+/// recorded market they traded it. Without it the volume client orders
+/// jumped would stay in the book for later rows.
+pub fn settle_jumped<P: MatchPolicy>(book: &mut OrderBook<P>, cmd: &RbCmd) {
+    if cmd.session_id != REPLAY_SESSION_ID {
+        return;
+    }
+    let side = match cmd.side {
+        OrderSide::Buy => OrderSide::Sell,
+        OrderSide::Sell => OrderSide::Buy,
+    };
+    let mut jumped: Vec<(i64, i64)> = vec![];
+    for ev in &cmd.match_event_list {
+        if ev.oid == cmd.oid
+            || ev.session_id == REPLAY_SESSION_ID
+            || !matches!(ev.status, OrderStatus::PartTrade | OrderStatus::TradeEd)
+        {
+            continue;
+        }
+        match jumped.last_mut() {
+            Some((price, volume)) if *price == ev.price => *volume += ev.volume,
+            _ => jumped.push((ev.price, ev.volume)),
+        }
+    }
+    for (price, volume) in jumped {
+        book.take_volume(side, price, volume, |o| o.session_id == REPLAY_SESSION_ID);
+    }
+}
+
+/// Historical records to feed into an engine, in time order.
+#[derive(Debug, Clone)]
+pub struct HistoricalReplay {
+    records: Vec<ReplayRecord>,
+}
+
+impl HistoricalReplay {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty() && !l.starts_with('#'));
+        match lines.next() {
+            Some((_, header)) if header.trim() == HEADER => {}
+            _ => bail!("missing header {HEADER:?}"),
+        }
+        let mut records: Vec<ReplayRecord> = vec![];
+        for (n, line) in lines {
+            let record = ReplayRecord::parse(line).with_context(|| format!("line {}", n + 1))?;
+            if let Some(last) = records.last()
+                && record.timestamp < last.timestamp
+            {
+                bail!("line {}: timestamp goes backwards", n + 1);
+            }
+            records.push(record);
+        }
+        Ok(Self { records })
+    }
+
+    pub fn records(&self) -> &[ReplayRecord] {
+        &self.records
+    }
+
+    /// Sends every record to `cmd_tx`, keeping the recorded gaps between
+    /// them divided by `speed`, or without pauses if `speed` is not positive.
+    pub fn start(self, cmd_tx: Sender<EngineCommand>, speed: f64) {
+        info!(
+            "Replaying {} records at speed {}",
+            self.records.len(),
+            speed
+        );
+        tokio::spawn(async move {
+            let mut last = self.records.first().map_or(0, |r| r.timestamp);
+            for record in &self.records {
+                if speed > 0.0 && record.timestamp > last {
+                    let gap = (record.timestamp - last) as f64 / 1000.0 / speed;
+                    tokio::time::sleep(Duration::from_secs_f64(gap)).await;
+                }
+                last = record.timestamp;
+                if cmd_tx.send(record.to_command()).await.is_err() {
+                    return;
+                }
+            }
+            info!("Replay finished");
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+timestamp,security_id,type,oid,side,price,volume
+# two sellers queue at 100 with a client between them, buyers take 30
+# then the last 10 of the sellers'
+1000,600519,A,1,S,100,20
+1000,600519,A,2,S,100,20
+2000,600519,T,1,B,100,30
+3000,600519,T,2,B,100,10
+";
+
+    fn play(position: QueuePosition) -> Vec<(i64, i64)> {
+        let replay = HistoricalReplay::parse(SAMPLE).unwrap();
+        let mut book =
+            OrderBook::with_policy("600519".to_string(), QueuePositionPolicy::new(position));
+        let mut fills = vec![];
+        for (i, record) in replay.records().iter().enumerate() {
+            if i == 1 {
+                let mut client = RbCmd {
+                    session_id: 1,
+                    side: OrderSide::Sell,
+                    match_event_list: vec![],
+                    price: 100,
+                    volume: 20,
+                    mid: 7,
+                    uid: 1,
+                    oid: 7,
+                    security_id: "600519".to_string(),
+                    time_in_force: TimeInForce::Day,
+                };
+                book.new_order(&mut client);
+            }
+            let EngineCommand::NewOrder(mut cmd) = record.to_command() else {
+                unreachable!();
+            };
+            book.new_order(&mut cmd);
+            settle_jumped(&mut book, &cmd);
+            fills.extend(
+                cmd.match_event_list
+                    .iter()
+                    .filter(|e| e.oid != cmd.oid && e.status != OrderStatus::OrderEd)
+                    .map(|e| (e.oid, e.volume)),
+            );
+        }
+        // the replayed orders are gone as in the recorded market
+        assert!(
+            book.open_orders(|o| o.session_id == REPLAY_SESSION_ID)
+                .is_empty()
+        );
+        fills
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_rows() {
+        assert!(HistoricalReplay::parse("1000,600519,A,1,S,100,20\n").is_err());
+        let err =
+            HistoricalReplay::parse(&format!("{HEADER}\n1000,600519,X,1,S,100,20\n")).unwrap_err();
+        assert!(format!("{err:#}").contains("line 2"), "{err:#}");
+        let backwards = format!("{HEADER}\n2000,600519,A,1,S,100,20\n1000,600519,A,2,S,100,20\n");
+        assert!(HistoricalReplay::parse(&backwards).is_err());
+    }
+
+    #[test]
+    fn test_queue_position() {
+        let (first, second) = (ORDER_OID_BASE - 1, ORDER_OID_BASE - 2);
+        assert_eq!(
+            play(QueuePosition::Front),
+            vec![(7, 20), (first, 10), (second, 10)]
+        );
+        assert_eq!(
+            play(QueuePosition::Fifo),
+            vec![(first, 20), (7, 10), (7, 10)]
+        );
+        assert_eq!(
+            play(QueuePosition::Back),
+            vec![(first, 20), (second, 10), (second, 10)]
+        );
+    }
+}
@@ -0,0 +1,554 @@
+//! Runs the QA scenarios in `testcase/` against a matcher over TCP.
+//!
+//! A case file lists steps in order. `Send` steps look up their row by
+//! `StepId` in `<test_data>.csv` next to the case file and send it as the
+//! message `msg_type`; `Receive` steps wait for the next message on the
+//! tool's connection and, when `verify_required` is `Y`, compare it field by
+//! field with their row. Empty expected cells are not compared and numbers
+//! compare by value, so `010` matches `10`. Every `test_tool` gets its own
+//! connection, that is its own session.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, anyhow, bail};
+use binary_codec::BinaryCodec;
+use bytes::BytesMut;
+use sse_binary::confirm::Confirm;
+use sse_binary::new_order_single::NewOrderSingle;
+use sse_binary::report::Report;
+use sse_binary::sse_binary::{SseBinary, SseBinaryBodyEnum};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::protocol::proto::{FrameDecoder, SseDecoder};
+
+const NEW_ORDER_SINGLE_MSG_TYPE: u32 = 58;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepAction {
+    Send,
+    Receive,
+}
+
+/// One line of a case file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub case_id: String,
+    pub case_title: String,
+    pub step_id: String,
+    pub sleep_ms: u64,
+    pub action: StepAction,
+    pub verify: bool,
+    pub tool: String,
+    pub msg_type: u32,
+    pub test_data: String,
+}
+
+/// Rows of a csv file by their first column, with the header's column names.
+#[derive(Debug, Clone)]
+struct Table {
+    rows: HashMap<String, Vec<(String, String)>>,
+}
+
+impl Table {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+        let header: Vec<String> = lines
+            .next()
+            .with_context(|| format!("{} is empty", path.display()))?
+            .split(',')
+            .map(|c| c.trim().to_string())
+            .collect();
+        let rows = lines
+            .map(|line| {
+                let row: Vec<(String, String)> = header
+                    .iter()
+                    .cloned()
+                    .zip(line.split(',').map(|v| v.trim().to_string()))
+                    .collect();
+                (row[0].1.clone(), row)
+            })
+            .collect();
+        Ok(Self { rows })
+    }
+
+    fn row(&self, step_id: &str) -> Option<&[(String, String)]> {
+        self.rows.get(step_id).map(Vec::as_slice)
+    }
+}
+
+/// Reads a case file such as `testcase/sse_test_case.csv`.
+pub fn load_steps(path: impl AsRef<Path>) -> anyhow::Result<Vec<Step>> {
+    let path = path.as_ref();
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let mut steps = vec![];
+    for (n, line) in text.lines().enumerate().skip(1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [
+            case_id,
+            case_title,
+            step_id,
+            sleep_ms,
+            _,
+            action,
+            verify,
+            tool,
+            msg_type,
+            data,
+        ] = fields[..]
+        else {
+            bail!("{}:{}: expected 10 fields", path.display(), n + 1);
+        };
+        let action = match action {
+            "Send" => StepAction::Send,
+            "Receive" => StepAction::Receive,
+            other => bail!("{}:{}: unknown action {other:?}", path.display(), n + 1),
+        };
+        steps.push(Step {
+            case_id: case_id.to_string(),
+            case_title: case_title.to_string(),
+            step_id: step_id.to_string(),
+            sleep_ms: sleep_ms.parse().unwrap_or(0),
+            action,
+            verify: verify == "Y",
+            tool: tool.to_string(),
+            msg_type: msg_type
+                .parse()
+                .with_context(|| format!("{}:{}: bad msg_type", path.display(), n + 1))?,
+            test_data: data.to_string(),
+        });
+    }
+    Ok(steps)
+}
+
+/// A field whose received value differs from the expected one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// A step that did not go as the case file says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepFailure {
+    pub case_id: String,
+    pub step_id: String,
+    pub error: Option<String>,
+    pub diffs: Vec<FieldDiff>,
+}
+
+/// Outcome of a run, printable as a failure report.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunReport {
+    pub steps: usize,
+    pub failures: Vec<StepFailure>,
+}
+
+impl RunReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} step(s), {} failed", self.steps, self.failures.len())?;
+        for failure in &self.failures {
+            writeln!(f, "[{}] {}", failure.case_id, failure.step_id)?;
+            if let Some(error) = &failure.error {
+                writeln!(f, "    {error}")?;
+            }
+            let width = failure
+                .diffs
+                .iter()
+                .map(|d| d.field.len())
+                .max()
+                .unwrap_or(0);
+            for diff in &failure.diffs {
+                writeln!(
+                    f,
+                    "    {:width$}  expected {:?}, got {:?}",
+                    diff.field, diff.expected, diff.actual
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// one tool's session
+struct Connection {
+    stream: TcpStream,
+    decoder: FrameDecoder<SseDecoder>,
+}
+
+impl Connection {
+    async fn send(&mut self, msg: &SseBinary) -> anyhow::Result<()> {
+        let mut buf = BytesMut::new();
+        msg.encode(&mut buf);
+        self.stream.write_all(&buf).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self, timeout: Duration) -> anyhow::Result<SseBinary> {
+        let mut buf = [0u8; 1024];
+        tokio::time::timeout(timeout, async {
+            loop {
+                if let Some(msg) = self.decoder.next_frame()? {
+                    return Ok(msg);
+                }
+                let n = self.stream.read(&mut buf).await?;
+                if n == 0 {
+                    bail!("connection closed");
+                }
+                self.decoder.feed(&buf[..n]);
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("no message within {timeout:?}"))?
+    }
+}
+
+/// Plays case files against the matcher listening on `addr`.
+pub struct TestCaseRunner {
+    addr: SocketAddr,
+    dir: PathBuf,
+    receive_timeout: Duration,
+    tables: HashMap<String, Table>,
+    connections: HashMap<String, Connection>,
+}
+
+impl TestCaseRunner {
+    /// Test data files are looked up in `dir`.
+    pub fn new(addr: SocketAddr, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            addr,
+            dir: dir.into(),
+            receive_timeout: Duration::from_secs(2),
+            tables: HashMap::new(),
+            connections: HashMap::new(),
+        }
+    }
+
+    /// How long a `Receive` step waits for its message, two seconds by default.
+    pub fn with_receive_timeout(mut self, timeout: Duration) -> Self {
+        self.receive_timeout = timeout;
+        self
+    }
+
+    /// Runs the steps in order. A failed step is recorded and the run goes on.
+    pub async fn run(&mut self, steps: &[Step]) -> RunReport {
+        let mut report = RunReport::default();
+        for step in steps {
+            report.steps += 1;
+            if step.sleep_ms > 0 {
+                tokio::time::sleep(Duration::from_millis(step.sleep_ms)).await;
+            }
+            let (error, diffs) = match self.run_step(step).await {
+                Ok(diffs) => (None, diffs),
+                Err(e) => (Some(format!("{e:#}")), vec![]),
+            };
+            if error.is_some() || !diffs.is_empty() {
+                report.failures.push(StepFailure {
+                    case_id: step.case_id.clone(),
+                    step_id: step.step_id.clone(),
+                    error,
+                    diffs,
+                });
+            }
+        }
+        report
+    }
+
+    async fn run_step(&mut self, step: &Step) -> anyhow::Result<Vec<FieldDiff>> {
+        let row = self.row(step)?.to_vec();
+        let timeout = self.receive_timeout;
+        let connection = self.connection(&step.tool).await?;
+        match step.action {
+            StepAction::Send => {
+                let body = match step.msg_type {
+                    NEW_ORDER_SINGLE_MSG_TYPE => {
+                        SseBinaryBodyEnum::NewOrderSingle(new_order_single(&row)?)
+                    }
+                    other => bail!("sending msg_type {other} is not supported"),
+                };
+                connection
+                    .send(&SseBinary {
+                        msg_type: step.msg_type,
+                        msg_seq_num: 1,
+                        msg_body_len: 0,
+                        body,
+                        checksum: 0,
+                    })
+                    .await?;
+                Ok(vec![])
+            }
+            StepAction::Receive => {
+                let msg = connection.receive(timeout).await?;
+                if msg.msg_type != step.msg_type {
+                    bail!("expected msg_type {}, got {}", step.msg_type, msg.msg_type);
+                }
+                if !step.verify {
+                    return Ok(vec![]);
+                }
+                let actual = fields(&msg.body)?;
+                Ok(diff(&row, &actual))
+            }
+        }
+    }
+
+    fn row(&mut self, step: &Step) -> anyhow::Result<&[(String, String)]> {
+        if !self.tables.contains_key(&step.test_data) {
+            let path = self.dir.join(format!("{}.csv", step.test_data));
+            self.tables
+                .insert(step.test_data.clone(), Table::load(&path)?);
+        }
+        self.tables[&step.test_data]
+            .row(&step.step_id)
+            .with_context(|| format!("no row {} in {}.csv", step.step_id, step.test_data))
+    }
+
+    async fn connection(&mut self, tool: &str) -> anyhow::Result<&mut Connection> {
+        if !self.connections.contains_key(tool) {
+            let stream = TcpStream::connect(self.addr)
+                .await
+                .with_context(|| format!("{tool}: connecting to {}", self.addr))?;
+            self.connections.insert(
+                tool.to_string(),
+                Connection {
+                    stream,
+                    decoder: FrameDecoder::new(SseDecoder),
+                },
+            );
+        }
+        Ok(self.connections.get_mut(tool).unwrap())
+    }
+}
+
+fn value<'a>(row: &'a [(String, String)], column: &str) -> &'a str {
+    row.iter()
+        .find(|(c, _)| c == column)
+        .map_or("", |(_, v)| v.as_str())
+}
+
+fn number<T: std::str::FromStr>(row: &[(String, String)], column: &str) -> anyhow::Result<T> {
+    let v = value(row, column);
+    v.parse()
+        .map_err(|_| anyhow!("column {column}: bad number {v:?}"))
+}
+
+fn new_order_single(row: &[(String, String)]) -> anyhow::Result<NewOrderSingle> {
+    Ok(NewOrderSingle {
+        biz_id: number(row, "BizID")?,
+        biz_pbu: value(row, "BizPbu").to_string(),
+        cl_ord_id: value(row, "ClOrdID").to_string(),
+        security_id: value(row, "SecurityID").to_string(),
+        account: value(row, "Account").to_string(),
+        owner_type: number(row, "OwnerType")?,
+        side: value(row, "Side").to_string(),
+        price: number(row, "Price")?,
+        order_qty: number(row, "OrderQty")?,
+        ord_type: value(row, "OrdType").to_string(),
+        time_in_force: value(row, "TimeInForce").to_string(),
+        transact_time: number(row, "TransactTime")?,
+        credit_tag: value(row, "CreditTag").to_string(),
+        clearing_firm: value(row, "ClearingFirm").to_string(),
+        branch_id: value(row, "BranchID").to_string(),
+        user_info: value(row, "UserInfo").to_string(),
+    })
+}
+
+// received message as (column, value) pairs named like the csv headers
+fn fields(body: &SseBinaryBodyEnum) -> anyhow::Result<Vec<(&'static str, String)>> {
+    match body {
+        SseBinaryBodyEnum::Confirm(c) => Ok(confirm_fields(c)),
+        SseBinaryBodyEnum::Report(r) => Ok(report_fields(r)),
+        _ => bail!("verifying this message is not supported"),
+    }
+}
+
+fn confirm_fields(c: &Confirm) -> Vec<(&'static str, String)> {
+    vec![
+        ("Pbu", c.pbu.clone()),
+        ("SetID", c.set_id.to_string()),
+        ("ReportIndex", c.report_index.to_string()),
+        ("BizID", c.biz_id.to_string()),
+        ("ExecType", c.exec_type.clone()),
+        ("BizPbu", c.biz_pbu.clone()),
+        ("ClOrdID", c.cl_ord_id.clone()),
+        ("SecurityID", c.security_id.clone()),
+        ("Account", c.account.clone()),
+        ("OwnerType", c.owner_type.to_string()),
+        ("Side", c.side.clone()),
+        ("Price", c.price.to_string()),
+        ("OrderQty", c.order_qty.to_string()),
+        ("LeavesQty", c.leaves_qty.to_string()),
+        ("CxlQty", c.cxl_qty.to_string()),
+        ("OrdType", c.ord_type.clone()),
+        ("TimeInForce", c.time_in_force.clone()),
+        ("OrdStatus", c.ord_status.clone()),
+        ("CreditTag", c.credit_tag.clone()),
+        ("OrigClOrdID", c.orig_cl_ord_id.clone()),
+        ("ClearingFirm", c.clearing_firm.clone()),
+        ("BranchID", c.branch_id.clone()),
+        ("OrdRejReason", c.ord_rej_reason.to_string()),
+        ("OrdCnfmID", c.ord_cnfm_id.clone()),
+        ("OrigOrdCnfmID", c.orig_ord_cnfm_id.clone()),
+        ("TradeDate", c.trade_date.to_string()),
+        ("TransactTime", c.transact_time.to_string()),
+        ("UserInfo", c.user_info.clone()),
+    ]
+}
+
+fn report_fields(r: &Report) -> Vec<(&'static str, String)> {
+    vec![
+        ("Pbu", r.pbu.clone()),
+        ("SetID", r.set_id.to_string()),
+        ("ReportIndex", r.report_index.to_string()),
+        ("BizID", r.biz_id.to_string()),
+        ("ExecType", r.exec_type.clone()),
+        ("BizPbu", r.biz_pbu.clone()),
+        ("ClOrdID", r.cl_ord_id.clone()),
+        ("SecurityID", r.security_id.clone()),
+        ("Account", r.account.clone()),
+        ("OwnerType", r.owner_type.to_string()),
+        ("OrderEntryTime", r.order_entry_time.to_string()),
+        ("LastPx", r.last_px.to_string()),
+        ("LastQty", r.last_qty.to_string()),
+        ("GrossTradeAmt", r.gross_trade_amt.to_string()),
+        ("Side", r.side.clone()),
+        ("OrderQty", r.order_qty.to_string()),
+        ("LeavesQty", r.leaves_qty.to_string()),
+        ("OrdStatus", r.ord_status.clone()),
+        ("CreditTag", r.credit_tag.clone()),
+        ("ClearingFirm", r.clearing_firm.clone()),
+        ("BranchID", r.branch_id.clone()),
+        ("TrdCnfmID", r.trd_cnfm_id.clone()),
+        ("OrdCnfmID", r.ord_cnfm_id.clone()),
+        ("TradeDate", r.trade_date.to_string()),
+        ("TransactTime", r.transact_time.to_string()),
+        ("UserInfo", r.user_info.clone()),
+    ]
+}
+
+// compares the non-empty expected cells, StepId is the row key not a field
+fn diff(expected: &[(String, String)], actual: &[(&'static str, String)]) -> Vec<FieldDiff> {
+    expected
+        .iter()
+        .skip(1)
+        .filter(|(_, v)| !v.is_empty())
+        .filter_map(|(column, expected)| {
+            let actual = actual
+                .iter()
+                .find(|(c, _)| c == column)
+                .map_or("<no such field>", |(_, v)| v.as_str());
+            (!same(expected, actual)).then(|| FieldDiff {
+                field: column.clone(),
+                expected: expected.clone(),
+                actual: actual.to_string(),
+            })
+        })
+        .collect()
+}
+
+// equal as text or as numbers, csv editors pad ids like 010
+fn same(expected: &str, actual: &str) -> bool {
+    expected == actual
+        || matches!(
+            (expected.parse::<i64>(), actual.parse::<i64>()),
+            (Ok(e), Ok(a)) if e == a
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_sse_case() {
+        let steps = load_steps("testcase/sse_test_case.csv").unwrap();
+        assert_eq!(steps[0].step_id, "new_order_001");
+        assert_eq!(steps[0].action, StepAction::Send);
+        assert_eq!(steps[0].msg_type, NEW_ORDER_SINGLE_MSG_TYPE);
+
+        let table = Table::load(Path::new("testcase/sse_58.csv")).unwrap();
+        let order = new_order_single(table.row("new_order_002").unwrap()).unwrap();
+        assert_eq!((order.cl_ord_id.as_str(), order.order_qty), ("100023", 800));
+    }
+
+    #[test]
+    fn test_confirm_rows_verify() {
+        let table = Table::load(Path::new("testcase/sse_32.csv")).unwrap();
+        let row = table.row("confirm_001").unwrap();
+        let confirm = Confirm {
+            pbu: "pbu001".to_string(),
+            set_id: 1,
+            report_index: 1,
+            biz_id: 10,
+            exec_type: "0".to_string(),
+            biz_pbu: "pbu001".to_string(),
+            cl_ord_id: "c10001".to_string(),
+            security_id: "600519".to_string(),
+            account: "a00001".to_string(),
+            owner_type: 1,
+            side: "1".to_string(),
+            price: 175000,
+            order_qty: 500,
+            leaves_qty: 500,
+            cxl_qty: 0,
+            ord_type: "2".to_string(),
+            time_in_force: "0".to_string(),
+            ord_status: "0".to_string(),
+            credit_tag: "1".to_string(),
+            orig_cl_ord_id: "".to_string(),
+            clearing_firm: "1001".to_string(),
+            branch_id: "b001".to_string(),
+            ord_rej_reason: 0,
+            ord_cnfm_id: "oc00001".to_string(),
+            orig_ord_cnfm_id: "".to_string(),
+            trade_date: 20250106,
+            transact_time: 101530,
+            user_info: "u000123".to_string(),
+        };
+        let actual = fields(&SseBinaryBodyEnum::Confirm(confirm)).unwrap();
+        assert_eq!(diff(row, &actual), vec![]);
+    }
+
+    #[test]
+    fn test_diff_skips_blank_cells() {
+        let expected: Vec<(String, String)> = [
+            ("StepId", "report_001"),
+            ("ClOrdID", "100023"),
+            ("LastQty", "500"),
+            ("TransactTime", ""),
+        ]
+        .iter()
+        .map(|(c, v)| (c.to_string(), v.to_string()))
+        .collect();
+        let actual = vec![
+            ("ClOrdID", "100023".to_string()),
+            ("LastQty", "400".to_string()),
+            ("TransactTime", "1736130930000".to_string()),
+        ];
+        let diffs = diff(&expected, &actual);
+        assert_eq!(
+            diffs,
+            vec![FieldDiff {
+                field: "LastQty".to_string(),
+                expected: "500".to_string(),
+                actual: "400".to_string(),
+            }]
+        );
+    }
+}
@@ -1,3 +1,7 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct L1MarketData {
     pub security_id: String,
@@ -55,6 +59,33 @@ pub enum CmdResultCode {
     Success,
     DuplicateOrderId,
     InvalidOrderId,
+    /// the book is halted or closed
+    NotTrading,
+    /// an auto engine's rule refused the order
+    AutoRejected,
+}
+
+impl CmdResultCode {
+    /// Every code a command may be rejected with.
+    pub const REJECTS: [CmdResultCode; 4] = [
+        CmdResultCode::DuplicateOrderId,
+        CmdResultCode::InvalidOrderId,
+        CmdResultCode::NotTrading,
+        CmdResultCode::AutoRejected,
+    ];
+}
+
+impl fmt::Display for CmdResultCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CmdResultCode::Success => "success",
+            CmdResultCode::DuplicateOrderId => "duplicate_order_id",
+            CmdResultCode::InvalidOrderId => "invalid_order_id",
+            CmdResultCode::NotTrading => "not_trading",
+            CmdResultCode::AutoRejected => "auto_rejected",
+        };
+        f.write_str(name)
+    }
 }
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OrderStatus {
@@ -63,25 +94,54 @@ pub enum OrderStatus {
     PartTrade,
     CancelEd,
     PartCancel,
+    Expired,
+    Rejected,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum OrderSide {
     Buy,
     Sell,
 }
 
+/// How long an order may rest in the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeInForce {
+    /// good till cancel, carried over to the next trading day
+    #[default]
+    Gtc,
+    /// removed at the end of the trading day it was entered on
+    Day,
+    /// good till the given time, in milliseconds since the Unix epoch
+    Gtd(i64),
+    /// immediate or cancel: trades what it can on entry, the rest is
+    /// cancelled instead of resting
+    Ioc,
+}
+
+impl TimeInForce {
+    pub fn is_expired(&self, now: i64, end_of_day: bool) -> bool {
+        match *self {
+            TimeInForce::Gtc | TimeInForce::Ioc => false,
+            TimeInForce::Day => end_of_day,
+            TimeInForce::Gtd(expire_time) => expire_time <= now,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Order {
     pub session_id: u64,
     pub oid: i64,
     pub mid: i64,
     pub uid: u64,
-    pub security_id: String,
     pub side: OrderSide,
     pub price: i64,
     pub volume: i64,
     pub tvolume: i64,
+    pub time_in_force: TimeInForce,
     pub timestamp: i64,
 }
 
@@ -91,6 +151,105 @@ impl Order {
     }
 }
 
+/// A resting order as queries see it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OrderInfo {
+    pub oid: i64,
+    pub session_id: u64,
+    pub uid: u64,
+    pub security_id: String,
+    pub side: OrderSide,
+    pub price: i64,
+    pub volume: i64,
+    pub traded: i64,
+    pub leaves: i64,
+    pub time_in_force: TimeInForce,
+    /// ms since the Unix epoch the order was booked
+    pub timestamp: i64,
+    /// orders ahead of it at its price
+    pub queue_position: usize,
+    /// their untraded volume
+    pub volume_ahead: i64,
+}
+
+impl OrderInfo {
+    pub fn new(order: &Order, security_id: &str, queue_position: usize, volume_ahead: i64) -> Self {
+        Self {
+            oid: order.oid,
+            session_id: order.session_id,
+            uid: order.uid,
+            security_id: security_id.to_string(),
+            side: order.side,
+            price: order.price,
+            volume: order.volume,
+            traded: order.tvolume,
+            leaves: order.remaining(),
+            time_in_force: order.time_in_force,
+            timestamp: order.timestamp,
+            queue_position,
+            volume_ahead,
+        }
+    }
+}
+
+/// One price level of a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DepthLevel {
+    pub price: i64,
+    pub volume: i64,
+    pub orders: usize,
+}
+
+/// Every level of a book, best first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BookDepth {
+    pub security_id: String,
+    pub last_price: i64,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+/// The best level of each side of a book and whether it trades.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TopOfBook {
+    pub security_id: String,
+    pub phase: TradingPhase,
+    pub last_price: i64,
+    pub bid: Option<DepthLevel>,
+    pub ask: Option<DepthLevel>,
+}
+
+/// What a book does with the orders and cancels it gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradingPhase {
+    /// orders match as they come
+    #[default]
+    Continuous,
+    /// no matching, new orders are rejected or queued for the re-opening
+    /// auction as the engine is configured
+    Halted,
+    /// orders are collected without matching, then trade at a single price
+    /// once the auction ends
+    Auction,
+    /// orders and cancels are rejected
+    Closed,
+}
+
+impl fmt::Display for TradingPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TradingPhase::Continuous => "continuous",
+            TradingPhase::Halted => "halted",
+            TradingPhase::Auction => "auction",
+            TradingPhase::Closed => "closed",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What happened to one order. For fills `volume` is the quantity of this
+/// fill, for cancels and expiries the quantity taken off the book.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MatchEvent {
     pub session_id: u64,
@@ -101,6 +260,10 @@ pub struct MatchEvent {
     pub tid: i64,
     pub volume: i64,
     pub price: i64,
+    // total traded so far
+    pub cum_volume: i64,
+    // still open in the book after this event
+    pub leaves_volume: i64,
 }
 impl MatchEvent {
     pub fn default() -> MatchEvent {
@@ -113,6 +276,21 @@ impl MatchEvent {
             tid: 0,
             volume: 0,
             price: 0,
+            cum_volume: 0,
+            leaves_volume: 0,
+        }
+    }
+
+    /// The answer to `cmd` when it can't be carried out.
+    pub fn rejected(cmd: &RbCmd, timestamp: i64) -> MatchEvent {
+        MatchEvent {
+            session_id: cmd.session_id,
+            mid: cmd.mid,
+            oid: cmd.oid,
+            status: OrderStatus::Rejected,
+            price: cmd.price,
+            timestamp,
+            ..MatchEvent::default()
         }
     }
 }
@@ -128,11 +306,61 @@ pub struct RbCmd {
     pub uid: u64,
     pub oid: i64,
     pub security_id: String,
+    pub time_in_force: TimeInForce,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EngineCommand {
     NewOrder(RbCmd),
+    /// cancels the resting order `oid` of `security_id`, answered with a
+    /// `Rejected` event if there is none
+    CancelOrder(RbCmd),
+    /// puts the book of `security_id`, or every book if None, in `phase`
+    SetPhase {
+        security_id: Option<String>,
+        phase: TradingPhase,
+    },
+    /// cancels the resting orders matching every filter given
+    MassCancel(MassCancel),
+}
+
+impl EngineCommand {
+    /// The order or cancel, None for commands of the operator.
+    pub fn rb_cmd(&self) -> Option<&RbCmd> {
+        match self {
+            EngineCommand::NewOrder(rb_cmd) | EngineCommand::CancelOrder(rb_cmd) => Some(rb_cmd),
+            EngineCommand::SetPhase { .. } | EngineCommand::MassCancel(_) => None,
+        }
+    }
+
+    /// The security the command is about, None if it is about all of them.
+    pub fn security_id(&self) -> Option<&str> {
+        match self {
+            EngineCommand::NewOrder(rb_cmd) | EngineCommand::CancelOrder(rb_cmd) => {
+                Some(&rb_cmd.security_id)
+            }
+            EngineCommand::SetPhase { security_id, .. }
+            | EngineCommand::MassCancel(MassCancel { security_id, .. }) => security_id.as_deref(),
+        }
+    }
+}
+
+/// Which resting orders a mass cancel takes off the books, all of them if
+/// no filter is given.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct MassCancel {
+    pub security_id: Option<String>,
+    pub session_id: Option<u64>,
+    pub uid: Option<u64>,
+}
+
+impl MassCancel {
+    /// Whether `order`, in a book of the filter's security, is taken off.
+    pub fn matches(&self, order: &Order) -> bool {
+        self.session_id.is_none_or(|s| order.session_id == s)
+            && self.uid.is_none_or(|u| order.uid == u)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use chrono::Utc;
+
+/// Source of "now" for the engine, in milliseconds since the Unix epoch.
+pub trait Clock: Send + Sync {
+    fn now_millis(&self) -> i64;
+}
+
+/// Wall clock time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        Utc::now().timestamp_millis()
+    }
+}
+
+/// A clock that only moves when told to, for simulations spanning several
+/// trading days. Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicI64>,
+}
+
+impl ManualClock {
+    pub fn new(now_millis: i64) -> Self {
+        Self {
+            now: Arc::new(AtomicI64::new(now_millis)),
+        }
+    }
+
+    pub fn set(&self, now_millis: i64) {
+        self.now.store(now_millis, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, millis: i64) {
+        self.now.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,131 @@
+//! Counters and histograms kept with atomics, and writing them out in the
+//! Prometheus text format.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the latency buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    1e-6, 2.5e-6, 5e-6, 1e-5, 2.5e-5, 5e-5, 1e-4, 2.5e-4, 5e-4, 1e-3, 1e-2, 1e-1,
+];
+
+/// Durations counted into the buckets of [`LATENCY_BUCKETS`].
+#[derive(Debug, Default)]
+pub struct Histogram {
+    // not cumulative, the last one counts what is above every bound
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// Builds one exposition, a family at a time.
+#[derive(Debug, Default)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    /// Starts a family, its samples follow.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) -> &mut Self {
+        let _ = writeln!(self.text, "# HELP {name} {help}");
+        let _ = writeln!(self.text, "# TYPE {name} {kind}");
+        self
+    }
+
+    pub fn sample(
+        &mut self,
+        name: &str,
+        labels: &[(&str, &str)],
+        value: impl ToString,
+    ) -> &mut Self {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            self.text.push('{');
+            for (i, (label, label_value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.text.push(',');
+                }
+                let _ = write!(self.text, "{label}=\"{}\"", escape(label_value));
+            }
+            self.text.push('}');
+        }
+        let _ = writeln!(self.text, " {}", value.to_string());
+        self
+    }
+
+    /// A whole histogram family with its cumulative buckets.
+    pub fn histogram(&mut self, name: &str, help: &str, histogram: &Histogram) -> &mut Self {
+        self.family(name, "histogram", help);
+        let bucket = format!("{name}_bucket");
+        let mut cumulative = 0;
+        for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            cumulative += histogram.buckets[i].load(Ordering::Relaxed);
+            self.sample(&bucket, &[("le", &bound.to_string())], cumulative);
+        }
+        cumulative += histogram.buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+        self.sample(&bucket, &[("le", "+Inf")], cumulative);
+        let sum = histogram.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        self.sample(&format!("{name}_sum"), &[], sum);
+        self.sample(&format!("{name}_count"), &[], cumulative)
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_exposition() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_nanos(800));
+        histogram.observe(Duration::from_micros(30));
+        histogram.observe(Duration::from_secs(1));
+
+        let mut exposition = Exposition::default();
+        exposition
+            .family("orders_total", "counter", "Orders.")
+            .sample("orders_total", &[("security_id", "60\"0")], 3)
+            .histogram("latency_seconds", "Latency.", &histogram);
+        let text = exposition.finish();
+
+        assert!(text.starts_with("# HELP orders_total Orders.\n# TYPE orders_total counter\n"));
+        assert!(text.contains("orders_total{security_id=\"60\\\"0\"} 3\n"));
+        assert!(text.contains("latency_seconds_bucket{le=\"0.000001\"} 1\n"));
+        assert!(text.contains("latency_seconds_bucket{le=\"0.000025\"} 1\n"));
+        assert!(text.contains("latency_seconds_bucket{le=\"0.00005\"} 2\n"));
+        assert!(text.contains("latency_seconds_bucket{le=\"0.1\"} 2\n"));
+        assert!(text.contains("latency_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("latency_seconds_sum 1.0000308\n"));
+        assert!(text.contains("latency_seconds_count 3\n"));
+    }
+}
@@ -0,0 +1,137 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use exchange_matcher::engine::match_engine::MatchEngine;
+use exchange_matcher::engine::query::query_channel;
+use exchange_matcher::interface::admin::AdminServer;
+use exchange_matcher::interface::channel::{AcceptorChannel, ChannelIds, TcpAcceptorChannel};
+use exchange_matcher::protocol::fix::*;
+use serde_json::{Value, json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, unbounded_channel};
+
+// one request, the status and JSON body of the answer
+async fn http(addr: SocketAddr, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: test\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[tokio::test]
+async fn test_drive_engine_over_http() {
+    let (cmd_tx, cmd_rx) = channel(1024);
+    let (event_tx, event_rx) = unbounded_channel();
+    let (queries, query_rx) = query_channel(16);
+    let mut engine = MatchEngine::new(cmd_rx, event_tx).with_queries(query_rx);
+    tokio::spawn(async move { engine.start().await });
+
+    let ids = Arc::new(ChannelIds::default());
+    let fix = TcpAcceptorChannel::<FixAdapter>::with_ids(
+        "127.0.0.1:0".parse().unwrap(),
+        cmd_tx.clone(),
+        ids.clone(),
+    );
+    fix.clone().start(event_rx).await.unwrap();
+    let admin = AdminServer::new("127.0.0.1:0".parse().unwrap(), cmd_tx, queries, ids)
+        .with_channel("fix", fix.clone())
+        .start()
+        .await
+        .unwrap();
+
+    // a FIX client logs on
+    let mut client = TcpStream::connect(fix.local_addr().unwrap()).await.unwrap();
+    let mut buf = BytesMut::new();
+    FixMessage::new(msg_type::LOGON)
+        .with(tag::SENDER_COMP_ID, "OMS")
+        .with(tag::TARGET_COMP_ID, "MATCHER")
+        .with(tag::MSG_SEQ_NUM, 1)
+        .with(tag::HEART_BT_INT, 30)
+        .encode(&mut buf);
+    client.write_all(&buf).await.unwrap();
+    let mut logon = [0u8; 256];
+    assert!(client.read(&mut logon).await.unwrap() > 0);
+
+    let (status, sessions) = http(admin, "GET", "/sessions", None).await;
+    assert_eq!(status, 200);
+    let session = &sessions[0]["sessions"][0];
+    assert_eq!(sessions[0]["channel"], "fix");
+    assert_eq!(
+        (&session["received"], &session["sent"]),
+        (&json!(1), &json!(1))
+    );
+    assert_eq!(session["seq_nums"], json!([1, 1]));
+
+    let sell = json!({ "security_id": "600519", "side": "sell", "price": 100, "volume": 300 });
+    let (status, queued) = http(admin, "POST", "/orders", Some(sell)).await;
+    assert_eq!(status, 200);
+    let oid = queued["oid"].as_i64().unwrap();
+
+    let (_, books) = http(admin, "GET", "/books", None).await;
+    assert_eq!(books[0]["security_id"], "600519");
+    assert_eq!(books[0]["phase"], "continuous");
+    assert_eq!(
+        books[0]["ask"],
+        json!({ "price": 100, "volume": 300, "orders": 1 })
+    );
+    let (status, order) = http(admin, "GET", &format!("/orders/{oid}"), None).await;
+    assert_eq!((status, &order["leaves"]), (200, &json!(300)));
+
+    // halted, the buy is rejected and the sell stays
+    let (status, _) = http(admin, "POST", "/books/600519/halt", None).await;
+    assert_eq!(status, 200);
+    let buy = json!({ "security_id": "600519", "side": "buy", "price": 100, "volume": 100 });
+    http(admin, "POST", "/orders", Some(buy.clone())).await;
+    let (_, depth) = http(admin, "GET", "/books/600519", None).await;
+    assert_eq!(depth["asks"][0]["volume"], 300);
+    assert!(depth["bids"].as_array().unwrap().is_empty());
+    let (_, books) = http(admin, "GET", "/books", None).await;
+    assert_eq!(books[0]["phase"], "halted");
+
+    http(admin, "POST", "/books/600519/resume", None).await;
+    http(admin, "POST", "/orders", Some(buy)).await;
+    let (_, depth) = http(admin, "GET", "/books/600519", None).await;
+    assert_eq!(depth["last_price"], 100);
+    assert_eq!(depth["asks"][0]["volume"], 200);
+
+    http(
+        admin,
+        "POST",
+        "/mass_cancel",
+        Some(json!({ "security_id": "600519" })),
+    )
+    .await;
+    let (_, orders) = http(admin, "GET", "/orders", None).await;
+    assert_eq!(orders, json!([]));
+    let (_, queues) = http(admin, "GET", "/queues", None).await;
+    assert_eq!(queues["commands"], 0);
+    assert_eq!(queues["channels"][0]["events"], 0);
+
+    let (status, _) = http(admin, "GET", "/books/600000", None).await;
+    assert_eq!(status, 404);
+    let (status, error) = http(admin, "POST", "/phase", Some(json!({ "phase": "lunch" }))).await;
+    assert_eq!(status, 400);
+    assert!(error["error"].as_str().unwrap().contains("lunch"));
+
+    // a Content-Length that would overflow is refused, not read
+    let mut stream = TcpStream::connect(admin).await.unwrap();
+    let request = format!(
+        "POST /orders HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        usize::MAX
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 400 "), "{response}");
+    assert!(response.ends_with("request too large"), "{response}");
+}
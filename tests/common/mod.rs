@@ -0,0 +1,49 @@
+//! Helpers shared by the order book integration tests.
+#![allow(dead_code)]
+
+use exchange_matcher::types::{OrderSide, RbCmd, TimeInForce};
+use proptest::prelude::*;
+
+pub const SECURITY_ID: &str = "600519";
+
+/// One random book command. Cancels pick among the oids issued so far.
+#[derive(Debug, Clone)]
+pub enum Op {
+    New {
+        session_id: u64,
+        side: OrderSide,
+        price: i64,
+        volume: i64,
+    },
+    Cancel(prop::sample::Index),
+}
+
+/// New orders around a narrow price band, so that most of them cross.
+pub fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (1..=3_u64, any::<bool>(), 95..=105_i64, 1..=50_i64).prop_map(
+            |(session_id, buy, price, volume)| Op::New {
+                session_id,
+                side: if buy { OrderSide::Buy } else { OrderSide::Sell },
+                price,
+                volume,
+            }
+        ),
+        1 => any::<prop::sample::Index>().prop_map(Op::Cancel),
+    ]
+}
+
+pub fn cmd(oid: i64, side: OrderSide, price: i64, volume: i64) -> RbCmd {
+    RbCmd {
+        session_id: 1,
+        side,
+        match_event_list: vec![],
+        price,
+        volume,
+        mid: oid,
+        uid: 1,
+        oid,
+        security_id: SECURITY_ID.to_string(),
+        time_in_force: TimeInForce::Gtc,
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::BytesMut;
+use exchange_matcher::engine::match_engine::MatchEngine;
+use exchange_matcher::interface::channel::{
+    AcceptorChannel, ChannelIds, SessionLimits, TcpAcceptorChannel,
+};
+use exchange_matcher::protocol::fix::*;
+use exchange_matcher::protocol::proto::FrameDecoder;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, unbounded_channel};
+use tokio::task::JoinSet;
+
+const MAX_CONNECTIONS: usize = 20;
+
+struct Client {
+    stream: TcpStream,
+    decoder: FrameDecoder<FixDecoder>,
+    seq: u64,
+}
+
+impl Client {
+    async fn logon(channel: &TcpAcceptorChannel<FixAdapter>) -> Client {
+        let stream = TcpStream::connect(channel.local_addr().unwrap())
+            .await
+            .unwrap();
+        let mut client = Client {
+            stream,
+            decoder: FrameDecoder::new(FixDecoder),
+            seq: 1,
+        };
+        client
+            .send(FixMessage::new(msg_type::LOGON).with(tag::HEART_BT_INT, 30))
+            .await;
+        assert_eq!(client.recv().await.msg_type, msg_type::LOGON);
+        client
+    }
+
+    async fn send(&mut self, msg: FixMessage) {
+        let msg = msg
+            .with(tag::SENDER_COMP_ID, "OMS")
+            .with(tag::TARGET_COMP_ID, "MATCHER")
+            .with(tag::MSG_SEQ_NUM, self.seq);
+        self.seq += 1;
+        let mut buf = BytesMut::new();
+        msg.encode(&mut buf);
+        self.stream.write_all(&buf).await.unwrap();
+    }
+
+    async fn recv(&mut self) -> FixMessage {
+        let mut buf = [0u8; 1024];
+        loop {
+            if let Some(msg) = self.decoder.next_frame().unwrap() {
+                return msg;
+            }
+            let n = read(&mut self.stream, &mut buf).await;
+            assert!(n > 0, "matcher closed the connection");
+            self.decoder.feed(&buf[..n]);
+        }
+    }
+}
+
+async fn read(stream: &mut TcpStream, buf: &mut [u8]) -> usize {
+    tokio::time::timeout(Duration::from_secs(2), stream.read(buf))
+        .await
+        .expect("no answer from matcher")
+        // a reset counts as closed
+        .unwrap_or(0)
+}
+
+async fn until(what: &str, mut done: impl FnMut() -> bool) {
+    for _ in 0..200 {
+        if done() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("timed out waiting for {what}");
+}
+
+#[tokio::test]
+async fn concurrent_sessions() {
+    let (cmd_tx, cmd_rx) = channel(1024);
+    let (event_tx, event_rx) = unbounded_channel();
+    let mut engine = MatchEngine::new(cmd_rx, event_tx);
+    tokio::spawn(async move { engine.start().await });
+
+    let channel = TcpAcceptorChannel::<FixAdapter>::with_limits(
+        "127.0.0.1:0".parse().unwrap(),
+        cmd_tx,
+        Arc::new(ChannelIds::default()),
+        SessionLimits {
+            max_connections: MAX_CONNECTIONS,
+            ..SessionLimits::default()
+        },
+    );
+    channel.clone().start(event_rx).await.unwrap();
+
+    // every client gets its own order acknowledged, all at the same time
+    let mut tasks = JoinSet::new();
+    for i in 0..MAX_CONNECTIONS {
+        let channel = channel.clone();
+        tasks.spawn(async move {
+            let mut client = Client::logon(&channel).await;
+            let cl_ord_id = format!("S{i}");
+            client
+                .send(
+                    FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+                        .with(tag::CL_ORD_ID, &cl_ord_id)
+                        .with(tag::SYMBOL, "600000")
+                        .with(tag::SIDE, "2")
+                        .with(tag::ORDER_QTY, 100)
+                        .with(tag::ORD_TYPE, "2")
+                        .with(tag::PRICE, format!("{}", 11 + i)),
+                )
+                .await;
+            let ack = client.recv().await;
+            assert_eq!(ack.get(tag::EXEC_TYPE), Some("0"));
+            assert_eq!(ack.get(tag::CL_ORD_ID), Some(cl_ord_id.as_str()));
+            client
+        });
+    }
+    let mut clients = tasks.join_all().await;
+    assert_eq!(channel.sessions(), MAX_CONNECTIONS);
+
+    // one too many is closed right away
+    let mut refused = TcpStream::connect(channel.local_addr().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(read(&mut refused, &mut [0u8; 64]).await, 0);
+
+    // a client leaving makes room for another
+    drop(clients.pop());
+    until("the session to go", || {
+        channel.sessions() == MAX_CONNECTIONS - 1
+    })
+    .await;
+    clients.push(Client::logon(&channel).await);
+
+    channel.stop().await.unwrap();
+    assert_eq!(channel.sessions(), 0);
+    for client in &mut clients {
+        assert_eq!(read(&mut client.stream, &mut [0u8; 64]).await, 0);
+    }
+    assert!(
+        TcpStream::connect(channel.local_addr().unwrap())
+            .await
+            .is_err()
+    );
+}
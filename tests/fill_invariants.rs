@@ -0,0 +1,116 @@
+//! Checks the fill model of `OrderBook` events: every trade reports the same
+//! quantity on both sides, and per order the fills add up to the cumulative
+//! volume while cumulative plus leaves always equals the order size.
+
+mod common;
+
+use std::collections::HashMap;
+
+use common::{Op, cmd, op};
+use exchange_matcher::order_book::OrderBook;
+use exchange_matcher::types::{CmdResultCode, L1MarketData, MatchEvent, OrderSide, OrderStatus};
+use proptest::prelude::*;
+
+// what the tests know about an order from its events so far
+struct Tracked {
+    session_id: u64,
+    volume: i64,
+    cum: i64,
+    leaves: i64,
+}
+
+fn is_fill(ev: &MatchEvent) -> bool {
+    matches!(ev.status, OrderStatus::TradeEd | OrderStatus::PartTrade)
+}
+
+fn apply(tracked: &mut HashMap<i64, Tracked>, ev: &MatchEvent) -> Result<(), TestCaseError> {
+    let order = tracked.get_mut(&ev.oid).expect("event for unknown order");
+    prop_assert_eq!(
+        ev.session_id,
+        order.session_id,
+        "session of order {}",
+        ev.oid
+    );
+    match ev.status {
+        OrderStatus::TradeEd | OrderStatus::PartTrade => {
+            prop_assert!(ev.volume > 0);
+            prop_assert_eq!(ev.cum_volume, order.cum + ev.volume);
+            prop_assert_eq!(ev.cum_volume + ev.leaves_volume, order.volume);
+            prop_assert_eq!(ev.status == OrderStatus::TradeEd, ev.leaves_volume == 0);
+            order.cum = ev.cum_volume;
+            order.leaves = ev.leaves_volume;
+        }
+        OrderStatus::CancelEd | OrderStatus::PartCancel => {
+            prop_assert_eq!(ev.volume, order.leaves);
+            prop_assert_eq!(ev.cum_volume, order.cum);
+            prop_assert_eq!(ev.leaves_volume, 0);
+            prop_assert_eq!(ev.status == OrderStatus::CancelEd, order.cum == 0);
+            order.leaves = 0;
+        }
+        OrderStatus::OrderEd => {
+            prop_assert_eq!(ev.leaves_volume, order.volume);
+        }
+        OrderStatus::Expired | OrderStatus::Rejected => {
+            prop_assert!(false, "unexpected {:?}", ev.status)
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn fills_add_up(ops in prop::collection::vec(op(), 1..300)) {
+        let mut book = OrderBook::new(common::SECURITY_ID.to_string());
+        let mut tracked: HashMap<i64, Tracked> = HashMap::new();
+        let mut next_oid = 1;
+
+        for op in ops {
+            let events = match op {
+                Op::New { session_id, side, price, volume } => {
+                    let oid = next_oid;
+                    next_oid += 1;
+                    tracked.insert(oid, Tracked { session_id, volume, cum: 0, leaves: volume });
+                    let mut c = cmd(oid, side, price, volume);
+                    c.session_id = session_id;
+                    prop_assert_eq!(book.new_order(&mut c), CmdResultCode::Success);
+
+                    // fills come in pairs sharing a trade id, incoming side first
+                    let fills: Vec<&MatchEvent> = c.match_event_list.iter().filter(|e| is_fill(e)).collect();
+                    prop_assert_eq!(fills.len() % 2, 0);
+                    for pair in fills.chunks(2) {
+                        prop_assert_eq!(pair[0].oid, oid);
+                        prop_assert_eq!(pair[0].tid, pair[1].tid);
+                        prop_assert_eq!(pair[0].volume, pair[1].volume);
+                        prop_assert_eq!(pair[0].price, pair[1].price);
+                    }
+                    let incoming: i64 = fills.iter().filter(|e| e.oid == oid).map(|e| e.volume).sum();
+                    let resting: i64 = fills.iter().filter(|e| e.oid != oid).map(|e| e.volume).sum();
+                    prop_assert_eq!(incoming, resting);
+                    c.match_event_list
+                }
+                Op::Cancel(index) => {
+                    if next_oid == 1 {
+                        continue;
+                    }
+                    let oid = 1 + index.index((next_oid - 1) as usize) as i64;
+                    let mut c = cmd(oid, OrderSide::Buy, 0, 0);
+                    book.cancel_order(&mut c, 0);
+                    c.match_event_list
+                }
+            };
+            for ev in &events {
+                apply(&mut tracked, ev)?;
+            }
+        }
+
+        // whatever is still open is exactly what rests in the book
+        let open: i64 = tracked.values().map(|o| o.leaves).sum();
+        let buy_size = book.limit_buy_bucket_size(usize::MAX);
+        let sell_size = book.limit_sell_bucket_size(usize::MAX);
+        let mut data = L1MarketData::new(buy_size, sell_size);
+        book.fill_buys(buy_size, &mut data);
+        book.fill_sells(sell_size, &mut data);
+        let resting: i64 = data.buy_volumes.iter().chain(data.sell_volumes.iter()).sum();
+        prop_assert_eq!(open, resting);
+    }
+}
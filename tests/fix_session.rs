@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use bytes::BytesMut;
+use exchange_matcher::engine::match_engine::MatchEngine;
+use exchange_matcher::interface::channel::{AcceptorChannel, TcpAcceptorChannel};
+use exchange_matcher::protocol::fix::*;
+use exchange_matcher::protocol::proto::FrameDecoder;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, unbounded_channel};
+
+struct Client {
+    stream: TcpStream,
+    decoder: FrameDecoder<FixDecoder>,
+    seq: u64,
+}
+
+impl Client {
+    async fn send(&mut self, msg: FixMessage) {
+        let msg = msg
+            .with(tag::SENDER_COMP_ID, "OMS")
+            .with(tag::TARGET_COMP_ID, "MATCHER")
+            .with(tag::MSG_SEQ_NUM, self.seq);
+        self.seq += 1;
+        let mut buf = BytesMut::new();
+        msg.encode(&mut buf);
+        self.stream.write_all(&buf).await.unwrap();
+    }
+
+    async fn recv(&mut self) -> FixMessage {
+        let mut buf = [0u8; 1024];
+        loop {
+            if let Some(msg) = self.decoder.next_frame().unwrap() {
+                return msg;
+            }
+            let n = tokio::time::timeout(Duration::from_secs(2), self.stream.read(&mut buf))
+                .await
+                .expect("no message from matcher")
+                .unwrap();
+            assert!(n > 0, "matcher closed the connection");
+            self.decoder.feed(&buf[..n]);
+        }
+    }
+}
+
+fn order(cl_ord_id: &str, side: &str, qty: i64, price: &str) -> FixMessage {
+    FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+        .with(tag::CL_ORD_ID, cl_ord_id)
+        .with(tag::SYMBOL, "600000")
+        .with(tag::SIDE, side)
+        .with(tag::ORDER_QTY, qty)
+        .with(tag::ORD_TYPE, "2")
+        .with(tag::PRICE, price)
+}
+
+#[tokio::test]
+async fn fix_session() {
+    let (cmd_tx, cmd_rx) = channel(1024);
+    let (event_tx, event_rx) = unbounded_channel();
+    let mut engine = MatchEngine::new(cmd_rx, event_tx);
+    tokio::spawn(async move { engine.start().await });
+
+    let channel =
+        TcpAcceptorChannel::<FixAdapter>::with_protocol("127.0.0.1:0".parse().unwrap(), cmd_tx);
+    channel.clone().start(event_rx).await.unwrap();
+    let mut client = Client {
+        stream: TcpStream::connect(channel.local_addr().unwrap())
+            .await
+            .unwrap(),
+        decoder: FrameDecoder::new(FixDecoder),
+        seq: 1,
+    };
+
+    client
+        .send(FixMessage::new(msg_type::LOGON).with(tag::HEART_BT_INT, 30))
+        .await;
+    let logon = client.recv().await;
+    assert_eq!(logon.msg_type, msg_type::LOGON);
+    assert_eq!(logon.get(tag::SENDER_COMP_ID), Some("MATCHER"));
+
+    client
+        .send(FixMessage::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, "T1"))
+        .await;
+    let heartbeat = client.recv().await;
+    assert_eq!(heartbeat.msg_type, msg_type::HEARTBEAT);
+    assert_eq!(heartbeat.get(tag::TEST_REQ_ID), Some("T1"));
+
+    client.send(order("S1", "2", 300, "10.5")).await;
+    let confirm = client.recv().await;
+    assert_eq!(confirm.get(tag::EXEC_TYPE), Some("0"));
+
+    client.send(order("B1", "1", 100, "10.5")).await;
+    let mut fills = vec![];
+    while fills.len() < 2 {
+        let report = client.recv().await;
+        if report.get(tag::EXEC_TYPE) == Some("0") {
+            // an order that trades on entry is acknowledged as well
+            assert_eq!(report.get(tag::CL_ORD_ID), Some("B1"));
+        } else {
+            fills.push(report);
+        }
+    }
+    fills.sort_by(|a, b| a.get(tag::CL_ORD_ID).cmp(&b.get(tag::CL_ORD_ID)));
+    assert_eq!(fills[0].get(tag::ORD_STATUS), Some("2"));
+    assert_eq!(fills[0].get(tag::LAST_PX), Some("10.5"));
+    assert_eq!(fills[1].get(tag::CL_ORD_ID), Some("S1"));
+    assert_eq!(fills[1].get(tag::LEAVES_QTY), Some("200"));
+
+    // the replacement keeps what was filled
+    let mut replace = order("S2", "2", 500, "10.6").with(tag::ORIG_CL_ORD_ID, "S1");
+    replace.msg_type = msg_type::ORDER_CANCEL_REPLACE_REQUEST.to_string();
+    client.send(replace).await;
+    let replaced = client.recv().await;
+    assert_eq!(replaced.get(tag::EXEC_TYPE), Some("5"));
+    assert_eq!(replaced.get(tag::ORIG_CL_ORD_ID), Some("S1"));
+    assert_eq!(replaced.get(tag::CUM_QTY), Some("100"));
+    assert_eq!(replaced.get(tag::LEAVES_QTY), Some("400"));
+
+    client.send(order("B2", "1", 400, "10.6")).await;
+    assert_eq!(client.recv().await.get(tag::EXEC_TYPE), Some("0"));
+    let mut fills = [client.recv().await, client.recv().await];
+    fills.sort_by(|a, b| a.get(tag::CL_ORD_ID).cmp(&b.get(tag::CL_ORD_ID)));
+    assert_eq!(fills[1].get(tag::CL_ORD_ID), Some("S2"));
+    assert_eq!(fills[1].get(tag::CUM_QTY), Some("500"));
+    assert_eq!(fills[1].get(tag::ORD_STATUS), Some("2"));
+
+    // S2 is filled and gone
+    client
+        .send(
+            FixMessage::new(msg_type::ORDER_CANCEL_REQUEST)
+                .with(tag::CL_ORD_ID, "C1")
+                .with(tag::ORIG_CL_ORD_ID, "S2")
+                .with(tag::SYMBOL, "600000")
+                .with(tag::SIDE, "2"),
+        )
+        .await;
+    let reject = client.recv().await;
+    assert_eq!(reject.msg_type, msg_type::ORDER_CANCEL_REJECT);
+    assert_eq!(reject.get(tag::CXL_REJ_RESPONSE_TO), Some("1"));
+
+    client.send(FixMessage::new(msg_type::LOGOUT)).await;
+    assert_eq!(client.recv().await.msg_type, msg_type::LOGOUT);
+}
@@ -0,0 +1,70 @@
+//! Feeds arbitrary bytes to the frame decoders: they must never panic, keep
+//! their buffer within the frame limit and always make progress, and a good
+//! message after the garbage still comes through where the protocol can
+//! resync.
+
+use bytes::BytesMut;
+use exchange_matcher::protocol::fix::{FixDecoder, FixMessage, msg_type, tag};
+use exchange_matcher::protocol::proto::{FrameDecoder, ProtocolDecoder, SseDecoder};
+use exchange_matcher::protocol::szse::SzseDecoder;
+use proptest::prelude::*;
+
+const MAX_FRAME_LEN: usize = 4096;
+
+// Drains the decoder, returning how many messages came out.
+fn drain<D: ProtocolDecoder>(decoder: &mut FrameDecoder<D>) -> Result<usize, TestCaseError> {
+    let mut messages = 0;
+    loop {
+        let before = decoder.buffered();
+        match decoder.next_frame() {
+            Ok(Some(_)) => messages += 1,
+            Ok(None) => return Ok(messages),
+            Err(e) => {
+                prop_assert!(decoder.buffered() < before, "{e} without progress");
+            }
+        }
+    }
+}
+
+fn feed_all<D: ProtocolDecoder>(decoder: D, chunks: &[Vec<u8>]) -> Result<(), TestCaseError> {
+    let mut decoder = FrameDecoder::new(decoder).with_max_frame_len(MAX_FRAME_LEN);
+    for chunk in chunks {
+        decoder.feed(chunk);
+        drain(&mut decoder)?;
+        prop_assert!(decoder.buffered() <= MAX_FRAME_LEN);
+    }
+    Ok(())
+}
+
+fn chunks() -> impl Strategy<Value = Vec<Vec<u8>>> {
+    prop::collection::vec(prop::collection::vec(any::<u8>(), 0..600), 0..20)
+}
+
+proptest! {
+    #[test]
+    fn sse_survives_garbage(chunks in chunks()) {
+        feed_all(SseDecoder, &chunks)?;
+    }
+
+    #[test]
+    fn szse_survives_garbage(chunks in chunks()) {
+        feed_all(SzseDecoder, &chunks)?;
+    }
+
+    #[test]
+    fn fix_survives_garbage(chunks in chunks()) {
+        feed_all(FixDecoder, &chunks)?;
+    }
+
+    #[test]
+    fn fix_resyncs_after_garbage(garbage in "[^8]{0,300}") {
+        let mut buf = BytesMut::new();
+        FixMessage::new(msg_type::HEARTBEAT)
+            .with(tag::MSG_SEQ_NUM, 2)
+            .encode(&mut buf);
+        let mut decoder = FrameDecoder::new(FixDecoder);
+        decoder.feed(garbage.as_bytes());
+        decoder.feed(&buf);
+        prop_assert_eq!(drain(&mut decoder)?, 1);
+    }
+}
@@ -0,0 +1,179 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::BytesMut;
+use exchange_matcher::engine::match_engine::MatchEngine;
+use exchange_matcher::engine::metrics::EngineMetrics;
+use exchange_matcher::interface::channel::{AcceptorChannel, TcpAcceptorChannel};
+use exchange_matcher::interface::metrics::MetricsServer;
+use exchange_matcher::protocol::fix::*;
+use exchange_matcher::protocol::proto::FrameDecoder;
+use exchange_matcher::types::{EngineCommand, OrderSide, RbCmd, TimeInForce, TradingPhase};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, unbounded_channel};
+
+async fn scrape(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: test\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains("Content-Type: text/plain; version=0.0.4"));
+    body.to_string()
+}
+
+// the value of the sample with exactly this name and labels
+fn value(text: &str, sample: &str) -> f64 {
+    text.lines()
+        .find_map(|line| line.strip_prefix(sample)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no {sample} in\n{text}"))
+        .parse()
+        .unwrap()
+}
+
+fn send(stream: &mut BytesMut, seq: u64, msg: FixMessage) {
+    msg.with(tag::SENDER_COMP_ID, "OMS")
+        .with(tag::TARGET_COMP_ID, "MATCHER")
+        .with(tag::MSG_SEQ_NUM, seq)
+        .encode(stream);
+}
+
+fn order(cl_ord_id: &str, side: &str, qty: i64) -> FixMessage {
+    FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+        .with(tag::CL_ORD_ID, cl_ord_id)
+        .with(tag::SYMBOL, "600000")
+        .with(tag::SIDE, side)
+        .with(tag::ORDER_QTY, qty)
+        .with(tag::ORD_TYPE, "2")
+        .with(tag::PRICE, "10.5")
+}
+
+#[tokio::test]
+async fn test_scrape_engine_and_channel_metrics() {
+    let (cmd_tx, cmd_rx) = channel(1024);
+    let (event_tx, event_rx) = unbounded_channel();
+    let metrics = Arc::new(EngineMetrics::default());
+    let mut engine = MatchEngine::new(cmd_rx, event_tx).with_metrics(metrics.clone());
+    tokio::spawn(async move { engine.start().await });
+
+    let fix = TcpAcceptorChannel::<FixAdapter>::with_protocol(
+        "127.0.0.1:0".parse().unwrap(),
+        cmd_tx.clone(),
+    );
+    fix.clone().start(event_rx).await.unwrap();
+    let server = MetricsServer::new("127.0.0.1:0".parse().unwrap(), cmd_tx.clone())
+        .with_engine(metrics)
+        .with_channel("fix", fix.clone())
+        .start()
+        .await
+        .unwrap();
+
+    // an order into a halted book is rejected
+    let halt = EngineCommand::SetPhase {
+        security_id: Some("600519".to_string()),
+        phase: TradingPhase::Halted,
+    };
+    cmd_tx.send(halt).await.unwrap();
+    let rejected = RbCmd {
+        session_id: 0,
+        side: OrderSide::Buy,
+        match_event_list: vec![],
+        price: 100,
+        volume: 100,
+        mid: 1000,
+        uid: 1,
+        oid: 1000,
+        security_id: "600519".to_string(),
+        time_in_force: TimeInForce::Gtc,
+    };
+    cmd_tx
+        .send(EngineCommand::NewOrder(rejected))
+        .await
+        .unwrap();
+
+    // a FIX client logs on and trades with itself
+    let mut client = TcpStream::connect(fix.local_addr().unwrap()).await.unwrap();
+    let mut buf = BytesMut::new();
+    send(
+        &mut buf,
+        1,
+        FixMessage::new(msg_type::LOGON).with(tag::HEART_BT_INT, 30),
+    );
+    send(&mut buf, 2, order("S1", "2", 300));
+    send(&mut buf, 3, order("B1", "1", 100));
+    client.write_all(&buf).await.unwrap();
+    let mut decoder = FrameDecoder::new(FixDecoder);
+    let mut fills = 0;
+    let mut read = [0u8; 4096];
+    while fills < 2 {
+        while let Some(msg) = decoder.next_frame().unwrap() {
+            if msg.get(tag::EXEC_TYPE) == Some("F") {
+                fills += 1;
+            }
+        }
+        if fills < 2 {
+            let n = tokio::time::timeout(Duration::from_secs(2), client.read(&mut read))
+                .await
+                .expect("no fill from matcher")
+                .unwrap();
+            decoder.feed(&read[..n]);
+        }
+    }
+    // a frame with a wrong checksum
+    let garbled = b"8=FIX.4.4\x019=5\x0135=0\x0110=000\x01";
+    client.write_all(garbled).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let text = scrape(server).await;
+    assert!(text.contains("# TYPE matcher_orders_total counter\n"));
+    assert_eq!(
+        value(&text, "matcher_orders_total{security_id=\"600000\"}"),
+        2.0
+    );
+    assert_eq!(
+        value(&text, "matcher_trades_total{security_id=\"600000\"}"),
+        1.0
+    );
+    assert_eq!(
+        value(&text, "matcher_traded_volume_total{security_id=\"600000\"}"),
+        100.0
+    );
+    assert_eq!(
+        value(&text, "matcher_rejects_total{code=\"not_trading\"}"),
+        1.0
+    );
+    assert_eq!(
+        value(&text, "matcher_rejects_total{code=\"duplicate_order_id\"}"),
+        0.0
+    );
+    assert_eq!(value(&text, "matcher_command_latency_seconds_count"), 4.0);
+    assert_eq!(
+        value(&text, "matcher_command_latency_seconds_bucket{le=\"+Inf\"}"),
+        4.0
+    );
+    assert_eq!(value(&text, "matcher_command_queue_depth"), 0.0);
+    assert_eq!(
+        value(&text, "matcher_channel_queued_events{channel=\"fix\"}"),
+        0.0
+    );
+    assert_eq!(value(&text, "matcher_sessions{channel=\"fix\"}"), 1.0);
+    assert_eq!(
+        value(&text, "matcher_session_queued_events{channel=\"fix\"}"),
+        0.0
+    );
+    assert_eq!(
+        value(&text, "matcher_bytes_received_total{channel=\"fix\"}"),
+        (buf.len() + garbled.len()) as f64
+    );
+    assert!(value(&text, "matcher_bytes_sent_total{channel=\"fix\"}") > 0.0);
+    assert_eq!(
+        value(&text, "matcher_decode_errors_total{channel=\"fix\"}"),
+        1.0
+    );
+}
@@ -0,0 +1,207 @@
+//! Randomized command sequences against `OrderBook` under every matching
+//! policy, running `check_invariants` after each command and checking the
+//! traded volume of every resting order against the fills reported for it.
+//!
+//! The default run covers a few hundred thousand commands. Raise
+//! `PROPTEST_CASES` (e.g. `PROPTEST_CASES=5000`) or run the ignored soak test
+//! to push millions of operations through the book.
+#![cfg(debug_assertions)]
+
+mod common;
+
+use std::collections::HashMap;
+
+use common::cmd;
+use exchange_matcher::match_policy::{
+    HybridProRataPolicy, MatchPolicy, PriceTimePolicy, ProRataPolicy,
+};
+use exchange_matcher::order_book::OrderBook;
+use exchange_matcher::types::{CmdResultCode, MatchEvent, OrderSide, OrderStatus, TimeInForce};
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestRunner};
+
+#[derive(Debug, Clone)]
+enum Command {
+    New {
+        side: OrderSide,
+        price: i64,
+        volume: i64,
+        time_in_force: TimeInForce,
+        // reuse an earlier oid instead of a fresh one
+        reuse: Option<prop::sample::Index>,
+    },
+    Cancel(prop::sample::Index),
+    // move the clock forward and sweep expired orders
+    Expire {
+        advance: i64,
+        end_of_day: bool,
+    },
+}
+
+fn time_in_force() -> impl Strategy<Value = TimeInForce> {
+    prop_oneof![
+        Just(TimeInForce::Gtc),
+        Just(TimeInForce::Day),
+        (1..=1_000_i64).prop_map(TimeInForce::Gtd),
+    ]
+}
+
+fn command() -> impl Strategy<Value = Command> {
+    prop_oneof![
+        12 => (
+            any::<bool>(),
+            90..=110_i64,
+            1..=200_i64,
+            time_in_force(),
+            prop::option::weighted(0.05, any::<prop::sample::Index>()),
+        )
+            .prop_map(|(buy, price, volume, time_in_force, reuse)| Command::New {
+                side: if buy { OrderSide::Buy } else { OrderSide::Sell },
+                price,
+                volume,
+                time_in_force,
+                reuse,
+            }),
+        4 => any::<prop::sample::Index>().prop_map(Command::Cancel),
+        1 => (0..=50_i64, prop::bool::weighted(0.1))
+            .prop_map(|(advance, end_of_day)| Command::Expire { advance, end_of_day }),
+    ]
+}
+
+// Adds up the fills of each order and checks the cumulative volume every
+// event reports against them.
+fn record(traded: &mut HashMap<i64, i64>, events: &[MatchEvent]) -> Result<(), TestCaseError> {
+    for ev in events {
+        let cum = traded.entry(ev.oid).or_default();
+        if matches!(ev.status, OrderStatus::PartTrade | OrderStatus::TradeEd) {
+            *cum += ev.volume;
+        }
+        if matches!(
+            ev.status,
+            OrderStatus::PartTrade
+                | OrderStatus::TradeEd
+                | OrderStatus::CancelEd
+                | OrderStatus::PartCancel
+                | OrderStatus::Expired
+        ) {
+            prop_assert_eq!(ev.cum_volume, *cum, "order {} {:?}", ev.oid, ev.status);
+        }
+    }
+    Ok(())
+}
+
+fn run<P: MatchPolicy>(policy: P, commands: Vec<Command>) -> Result<(), TestCaseError> {
+    let mut book = OrderBook::with_policy(common::SECURITY_ID.to_string(), policy);
+    let mut next_oid = 1_i64;
+    let mut now = 0_i64;
+    // volume traded by oid, as the events tell
+    let mut traded = HashMap::new();
+
+    for (step, command) in commands.into_iter().enumerate() {
+        match command {
+            Command::New {
+                side,
+                price,
+                volume,
+                time_in_force,
+                reuse,
+            } => {
+                let oid = match reuse {
+                    Some(index) if next_oid > 1 => 1 + index.index((next_oid - 1) as usize) as i64,
+                    _ => {
+                        next_oid += 1;
+                        next_oid - 1
+                    }
+                };
+                let mut c = cmd(oid, side, price, volume);
+                c.time_in_force = match time_in_force {
+                    TimeInForce::Gtd(offset) => TimeInForce::Gtd(now + offset),
+                    other => other,
+                };
+                if book.order_info(oid).is_none() {
+                    // a fresh order under an oid used before
+                    traded.remove(&oid);
+                }
+                let result = book.new_order(&mut c);
+                record(&mut traded, &c.match_event_list)?;
+                prop_assert!(
+                    matches!(
+                        result,
+                        CmdResultCode::Success | CmdResultCode::DuplicateOrderId
+                    ),
+                    "unexpected {result:?}"
+                );
+            }
+            Command::Cancel(index) => {
+                if next_oid == 1 {
+                    continue;
+                }
+                let oid = 1 + index.index((next_oid - 1) as usize) as i64;
+                let mut c = cmd(oid, OrderSide::Buy, 0, 0);
+                book.cancel_order(&mut c, 0);
+                record(&mut traded, &c.match_event_list)?;
+            }
+            Command::Expire {
+                advance,
+                end_of_day,
+            } => {
+                now += advance;
+                let events = book.expire_orders(now, end_of_day);
+                for ev in &events {
+                    prop_assert!(ev.volume > 0);
+                }
+                record(&mut traded, &events)?;
+                if let Some(next) = book.next_expiry() {
+                    prop_assert!(next > now);
+                }
+            }
+        }
+        if let Err(violation) = book.check_invariants() {
+            return Err(TestCaseError::fail(format!("step {step}: {violation}")));
+        }
+        for order in book.open_orders(|_| true) {
+            let reported = traded.get(&order.oid).copied().unwrap_or(0);
+            prop_assert_eq!(order.traded, reported, "step {}: order {}", step, order.oid);
+        }
+    }
+    Ok(())
+}
+
+fn commands(max_len: usize) -> impl Strategy<Value = Vec<Command>> {
+    prop::collection::vec(command(), 1..max_len)
+}
+
+proptest! {
+    #[test]
+    fn price_time_keeps_invariants(commands in commands(500)) {
+        run(PriceTimePolicy, commands)?;
+    }
+
+    #[test]
+    fn pro_rata_keeps_invariants(commands in commands(500)) {
+        run(ProRataPolicy::new(5, 10), commands)?;
+    }
+
+    #[test]
+    fn hybrid_keeps_invariants(commands in commands(500)) {
+        run(HybridProRataPolicy::new(ProRataPolicy::new(1, 1), 25), commands)?;
+    }
+}
+
+#[test]
+#[ignore = "soak test, several million commands"]
+fn soak_invariants() {
+    let mut runner = TestRunner::new(Config {
+        cases: 2000,
+        failure_persistence: None,
+        ..Config::default()
+    });
+    runner
+        .run(&commands(2000), |commands| run(PriceTimePolicy, commands))
+        .unwrap();
+    runner
+        .run(&commands(2000), |commands| {
+            run(HybridProRataPolicy::default(), commands)
+        })
+        .unwrap();
+}
@@ -0,0 +1,143 @@
+//! Drives `OrderBook` and a deliberately naive reference book with the same
+//! random commands and checks they agree on fills and depth after every step.
+
+mod common;
+
+use common::{Op, cmd, op};
+use exchange_matcher::order_book::OrderBook;
+use exchange_matcher::types::{CmdResultCode, L1MarketData, OrderSide, OrderStatus};
+use proptest::prelude::*;
+
+struct RefOrder {
+    oid: i64,
+    side: OrderSide,
+    price: i64,
+    remaining: i64,
+}
+
+// resting orders in arrival order, matched by scanning everything
+#[derive(Default)]
+struct RefBook {
+    orders: Vec<RefOrder>,
+}
+
+impl RefBook {
+    fn new_order(&mut self, oid: i64, side: OrderSide, price: i64, volume: i64) -> i64 {
+        let mut left = volume;
+        while left > 0 {
+            let best = self
+                .orders
+                .iter()
+                .enumerate()
+                .filter(|(_, o)| match side {
+                    OrderSide::Buy => o.side == OrderSide::Sell && o.price <= price,
+                    OrderSide::Sell => o.side == OrderSide::Buy && o.price >= price,
+                })
+                // best price first, earliest arrival among equals
+                .min_by_key(|(i, o)| match side {
+                    OrderSide::Buy => (o.price, *i),
+                    OrderSide::Sell => (-o.price, *i),
+                })
+                .map(|(i, _)| i);
+            let Some(i) = best else {
+                break;
+            };
+            let traded = left.min(self.orders[i].remaining);
+            self.orders[i].remaining -= traded;
+            left -= traded;
+            if self.orders[i].remaining == 0 {
+                self.orders.remove(i);
+            }
+        }
+        if left > 0 {
+            self.orders.push(RefOrder {
+                oid,
+                side,
+                price,
+                remaining: left,
+            });
+        }
+        volume - left
+    }
+
+    fn cancel(&mut self, oid: i64) -> bool {
+        match self.orders.iter().position(|o| o.oid == oid) {
+            Some(i) => {
+                self.orders.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn depth(&self, side: OrderSide) -> Levels {
+        let mut levels: Levels = vec![];
+        for o in self.orders.iter().filter(|o| o.side == side) {
+            match levels.iter_mut().find(|(p, _)| *p == o.price) {
+                Some(level) => level.1 += o.remaining,
+                None => levels.push((o.price, o.remaining)),
+            }
+        }
+        levels.sort_by_key(|&(p, _)| if side == OrderSide::Buy { -p } else { p });
+        levels
+    }
+}
+
+// (price, volume) per level, best first
+type Levels = Vec<(i64, i64)>;
+
+fn depth(book: &OrderBook) -> (Levels, Levels) {
+    let buy_size = book.limit_buy_bucket_size(usize::MAX);
+    let sell_size = book.limit_sell_bucket_size(usize::MAX);
+    let mut data = L1MarketData::new(buy_size, sell_size);
+    book.fill_buys(buy_size, &mut data);
+    book.fill_sells(sell_size, &mut data);
+    let buys = (0..data.buy_size)
+        .map(|i| (data.buy_prices[i], data.buy_volumes[i]))
+        .collect();
+    let sells = (0..data.sell_size)
+        .map(|i| (data.sell_prices[i], data.sell_volumes[i]))
+        .collect();
+    (buys, sells)
+}
+
+proptest! {
+    #[test]
+    fn order_book_matches_reference(ops in prop::collection::vec(op(), 1..300)) {
+        let mut book = OrderBook::new(common::SECURITY_ID.to_string());
+        let mut reference = RefBook::default();
+        let mut next_oid = 1;
+
+        for op in ops {
+            match op {
+                Op::New { side, price, volume, .. } => {
+                    let oid = next_oid;
+                    next_oid += 1;
+                    let mut c = cmd(oid, side, price, volume);
+                    prop_assert_eq!(book.new_order(&mut c), CmdResultCode::Success);
+                    let traded: i64 = c
+                        .match_event_list
+                        .iter()
+                        .filter(|e| e.oid == oid)
+                        .filter(|e| matches!(e.status, OrderStatus::TradeEd | OrderStatus::PartTrade))
+                        .map(|e| e.volume)
+                        .sum();
+                    prop_assert_eq!(traded, reference.new_order(oid, side, price, volume));
+                }
+                Op::Cancel(index) => {
+                    if next_oid == 1 {
+                        continue;
+                    }
+                    let oid = 1 + index.index((next_oid - 1) as usize) as i64;
+                    let mut c = cmd(oid, OrderSide::Buy, 0, 0);
+                    let found = book.cancel_order(&mut c, 0) == CmdResultCode::Success;
+                    prop_assert_eq!(found, reference.cancel(oid));
+                }
+            }
+
+            let (buys, sells) = depth(&book);
+            prop_assert_eq!(buys, reference.depth(OrderSide::Buy));
+            prop_assert_eq!(sells, reference.depth(OrderSide::Sell));
+        }
+    }
+}
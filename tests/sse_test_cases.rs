@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use exchange_matcher::engine::match_engine::MatchEngine;
+use exchange_matcher::interface::channel::{AcceptorChannel, TcpAcceptorChannel};
+use exchange_matcher::testcase::{TestCaseRunner, load_steps};
+use tokio::sync::mpsc::{channel, unbounded_channel};
+
+// the scenarios QA runs with gt-auto, against an in-process matcher
+#[tokio::test]
+async fn sse_test_case() {
+    let (cmd_tx, cmd_rx) = channel(1024);
+    let (event_tx, event_rx) = unbounded_channel();
+    let mut engine = MatchEngine::new(cmd_rx, event_tx);
+    tokio::spawn(async move { engine.start().await });
+
+    let channel = TcpAcceptorChannel::new("127.0.0.1:0".parse().unwrap(), cmd_tx);
+    Arc::clone(&channel).start(event_rx).await.unwrap();
+    let addr = channel.local_addr().unwrap();
+
+    let steps = load_steps("testcase/sse_test_case.csv").unwrap();
+    let report = TestCaseRunner::new(addr, "testcase").run(&steps).await;
+    assert!(report.passed(), "\n{report}");
+}
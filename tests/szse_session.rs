@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use bytes::BytesMut;
+use exchange_matcher::engine::match_engine::MatchEngine;
+use exchange_matcher::interface::channel::{AcceptorChannel, TcpAcceptorChannel};
+use exchange_matcher::protocol::proto::FrameDecoder;
+use exchange_matcher::protocol::szse::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, unbounded_channel};
+
+struct Client {
+    stream: TcpStream,
+    decoder: FrameDecoder<SzseDecoder>,
+}
+
+impl Client {
+    async fn send(&mut self, msg: SzseMessage) {
+        let mut buf = BytesMut::new();
+        msg.encode(&mut buf);
+        self.stream.write_all(&buf).await.unwrap();
+    }
+
+    async fn recv(&mut self) -> SzseMessage {
+        let mut buf = [0u8; 1024];
+        loop {
+            if let Some(msg) = self.decoder.next_frame().unwrap() {
+                return msg;
+            }
+            let n = tokio::time::timeout(Duration::from_secs(2), self.stream.read(&mut buf))
+                .await
+                .expect("no message from matcher")
+                .unwrap();
+            assert!(n > 0, "matcher closed the connection");
+            self.decoder.feed(&buf[..n]);
+        }
+    }
+
+    async fn report(&mut self) -> ExecutionReport {
+        match self.recv().await {
+            SzseMessage::ExecutionReport(report) => report,
+            other => panic!("expected an execution report, got {other:?}"),
+        }
+    }
+
+    async fn trade(&mut self) -> TradeReport {
+        match self.recv().await {
+            SzseMessage::TradeReport(report) => report,
+            other => panic!("expected a trade report, got {other:?}"),
+        }
+    }
+}
+
+fn order(cl_ord_id: &str, side: &str, qty: i64, price: i64) -> SzseMessage {
+    SzseMessage::NewOrder(NewOrder {
+        appl_id: "010".to_string(),
+        security_id: "000001".to_string(),
+        security_id_source: "102".to_string(),
+        cl_ord_id: cl_ord_id.to_string(),
+        side: side.to_string(),
+        ord_type: "2".to_string(),
+        order_qty: qty,
+        price,
+        ..NewOrder::default()
+    })
+}
+
+fn cancel(cl_ord_id: &str, orig_cl_ord_id: &str) -> SzseMessage {
+    SzseMessage::OrderCancelRequest(OrderCancelRequest {
+        appl_id: "010".to_string(),
+        security_id: "000001".to_string(),
+        cl_ord_id: cl_ord_id.to_string(),
+        orig_cl_ord_id: orig_cl_ord_id.to_string(),
+        ..OrderCancelRequest::default()
+    })
+}
+
+#[tokio::test]
+async fn szse_session() {
+    let (cmd_tx, cmd_rx) = channel(1024);
+    let (event_tx, event_rx) = unbounded_channel();
+    let mut engine = MatchEngine::new(cmd_rx, event_tx);
+    tokio::spawn(async move { engine.start().await });
+
+    let channel =
+        TcpAcceptorChannel::<SzseAdapter>::with_protocol("127.0.0.1:0".parse().unwrap(), cmd_tx);
+    channel.clone().start(event_rx).await.unwrap();
+    let mut client = Client {
+        stream: TcpStream::connect(channel.local_addr().unwrap())
+            .await
+            .unwrap(),
+        decoder: FrameDecoder::new(SzseDecoder),
+    };
+
+    client
+        .send(SzseMessage::Logon(Logon {
+            sender_comp_id: "CLIENT".to_string(),
+            target_comp_id: "MATCHER".to_string(),
+            heart_bt_int: 30,
+            ..Logon::default()
+        }))
+        .await;
+    match client.recv().await {
+        SzseMessage::Logon(logon) => assert_eq!(logon.sender_comp_id, "MATCHER"),
+        other => panic!("expected a logon, got {other:?}"),
+    }
+
+    client.send(order("S1", "2", 300, 100_000)).await;
+    let confirm = client.report().await;
+    assert_eq!(
+        (confirm.cl_ord_id.as_str(), confirm.exec_type.as_str()),
+        ("S1", "0")
+    );
+
+    client.send(order("B1", "1", 100, 100_000)).await;
+    let mut fills = [client.trade().await, client.trade().await];
+    fills.sort_by(|a, b| a.cl_ord_id.cmp(&b.cl_ord_id));
+    assert_eq!(fills[0].cl_ord_id, "B1");
+    assert_eq!(
+        (fills[0].ord_status.as_str(), fills[0].last_qty),
+        ("2", 100)
+    );
+    assert_eq!(fills[1].cl_ord_id, "S1");
+    assert_eq!(
+        (fills[1].ord_status.as_str(), fills[1].leaves_qty),
+        ("1", 200)
+    );
+
+    // a duplicate client order id never reaches the engine
+    client.send(order("S1", "2", 100, 100_000)).await;
+    let reject = client.report().await;
+    assert_eq!((reject.exec_type.as_str(), reject.ord_rej_reason), ("8", 6));
+
+    client.send(cancel("C1", "NOPE")).await;
+    let reject = client.report().await;
+    assert_eq!(reject.exec_type, "8");
+    assert_eq!(reject.orig_cl_ord_id, "NOPE");
+
+    client.send(cancel("C2", "S1")).await;
+    let cancelled = client.report().await;
+    assert_eq!(cancelled.exec_type, "4");
+    assert_eq!(
+        (
+            cancelled.cl_ord_id.as_str(),
+            cancelled.orig_cl_ord_id.as_str()
+        ),
+        ("C2", "S1")
+    );
+    assert_eq!(cancelled.cum_qty, 100);
+
+    client
+        .send(SzseMessage::Logout(Logout {
+            session_status: 0,
+            text: String::new(),
+        }))
+        .await;
+    assert!(matches!(client.recv().await, SzseMessage::Logout(_)));
+    let mut buf = [0u8; 16];
+    let n = tokio::time::timeout(Duration::from_secs(2), client.stream.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(n, 0);
+}
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use exchange_matcher::engine::match_engine::MatchEngine;
+use exchange_matcher::interface::channel::{
+    AcceptorChannel, ChannelIds, SessionLimits, TcpAcceptorChannel, ThrottleAction, ThrottleConfig,
+};
+use exchange_matcher::protocol::fix::*;
+use exchange_matcher::protocol::proto::FrameDecoder;
+use exchange_matcher::types::{EngineCommand, EngineEvent};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{Sender, UnboundedReceiver, channel, unbounded_channel};
+
+struct Client {
+    stream: TcpStream,
+    decoder: FrameDecoder<FixDecoder>,
+    seq: u64,
+}
+
+impl Client {
+    async fn connect(throttle: ThrottleConfig) -> Client {
+        let (cmd_tx, cmd_rx) = channel(1024);
+        let (event_tx, event_rx) = unbounded_channel();
+        let mut engine = MatchEngine::new(cmd_rx, event_tx);
+        tokio::spawn(async move { engine.start().await });
+        Client::connect_to(throttle, cmd_tx, event_rx).await
+    }
+
+    async fn connect_to(
+        throttle: ThrottleConfig,
+        cmd_tx: Sender<EngineCommand>,
+        event_rx: UnboundedReceiver<EngineEvent>,
+    ) -> Client {
+        let channel = TcpAcceptorChannel::<FixAdapter>::with_limits(
+            "127.0.0.1:0".parse().unwrap(),
+            cmd_tx,
+            Arc::new(ChannelIds::default()),
+            SessionLimits {
+                throttle: Some(throttle),
+                ..SessionLimits::default()
+            },
+        );
+        channel.clone().start(event_rx).await.unwrap();
+        let mut client = Client {
+            stream: TcpStream::connect(channel.local_addr().unwrap())
+                .await
+                .unwrap(),
+            decoder: FrameDecoder::new(FixDecoder),
+            seq: 1,
+        };
+        client
+            .send(&[FixMessage::new(msg_type::LOGON).with(tag::HEART_BT_INT, 30)])
+            .await;
+        assert_eq!(client.recv().await.msg_type, msg_type::LOGON);
+        client
+    }
+
+    // all of `msgs` in one write, so that they arrive at once
+    async fn send(&mut self, msgs: &[FixMessage]) {
+        let mut buf = BytesMut::new();
+        for msg in msgs {
+            msg.clone()
+                .with(tag::SENDER_COMP_ID, "OMS")
+                .with(tag::TARGET_COMP_ID, "MATCHER")
+                .with(tag::MSG_SEQ_NUM, self.seq)
+                .encode(&mut buf);
+            self.seq += 1;
+        }
+        self.stream.write_all(&buf).await.unwrap();
+    }
+
+    async fn recv(&mut self) -> FixMessage {
+        let mut buf = [0u8; 1024];
+        loop {
+            if let Some(msg) = self.decoder.next_frame().unwrap() {
+                return msg;
+            }
+            let n = tokio::time::timeout(Duration::from_secs(3), self.stream.read(&mut buf))
+                .await
+                .expect("no message from matcher")
+                .unwrap();
+            assert!(n > 0, "matcher closed the connection");
+            self.decoder.feed(&buf[..n]);
+        }
+    }
+
+    // ExecType of the answer to each order, by ClOrdID
+    async fn answers(&mut self, n: usize) -> Vec<(String, String)> {
+        let mut answers = vec![];
+        for _ in 0..n {
+            let report = self.recv().await;
+            answers.push((
+                report.get(tag::CL_ORD_ID).unwrap().to_string(),
+                report.get(tag::EXEC_TYPE).unwrap().to_string(),
+            ));
+        }
+        answers.sort();
+        answers
+    }
+}
+
+// resting sells that never trade
+fn orders(prefix: &str, n: usize) -> Vec<FixMessage> {
+    (0..n)
+        .map(|i| {
+            FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+                .with(tag::CL_ORD_ID, format!("{prefix}{i}"))
+                .with(tag::SYMBOL, "600000")
+                .with(tag::SIDE, "2")
+                .with(tag::ORDER_QTY, 100)
+                .with(tag::ORD_TYPE, "2")
+                .with(tag::PRICE, 10 + i)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn orders_over_the_rate_are_rejected() {
+    let mut client = Client::connect(ThrottleConfig {
+        rate: 3,
+        action: ThrottleAction::Reject,
+    })
+    .await;
+
+    client.send(&orders("A", 5)).await;
+    let mut rejects = 0;
+    for _ in 0..5 {
+        let report = client.recv().await;
+        match report.get(tag::EXEC_TYPE) {
+            Some("0") => {}
+            Some("8") => {
+                rejects += 1;
+                assert_eq!(report.get(tag::ORD_REJ_REASON), Some("99"));
+                assert_eq!(report.get(tag::TEXT), Some("throttle limit exceeded"));
+            }
+            other => panic!("unexpected ExecType {other:?}"),
+        }
+    }
+    assert_eq!(rejects, 2);
+
+    // the rate allows more a second later
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    client.send(&orders("B", 3)).await;
+    let answers = client.answers(3).await;
+    assert!(answers.iter().all(|(_, exec_type)| exec_type == "0"));
+}
+
+#[tokio::test]
+async fn orders_over_the_rate_are_delayed() {
+    let mut client = Client::connect(ThrottleConfig {
+        rate: 5,
+        action: ThrottleAction::Delay,
+    })
+    .await;
+
+    let start = Instant::now();
+    client.send(&orders("A", 10)).await;
+    let answers = client.answers(10).await;
+    assert!(answers.iter().all(|(_, exec_type)| exec_type == "0"));
+    // five at once, the other five over the following second
+    assert!(start.elapsed() >= Duration::from_millis(900));
+}
+
+#[tokio::test]
+async fn orders_for_a_full_engine_queue_are_rejected() {
+    // an engine that takes nothing, its queue holds one command
+    let (cmd_tx, _cmd_rx) = channel(1);
+    let (_event_tx, event_rx) = unbounded_channel();
+    let throttle = ThrottleConfig {
+        rate: 100,
+        action: ThrottleAction::Reject,
+    };
+    let mut client = Client::connect_to(throttle, cmd_tx, event_rx).await;
+
+    client.send(&orders("A", 2)).await;
+    let report = client.recv().await;
+    assert_eq!(report.get(tag::CL_ORD_ID), Some("A1"));
+    assert_eq!(report.get(tag::EXEC_TYPE), Some("8"));
+}